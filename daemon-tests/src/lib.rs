@@ -1,6 +1,7 @@
 use crate::mocks::oracle::OracleActor;
 use crate::mocks::price_feed::PriceFeedActor;
 use crate::mocks::wallet::WalletActor;
+use daemon::auto_close;
 use daemon::auto_rollover;
 use daemon::bdk::bitcoin::secp256k1::schnorrsig;
 use daemon::bdk::bitcoin::Amount;
@@ -10,13 +11,17 @@ use daemon::connection::connect;
 use daemon::connection::ConnectionStatus;
 use daemon::db;
 use daemon::maker_cfd;
+use daemon::metrics::Metrics;
 use daemon::model;
 use daemon::model::cfd::OrderId;
 use daemon::model::cfd::Role;
+use daemon::model::cfd::CET_TIMELOCK;
 use daemon::model::FundingRate;
 use daemon::model::Identity;
+use daemon::model::Leverage;
 use daemon::model::OpeningFee;
 use daemon::model::Price;
+use daemon::model::RoundingStrategy;
 use daemon::model::Timestamp;
 use daemon::model::TxFeeRate;
 use daemon::model::Usd;
@@ -24,12 +29,16 @@ use daemon::projection;
 use daemon::projection::Cfd;
 use daemon::projection::CfdOrder;
 use daemon::projection::Feeds;
+use daemon::projection::PendingProposal;
 use daemon::seed::RandomSeed;
 use daemon::seed::Seed;
 use daemon::MakerActorSystem;
 use daemon::HEARTBEAT_INTERVAL;
 use daemon::N_PAYOUTS;
 use daemon::SETTLEMENT_INTERVAL;
+use daemon::SETTLEMENT_PROPOSAL_VALIDITY;
+use daemon::SETUP_TIMEOUT;
+use daemon::TCP_KEEPALIVE_INTERVAL;
 use rust_decimal_macros::dec;
 use std::net::IpAddr;
 use std::net::Ipv4Addr;
@@ -44,6 +53,7 @@ use tracing_subscriber::filter::LevelFilter;
 use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::EnvFilter;
 use xtra::Actor;
+use xtra::Address;
 
 pub mod flow;
 pub mod maia;
@@ -67,7 +77,14 @@ pub struct MakerConfig {
     seed: RandomSeed,
     pub heartbeat_interval: Duration,
     n_payouts: usize,
+    rounding: RoundingStrategy,
+    max_leverage: Leverage,
+    cet_timelock: u32,
     dedicated_port: Option<u16>,
+    auto_accept_takes: Option<maker_cfd::AutoAcceptTakes>,
+    require_confirmed_inputs: bool,
+    settlement_proposal_validity: time::Duration,
+    setup_timeout: Duration,
 }
 
 impl MakerConfig {
@@ -78,12 +95,62 @@ impl MakerConfig {
         }
     }
 
+    pub fn with_max_leverage(self, max_leverage: Leverage) -> Self {
+        Self {
+            max_leverage,
+            ..self
+        }
+    }
+
+    pub fn with_oracle_pk(self, oracle_pk: schnorrsig::PublicKey) -> Self {
+        Self { oracle_pk, ..self }
+    }
+
+    pub fn with_cet_timelock(self, cet_timelock: u32) -> Self {
+        Self {
+            cet_timelock,
+            ..self
+        }
+    }
+
+    pub fn with_rounding(self, rounding: RoundingStrategy) -> Self {
+        Self { rounding, ..self }
+    }
+
     pub fn with_dedicated_port(self, port: u16) -> Self {
         Self {
             dedicated_port: Some(port),
             ..self
         }
     }
+
+    pub fn with_auto_accept_takes(self, policy: maker_cfd::AutoAcceptTakes) -> Self {
+        Self {
+            auto_accept_takes: Some(policy),
+            ..self
+        }
+    }
+
+    pub fn with_required_input_confirmation(self) -> Self {
+        Self {
+            require_confirmed_inputs: true,
+            ..self
+        }
+    }
+
+    pub fn with_settlement_proposal_validity(self, validity: time::Duration) -> Self {
+        Self {
+            settlement_proposal_validity: validity,
+            ..self
+        }
+    }
+
+    pub fn with_setup_timeout(self, setup_timeout: Duration) -> Self {
+        Self {
+            setup_timeout,
+            ..self
+        }
+    }
 }
 
 impl Default for MakerConfig {
@@ -93,7 +160,14 @@ impl Default for MakerConfig {
             seed: RandomSeed::default(),
             heartbeat_interval: HEARTBEAT_INTERVAL,
             n_payouts: N_PAYOUTS,
+            rounding: RoundingStrategy::default(),
+            max_leverage: Leverage::new(2).unwrap(),
+            cet_timelock: CET_TIMELOCK,
             dedicated_port: None,
+            auto_accept_takes: None,
+            require_confirmed_inputs: false,
+            settlement_proposal_validity: SETTLEMENT_PROPOSAL_VALIDITY,
+            setup_timeout: SETUP_TIMEOUT,
         }
     }
 }
@@ -103,7 +177,7 @@ pub struct TakerConfig {
     oracle_pk: schnorrsig::PublicKey,
     seed: RandomSeed,
     pub heartbeat_interval: Duration,
-    n_payouts: usize,
+    setup_timeout: Duration,
 }
 
 impl TakerConfig {
@@ -113,6 +187,13 @@ impl TakerConfig {
             ..self
         }
     }
+
+    pub fn with_setup_timeout(self, setup_timeout: Duration) -> Self {
+        Self {
+            setup_timeout,
+            ..self
+        }
+    }
 }
 
 impl Default for TakerConfig {
@@ -121,7 +202,7 @@ impl Default for TakerConfig {
             oracle_pk: oracle_pk(),
             seed: RandomSeed::default(),
             heartbeat_interval: HEARTBEAT_INTERVAL,
-            n_payouts: N_PAYOUTS,
+            setup_timeout: SETUP_TIMEOUT,
         }
     }
 }
@@ -149,6 +230,10 @@ impl Maker {
         &mut self.feeds.connected_takers
     }
 
+    pub fn proposal_feed(&mut self) -> &mut watch::Receiver<Vec<PendingProposal>> {
+        &mut self.feeds.proposals
+    }
+
     pub async fn start(config: &MakerConfig) -> Self {
         let port = match config.dedicated_port {
             Some(port) => port,
@@ -194,15 +279,29 @@ impl Maker {
             |_| Ok(monitor),
             settlement_interval,
             config.n_payouts,
+            config.rounding,
+            config.max_leverage,
+            config.cet_timelock,
+            config.auto_accept_takes,
+            config.require_confirmed_inputs,
+            config.settlement_proposal_validity,
+            config.setup_timeout,
             projection_actor.clone(),
             identity_sk,
             config.heartbeat_interval,
+            TCP_KEEPALIVE_INTERVAL,
             address,
+            Metrics::default(),
         )
         .unwrap();
 
-        let (proj_actor, feeds) =
-            projection::Actor::new(db, Role::Maker, Network::Testnet, &price_feed_addr);
+        let (proj_actor, feeds) = projection::Actor::new(
+            db,
+            Role::Maker,
+            Network::Testnet,
+            &price_feed_addr,
+            Duration::ZERO,
+        );
         tasks.add(projection_context.run(proj_actor));
 
         Self {
@@ -233,6 +332,7 @@ pub struct Taker {
     pub system: daemon::TakerActorSystem<OracleActor, WalletActor, PriceFeedActor>,
     pub mocks: mocks::Mocks,
     pub feeds: Feeds,
+    pub projection: Address<projection::Actor>,
     _tasks: Tasks,
 }
 
@@ -249,6 +349,10 @@ impl Taker {
         &mut self.feeds.quote
     }
 
+    pub fn proposal_feed(&mut self) -> &mut watch::Receiver<Vec<PendingProposal>> {
+        &mut self.feeds.proposals
+    }
+
     pub fn maker_status_feed(&mut self) -> &mut watch::Receiver<ConnectionStatus> {
         &mut self.system.maker_online_status_feed_receiver
     }
@@ -283,16 +387,23 @@ impl Taker {
             |_| oracle,
             |_| Ok(monitor),
             move |_| price_feed.clone(),
-            config.n_payouts,
             config.heartbeat_interval,
             Duration::from_secs(10),
-            projection_actor,
+            TCP_KEEPALIVE_INTERVAL,
+            config.setup_timeout,
+            projection_actor.clone(),
             maker_identity,
+            Metrics::default(),
         )
         .unwrap();
 
-        let (proj_actor, feeds) =
-            projection::Actor::new(db, Role::Taker, Network::Testnet, &taker.price_feed_actor);
+        let (proj_actor, feeds) = projection::Actor::new(
+            db,
+            Role::Taker,
+            Network::Testnet,
+            &taker.price_feed_actor,
+            Duration::ZERO,
+        );
         tasks.add(projection_context.run(proj_actor));
 
         tasks.add(connect(
@@ -307,6 +418,7 @@ impl Taker {
             system: taker,
             feeds,
             mocks,
+            projection: projection_actor,
             _tasks: tasks,
         }
     }
@@ -318,6 +430,14 @@ impl Taker {
             .await
             .unwrap()
     }
+
+    pub async fn trigger_auto_close(&self) {
+        self.system
+            .auto_close_actor
+            .send(auto_close::AutoClose)
+            .await
+            .unwrap()
+    }
 }
 
 /// Deliver monitor event to both actor systems
@@ -359,10 +479,14 @@ pub fn dummy_price() -> Price {
 }
 
 pub fn dummy_quote() -> Quote {
+    quote_at(dummy_price())
+}
+
+pub fn quote_at(price: Price) -> Quote {
     Quote {
         timestamp: Timestamp::now(),
-        bid: dummy_price(),
-        ask: dummy_price(),
+        bid: price,
+        ask: price,
     }
 }
 
@@ -375,6 +499,7 @@ pub fn dummy_new_order() -> maker_cfd::NewOrder {
         // 8.76% annualized = rate of 0.0876 annualized = rate of 0.00024 daily
         funding_rate: FundingRate::new(dec!(0.00024)).unwrap(),
         opening_fee: OpeningFee::new(Amount::from_sat(2)),
+        n_payouts: None,
     }
 }
 
@@ -3,8 +3,11 @@ use self::oracle::OracleActor;
 use self::wallet::WalletActor;
 use super::maia::OliviaData;
 use crate::mocks::price_feed::PriceFeedActor;
+use daemon::bdk::bitcoin::Txid;
 use daemon::bitmex_price_feed;
+use daemon::model::cfd::OrderId;
 use std::sync::Arc;
+use tokio::sync::mpsc;
 use tokio::sync::Mutex;
 use tokio::sync::MutexGuard;
 
@@ -52,7 +55,7 @@ impl Mocks {
         self.monitor()
             .await
             .expect_broadcast()
-            .returning(|_| Ok(()));
+            .returning(|msg| Ok(msg.tx.txid()));
     }
 
     pub async fn mock_oracle_announcement(&mut self) {
@@ -85,6 +88,23 @@ impl Mocks {
             .returning(|msg| wallet::build_party_params(msg));
     }
 
+    pub async fn mock_wallet_check_funds(&mut self) {
+        self.wallet()
+            .await
+            .expect_check_funds()
+            .returning(|_| Ok(()));
+    }
+
+    /// Expect exactly one rescan of the given CFD.
+    pub async fn mock_monitor_rescan(&mut self, order_id: OrderId) {
+        self.monitor()
+            .await
+            .expect_rescan()
+            .withf(move |msg| msg.id == order_id)
+            .times(1)
+            .returning(|_| Ok(None));
+    }
+
     pub async fn mock_monitor_oracle_attestation(&mut self) {
         self.monitor()
             .await
@@ -96,14 +116,54 @@ impl Mocks {
         self.monitor()
             .await
             .expect_start_monitoring()
-            .return_const(());
+            .returning(|_| Ok(()));
     }
 
     pub async fn mock_monitor_collaborative_settlement(&mut self) {
         self.monitor()
             .await
             .expect_collaborative_settlement()
-            .return_const(());
+            .returning(|_| Ok(()));
+    }
+
+    /// Replace the monitor expectations such that broadcasting the collaborative close
+    /// transaction fails while everything else keeps working.
+    ///
+    /// Returns the collaborative settlements the monitor was asked to watch and the ids of the
+    /// collaborative close transactions that failed to broadcast.
+    pub async fn mock_failing_collaborative_close_broadcast(
+        &mut self,
+    ) -> (
+        mpsc::UnboundedReceiver<daemon::monitor::CollaborativeSettlement>,
+        mpsc::UnboundedReceiver<Txid>,
+    ) {
+        let (watched_sender, watched) = mpsc::unbounded_channel();
+        let (failed_sender, failed) = mpsc::unbounded_channel();
+
+        let mut monitor = self.monitor().await;
+        monitor.checkpoint();
+
+        monitor.expect_sync().return_const(());
+        monitor.expect_oracle_attestation().return_const(());
+        monitor
+            .expect_collaborative_settlement()
+            .returning(move |msg| {
+                let _ = watched_sender.send(msg);
+                Ok(())
+            });
+        monitor.expect_broadcast().returning(move |msg| {
+            if matches!(
+                msg.kind,
+                daemon::monitor::TransactionKind::CollaborativeClose
+            ) {
+                let _ = failed_sender.send(msg.tx.txid());
+                anyhow::bail!("Electrum server not reachable")
+            }
+
+            Ok(msg.tx.txid())
+        });
+
+        (watched, failed)
     }
 
     pub async fn mock_latest_quote(&mut self, latest_quote: Option<bitmex_price_feed::Quote>) {
@@ -1,4 +1,7 @@
 use anyhow::Result;
+use daemon::bdk::bitcoin::OutPoint;
+use daemon::bdk::bitcoin::Txid;
+use daemon::model::BitMexPriceEventId;
 use daemon::monitor;
 use daemon::oracle;
 use mockall::*;
@@ -21,11 +24,11 @@ impl MonitorActor {
         self.mock.lock().await.sync(msg)
     }
 
-    async fn handle(&mut self, msg: monitor::StartMonitoring) {
+    async fn handle(&mut self, msg: monitor::StartMonitoring) -> Result<()> {
         self.mock.lock().await.start_monitoring(msg)
     }
 
-    async fn handle(&mut self, msg: monitor::CollaborativeSettlement) {
+    async fn handle(&mut self, msg: monitor::CollaborativeSettlement) -> Result<()> {
         self.mock.lock().await.collaborative_settlement(msg)
     }
 
@@ -33,9 +36,17 @@ impl MonitorActor {
         self.mock.lock().await.oracle_attestation(msg);
     }
 
-    async fn handle(&mut self, msg: monitor::TryBroadcastTransaction) -> Result<()> {
+    async fn handle(&mut self, msg: monitor::TryBroadcastTransaction) -> Result<Txid> {
         self.mock.lock().await.broadcast(msg)
     }
+
+    async fn handle(&mut self, msg: monitor::Rescan) -> Result<Option<BitMexPriceEventId>> {
+        self.mock.lock().await.rescan(msg)
+    }
+
+    async fn handle(&mut self, msg: monitor::GetUnconfirmedInputs) -> Result<Vec<OutPoint>> {
+        self.mock.lock().await.unconfirmed_inputs(msg)
+    }
 }
 
 #[automock]
@@ -44,11 +55,11 @@ pub trait Monitor {
         unreachable!("mockall will reimplement this method")
     }
 
-    fn start_monitoring(&mut self, _msg: monitor::StartMonitoring) {
+    fn start_monitoring(&mut self, _msg: monitor::StartMonitoring) -> Result<()> {
         unreachable!("mockall will reimplement this method")
     }
 
-    fn collaborative_settlement(&mut self, _msg: monitor::CollaborativeSettlement) {
+    fn collaborative_settlement(&mut self, _msg: monitor::CollaborativeSettlement) -> Result<()> {
         unreachable!("mockall will reimplement this method")
     }
 
@@ -56,7 +67,15 @@ pub trait Monitor {
         unreachable!("mockall will reimplement this method")
     }
 
-    fn broadcast(&mut self, _msg: monitor::TryBroadcastTransaction) -> Result<()> {
+    fn broadcast(&mut self, _msg: monitor::TryBroadcastTransaction) -> Result<Txid> {
+        unreachable!("mockall will reimplement this method")
+    }
+
+    fn rescan(&mut self, _msg: monitor::Rescan) -> Result<Option<BitMexPriceEventId>> {
+        unreachable!("mockall will reimplement this method")
+    }
+
+    fn unconfirmed_inputs(&mut self, _msg: monitor::GetUnconfirmedInputs) -> Result<Vec<OutPoint>> {
         unreachable!("mockall will reimplement this method")
     }
 }
@@ -36,9 +36,18 @@ impl WalletActor {
     async fn handle(&mut self, msg: wallet::Sign) -> Result<PartiallySignedTransaction> {
         self.mock.lock().await.sign(msg)
     }
+    async fn handle(&mut self, msg: wallet::CheckFunds) -> Result<()> {
+        self.mock.lock().await.check_funds(msg)
+    }
     async fn handle(&mut self, msg: wallet::Withdraw) -> Result<Txid> {
         self.mock.lock().await.withdraw(msg)
     }
+    async fn handle(&mut self, msg: wallet::ListUtxos) -> Result<Vec<wallet::Utxo>> {
+        self.mock.lock().await.list_utxos(msg)
+    }
+    async fn handle(&mut self, msg: wallet::NewAddress) -> Result<bdk::bitcoin::Address> {
+        self.mock.lock().await.new_address(msg)
+    }
 }
 
 #[automock]
@@ -51,9 +60,21 @@ pub trait Wallet {
         unreachable!("mockall will reimplement this method")
     }
 
+    fn check_funds(&mut self, _msg: wallet::CheckFunds) -> Result<()> {
+        unreachable!("mockall will reimplement this method")
+    }
+
     fn withdraw(&mut self, _msg: wallet::Withdraw) -> Result<Txid> {
         unreachable!("mockall will reimplement this method")
     }
+
+    fn list_utxos(&mut self, _msg: wallet::ListUtxos) -> Result<Vec<wallet::Utxo>> {
+        unreachable!("mockall will reimplement this method")
+    }
+
+    fn new_address(&mut self, _msg: wallet::NewAddress) -> Result<bdk::bitcoin::Address> {
+        unreachable!("mockall will reimplement this method")
+    }
 }
 
 #[allow(dead_code)]
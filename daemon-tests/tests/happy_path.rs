@@ -1,13 +1,32 @@
+use daemon::bdk::bitcoin::secp256k1::schnorrsig;
 use daemon::bdk::bitcoin::Amount;
+use daemon::connection::ConnectionCloseReason;
 use daemon::connection::ConnectionStatus;
+use daemon::connection::MakerStreamMessage;
+use daemon::maker_cfd::AutoAcceptTakes;
+use daemon::maker_cfd::FromTaker;
+use daemon::metrics::Snapshot;
 use daemon::model::cfd::calculate_long_margin;
+use daemon::model::cfd::AutoCloseThresholds;
 use daemon::model::cfd::OrderId;
+use daemon::model::cfd::RejectReason;
+use daemon::model::BitMexPriceEventId;
 use daemon::model::Identity;
+use daemon::model::Leverage;
+use daemon::model::Price;
+use daemon::model::RoundingStrategy;
 use daemon::model::Usd;
 use daemon::monitor::Event;
 use daemon::oracle;
+use daemon::projection;
 use daemon::projection::CfdOrder;
 use daemon::projection::CfdState;
+use daemon::projection::PendingProposal;
+use daemon::projection::ProposalDirection;
+use daemon::projection::ProposalKind;
+use daemon::projection::SetupProgress;
+use daemon::taker_cfd;
+use daemon::wire;
 use daemon_tests::deliver_event;
 use daemon_tests::dummy_new_order;
 use daemon_tests::dummy_quote;
@@ -19,6 +38,7 @@ use daemon_tests::flow::one_cfd_with_state;
 use daemon_tests::init_tracing;
 use daemon_tests::maia::OliviaData;
 use daemon_tests::mocks::oracle::dummy_wrong_attestation;
+use daemon_tests::quote_at;
 use daemon_tests::start_both;
 use daemon_tests::wait_next_state;
 use daemon_tests::Maker;
@@ -26,7 +46,9 @@ use daemon_tests::MakerConfig;
 use daemon_tests::Taker;
 use daemon_tests::TakerConfig;
 use rust_decimal_macros::dec;
+use std::str::FromStr;
 use std::time::Duration;
+use time::OffsetDateTime;
 use tokio::time::sleep;
 
 #[tokio::test]
@@ -45,19 +67,100 @@ async fn taker_receives_order_from_maker_on_publication() {
     assert_eq_order(published, received);
 }
 
+#[tokio::test]
+async fn taker_receives_order_with_updated_price() {
+    let _guard = init_tracing();
+    let (mut maker, mut taker) = start_both().await;
+
+    maker.publish_order(dummy_new_order()).await;
+    let (published, _) = next_order(maker.order_feed(), taker.order_feed())
+        .await
+        .unwrap();
+
+    let new_price = Price::new(dec!(60_000)).unwrap();
+    maker.system.update_order_price(new_price).await.unwrap();
+
+    let (updated, received) = next_order(maker.order_feed(), taker.order_feed())
+        .await
+        .unwrap();
+
+    assert_eq!(received.price, new_price);
+    assert_ne!(
+        received.id, published.id,
+        "re-priced order must get a new id"
+    );
+    assert_eq!(received.min_quantity, published.min_quantity);
+    assert_eq!(received.max_quantity, published.max_quantity);
+    assert_eq_order(updated, received);
+}
+
+#[tokio::test]
+async fn taker_receives_current_order_on_connect() {
+    let _guard = init_tracing();
+    let mut maker = Maker::start(&MakerConfig::default()).await;
+
+    maker.publish_order(dummy_new_order()).await;
+    let published = next_with(maker.order_feed(), |order| order).await.unwrap();
+
+    // The taker connects after the order was published. The maker does not push its order to new
+    // takers, hence the taker only receives it if the maker answers its request.
+    let mut taker = Taker::start(&TakerConfig::default(), maker.listen_addr, maker.identity).await;
+    let received = next_with(taker.order_feed(), |order| order).await.unwrap();
+    let order_id = received.id;
+
+    assert_eq_order(published, received);
+
+    // Every request is answered, e.g. after the taker reconnected
+    maker
+        .system
+        .cfd_actor
+        .send(FromTaker {
+            taker_id: taker.id,
+            msg: wire::TakerToMaker::RequestCurrentOrder,
+        })
+        .await
+        .unwrap();
+    let resent = next_with(taker.order_feed(), |order| order).await.unwrap();
+
+    assert_eq!(resent.id, order_id);
+}
+
 fn assert_eq_order(mut published: CfdOrder, received: CfdOrder) {
     // align margin_per_parcel to be the long margin_per_parcel
     let long_margin_per_parcel =
         calculate_long_margin(published.price, published.parcel_size, published.leverage);
-    published.margin_per_parcel = long_margin_per_parcel;
+    published.margin_per_parcel = long_margin_per_parcel.into();
 
     assert_eq!(published, received);
 
     // Hard-coded to match the dummy_new_order()
-    assert_eq!(received.opening_fee.unwrap(), Amount::from_sat(2));
+    assert_eq!(received.opening_fee.unwrap().value(), Amount::from_sat(2));
     assert_eq!(received.funding_rate_hourly_percent, "0.00100");
 }
 
+#[tokio::test]
+async fn taker_can_query_latest_order_from_maker() {
+    let _guard = init_tracing();
+    let (mut maker, mut taker) = start_both().await;
+
+    is_next_none(taker.order_feed()).await.unwrap();
+    assert_eq!(taker.system.current_order().await.unwrap(), None);
+
+    maker.publish_order(dummy_new_order()).await;
+    let (_, first) = next_order(maker.order_feed(), taker.order_feed())
+        .await
+        .unwrap();
+
+    maker.publish_order(dummy_new_order()).await;
+    let (_, second) = next_order(maker.order_feed(), taker.order_feed())
+        .await
+        .unwrap();
+
+    let current_order = taker.system.current_order().await.unwrap().unwrap();
+    assert_ne!(first.id, second.id);
+    assert_eq!(current_order.id, second.id);
+}
+
 #[tokio::test]
 async fn taker_takes_order_and_maker_rejects() {
     let _guard = init_tracing();
@@ -74,23 +177,512 @@ async fn taker_takes_order_and_maker_rejects() {
 
     taker.mocks.mock_oracle_announcement().await;
     maker.mocks.mock_oracle_announcement().await;
+    taker.mocks.mock_wallet_check_funds().await;
+    taker
+        .system
+        .take_offer(received.id, Usd::new(dec!(10)), None)
+        .await
+        .unwrap();
+
+    wait_next_state!(received.id, maker, taker, CfdState::PendingSetup);
+
+    maker.system.reject_order(received.id).await.unwrap();
+
+    wait_next_state!(received.id, maker, taker, CfdState::Rejected);
+    assert_eq!(
+        taker.cfd_feed().borrow()[0].rejection_reason,
+        Some(RejectReason::ManualReject)
+    );
+}
+
+#[tokio::test]
+async fn taker_retries_announcement_that_is_not_yet_published() {
+    let _guard = init_tracing();
+    let (mut maker, mut taker) = start_both().await;
+
+    is_next_none(taker.order_feed()).await.unwrap();
+
+    maker.publish_order(dummy_new_order()).await;
+
+    let (_, received) = next_order(maker.order_feed(), taker.order_feed())
+        .await
+        .unwrap();
+
+    let announcement = OliviaData::example_0().announcement();
+    let mut published = false;
+    taker
+        .mocks
+        .oracle()
+        .await
+        .expect_get_announcement()
+        .returning(move |msg| {
+            if !published {
+                published = true;
+                return Err(oracle::NoAnnouncement::NotYetPublished(msg.0));
+            }
+
+            Ok(announcement.clone())
+        });
+    maker.mocks.mock_oracle_announcement().await;
+    taker.mocks.mock_wallet_check_funds().await;
+    taker
+        .system
+        .take_offer(received.id, Usd::new(dec!(10)), None)
+        .await
+        .unwrap();
+
+    wait_next_state!(received.id, maker, taker, CfdState::PendingSetup);
+}
+
+#[tokio::test]
+async fn taker_cancels_order_request_before_maker_accepts() {
+    let _guard = init_tracing();
+    let (mut maker, mut taker) = start_both().await;
+
+    is_next_none(taker.order_feed()).await.unwrap();
+
+    maker.publish_order(dummy_new_order()).await;
+
+    let (_, received) = next_order(maker.order_feed(), taker.order_feed())
+        .await
+        .unwrap();
+
+    taker.mocks.mock_oracle_announcement().await;
+    maker.mocks.mock_oracle_announcement().await;
+    taker.mocks.mock_wallet_check_funds().await;
+    taker
+        .system
+        .take_offer(received.id, Usd::new(dec!(10)), None)
+        .await
+        .unwrap();
+
+    wait_next_state!(received.id, maker, taker, CfdState::PendingSetup);
+
+    taker
+        .system
+        .cancel_order_request(received.id)
+        .await
+        .unwrap();
+
+    wait_next_state!(received.id, maker, taker, CfdState::SetupFailed);
+
+    // Neither party has a contract setup for the order left that could be resumed
+    assert!(maker.system.accept_order(received.id).await.is_err());
+    assert!(taker
+        .system
+        .cancel_order_request(received.id)
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn maker_rejects_take_with_leverage_above_its_cap() {
+    let _guard = init_tracing();
+    let mut maker =
+        Maker::start(&MakerConfig::default().with_max_leverage(Leverage::new(10).unwrap())).await;
+    let mut taker = Taker::start(&TakerConfig::default(), maker.listen_addr, maker.identity).await;
+
+    is_next_none(taker.order_feed()).await.unwrap();
+
+    maker.publish_order(dummy_new_order()).await;
+
+    let (published, received) = next_order(maker.order_feed(), taker.order_feed())
+        .await
+        .unwrap();
+    assert_eq!(published.max_leverage, Leverage::new(10).unwrap());
+    assert_eq!(received.max_leverage, Leverage::new(10).unwrap());
+
+    taker.mocks.mock_oracle_announcement().await;
+    taker.mocks.mock_wallet_check_funds().await;
+    taker
+        .system
+        .take_offer(
+            received.id,
+            Usd::new(dec!(10)),
+            Some(Leverage::new(20).unwrap()),
+        )
+        .await
+        .unwrap();
+
+    wait_next_state!(received.id, maker, taker, CfdState::Rejected);
+    assert_eq!(
+        taker.cfd_feed().borrow()[0].rejection_reason,
+        Some(RejectReason::LeverageTooHigh)
+    );
+    assert_eq!(
+        maker.cfd_feed().borrow()[0].rejection_reason,
+        Some(RejectReason::LeverageTooHigh)
+    );
+}
+
+#[tokio::test]
+async fn taker_refuses_order_of_maker_using_another_oracle() {
+    let _guard = init_tracing();
+    let other_oracle_pk = schnorrsig::PublicKey::from_str(
+        "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+    )
+    .unwrap();
+    let mut maker = Maker::start(&MakerConfig::default().with_oracle_pk(other_oracle_pk)).await;
+    let mut taker = Taker::start(&TakerConfig::default(), maker.listen_addr, maker.identity).await;
+
+    is_next_none(taker.order_feed()).await.unwrap();
+
+    maker.publish_order(dummy_new_order()).await;
+
+    let (_, received) = next_order(maker.order_feed(), taker.order_feed())
+        .await
+        .unwrap();
+
+    let error = taker
+        .system
+        .take_offer(received.id, Usd::new(dec!(10)), None)
+        .await
+        .unwrap_err();
+
+    assert!(format!("{error:#}").contains("oracle"));
+    assert!(taker.cfd_feed().borrow().is_empty());
+    assert!(maker.cfd_feed().borrow().is_empty());
+}
+
+#[tokio::test]
+async fn taker_refuses_order_whose_oracle_event_has_likely_occurred() {
+    let _guard = init_tracing();
+    let (mut maker, mut taker) = start_both().await;
+
+    is_next_none(taker.order_feed()).await.unwrap();
+
+    maker.publish_order(dummy_new_order()).await;
+
+    let (_, received) = next_order(maker.order_feed(), taker.order_feed())
+        .await
+        .unwrap();
+
+    // Pretend the maker sent an order settling on an event in the past
+    let mut order = taker.system.current_order().await.unwrap().unwrap();
+    order.oracle_event_id =
+        BitMexPriceEventId::with_20_digits(OffsetDateTime::now_utc() - time::Duration::hours(2));
+    taker
+        .system
+        .cfd_actor
+        .send(taker_cfd::CurrentOrder(Some(order)))
+        .await
+        .unwrap()
+        .unwrap();
+
+    let error = taker
+        .system
+        .take_offer(received.id, Usd::new(dec!(10)), None)
+        .await
+        .unwrap_err();
+
+    assert!(format!("{error:#}").contains("has likely already occurred"));
+    assert!(taker.cfd_feed().borrow().is_empty());
+    assert!(maker.cfd_feed().borrow().is_empty());
+}
+
+#[tokio::test]
+async fn maker_rejects_second_take_of_order_in_setup() {
+    let _guard = init_tracing();
+    let (mut maker, mut taker) = start_both().await;
+    let mut other_taker =
+        Taker::start(&TakerConfig::default(), maker.listen_addr, maker.identity).await;
+
+    is_next_none(taker.order_feed()).await.unwrap();
+    is_next_none(other_taker.order_feed()).await.unwrap();
+
+    maker.publish_order(dummy_new_order()).await;
+
+    let (_, received) = next_order(maker.order_feed(), taker.order_feed())
+        .await
+        .unwrap();
+    next_with(other_taker.order_feed(), |order| order)
+        .await
+        .unwrap();
+
+    maker.mocks.mock_oracle_announcement().await;
+    for taker in [&mut taker, &mut other_taker] {
+        taker.mocks.mock_oracle_announcement().await;
+        taker.mocks.mock_wallet_check_funds().await;
+    }
+
+    let quantity = Usd::new(dec!(10));
+    let (first, second) = tokio::join!(
+        taker.system.take_offer(received.id, quantity, None),
+        other_taker.system.take_offer(received.id, quantity, None)
+    );
+    first.unwrap();
+    second.unwrap();
+
+    // Whichever take reached the maker first is set up, the other one is told so and rejected
+    let maker_cfd = next_with(maker.cfd_feed(), one_cfd_with_state(CfdState::PendingSetup))
+        .await
+        .unwrap();
+    let (mut first_taker, mut second_taker) = if maker_cfd.counterparty == taker.id {
+        (taker, other_taker)
+    } else {
+        (other_taker, taker)
+    };
+
+    let rejected = next_with(
+        second_taker.cfd_feed(),
+        one_cfd_with_state(CfdState::Rejected),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        rejected.rejection_reason,
+        Some(RejectReason::AlreadyInProgress)
+    );
+    next_with(
+        first_taker.cfd_feed(),
+        one_cfd_with_state(CfdState::PendingSetup),
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn metrics_count_takes_and_accepts() {
+    let _guard = init_tracing();
+    let (mut maker, mut taker) = start_both().await;
+
+    is_next_none(taker.order_feed()).await.unwrap();
+
+    maker.publish_order(dummy_new_order()).await;
+
+    let (_, received) = next_order(maker.order_feed(), taker.order_feed())
+        .await
+        .unwrap();
+
+    taker.mocks.mock_oracle_announcement().await;
+    maker.mocks.mock_oracle_announcement().await;
+    taker.mocks.mock_party_params().await;
+    taker.mocks.mock_wallet_check_funds().await;
+
+    taker
+        .system
+        .take_offer(received.id, Usd::new(dec!(5)), None)
+        .await
+        .unwrap();
+    wait_next_state!(received.id, maker, taker, CfdState::PendingSetup);
+
+    assert_eq!(maker.system.metrics.snapshot().takes, 1);
+    assert_eq!(taker.system.metrics.snapshot().takes, 1);
+    assert_eq!(maker.system.metrics.snapshot().accepts, 0);
+
+    maker.mocks.mock_party_params().await;
+    maker.mocks.mock_wallet_check_funds().await;
+    maker.mocks.mock_monitor_oracle_attestation().await;
+    taker.mocks.mock_monitor_oracle_attestation().await;
+    maker.mocks.mock_oracle_monitor_attestation().await;
+    taker.mocks.mock_oracle_monitor_attestation().await;
+    maker.mocks.mock_monitor_start_monitoring().await;
+    taker.mocks.mock_monitor_start_monitoring().await;
+    maker.mocks.mock_wallet_sign_and_broadcast().await;
+    taker.mocks.mock_wallet_sign_and_broadcast().await;
+
+    maker.system.accept_order(received.id).await.unwrap();
+    wait_next_state!(received.id, maker, taker, CfdState::ContractSetup);
+
+    let expected = Snapshot {
+        takes: 1,
+        accepts: 1,
+        ..Snapshot::default()
+    };
+    assert_eq!(maker.system.metrics.snapshot(), expected);
+    assert_eq!(taker.system.metrics.snapshot(), expected);
+}
+
+#[tokio::test]
+async fn taker_takes_order_and_maker_accepts_and_contract_setup() {
+    let _guard = init_tracing();
+    let (mut maker, mut taker) = start_both().await;
+
+    is_next_none(taker.order_feed()).await.unwrap();
+
+    maker.publish_order(dummy_new_order()).await;
+
+    let (_, received) = next_order(maker.order_feed(), taker.order_feed())
+        .await
+        .unwrap();
+
+    taker.mocks.mock_oracle_announcement().await;
+    maker.mocks.mock_oracle_announcement().await;
+    taker.mocks.mock_party_params().await;
+    taker.mocks.mock_wallet_check_funds().await;
+
+    taker
+        .system
+        .take_offer(received.id, Usd::new(dec!(5)), None)
+        .await
+        .unwrap();
+    wait_next_state!(received.id, maker, taker, CfdState::PendingSetup);
+
+    maker.mocks.mock_party_params().await;
+    maker.mocks.mock_wallet_check_funds().await;
+
+    maker.mocks.mock_monitor_oracle_attestation().await;
+    taker.mocks.mock_monitor_oracle_attestation().await;
+
+    maker.mocks.mock_oracle_monitor_attestation().await;
+    taker.mocks.mock_oracle_monitor_attestation().await;
+
+    maker.mocks.mock_monitor_start_monitoring().await;
+    taker.mocks.mock_monitor_start_monitoring().await;
+
+    maker.mocks.mock_wallet_sign_and_broadcast().await;
+    taker.mocks.mock_wallet_sign_and_broadcast().await;
+
+    maker.system.accept_order(received.id).await.unwrap();
+    wait_next_state!(received.id, maker, taker, CfdState::ContractSetup);
+
+    sleep(Duration::from_secs(5)).await; // need to wait a bit until both transition
+    wait_next_state!(received.id, maker, taker, CfdState::PendingOpen);
+
+    let expected_progress = vec![
+        SetupProgress::ParametersExchanged,
+        SetupProgress::TransactionsCreated,
+        SetupProgress::SignaturesVerified,
+        SetupProgress::LockSigned,
+    ];
+    assert_eq!(
+        maker.cfd_feed().borrow()[0].setup_progress,
+        expected_progress
+    );
+    assert_eq!(
+        taker.cfd_feed().borrow()[0].setup_progress,
+        expected_progress
+    );
+
+    deliver_event!(maker, taker, Event::LockFinality(received.id));
+    wait_next_state!(received.id, maker, taker, CfdState::Open);
+}
+
+#[tokio::test]
+async fn contract_setup_uses_the_rounding_of_the_maker() {
+    let _guard = init_tracing();
+
+    // Both parties have to build the same CETs, the setup fails unless the taker follows the
+    // rounding the maker put into its order
+    let (mut maker, mut taker, _) = start_from_open_cfd_state_with_config(
+        OliviaData::example_0().announcement(),
+        &MakerConfig::default().with_rounding(RoundingStrategy::Bankers),
+    )
+    .await;
+
+    assert_eq!(maker.cfd_feed().borrow()[0].state, CfdState::Open);
+    assert_eq!(taker.cfd_feed().borrow()[0].state, CfdState::Open);
+}
+
+#[tokio::test]
+async fn maker_rejects_setup_funded_with_unconfirmed_inputs() {
+    let _guard = init_tracing();
+    let mut maker = Maker::start(&MakerConfig::default().with_required_input_confirmation()).await;
+    let mut taker = Taker::start(
+        &TakerConfig::default().with_setup_timeout(Duration::from_secs(2)),
+        maker.listen_addr,
+        maker.identity,
+    )
+    .await;
+
+    is_next_none(taker.order_feed()).await.unwrap();
+
+    maker.publish_order(dummy_new_order()).await;
+
+    let (_, received) = next_order(maker.order_feed(), taker.order_feed())
+        .await
+        .unwrap();
+
+    taker.mocks.mock_oracle_announcement().await;
+    maker.mocks.mock_oracle_announcement().await;
+    taker.mocks.mock_party_params().await;
+    taker.mocks.mock_wallet_check_funds().await;
+    maker.mocks.mock_party_params().await;
+    maker.mocks.mock_wallet_check_funds().await;
+
+    // None of the taker's inputs are in a block yet
+    maker
+        .mocks
+        .monitor()
+        .await
+        .expect_unconfirmed_inputs()
+        .returning(|msg| {
+            Ok(msg
+                .psbt
+                .global
+                .unsigned_tx
+                .input
+                .iter()
+                .map(|input| input.previous_output)
+                .collect())
+        });
+
+    taker
+        .system
+        .take_offer(received.id, Usd::new(dec!(5)), None)
+        .await
+        .unwrap();
+    wait_next_state!(received.id, maker, taker, CfdState::PendingSetup);
+
+    maker.system.accept_order(received.id).await.unwrap();
+
+    wait_next_state!(received.id, maker, taker, CfdState::SetupFailed);
+}
+
+#[tokio::test]
+async fn contract_setup_times_out_if_maker_never_responds() {
+    let _guard = init_tracing();
+    let mut maker = Maker::start(&MakerConfig::default()).await;
+    let mut taker = Taker::start(
+        &TakerConfig::default().with_setup_timeout(Duration::from_secs(2)),
+        maker.listen_addr,
+        maker.identity,
+    )
+    .await;
+
+    is_next_none(taker.order_feed()).await.unwrap();
+
+    maker.publish_order(dummy_new_order()).await;
+
+    let (_, received) = next_order(maker.order_feed(), taker.order_feed())
+        .await
+        .unwrap();
+
+    taker.mocks.mock_oracle_announcement().await;
+    maker.mocks.mock_oracle_announcement().await;
+    taker.mocks.mock_party_params().await;
+    taker.mocks.mock_wallet_check_funds().await;
+
     taker
         .system
-        .take_offer(received.id, Usd::new(dec!(10)))
+        .take_offer(received.id, Usd::new(dec!(5)), None)
         .await
         .unwrap();
-
     wait_next_state!(received.id, maker, taker, CfdState::PendingSetup);
 
-    maker.system.reject_order(received.id).await.unwrap();
+    // The maker fails before sending its first protocol message, the taker never hears back
+    maker
+        .mocks
+        .wallet()
+        .await
+        .expect_build_party_params()
+        .returning(|_| anyhow::bail!("Wallet unavailable"));
 
-    wait_next_state!(received.id, maker, taker, CfdState::Rejected);
+    maker.system.accept_order(received.id).await.unwrap();
+
+    wait_next_state!(received.id, maker, taker, CfdState::SetupFailed);
 }
 
 #[tokio::test]
-async fn taker_takes_order_and_maker_accepts_and_contract_setup() {
+async fn taker_takes_order_within_limits_and_maker_auto_accepts() {
     let _guard = init_tracing();
-    let (mut maker, mut taker) = start_both().await;
+    let maker_config = MakerConfig::default().with_auto_accept_takes(AutoAcceptTakes {
+        max_quantity: Usd::new(dec!(10)),
+        max_leverage: Leverage::new(2).unwrap(),
+        max_exposure: Usd::new(dec!(100)),
+    });
+    let mut maker = Maker::start(&maker_config).await;
+    let mut taker = Taker::start(&TakerConfig::default(), maker.listen_addr, maker.identity).await;
 
     is_next_none(taker.order_feed()).await.unwrap();
 
@@ -100,18 +692,14 @@ async fn taker_takes_order_and_maker_accepts_and_contract_setup() {
         .await
         .unwrap();
 
+    // Contract setup starts as soon as the take arrives, hence all mocks are needed upfront
     taker.mocks.mock_oracle_announcement().await;
     maker.mocks.mock_oracle_announcement().await;
 
-    taker
-        .system
-        .take_offer(received.id, Usd::new(dec!(5)))
-        .await
-        .unwrap();
-    wait_next_state!(received.id, maker, taker, CfdState::PendingSetup);
-
     maker.mocks.mock_party_params().await;
+    maker.mocks.mock_wallet_check_funds().await;
     taker.mocks.mock_party_params().await;
+    taker.mocks.mock_wallet_check_funds().await;
 
     maker.mocks.mock_monitor_oracle_attestation().await;
     taker.mocks.mock_monitor_oracle_attestation().await;
@@ -125,8 +713,11 @@ async fn taker_takes_order_and_maker_accepts_and_contract_setup() {
     maker.mocks.mock_wallet_sign_and_broadcast().await;
     taker.mocks.mock_wallet_sign_and_broadcast().await;
 
-    maker.system.accept_order(received.id).await.unwrap();
-    wait_next_state!(received.id, maker, taker, CfdState::ContractSetup);
+    taker
+        .system
+        .take_offer(received.id, Usd::new(dec!(5)), None)
+        .await
+        .unwrap();
 
     sleep(Duration::from_secs(5)).await; // need to wait a bit until both transition
     wait_next_state!(received.id, maker, taker, CfdState::PendingOpen);
@@ -170,6 +761,241 @@ async fn collaboratively_close_an_open_cfd() {
     wait_next_state!(order_id, maker, taker, CfdState::Closed);
 }
 
+#[tokio::test]
+async fn collaborative_close_published_by_counterparty_is_detected() {
+    let _guard = init_tracing();
+    let (mut maker, mut taker, order_id) =
+        start_from_open_cfd_state(OliviaData::example_0().announcement()).await;
+
+    taker.mocks.mock_latest_quote(Some(dummy_quote())).await;
+    maker.mocks.mock_latest_quote(Some(dummy_quote())).await;
+    next_with(taker.quote_feed(), |q| q).await.unwrap(); // if quote is available on feed, it propagated through the system
+
+    taker.system.propose_settlement(order_id).await.unwrap();
+
+    wait_next_state!(
+        order_id,
+        maker,
+        taker,
+        CfdState::IncomingSettlementProposal,
+        CfdState::OutgoingSettlementProposal
+    );
+
+    // The maker cannot publish the close transaction, the counterparty gets it confirmed instead
+    let (mut watched, mut failed_broadcasts) = maker
+        .mocks
+        .mock_failing_collaborative_close_broadcast()
+        .await;
+    taker.mocks.mock_monitor_collaborative_settlement().await;
+
+    maker.system.accept_settlement(order_id).await.unwrap();
+    sleep(Duration::from_secs(5)).await; // need to wait a bit until both transition
+
+    wait_next_state!(order_id, maker, taker, CfdState::PendingClose);
+
+    // The maker still watches the transaction it failed to publish
+    let failed_txid = failed_broadcasts.recv().await.unwrap();
+    let watched = watched.recv().await.unwrap();
+    assert_eq!(watched.order_id, order_id);
+    assert_eq!(watched.tx.0, failed_txid);
+
+    deliver_event!(maker, taker, Event::CloseFinality(order_id));
+
+    sleep(Duration::from_secs(5)).await; // need to wait a bit until both transition
+
+    wait_next_state!(order_id, maker, taker, CfdState::Closed);
+}
+
+#[tokio::test]
+async fn rejected_settlement_proposal_is_removed_from_proposal_feed() {
+    let _guard = init_tracing();
+    let (mut maker, mut taker, order_id) =
+        start_from_open_cfd_state(OliviaData::example_0().announcement()).await;
+
+    taker.mocks.mock_latest_quote(Some(dummy_quote())).await;
+    maker.mocks.mock_latest_quote(Some(dummy_quote())).await;
+    next_with(taker.quote_feed(), |q| q).await.unwrap(); // if quote is available on feed, it propagated through the system
+
+    taker.system.propose_settlement(order_id).await.unwrap();
+
+    let maker_proposals = next(maker.proposal_feed()).await.unwrap();
+    let taker_proposals = next(taker.proposal_feed()).await.unwrap();
+    assert_eq!(
+        maker_proposals,
+        vec![PendingProposal {
+            order_id,
+            kind: ProposalKind::Settlement,
+            direction: ProposalDirection::Incoming
+        }]
+    );
+    assert_eq!(
+        taker_proposals,
+        vec![PendingProposal {
+            order_id,
+            kind: ProposalKind::Settlement,
+            direction: ProposalDirection::Outgoing
+        }]
+    );
+
+    maker.system.reject_settlement(order_id).await.unwrap();
+
+    assert!(next(maker.proposal_feed()).await.unwrap().is_empty());
+    assert!(next(taker.proposal_feed()).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn maker_cannot_accept_expired_settlement_proposal() {
+    let _guard = init_tracing();
+    let maker_config =
+        MakerConfig::default().with_settlement_proposal_validity(time::Duration::ZERO);
+    let (mut maker, mut taker, order_id) = start_from_open_cfd_state_with_config(
+        OliviaData::example_0().announcement(),
+        &maker_config,
+    )
+    .await;
+
+    taker.mocks.mock_latest_quote(Some(dummy_quote())).await;
+    maker.mocks.mock_latest_quote(Some(dummy_quote())).await;
+    next_with(taker.quote_feed(), |q| q).await.unwrap(); // if quote is available on feed, it propagated through the system
+
+    taker.system.propose_settlement(order_id).await.unwrap();
+
+    wait_next_state!(
+        order_id,
+        maker,
+        taker,
+        CfdState::IncomingSettlementProposal,
+        CfdState::OutgoingSettlementProposal
+    );
+
+    sleep(Duration::from_secs(2)).await; // let the proposal expire
+
+    let error = maker.system.accept_settlement(order_id).await.unwrap_err();
+    assert!(format!("{error:#}").contains("expired"), "{error:#}");
+
+    wait_next_state!(order_id, maker, taker, CfdState::Open);
+}
+
+#[tokio::test]
+async fn taker_proposes_settlement_once_take_profit_threshold_is_crossed() {
+    let _guard = init_tracing();
+    let (mut maker, mut taker, order_id) =
+        start_from_open_cfd_state(OliviaData::example_0().announcement()).await;
+
+    taker.mocks.mock_latest_quote(Some(dummy_quote())).await;
+    maker.mocks.mock_latest_quote(Some(dummy_quote())).await;
+    next_with(taker.quote_feed(), |q| q).await.unwrap(); // if quote is available on feed, it propagated through the system
+
+    taker
+        .system
+        .set_auto_close_thresholds(
+            order_id,
+            AutoCloseThresholds {
+                take_profit: Some(dec!(10).into()),
+                stop_loss: None,
+            },
+        )
+        .await
+        .unwrap();
+
+    // The price did not move yet, hence nothing happens
+    taker.trigger_auto_close().await;
+    assert!(taker
+        .system
+        .auto_close_thresholds(order_id)
+        .await
+        .unwrap()
+        .is_set());
+
+    taker
+        .mocks
+        .mock_latest_quote(Some(quote_at(Price::new(dec!(60_000)).unwrap())))
+        .await;
+    taker.trigger_auto_close().await;
+
+    wait_next_state!(
+        order_id,
+        maker,
+        taker,
+        CfdState::IncomingSettlementProposal,
+        CfdState::OutgoingSettlementProposal
+    );
+
+    // Thresholds are cleared once crossed, so checking again does not propose another settlement
+    assert!(!taker
+        .system
+        .auto_close_thresholds(order_id)
+        .await
+        .unwrap()
+        .is_set());
+    taker.trigger_auto_close().await;
+    assert!(!taker
+        .system
+        .auto_close_thresholds(order_id)
+        .await
+        .unwrap()
+        .is_set());
+}
+
+#[tokio::test]
+async fn cfd_label_is_published_on_the_feed() {
+    let _guard = init_tracing();
+    let (_maker, mut taker, order_id) =
+        start_from_open_cfd_state(OliviaData::example_0().announcement()).await;
+
+    taker
+        .projection
+        .send(projection::SetLabel {
+            order_id,
+            label: Some("hedge".to_owned()),
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    let label = next_with(taker.cfd_feed(), |cfds| {
+        cfds.into_iter().find_map(|cfd| cfd.label)
+    })
+    .await
+    .unwrap();
+    assert_eq!(label, "hedge");
+
+    taker
+        .projection
+        .send(projection::SetLabel {
+            order_id,
+            label: None,
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    next_with(taker.cfd_feed(), |cfds| {
+        cfds.iter().all(|cfd| cfd.label.is_none()).then(|| ())
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn resync_moves_cfd_with_confirmed_commit_to_open_committed() {
+    let _guard = init_tracing();
+    let (mut maker, mut taker, order_id) =
+        start_from_open_cfd_state(OliviaData::example_0().announcement()).await;
+
+    maker.mocks.mock_monitor_rescan(order_id).await;
+    taker.mocks.mock_monitor_rescan(order_id).await;
+
+    maker.system.resync(order_id).await.unwrap();
+    taker.system.resync(order_id).await.unwrap();
+
+    // The commit transaction was confirmed while neither party was watching, syncing after the
+    // rescan reports its finality
+    deliver_event!(maker, taker, Event::CommitFinality(order_id));
+
+    wait_next_state!(order_id, maker, taker, CfdState::OpenCommitted);
+}
+
 #[tokio::test]
 async fn force_close_an_open_cfd() {
     let _guard = init_tracing();
@@ -250,6 +1076,40 @@ async fn maker_rejects_rollover_of_open_cfd() {
     wait_next_state!(order_id, maker, taker, CfdState::Open);
 }
 
+#[tokio::test]
+async fn rollover_proposal_is_labeled_with_its_direction_in_proposal_feed() {
+    let _guard = init_tracing();
+    let oracle_data = OliviaData::example_0();
+    let (mut maker, mut taker, order_id) =
+        start_from_open_cfd_state(oracle_data.announcement()).await;
+
+    taker.trigger_rollover(order_id).await;
+
+    let maker_proposals = next(maker.proposal_feed()).await.unwrap();
+    let taker_proposals = next(taker.proposal_feed()).await.unwrap();
+    assert_eq!(
+        maker_proposals,
+        vec![PendingProposal {
+            order_id,
+            kind: ProposalKind::Rollover,
+            direction: ProposalDirection::Incoming
+        }]
+    );
+    assert_eq!(
+        taker_proposals,
+        vec![PendingProposal {
+            order_id,
+            kind: ProposalKind::Rollover,
+            direction: ProposalDirection::Outgoing
+        }]
+    );
+
+    maker.system.reject_rollover(order_id).await.unwrap();
+
+    assert!(next(maker.proposal_feed()).await.unwrap().is_empty());
+    assert!(next(taker.proposal_feed()).await.unwrap().is_empty());
+}
+
 #[tokio::test]
 async fn open_cfd_is_refunded() {
     let _guard = init_tracing();
@@ -308,6 +1168,109 @@ async fn taker_notices_lack_of_maker() {
     );
 }
 
+#[tokio::test]
+async fn taker_does_not_reconnect_after_explicit_disconnect() {
+    let _guard = init_tracing();
+    let (_maker, mut taker) = start_both().await;
+
+    assert_eq!(
+        ConnectionStatus::Online,
+        next(taker.maker_status_feed()).await.unwrap()
+    );
+
+    taker.system.disconnect().await.unwrap();
+
+    assert_eq!(
+        ConnectionStatus::Offline {
+            reason: Some(ConnectionCloseReason::Requested)
+        },
+        next(taker.maker_status_feed()).await.unwrap(),
+    );
+
+    let reconnected =
+        tokio::time::timeout(Duration::from_secs(5), taker.maker_status_feed().changed()).await;
+    assert!(reconnected.is_err(), "taker reconnected to the maker");
+}
+
+#[tokio::test]
+async fn taker_stops_reconnecting_after_explicit_disconnect() {
+    let short_interval = Duration::from_secs(1);
+
+    let _guard = init_tracing();
+
+    let maker_config = MakerConfig::default()
+        .with_heartbeat_interval(short_interval)
+        .with_dedicated_port(35124); // set a fixed port so the taker could reconnect
+    let maker = Maker::start(&maker_config).await;
+
+    let taker_config = TakerConfig::default().with_heartbeat_interval(short_interval);
+    let mut taker = Taker::start(&taker_config, maker.listen_addr, maker.identity).await;
+
+    assert_eq!(
+        ConnectionStatus::Online,
+        next(taker.maker_status_feed()).await.unwrap()
+    );
+
+    std::mem::drop(maker);
+
+    sleep(taker_config.heartbeat_interval).await;
+
+    assert_eq!(
+        ConnectionStatus::Offline { reason: None },
+        next(taker.maker_status_feed()).await.unwrap(),
+    );
+
+    // The taker keeps trying to reconnect until we tell it to stop
+    taker.system.disconnect().await.unwrap();
+
+    assert_eq!(
+        ConnectionStatus::Offline {
+            reason: Some(ConnectionCloseReason::Requested)
+        },
+        next(taker.maker_status_feed()).await.unwrap(),
+    );
+
+    let _maker = Maker::start(&maker_config).await;
+
+    let reconnected =
+        tokio::time::timeout(Duration::from_secs(10), taker.maker_status_feed().changed()).await;
+    assert!(reconnected.is_err(), "taker reconnected to the maker");
+}
+
+#[tokio::test]
+async fn taker_closes_connection_if_maker_sends_hello_mid_session() {
+    let _guard = init_tracing();
+    let (_maker, mut taker) = start_both().await;
+
+    assert_eq!(
+        ConnectionStatus::Online,
+        next(taker.maker_status_feed()).await.unwrap()
+    );
+
+    taker
+        .system
+        .connection_actor
+        .send(MakerStreamMessage {
+            item: Ok(wire::MakerToTaker::Hello(wire::Version::current())),
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(
+        ConnectionStatus::Offline {
+            reason: Some(ConnectionCloseReason::ProtocolViolation)
+        },
+        next(taker.maker_status_feed()).await.unwrap(),
+    );
+
+    let reconnected =
+        tokio::time::timeout(Duration::from_secs(10), taker.maker_status_feed().changed()).await;
+    assert!(
+        reconnected.is_err(),
+        "taker reconnected right after a protocol violation"
+    );
+}
+
 #[tokio::test]
 async fn maker_notices_lack_of_taker() {
     let _guard = init_tracing();
@@ -331,7 +1294,14 @@ async fn maker_notices_lack_of_taker() {
 /// For convenience, returns also OrderId of the opened Cfd.
 /// `announcement` is used during Cfd's creation.
 async fn start_from_open_cfd_state(announcement: oracle::Announcement) -> (Maker, Taker, OrderId) {
-    let mut maker = Maker::start(&MakerConfig::default()).await;
+    start_from_open_cfd_state_with_config(announcement, &MakerConfig::default()).await
+}
+
+async fn start_from_open_cfd_state_with_config(
+    announcement: oracle::Announcement,
+    maker_config: &MakerConfig,
+) -> (Maker, Taker, OrderId) {
+    let mut maker = Maker::start(maker_config).await;
     let mut taker = Taker::start(&TakerConfig::default(), maker.listen_addr, maker.identity).await;
 
     is_next_none(taker.order_feed()).await.unwrap();
@@ -350,16 +1320,18 @@ async fn start_from_open_cfd_state(announcement: oracle::Announcement) -> (Maker
         .mocks
         .mock_oracle_announcement_with(announcement)
         .await;
+    taker.mocks.mock_party_params().await;
+    taker.mocks.mock_wallet_check_funds().await;
 
     taker
         .system
-        .take_offer(received.id, Usd::new(dec!(5)))
+        .take_offer(received.id, Usd::new(dec!(5)), None)
         .await
         .unwrap();
     wait_next_state!(received.id, maker, taker, CfdState::PendingSetup);
 
     maker.mocks.mock_party_params().await;
-    taker.mocks.mock_party_params().await;
+    maker.mocks.mock_wallet_check_funds().await;
 
     maker.mocks.mock_monitor_oracle_attestation().await;
     taker.mocks.mock_monitor_oracle_attestation().await;
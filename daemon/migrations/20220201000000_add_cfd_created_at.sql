@@ -0,0 +1,19 @@
+ALTER TABLE
+    cfds
+ADD
+    COLUMN created_at integer NOT NULL DEFAULT 0;
+-- Best effort for existing CFDs: they were created shortly before their first event.
+UPDATE
+    cfds
+SET
+    created_at = COALESCE(
+        (
+            SELECT
+                CAST(MIN(events.created_at) AS integer)
+            FROM
+                events
+            WHERE
+                events.cfd_id = cfds.id
+        ),
+        0
+    );
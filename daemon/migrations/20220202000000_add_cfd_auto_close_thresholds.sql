@@ -0,0 +1,8 @@
+ALTER TABLE
+    cfds
+ADD
+    COLUMN auto_close_take_profit text;
+ALTER TABLE
+    cfds
+ADD
+    COLUMN auto_close_stop_loss text;
@@ -0,0 +1,4 @@
+ALTER TABLE
+    cfds
+ADD
+    COLUMN label text;
@@ -0,0 +1,5 @@
+-- CFDs set up before the number of payouts became configurable used the former constant of 200
+ALTER TABLE
+    cfds
+ADD
+    COLUMN n_payouts integer NOT NULL DEFAULT 200;
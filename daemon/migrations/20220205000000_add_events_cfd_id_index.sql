@@ -0,0 +1,2 @@
+-- Events are loaded by CFD on nearly every message, ordered by their insertion
+CREATE INDEX IF NOT EXISTS events_cfd_id ON events (cfd_id, id);
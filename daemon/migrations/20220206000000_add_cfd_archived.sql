@@ -0,0 +1,4 @@
+ALTER TABLE
+    cfds
+ADD
+    COLUMN archived boolean NOT NULL DEFAULT false;
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS monitored_scripts (
+    id integer PRIMARY KEY autoincrement,
+    cfd_id integer UNIQUE NOT NULL,
+    data text NOT NULL,
+    FOREIGN KEY (cfd_id) REFERENCES cfds (id)
+);
@@ -0,0 +1,5 @@
+-- CFDs set up before the rounding became configurable rounded fees up and payouts down
+ALTER TABLE
+    cfds
+ADD
+    COLUMN rounding text NOT NULL DEFAULT 'FeesUpPayoutsDown';
@@ -0,0 +1,130 @@
+use crate::bitmex_price_feed;
+use crate::bitmex_price_feed::QUOTE_INTERVAL_MINUTES;
+use crate::cfd_actors::load_cfd;
+use crate::db;
+use crate::model::cfd::AutoCloseThresholds;
+use crate::taker_cfd;
+use crate::try_continue;
+use crate::Tasks;
+use anyhow::Context;
+use anyhow::Result;
+use async_trait::async_trait;
+use std::time::Duration;
+use time::ext::NumericalDuration;
+use xtra::prelude::MessageChannel;
+use xtra_productivity::xtra_productivity;
+use xtras::SendInterval;
+
+/// How often we check the open CFDs against their auto-close thresholds.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Proposes a collaborative settlement for CFDs whose unrealized profit or loss crossed the
+/// thresholds configured by the user.
+pub struct Actor {
+    db: sqlx::SqlitePool,
+    cfd_actor: Box<dyn MessageChannel<taker_cfd::ProposeSettlement>>,
+    price_feed: Box<dyn MessageChannel<bitmex_price_feed::LatestQuote>>,
+    tasks: Tasks,
+}
+
+impl Actor {
+    pub fn new(
+        db: sqlx::SqlitePool,
+        cfd_actor: &(impl MessageChannel<taker_cfd::ProposeSettlement> + 'static),
+        price_feed: &(impl MessageChannel<bitmex_price_feed::LatestQuote> + 'static),
+    ) -> Self {
+        Self {
+            db,
+            cfd_actor: cfd_actor.clone_channel(),
+            price_feed: price_feed.clone_channel(),
+            tasks: Tasks::default(),
+        }
+    }
+}
+
+#[xtra_productivity]
+impl Actor {
+    async fn handle(&mut self, _msg: AutoClose) {
+        tracing::trace!("Checking all CFDs against their auto-close thresholds");
+
+        // Auto-close is invoked periodically by `addr.send_interval()`,
+        // which does not handle errors - forward implementation to allow `?` inside
+        if let Err(e) = self.handle_auto_close_impl().await {
+            tracing::error!("Auto-close failed: {:#}", e);
+        }
+    }
+}
+
+impl Actor {
+    async fn handle_auto_close_impl(&mut self) -> Result<()> {
+        let latest_quote = self
+            .price_feed
+            .send(bitmex_price_feed::LatestQuote)
+            .await
+            .context("Price feed not available")?
+            .context("No quote available")?;
+
+        if latest_quote.is_older_than(QUOTE_INTERVAL_MINUTES.minutes()) {
+            tracing::debug!("Latest quote is too old to evaluate auto-close thresholds");
+            return Ok(());
+        }
+
+        let current_price = latest_quote.for_taker();
+
+        let mut conn = self.db.acquire().await?;
+        for (order_id, thresholds) in db::load_auto_close_thresholds(&mut conn).await? {
+            try_continue!(async {
+                let cfd = load_cfd(order_id, &mut conn).await?;
+                if !cfd.can_auto_close() {
+                    return anyhow::Ok(());
+                }
+
+                let profit = cfd.profit(current_price)?;
+                if !thresholds.is_breached(profit) {
+                    tracing::trace!(%order_id, %profit, "Auto-close thresholds not breached");
+                    return anyhow::Ok(());
+                }
+
+                tracing::info!(
+                    %order_id,
+                    %profit,
+                    "Auto-close threshold breached, proposing settlement"
+                );
+
+                // Keep the thresholds if the proposal fails so we try again on the next check
+                self.cfd_actor
+                    .send(taker_cfd::ProposeSettlement {
+                        order_id,
+                        current_price,
+                    })
+                    .await
+                    .context("Cfd actor not available")??;
+
+                // The settlement is proposed, clear the thresholds so we propose it only once
+                db::set_auto_close_thresholds(order_id, AutoCloseThresholds::default(), &mut conn)
+                    .await?;
+
+                anyhow::Ok(())
+            }
+            .await
+            .with_context(|| format!("Cannot auto-close CFD {order_id}")));
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl xtra::Actor for Actor {
+    async fn started(&mut self, ctx: &mut xtra::Context<Self>) {
+        let this = ctx.address().expect("we are alive");
+        self.tasks
+            .add(this.send_interval(CHECK_INTERVAL, || AutoClose));
+    }
+}
+
+/// Message sent to ourselves at an interval to check whether any CFD crossed its auto-close
+/// thresholds.
+///
+/// Public so the check can be triggered in the tests unconditionally of time.
+pub struct AutoClose;
@@ -27,7 +27,6 @@ pub struct Actor<O> {
     process_manager: Address<process_manager::Actor>,
     conn: Address<connection::Actor>,
     oracle: Address<O>,
-    n_payouts: usize,
     rollover_actors: AddressMap<OrderId, rollover_taker::Actor>,
     tasks: Tasks,
 }
@@ -39,7 +38,6 @@ impl<O> Actor<O> {
         process_manager: Address<process_manager::Actor>,
         conn: Address<connection::Actor>,
         oracle: Address<O>,
-        n_payouts: usize,
     ) -> Self {
         Self {
             db,
@@ -47,7 +45,6 @@ impl<O> Actor<O> {
             process_manager,
             conn,
             oracle,
-            n_payouts,
             rollover_actors: AddressMap::default(),
             tasks: Tasks::default(),
         }
@@ -83,7 +80,6 @@ where
             .expect("actor to be able to give address to itself");
         let (addr, fut) = rollover_taker::Actor::new(
             order_id,
-            self.n_payouts,
             self.oracle_pk,
             self.conn.clone(),
             &self.oracle,
@@ -177,9 +177,11 @@ impl Quote {
     }
 
     pub fn is_older_than(&self, duration: time::Duration) -> bool {
-        let required_quote_timestamp = (OffsetDateTime::now_utc() - duration).unix_timestamp();
-
-        self.timestamp.seconds() < required_quote_timestamp
+        self.timestamp
+            .to_offset_datetime()
+            .map_or(true, |timestamp| {
+                timestamp < OffsetDateTime::now_utc() - duration
+            })
     }
 }
 
@@ -240,7 +242,7 @@ mod tests {
 
     fn dummy_quote_at(time: OffsetDateTime) -> Quote {
         Quote {
-            timestamp: Timestamp::new(time.unix_timestamp()),
+            timestamp: Timestamp::from(time),
             bid: Price::new(dec!(10)).unwrap(),
             ask: Price::new(dec!(10)).unwrap(),
         }
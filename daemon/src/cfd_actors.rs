@@ -1,11 +1,15 @@
 use crate::db;
 use crate::model::cfd::Cfd;
+use crate::model::cfd::Event;
 use crate::model::cfd::OrderId;
 use crate::model::cfd::RefundTimelockExpiryError;
+use crate::model::cfd::Role;
+use crate::model::cfd::TxUnavailable;
 use crate::monitor;
 use crate::oracle;
 use crate::process_manager;
 use crate::projection;
+use crate::projection::CfdState;
 use crate::try_continue;
 use anyhow::Context;
 use anyhow::Result;
@@ -37,6 +41,10 @@ pub async fn handle_monitoring_event(
     let cfd = load_cfd(order_id, &mut conn).await?;
 
     let event = match event {
+        monitor::Event::LockSeen(_) => match cfd.handle_lock_seen() {
+            Some(event) => event,
+            None => return Ok(()), // Early return from a no-op
+        },
         monitor::Event::LockFinality(_) => cfd.handle_lock_confirmed(),
         monitor::Event::CommitFinality(_) => cfd.handle_commit_confirmed(),
         monitor::Event::CloseFinality(_) => cfd.handle_collaborative_settlement_confirmed(),
@@ -53,7 +61,7 @@ pub async fn handle_monitoring_event(
             match cfd.handle_refund_timelock_expired() {
                 Ok(event) => event,
                 Err(e) => {
-                    if let NoDlc | Signing(_) = e {
+                    if let Unavailable(TxUnavailable::NoDlc) | Signing(_) = e {
                         tracing::error!("Failed to handle refund timelock expiry: {e}");
                     }
 
@@ -63,6 +71,7 @@ pub async fn handle_monitoring_event(
         }
         monitor::Event::RefundFinality(_) => cfd.handle_refund_confirmed(),
         monitor::Event::RevokedTransactionFound(_) => cfd.handle_revoke_confirmed(),
+        monitor::Event::Reorg { tx, .. } => cfd.handle_reorg(tx)?,
     };
 
     if let Err(e) = process_manager
@@ -75,25 +84,64 @@ pub async fn handle_monitoring_event(
     Ok(())
 }
 
+/// Append an event to the CFD's event log, recording the state it transitions the CFD to in the
+/// logs.
+///
+/// This is the single place events are persisted for both maker and taker, which gives operators
+/// an audit trail of every state change.
+pub async fn append_cfd_state(
+    event: Event,
+    role: Role,
+    conn: &mut PoolConnection<Sqlite>,
+) -> Result<()> {
+    // Every event fully determines the state, hence there is no need to load the CFD
+    let state = CfdState::after(&event.event, role);
+
+    let order_id = event.id;
+    let timestamp = event.timestamp;
+
+    db::append_event(event, conn).await?;
+
+    tracing::info!(
+        %order_id,
+        ?state,
+        timestamp = timestamp.seconds(),
+        "CFD state transition"
+    );
+
+    Ok(())
+}
+
 /// Load a CFD from the database and rehydrate as the [`model::cfd::Cfd`] aggregate.
 pub async fn load_cfd(order_id: OrderId, conn: &mut PoolConnection<Sqlite>) -> Result<Cfd> {
-    let (
-        db::Cfd {
-            id,
-            position,
-            initial_price,
-            leverage,
-            settlement_interval,
-            counterparty_network_identity,
-            role,
-            quantity_usd,
-            opening_fee,
-            initial_funding_rate,
-            initial_tx_fee_rate,
-        },
-        events,
-    ) = db::load_cfd(order_id, conn).await?;
-    let cfd = Cfd::rehydrate(
+    let (cfd, events) = db::load_cfd(order_id, conn).await?;
+    let cfd = rehydrate(cfd, events);
+
+    Ok(cfd)
+}
+
+/// Rehydrate the [`model::cfd::Cfd`] aggregate from a CFD and its events loaded from the
+/// database.
+pub fn rehydrate(
+    db::Cfd {
+        id,
+        position,
+        initial_price,
+        leverage,
+        settlement_interval,
+        counterparty_network_identity,
+        role,
+        quantity_usd,
+        opening_fee,
+        initial_funding_rate,
+        initial_tx_fee_rate,
+        n_payouts,
+        rounding,
+        ..
+    }: db::Cfd,
+    events: Vec<Event>,
+) -> Cfd {
+    Cfd::rehydrate(
         id,
         position,
         initial_price,
@@ -105,9 +153,10 @@ pub async fn load_cfd(order_id: OrderId, conn: &mut PoolConnection<Sqlite>) -> R
         opening_fee,
         initial_funding_rate,
         initial_tx_fee_rate,
+        n_payouts,
+        rounding,
         events,
-    );
-    Ok(cfd)
+    )
 }
 
 pub async fn handle_oracle_attestation(
@@ -120,22 +169,184 @@ pub async fn handle_oracle_attestation(
 
     tracing::debug!("Learnt latest oracle attestation for event: {price_event_id}");
 
+    // Most CFDs don't care about a given attestation, logging each of them would flood the logs
+    let mut unaffected = 0;
+
     for id in db::load_all_cfd_ids(&mut conn).await? {
         let cfd = try_continue!(load_cfd(id, &mut conn).await);
         let event = try_continue!(cfd
             .decrypt_cet(&attestation)
             .context("Failed to decrypt CET using attestation"));
 
-        if let Some(event) = event {
-            // Note: ? OK, because if the actor is disconnected we can fail the loop
-            if let Err(e) = process_manager
-                .send(process_manager::Event::new(event.clone()))
-                .await?
-            {
-                tracing::error!("Sending event to process manager failed: {:#}", e);
+        match event {
+            Some(event) => {
+                // Note: ? OK, because if the actor is disconnected we can fail the loop
+                if let Err(e) = process_manager
+                    .send(process_manager::Event::new(event.clone()))
+                    .await?
+                {
+                    tracing::error!("Sending event to process manager failed: {:#}", e);
+                }
             }
+            None => unaffected += 1,
         }
     }
 
+    if unaffected > 0 {
+        tracing::debug!(%price_event_id, "{unaffected} CFDs unaffected by attestation");
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::cfd::CfdEvent;
+    use crate::model::BitMexPriceEventId;
+    use crate::model::FundingRate;
+    use crate::model::Leverage;
+    use crate::model::OpeningFee;
+    use crate::model::Position;
+    use crate::model::Price;
+    use crate::model::RoundingStrategy;
+    use crate::model::TxFeeRate;
+    use crate::model::Usd;
+    use crate::N_PAYOUTS;
+    use bdk::bitcoin::Amount;
+    use rust_decimal_macros::dec;
+    use std::io;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+    use time::macros::datetime;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    #[tokio::test]
+    async fn appending_event_logs_state_transition() {
+        let logs = CapturedLogs::default();
+        let _guard = tracing_subscriber::fmt()
+            .with_writer(logs.clone())
+            .with_ansi(false)
+            .set_default();
+
+        let db = db::memory().await.unwrap();
+        let mut conn = db.acquire().await.unwrap();
+        let cfd = dummy_cfd();
+        db::insert_cfd(&cfd, &mut conn).await.unwrap();
+
+        append_cfd_state(
+            Event::new(cfd.id(), CfdEvent::ContractSetupStarted),
+            Role::Taker,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+        append_cfd_state(
+            Event::new(cfd.id(), CfdEvent::OfferRejected(None)),
+            Role::Taker,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+
+        let logs = logs.contents();
+        let transitions = logs
+            .lines()
+            .filter(|line| line.contains("CFD state transition"))
+            .collect::<Vec<_>>();
+        assert_eq!(transitions.len(), 2, "{logs}");
+        assert!(transitions[0].contains(" INFO "), "{logs}");
+        assert!(
+            transitions[0].contains(&format!("order_id={}", cfd.id())),
+            "{logs}"
+        );
+        assert!(transitions[0].contains("state=ContractSetup"), "{logs}");
+        assert!(transitions[1].contains("state=Rejected"), "{logs}");
+        assert!(transitions[1].contains("timestamp="), "{logs}");
+    }
+
+    #[tokio::test]
+    async fn irrelevant_attestation_is_logged_once_for_all_cfds() {
+        let logs = CapturedLogs::default();
+        let _guard = tracing_subscriber::fmt()
+            .with_writer(logs.clone())
+            .with_ansi(false)
+            .with_max_level(tracing::Level::DEBUG)
+            .set_default();
+
+        let db = db::memory().await.unwrap();
+        {
+            let mut conn = db.acquire().await.unwrap();
+            for _ in 0..3 {
+                db::insert_cfd(&dummy_cfd(), &mut conn).await.unwrap();
+            }
+        }
+        let (process_manager, _) = xtra::Context::<process_manager::Actor>::new(None);
+        let attestation = oracle::Attestation {
+            id: BitMexPriceEventId::with_20_digits(datetime!(2021-09-23 10:00:00).assume_utc()),
+            price: 60_000,
+            scalars: vec![],
+        };
+
+        handle_oracle_attestation(attestation, &db, &process_manager)
+            .await
+            .unwrap();
+
+        let logs = logs.contents();
+        let summaries = logs
+            .lines()
+            .filter(|line| line.contains("unaffected by attestation"))
+            .collect::<Vec<_>>();
+        assert_eq!(summaries.len(), 1, "{logs}");
+        assert!(summaries[0].contains("3 CFDs unaffected"), "{logs}");
+    }
+
+    fn dummy_cfd() -> Cfd {
+        Cfd::new(
+            OrderId::default(),
+            Position::Long,
+            Price::new(dec!(60_000)).unwrap(),
+            Leverage::new(2).unwrap(),
+            time::Duration::hours(24),
+            Role::Taker,
+            Usd::new(dec!(1_000)),
+            "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF"
+                .parse()
+                .unwrap(),
+            OpeningFee::new(Amount::from_sat(2000)),
+            FundingRate::default(),
+            TxFeeRate::default(),
+            N_PAYOUTS,
+            RoundingStrategy::default(),
+        )
+    }
+
+    /// Collects everything the subscriber writes so we can assert on it.
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+    impl CapturedLogs {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    impl io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> tracing_subscriber::fmt::MakeWriter<'a> for CapturedLogs {
+        type Writer = Self;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+}
@@ -4,11 +4,14 @@ use crate::model::cfd::CollaborativeSettlementCompleted;
 use crate::model::cfd::Completed;
 use crate::model::cfd::SettlementProposal;
 use crate::model::Identity;
+use crate::model::Timestamp;
 use crate::process_manager;
 use anyhow::Context;
 use anyhow::Result;
 use async_trait::async_trait;
 use maia::secp256k1_zkp::Signature;
+use time::Duration;
+use time::OffsetDateTime;
 use xtra::prelude::MessageChannel;
 use xtra_productivity::xtra_productivity;
 use xtras::address_map::Stopping;
@@ -21,7 +24,8 @@ pub struct Actor {
     on_stopping: Vec<Box<dyn MessageChannel<Stopping<Self>>>>,
     has_accepted: bool,
     db: sqlx::SqlitePool,
-    n_payouts: usize,
+    /// How long after its creation the proposal can still be accepted.
+    proposal_validity: Duration,
 }
 
 pub struct Accepted;
@@ -32,7 +36,14 @@ pub struct Initiated {
 
 #[xtra_productivity]
 impl Actor {
-    async fn handle(&mut self, _: Accepted, ctx: &mut xtra::Context<Self>) {
+    async fn handle(&mut self, _: Accepted, ctx: &mut xtra::Context<Self>) -> Result<()> {
+        if let Err(reason) = self.ensure_not_expired() {
+            tracing::info!(order_id = %self.proposal.order_id, "{reason:#}");
+
+            self.reject_due_to(anyhow::anyhow!("{reason:#}"), ctx).await;
+            return Err(reason);
+        }
+
         if let Err(e) = self.accept(ctx).await {
             self.complete(
                 Completed::Failed {
@@ -43,6 +54,8 @@ impl Actor {
             )
             .await;
         }
+
+        Ok(())
     }
 
     async fn handle(&mut self, _: Rejected, ctx: &mut xtra::Context<Self>) {
@@ -119,7 +132,7 @@ impl Actor {
             &(impl MessageChannel<Stopping<Self>> + 'static),
         ),
         db: sqlx::SqlitePool,
-        n_payouts: usize,
+        proposal_validity: Duration,
     ) -> Self {
         Self {
             proposal,
@@ -129,16 +142,25 @@ impl Actor {
             on_stopping: vec![on_stopping0.clone_channel(), on_stopping1.clone_channel()],
             has_accepted: false,
             db,
-            n_payouts,
+            proposal_validity,
         }
     }
 
+    /// Refuse proposals that were created more than `proposal_validity` ago, their price is
+    /// likely stale by now.
+    fn ensure_not_expired(&self) -> Result<()> {
+        ensure_not_expired(
+            self.proposal.timestamp,
+            OffsetDateTime::now_utc(),
+            self.proposal_validity,
+        )
+    }
+
     async fn handle_proposal(&mut self) -> Result<()> {
         let mut conn = self.db.acquire().await?;
         let cfd = load_cfd(self.proposal.order_id, &mut conn).await?;
 
-        let event =
-            cfd.receive_collaborative_settlement_proposal(self.proposal.clone(), self.n_payouts)?;
+        let event = cfd.receive_collaborative_settlement_proposal(self.proposal.clone())?;
         self.process_manager
             .send(process_manager::Event::new(event))
             .await??;
@@ -211,6 +233,13 @@ impl Actor {
         let order_id = self.proposal.order_id;
         tracing::info!(%order_id, "Settlement proposal rejected");
 
+        self.reject_due_to(anyhow::format_err!("unknown"), ctx)
+            .await
+    }
+
+    async fn reject_due_to(&mut self, reason: anyhow::Error, ctx: &mut xtra::Context<Self>) {
+        let order_id = self.proposal.order_id;
+
         let _ = self
             .connections
             .send(maker_inc_connections::settlement::Response {
@@ -220,6 +249,73 @@ impl Actor {
             })
             .await;
 
-        self.complete(Completed::rejected(order_id), ctx).await;
+        self.complete(Completed::rejected_due_to(order_id, reason), ctx)
+            .await;
+    }
+}
+
+/// How far into the future a proposal's timestamp may lie to account for clock differences.
+const MAX_CLOCK_SKEW: Duration = Duration::minutes(1);
+
+fn ensure_not_expired(
+    timestamp: Timestamp,
+    now: OffsetDateTime,
+    proposal_validity: Duration,
+) -> Result<()> {
+    let created_at = timestamp
+        .to_offset_datetime()
+        .context("Invalid settlement proposal")?;
+    let age = now - created_at;
+
+    anyhow::ensure!(
+        age >= -MAX_CLOCK_SKEW,
+        "Invalid settlement proposal: created {} seconds in the future",
+        -age.whole_seconds()
+    );
+    anyhow::ensure!(
+        age <= proposal_validity,
+        "Settlement proposal expired: created {} seconds ago but only valid for {} seconds",
+        age.whole_seconds(),
+        proposal_validity.whole_seconds()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    const NOW: OffsetDateTime = datetime!(2021-11-19 10:00:00 UTC);
+    const VALIDITY: Duration = Duration::minutes(5);
+
+    #[test]
+    fn proposal_within_validity_is_accepted() {
+        let timestamp = Timestamp::from(NOW - Duration::minutes(1));
+
+        assert!(ensure_not_expired(timestamp, NOW, VALIDITY).is_ok());
+    }
+
+    #[test]
+    fn proposal_older_than_validity_is_rejected() {
+        let timestamp = Timestamp::from(NOW - Duration::minutes(6));
+
+        assert!(ensure_not_expired(timestamp, NOW, VALIDITY).is_err());
+    }
+
+    #[test]
+    fn proposal_from_the_future_is_rejected() {
+        let slightly_ahead = Timestamp::from(NOW + Duration::seconds(30));
+        let far_ahead = Timestamp::from(NOW + Duration::hours(1));
+
+        assert!(ensure_not_expired(slightly_ahead, NOW, VALIDITY).is_ok());
+        assert!(ensure_not_expired(far_ahead, NOW, VALIDITY).is_err());
+    }
+
+    #[test]
+    fn proposal_with_out_of_range_timestamp_is_rejected() {
+        assert!(ensure_not_expired(Timestamp::new(i64::MAX), NOW, VALIDITY).is_err());
+        assert!(ensure_not_expired(Timestamp::new(i64::MIN), NOW, VALIDITY).is_err());
     }
 }
@@ -11,6 +11,7 @@ use crate::model::Price;
 use crate::process_manager;
 use crate::wire;
 use anyhow::anyhow;
+use anyhow::Context;
 use anyhow::Result;
 use async_trait::async_trait;
 use std::time::Duration;
@@ -26,7 +27,6 @@ pub struct Actor {
     proposal: Option<SettlementProposal>,
     order_id: OrderId,
     current_price: Price,
-    n_payouts: usize,
     connection: xtra::Address<connection::Actor>,
     process_manager: xtra::Address<process_manager::Actor>,
     db: sqlx::SqlitePool,
@@ -38,7 +38,6 @@ impl Actor {
     pub fn new(
         order_id: OrderId,
         current_price: Price,
-        n_payouts: usize,
         connection: xtra::Address<connection::Actor>,
         process_manager: xtra::Address<process_manager::Actor>,
         db: sqlx::SqlitePool,
@@ -46,7 +45,6 @@ impl Actor {
         Self {
             proposal: None,
             order_id,
-            n_payouts,
             current_price,
             connection,
             process_manager,
@@ -60,7 +58,7 @@ impl Actor {
         let mut conn = self.db.acquire().await?;
         let cfd = load_cfd(self.order_id, &mut conn).await?;
 
-        let event = cfd.propose_collaborative_settlement(self.current_price, self.n_payouts)?;
+        let event = cfd.propose_collaborative_settlement(self.current_price)?;
         let proposal = if let cfd::Event {
             event: CfdEvent::CollaborativeSettlementStarted { ref proposal },
             ..
@@ -103,6 +101,13 @@ impl Actor {
         // start_collaborative_settlement
         let proposal = self.proposal.take().expect("proposal to exist");
         let (tx, sig, payout_script_pubkey) = cfd.sign_collaborative_settlement_taker(&proposal)?;
+        let settlement =
+            CollaborativeSettlement::new(tx, payout_script_pubkey, self.current_price)?;
+
+        let (maker_script_pubkey, taker_script_pubkey) = cfd.payout_script_pubkeys()?;
+        settlement
+            .verify(&proposal, &maker_script_pubkey, &taker_script_pubkey)
+            .context("Refusing to sign settlement transaction")?;
 
         self.connection
             .send_async_safe(wire::TakerToMaker::Settlement {
@@ -111,11 +116,7 @@ impl Actor {
             })
             .await?;
 
-        Ok(CollaborativeSettlement::new(
-            tx,
-            payout_script_pubkey,
-            self.current_price,
-        )?)
+        Ok(settlement)
     }
 
     async fn complete(
@@ -1,7 +1,10 @@
 use crate::collab_settlement_taker;
 use crate::future_ext::FutureExt;
+use crate::health;
 use crate::model::cfd::OrderId;
+use crate::model::cfd::RejectReason;
 use crate::model::Identity;
+use crate::model::Leverage;
 use crate::model::Price;
 use crate::model::Timestamp;
 use crate::model::Usd;
@@ -21,6 +24,7 @@ use bdk::bitcoin::Amount;
 use futures::SinkExt;
 use futures::StreamExt;
 use futures::TryStreamExt;
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::time::Duration;
 use std::time::SystemTime;
@@ -39,6 +43,11 @@ use xtras::SendInterval;
 /// Time between reconnection attempts
 const CONNECT_TO_MAKER_INTERVAL: Duration = Duration::from_secs(5);
 
+/// Time we wait before reconnecting to a maker that violated the protocol.
+///
+/// Reconnecting right away would allow a misbehaving maker to keep us busy with handshakes.
+const RECONNECT_AFTER_PROTOCOL_VIOLATION_DELAY: Duration = Duration::from_secs(60);
+
 /// The "Connected" state of our connection with the maker.
 #[allow(clippy::large_enum_variant)]
 enum State {
@@ -48,6 +57,7 @@ enum State {
         /// task is not lagging too much.
         last_pulse: SystemTime,
         write: wire::Write<wire::MakerToTaker, wire::TakerToMaker>,
+        compression: wire::CompressionSwitch,
         _tasks: Tasks,
     },
     Disconnected,
@@ -151,6 +161,9 @@ pub struct Actor {
     heartbeat_timeout: Duration,
     /// TCP connection timeout
     connect_timeout: Duration,
+    /// Idle time before TCP keep-alive probes are sent
+    tcp_keepalive_interval: Duration,
+    address_health: AddressHealth,
     state: State,
     setup_actors: AddressMap<OrderId, setup_taker::Actor>,
     collab_settlement_actors: AddressMap<OrderId, collab_settlement_taker::Actor>,
@@ -162,6 +175,14 @@ pub struct Connect {
     pub maker_addr: SocketAddr,
 }
 
+/// Close the connection to the maker deliberately, e.g. when logging out.
+///
+/// Unlike losing the connection, this does not trigger a reconnect.
+pub struct Disconnect;
+
+/// Private message to order the maker addresses by how likely a connection attempt is to succeed.
+struct PrioritizeAddresses(Vec<SocketAddr>);
+
 pub struct MakerStreamMessage {
     pub item: Result<wire::MakerToTaker>,
 }
@@ -183,6 +204,22 @@ pub enum ConnectionCloseReason {
         taker_version: Version,
         maker_version: Version,
     },
+    /// We closed the connection through [`Disconnect`].
+    Requested,
+    /// We closed the connection because the maker did not adhere to the protocol, e.g. sent a
+    /// second `Hello`.
+    ProtocolViolation,
+}
+
+impl ConnectionStatus {
+    fn is_disconnected_deliberately(&self) -> bool {
+        matches!(
+            self,
+            ConnectionStatus::Offline {
+                reason: Some(ConnectionCloseReason::Requested)
+            }
+        )
+    }
 }
 
 /// Message sent from the `setup_taker::Actor` to the
@@ -195,6 +232,7 @@ pub enum ConnectionCloseReason {
 pub struct TakeOrder {
     pub order_id: OrderId,
     pub quantity: Usd,
+    pub leverage: Leverage,
     pub address: xtra::Address<setup_taker::Actor>,
 }
 
@@ -220,6 +258,7 @@ impl Actor {
         identity_sk: x25519_dalek::StaticSecret,
         maker_heartbeat_interval: Duration,
         connect_timeout: Duration,
+        tcp_keepalive_interval: Duration,
     ) -> Self {
         Self {
             status_sender,
@@ -233,6 +272,8 @@ impl Actor {
             state: State::Disconnected,
             setup_actors: AddressMap::default(),
             connect_timeout,
+            tcp_keepalive_interval,
+            address_health: AddressHealth::default(),
             collab_settlement_actors: AddressMap::default(),
             rollover_actors: AddressMap::default(),
         }
@@ -251,6 +292,8 @@ impl Actor {
     async fn handle_rollover_actor_stopping(&mut self, message: Stopping<rollover_taker::Actor>) {
         self.rollover_actors.gc(message);
     }
+
+    fn handle_ping(&mut self, _: health::Ping) {}
 }
 
 #[xtra_productivity]
@@ -266,6 +309,7 @@ impl Actor {
             .send(wire::TakerToMaker::TakeOrder {
                 order_id: msg.order_id,
                 quantity: msg.quantity,
+                leverage: msg.leverage,
             })
             .await?;
 
@@ -330,10 +374,61 @@ impl Actor {
             maker_identity,
         }: Connect,
         ctx: &mut xtra::Context<Self>,
+    ) -> Result<()> {
+        // A reconnection attempt may have been queued before we were asked to disconnect
+        if self.status_sender.borrow().is_disconnected_deliberately() {
+            bail!("Not connecting to the maker after deliberate disconnect");
+        }
+
+        let result = self.connect_to_maker(maker_identity, maker_addr, ctx).await;
+
+        match result {
+            Ok(()) => self.address_health.record_success(maker_addr),
+            Err(_) => self.address_health.record_failure(maker_addr),
+        }
+
+        result
+    }
+
+    fn handle_prioritize_addresses(&mut self, msg: PrioritizeAddresses) -> Vec<SocketAddr> {
+        self.address_health.prioritize(msg.0)
+    }
+
+    async fn handle_disconnect(&mut self, _: Disconnect) {
+        self.close_connection(ConnectionCloseReason::Requested)
+            .await;
+
+        tracing::info!("Disconnected from maker");
+    }
+}
+
+impl Actor {
+    async fn close_connection(&mut self, reason: ConnectionCloseReason) {
+        // Dropping the connected state cancels reading from the maker and measuring the pulse
+        if let State::Connected { mut write, .. } =
+            std::mem::replace(&mut self.state, State::Disconnected)
+        {
+            if let Err(e) = write.close().await {
+                tracing::debug!("Failed to close connection to maker cleanly: {e:#}");
+            }
+        }
+
+        self.status_sender
+            .send(ConnectionStatus::Offline {
+                reason: Some(reason),
+            })
+            .expect("receiver to outlive the actor");
+    }
+
+    async fn connect_to_maker(
+        &mut self,
+        maker_identity: Identity,
+        maker_addr: SocketAddr,
+        ctx: &mut xtra::Context<Self>,
     ) -> Result<()> {
         tracing::debug!(address = %maker_addr, "Connecting to maker");
 
-        let (mut write, mut read) = {
+        let (mut write, mut read, compression) = {
             let mut connection = TcpStream::connect(&maker_addr)
                 .timeout(self.connect_timeout)
                 .await
@@ -343,6 +438,7 @@ impl Actor {
                     format!("Connection attempt to {maker_addr} timed out after {seconds}s",)
                 })?
                 .with_context(|| format!("Failed to connect to {maker_addr}"))?;
+            wire::enable_tcp_keepalive(&connection, self.tcp_keepalive_interval)?;
             let noise = noise::initiator_handshake(
                 &mut connection,
                 &self.identity_sk,
@@ -350,7 +446,11 @@ impl Actor {
             )
             .await?;
 
-            Framed::new(connection, EncryptedJsonCodec::new(noise)).split()
+            let framed = Framed::new(connection, EncryptedJsonCodec::new(noise));
+            let compression = framed.codec().compression_switch();
+            let (write, read) = framed.split();
+
+            (write, read, compression)
         };
 
         let our_version = Version::current();
@@ -367,7 +467,7 @@ impl Actor {
             })?
             .with_context(|| format!("Failed to read first message from maker {maker_identity}"))? {
             Some(wire::MakerToTaker::Hello(maker_version)) => {
-                if our_version != maker_version {
+                if !our_version.is_compatible_with(&maker_version) {
                     self.status_sender
                         .send(ConnectionStatus::Offline {
                             reason: Some(ConnectionCloseReason::VersionMismatch {
@@ -396,6 +496,16 @@ impl Actor {
 
         tracing::info!(address = %maker_addr, "Established connection to maker");
 
+        // We only compress towards the maker once it replied with the algorithms it supports
+        write
+            .send(TakerToMaker::SupportedCompression(
+                wire::Compression::supported(),
+            ))
+            .await?;
+
+        // Don't rely on the maker pushing its order, we might have missed it while reconnecting
+        write.send(TakerToMaker::RequestCurrentOrder).await?;
+
         let this = ctx.address().expect("self to be alive");
 
         let mut tasks = Tasks::default();
@@ -409,6 +519,7 @@ impl Actor {
             last_heartbeat: SystemTime::now(),
             last_pulse: SystemTime::now(),
             write,
+            compression,
             _tasks: tasks,
         };
         self.status_sender
@@ -417,7 +528,10 @@ impl Actor {
 
         Ok(())
     }
+}
 
+#[xtra_productivity]
+impl Actor {
     async fn handle_wire_message(
         &mut self,
         message: MakerStreamMessage,
@@ -447,10 +561,10 @@ impl Actor {
                     tracing::warn!(%order_id, "No active contract setup");
                 }
             }
-            wire::MakerToTaker::RejectOrder(order_id) => {
+            wire::MakerToTaker::RejectOrder { order_id, reason } => {
                 if self
                     .setup_actors
-                    .send_fallible(&order_id, setup_taker::Rejected::without_reason())
+                    .send_fallible(&order_id, setup_taker::Rejected(reason))
                     .await
                     .is_err()
                 {
@@ -470,7 +584,23 @@ impl Actor {
             wire::MakerToTaker::InvalidOrderId(order_id) => {
                 if self
                     .setup_actors
-                    .send_fallible(&order_id, setup_taker::Rejected::invalid_order_id())
+                    .send_fallible(
+                        &order_id,
+                        setup_taker::Rejected(RejectReason::InvalidOrderId),
+                    )
+                    .await
+                    .is_err()
+                {
+                    tracing::warn!(%order_id, "No active contract setup");
+                }
+            }
+            wire::MakerToTaker::TakeOrderAlreadyInProgress(order_id) => {
+                if self
+                    .setup_actors
+                    .send_fallible(
+                        &order_id,
+                        setup_taker::Rejected(RejectReason::AlreadyInProgress),
+                    )
                     .await
                     .is_err()
                 {
@@ -531,8 +661,23 @@ impl Actor {
                     .log_failure("Failed to forward current order from maker")
                     .await;
             }
+            wire::MakerToTaker::SupportedCompression(algorithms) => {
+                if let State::Connected { compression, .. } = &self.state {
+                    if compression.enable_if_supported(&algorithms) {
+                        tracing::debug!("Compressing large messages to maker");
+                    }
+                }
+            }
             wire::MakerToTaker::Hello(_) => {
-                tracing::warn!("Ignoring unexpected Hello message from maker. Hello is only expected when opening a new connection.")
+                // Hello is only expected when opening a new connection, a maker sending it
+                // mid-session is either broken or trying to abuse us.
+                tracing::warn!("Closing connection to maker after receiving unexpected Hello");
+
+                self.close_connection(ConnectionCloseReason::ProtocolViolation)
+                    .await;
+            }
+            wire::MakerToTaker::Unknown => {
+                tracing::debug!("Ignoring message of unknown type from maker")
             }
         }
         KeepRunning::Yes
@@ -571,6 +716,45 @@ impl Actor {
 
 impl xtra::Actor for Actor {}
 
+/// Tracks the outcome of connection attempts per maker address.
+///
+/// Used to try the address we last connected through first and to deprioritize addresses that
+/// failed recently, instead of always walking the configured addresses in the same order.
+#[derive(Debug, Default)]
+struct AddressHealth {
+    last_success: Option<SocketAddr>,
+    consecutive_failures: HashMap<SocketAddr, u32>,
+}
+
+impl AddressHealth {
+    fn record_success(&mut self, address: SocketAddr) {
+        self.last_success = Some(address);
+        self.consecutive_failures.remove(&address);
+    }
+
+    fn record_failure(&mut self, address: SocketAddr) {
+        if self.last_success == Some(address) {
+            self.last_success = None;
+        }
+        *self.consecutive_failures.entry(address).or_default() += 1;
+    }
+
+    /// Order the given addresses by preference.
+    ///
+    /// The last successful address comes first, the remaining ones are ordered by their number of
+    /// consecutive failures. Ties keep the configured order.
+    fn prioritize(&self, mut addresses: Vec<SocketAddr>) -> Vec<SocketAddr> {
+        addresses.sort_by_key(|address| {
+            (
+                self.last_success != Some(*address),
+                self.consecutive_failures.get(address).copied().unwrap_or(0),
+            )
+        });
+
+        addresses
+    }
+}
+
 // TODO: Move the reconnection logic inside the connection::Actor instead of
 // depending on a watch channel
 pub async fn connect(
@@ -581,10 +765,42 @@ pub async fn connect(
 ) {
     loop {
         let connection_status = maker_online_status_feed_receiver.borrow().clone();
-        if matches!(connection_status, ConnectionStatus::Offline { .. }) {
+        if connection_status.is_disconnected_deliberately() {
+            tracing::debug!("Not reconnecting to the maker after deliberate disconnect");
+        } else if matches!(connection_status, ConnectionStatus::Offline { .. }) {
             tracing::debug!("No connection to the maker");
+
+            let violated_protocol = matches!(
+                connection_status,
+                ConnectionStatus::Offline {
+                    reason: Some(ConnectionCloseReason::ProtocolViolation)
+                }
+            );
+            if violated_protocol {
+                let seconds = RECONNECT_AFTER_PROTOCOL_VIOLATION_DELAY.as_secs();
+
+                tracing::warn!("Maker violated the protocol, reconnecting in {seconds} seconds");
+
+                tokio::time::sleep(RECONNECT_AFTER_PROTOCOL_VIOLATION_DELAY).await;
+            }
+
             'connect: loop {
-                for address in &maker_addresses {
+                if maker_online_status_feed_receiver
+                    .borrow()
+                    .is_disconnected_deliberately()
+                {
+                    tracing::debug!(
+                        "Giving up reconnecting to the maker after deliberate disconnect"
+                    );
+                    break 'connect;
+                }
+
+                let addresses = connection_actor_addr
+                    .send(PrioritizeAddresses(maker_addresses.clone()))
+                    .await
+                    .expect("Taker actor to be present");
+
+                for address in &addresses {
                     let connect_msg = Connect {
                         maker_identity,
                         maker_addr: *address,
@@ -617,3 +833,36 @@ pub async fn connect(
             .expect("watch channel should outlive the future");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn previously_successful_address_is_tried_first_on_reconnect() {
+        let first = "127.0.0.1:9999".parse().unwrap();
+        let second = "127.0.0.1:10000".parse().unwrap();
+        let third = "127.0.0.1:10001".parse().unwrap();
+        let mut health = AddressHealth::default();
+
+        health.record_failure(first);
+        health.record_success(second);
+
+        assert_eq!(
+            health.prioritize(vec![first, second, third]),
+            vec![second, third, first]
+        );
+    }
+
+    #[test]
+    fn failing_last_successful_address_loses_priority() {
+        let first = "127.0.0.1:9999".parse().unwrap();
+        let second = "127.0.0.1:10000".parse().unwrap();
+        let mut health = AddressHealth::default();
+
+        health.record_success(second);
+        health.record_failure(second);
+
+        assert_eq!(health.prioritize(vec![first, second]), vec![first, second]);
+    }
+}
@@ -1,4 +1,5 @@
 use crate::model;
+use crate::model::cfd::AutoCloseThresholds;
 use crate::model::cfd::CfdEvent;
 use crate::model::cfd::Event;
 use crate::model::cfd::OrderId;
@@ -7,17 +8,26 @@ use crate::model::FundingRate;
 use crate::model::Identity;
 use crate::model::Leverage;
 use crate::model::OpeningFee;
+use crate::model::Percent;
 use crate::model::Position;
 use crate::model::Price;
+use crate::model::RoundingStrategy;
+use crate::model::Timestamp;
 use crate::model::TxFeeRate;
 use crate::model::Usd;
+use anyhow::bail;
 use anyhow::Context;
 use anyhow::Result;
 use futures::future::BoxFuture;
 use futures::FutureExt;
+use rust_decimal::Decimal;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use sqlx::migrate::MigrateError;
+use sqlx::migrate::Migrator;
 use sqlx::pool::PoolConnection;
 use sqlx::sqlite::SqliteConnectOptions;
+use sqlx::Row;
 use sqlx::Sqlite;
 use sqlx::SqlitePool;
 use std::path::PathBuf;
@@ -78,6 +88,19 @@ pub fn connect(path: PathBuf) -> BoxFuture<'static, Result<SqlitePool>> {
     .boxed()
 }
 
+/// Open an existing database without modifying it, e.g. to inspect it while the daemon is not
+/// running.
+///
+/// No migrations are applied, the database is expected to have been written by this version.
+pub async fn connect_read_only(path: PathBuf) -> Result<SqlitePool> {
+    let pool =
+        SqlitePool::connect_with(SqliteConnectOptions::new().read_only(true).filename(&path))
+            .await
+            .with_context(|| format!("Failed to open database at {} read-only", path.display()))?;
+
+    Ok(pool)
+}
+
 pub async fn memory() -> Result<SqlitePool> {
     // Note: Every :memory: database is distinct from every other. So, opening two database
     // connections each with the filename ":memory:" will create two independent in-memory
@@ -89,15 +112,68 @@ pub async fn memory() -> Result<SqlitePool> {
     Ok(pool)
 }
 
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
 async fn run_migrations(pool: &SqlitePool) -> Result<()> {
-    sqlx::migrate!("./migrations")
+    let expected = expected_schema_version();
+
+    // Refuse to touch a database created by a newer version of the software, e.g. after a
+    // downgrade. Running our migrations against it would fail and make us start from scratch.
+    if let Some(applied) = applied_schema_version(pool).await? {
+        if applied > expected {
+            bail!(
+                "Database schema version {applied} is newer than the supported version {expected}, \
+                 refusing to start"
+            );
+        }
+    }
+
+    MIGRATOR
         .run(pool)
         .await
         .context("Failed to run migrations")?;
 
+    let applied = applied_schema_version(pool).await?;
+    if applied != Some(expected) {
+        bail!(
+            "Database schema version {applied:?} does not match expected version {expected} \
+             after running migrations"
+        );
+    }
+
     Ok(())
 }
 
+/// The version of the latest migration compiled into this binary.
+fn expected_schema_version() -> i64 {
+    MIGRATOR
+        .iter()
+        .map(|migration| migration.version)
+        .max()
+        .expect("at least one migration")
+}
+
+/// The version of the latest migration that was successfully applied to the database, if any.
+async fn applied_schema_version(pool: &SqlitePool) -> Result<Option<i64>> {
+    let has_migrations_table = sqlx::query(
+        "SELECT name FROM sqlite_master WHERE type = 'table' AND name = '_sqlx_migrations'",
+    )
+    .fetch_optional(pool)
+    .await?
+    .is_some();
+
+    if !has_migrations_table {
+        return Ok(None);
+    }
+
+    let row = sqlx::query("SELECT MAX(version) AS version FROM _sqlx_migrations WHERE success = 1")
+        .fetch_one(pool)
+        .await
+        .context("Failed to load schema version")?;
+
+    Ok(row.try_get("version")?)
+}
+
 pub async fn insert_cfd(cfd: &model::cfd::Cfd, conn: &mut PoolConnection<Sqlite>) -> Result<()> {
     let query_result = sqlx::query(
         r#"
@@ -112,8 +188,11 @@ pub async fn insert_cfd(cfd: &model::cfd::Cfd, conn: &mut PoolConnection<Sqlite>
             role,
             opening_fee,
             initial_funding_rate,
-            initial_tx_fee_rate
-        ) values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)"#,
+            initial_tx_fee_rate,
+            created_at,
+            n_payouts,
+            rounding
+        ) values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)"#,
     )
     .bind(&cfd.id())
     .bind(&cfd.position())
@@ -126,6 +205,9 @@ pub async fn insert_cfd(cfd: &model::cfd::Cfd, conn: &mut PoolConnection<Sqlite>
     .bind(&cfd.opening_fee())
     .bind(&cfd.initial_funding_rate())
     .bind(&cfd.initial_tx_fee_rate())
+    .bind(&Timestamp::now())
+    .bind(i64::try_from(cfd.n_payouts())?)
+    .bind(&cfd.rounding())
     .execute(conn)
     .await?;
 
@@ -179,8 +261,35 @@ pub async fn append_event(
     Ok(())
 }
 
+/// Export the event log of a CFD as a JSON array, oldest event first.
+///
+/// Meant for support and debugging, the format is not stable.
+pub async fn export_cfd_events(
+    id: OrderId,
+    conn: &mut PoolConnection<Sqlite>,
+) -> Result<serde_json::Value> {
+    let (_, events) = load_cfd(id, conn).await?;
+
+    let events = events
+        .into_iter()
+        .map(|event| {
+            let (name, data) = event.event.to_json();
+            let data = serde_json::from_str::<serde_json::Value>(&data)?;
+
+            Ok(serde_json::json!({
+                "name": name,
+                "timestamp": event.timestamp,
+                "data": data,
+            }))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(serde_json::Value::Array(events))
+}
+
 // TODO: Make sqlx directly instantiate this struct instead of mapping manually. Need to create
 // newtype for `settlement_interval`.
+#[derive(Clone)]
 pub struct Cfd {
     pub id: OrderId,
     pub position: Position,
@@ -193,8 +302,16 @@ pub struct Cfd {
     pub opening_fee: OpeningFee,
     pub initial_funding_rate: FundingRate,
     pub initial_tx_fee_rate: TxFeeRate,
+    pub n_payouts: usize,
+    pub rounding: RoundingStrategy,
+    pub label: Option<String>,
+    pub archived: bool,
 }
 
+#[derive(thiserror::Error, Debug, Clone, Copy)]
+#[error("Unknown CFD {0}")]
+pub struct CfdNotFound(pub OrderId);
+
 pub async fn load_cfd(id: OrderId, conn: &mut PoolConnection<Sqlite>) -> Result<(Cfd, Vec<Event>)> {
     let cfd_row = sqlx::query!(
         r#"
@@ -210,7 +327,11 @@ pub async fn load_cfd(id: OrderId, conn: &mut PoolConnection<Sqlite>) -> Result<
                 role as "role: crate::model::cfd::Role",
                 opening_fee as "opening_fee: crate::model::OpeningFee",
                 initial_funding_rate as "initial_funding_rate: crate::model::FundingRate",
-                initial_tx_fee_rate as "initial_tx_fee_rate: crate::model::TxFeeRate"
+                initial_tx_fee_rate as "initial_tx_fee_rate: crate::model::TxFeeRate",
+                n_payouts,
+                rounding as "rounding: crate::model::RoundingStrategy",
+                label,
+                archived
             from
                 cfds
             where
@@ -218,8 +339,9 @@ pub async fn load_cfd(id: OrderId, conn: &mut PoolConnection<Sqlite>) -> Result<
             "#,
             id
     )
-    .fetch_one(&mut *conn)
-    .await?;
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or(CfdNotFound(id))?;
 
     let cfd = Cfd {
         id: cfd_row.uuid,
@@ -233,6 +355,10 @@ pub async fn load_cfd(id: OrderId, conn: &mut PoolConnection<Sqlite>) -> Result<
         opening_fee: cfd_row.opening_fee,
         initial_funding_rate: cfd_row.initial_funding_rate,
         initial_tx_fee_rate: cfd_row.initial_tx_fee_rate,
+        n_payouts: usize::try_from(cfd_row.n_payouts)?,
+        rounding: cfd_row.rounding,
+        label: cfd_row.label,
+        archived: cfd_row.archived,
     };
 
     let events = sqlx::query!(
@@ -284,6 +410,281 @@ pub async fn load_all_cfd_ids(conn: &mut PoolConnection<Sqlite>) -> Result<Vec<O
     Ok(ids)
 }
 
+/// Position of a CFD in the list of all CFDs ordered by creation, newest first.
+///
+/// CFDs can be created within the same second, hence the creation timestamp alone is ambiguous
+/// and the row id breaks ties.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct CfdCursor {
+    pub created_at: Timestamp,
+    pub id: i64,
+}
+
+/// Loads the ids of at most `limit` CFDs positioned after `before`, newest first.
+///
+/// Each id is returned together with the cursor of the CFD so that the last one can be used as
+/// `before` to load the next page.
+pub async fn load_cfd_ids_page(
+    before: Option<CfdCursor>,
+    limit: u32,
+    conn: &mut PoolConnection<Sqlite>,
+) -> Result<Vec<(OrderId, CfdCursor)>> {
+    let (before_created_at, before_id) = match before {
+        Some(CfdCursor { created_at, id }) => (created_at.seconds(), id),
+        None => (i64::MAX, i64::MAX),
+    };
+
+    let rows = sqlx::query(
+        r#"
+            select
+                id,
+                uuid,
+                created_at
+            from
+                cfds
+            where
+                created_at < $1 or (created_at = $1 and id < $2)
+            order by created_at desc, id desc
+            limit $3
+            "#,
+    )
+    .bind(before_created_at)
+    .bind(before_id)
+    .bind(limit)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    rows.into_iter()
+        .map(|row| {
+            let order_id = row.try_get::<OrderId, _>("uuid")?;
+            let cursor = CfdCursor {
+                created_at: row.try_get::<Timestamp, _>("created_at")?,
+                id: row.try_get::<i64, _>("id")?,
+            };
+
+            Ok((order_id, cursor))
+        })
+        .collect()
+}
+
+/// Store the auto-close thresholds of a CFD, overwriting any previous ones.
+///
+/// Passing [`AutoCloseThresholds::default`] clears them.
+pub async fn set_auto_close_thresholds(
+    id: OrderId,
+    thresholds: AutoCloseThresholds,
+    conn: &mut PoolConnection<Sqlite>,
+) -> Result<()> {
+    let result = sqlx::query(
+        r#"
+            update
+                cfds
+            set
+                auto_close_take_profit = $1,
+                auto_close_stop_loss = $2
+            where
+                uuid = $3
+            "#,
+    )
+    .bind(
+        thresholds
+            .take_profit
+            .map(|percent| percent.value().to_string()),
+    )
+    .bind(
+        thresholds
+            .stop_loss
+            .map(|percent| percent.value().to_string()),
+    )
+    .bind(id)
+    .execute(&mut *conn)
+    .await?;
+
+    if result.rows_affected() != 1 {
+        anyhow::bail!("No CFD with id {id}");
+    }
+
+    Ok(())
+}
+
+/// Load the auto-close thresholds of all CFDs that have at least one threshold set.
+pub async fn load_auto_close_thresholds(
+    conn: &mut PoolConnection<Sqlite>,
+) -> Result<Vec<(OrderId, AutoCloseThresholds)>> {
+    let rows = sqlx::query(
+        r#"
+            select
+                uuid,
+                auto_close_take_profit,
+                auto_close_stop_loss
+            from
+                cfds
+            where
+                auto_close_take_profit is not null or auto_close_stop_loss is not null
+            "#,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    rows.into_iter()
+        .map(|row| {
+            let id = row.try_get::<OrderId, _>("uuid")?;
+            let take_profit = row.try_get::<Option<String>, _>("auto_close_take_profit")?;
+            let stop_loss = row.try_get::<Option<String>, _>("auto_close_stop_loss")?;
+
+            let thresholds = AutoCloseThresholds {
+                take_profit: take_profit.as_deref().map(parse_percent).transpose()?,
+                stop_loss: stop_loss.as_deref().map(parse_percent).transpose()?,
+            };
+
+            Ok((id, thresholds))
+        })
+        .collect()
+}
+
+/// Store the label of a CFD, overwriting any previous one.
+///
+/// Passing `None` clears it.
+pub async fn set_cfd_label(
+    id: OrderId,
+    label: Option<&str>,
+    conn: &mut PoolConnection<Sqlite>,
+) -> Result<()> {
+    let result = sqlx::query(
+        r#"
+            update
+                cfds
+            set
+                label = $1
+            where
+                uuid = $2
+            "#,
+    )
+    .bind(label)
+    .bind(id)
+    .execute(&mut *conn)
+    .await?;
+
+    if result.rows_affected() != 1 {
+        anyhow::bail!("No CFD with id {id}");
+    }
+
+    Ok(())
+}
+
+/// Mark a CFD as archived, or restore it if `archived` is `false`.
+///
+/// Archiving only hides a CFD from the feed, its events are kept.
+pub async fn set_cfd_archived(
+    id: OrderId,
+    archived: bool,
+    conn: &mut PoolConnection<Sqlite>,
+) -> Result<()> {
+    let result = sqlx::query(
+        r#"
+            update
+                cfds
+            set
+                archived = $1
+            where
+                uuid = $2
+            "#,
+    )
+    .bind(archived)
+    .bind(id)
+    .execute(&mut *conn)
+    .await?;
+
+    if result.rows_affected() != 1 {
+        anyhow::bail!("No CFD with id {id}");
+    }
+
+    Ok(())
+}
+
+/// Store the scripts the monitor watches on behalf of a CFD, replacing any previous ones.
+pub async fn save_monitored_scripts<T>(
+    id: OrderId,
+    scripts: &T,
+    conn: &mut PoolConnection<Sqlite>,
+) -> Result<()>
+where
+    T: Serialize,
+{
+    let data = serde_json::to_string(scripts)?;
+
+    let result = sqlx::query(
+        r#"
+            insert into monitored_scripts (
+                cfd_id,
+                data
+            ) values (
+                (select id from cfds where cfds.uuid = $1),
+                $2
+            )
+            on conflict (cfd_id) do update set data = excluded.data
+            "#,
+    )
+    .bind(id)
+    .bind(data)
+    .execute(&mut *conn)
+    .await?;
+
+    if result.rows_affected() != 1 {
+        anyhow::bail!("No CFD with id {id}");
+    }
+
+    Ok(())
+}
+
+/// Load the scripts the monitor watched on behalf of a CFD.
+///
+/// Returns `None` if they were never stored.
+pub async fn load_monitored_scripts<T>(
+    id: OrderId,
+    conn: &mut PoolConnection<Sqlite>,
+) -> Result<Option<T>>
+where
+    T: DeserializeOwned,
+{
+    let row = sqlx::query(
+        r#"
+            select
+                monitored_scripts.data
+            from
+                monitored_scripts
+            join
+                cfds on cfds.id = monitored_scripts.cfd_id
+            where
+                cfds.uuid = $1
+            "#,
+    )
+    .bind(id)
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    let scripts = match row {
+        Some(row) => {
+            let data = row.try_get::<String, _>("data")?;
+            let scripts = serde_json::from_str(&data)
+                .with_context(|| format!("Invalid monitored scripts for CFD {id}"))?;
+
+            Some(scripts)
+        }
+        None => None,
+    };
+
+    Ok(scripts)
+}
+
+fn parse_percent(value: &str) -> Result<Percent> {
+    let decimal = value
+        .parse::<Decimal>()
+        .with_context(|| format!("Invalid percentage {value} in database"))?;
+
+    Ok(Percent::from(decimal))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -319,6 +720,9 @@ mod tests {
                 opening_fee,
                 initial_funding_rate,
                 initial_tx_fee_rate,
+                n_payouts,
+                rounding,
+                ..
             },
             _,
         ) = load_cfd(cfd.id(), &mut conn).await.unwrap();
@@ -337,6 +741,17 @@ mod tests {
         assert_eq!(cfd.opening_fee(), opening_fee);
         assert_eq!(cfd.initial_funding_rate(), initial_funding_rate);
         assert_eq!(cfd.initial_tx_fee_rate(), initial_tx_fee_rate);
+        assert_eq!(cfd.n_payouts(), n_payouts);
+        assert_eq!(cfd.rounding(), rounding);
+    }
+
+    #[tokio::test]
+    async fn loading_unknown_cfd_fails_with_not_found() {
+        let mut conn = setup_test_db().await;
+
+        let error = load_cfd(OrderId::default(), &mut conn).await.unwrap_err();
+
+        assert!(error.downcast_ref::<CfdNotFound>().is_some());
     }
 
     #[tokio::test]
@@ -352,6 +767,134 @@ mod tests {
         assert_eq!(vec![cfd_3.id(), cfd_2.id(), cfd_1.id()], ids)
     }
 
+    #[tokio::test]
+    async fn auto_close_thresholds_can_be_set_and_cleared() {
+        let mut conn = setup_test_db().await;
+
+        let cfd = Cfd::dummy().insert(&mut conn).await;
+        let _without_thresholds = Cfd::dummy().insert(&mut conn).await;
+        let thresholds = AutoCloseThresholds {
+            take_profit: Some(Percent::from(dec!(50.5))),
+            stop_loss: None,
+        };
+
+        set_auto_close_thresholds(cfd.id(), thresholds, &mut conn)
+            .await
+            .unwrap();
+        let loaded = load_auto_close_thresholds(&mut conn).await.unwrap();
+        assert_eq!(loaded, vec![(cfd.id(), thresholds)]);
+
+        set_auto_close_thresholds(cfd.id(), AutoCloseThresholds::default(), &mut conn)
+            .await
+            .unwrap();
+        let loaded = load_auto_close_thresholds(&mut conn).await.unwrap();
+        assert!(loaded.is_empty());
+    }
+
+    #[tokio::test]
+    async fn cfd_label_can_be_set_and_cleared() {
+        let mut conn = setup_test_db().await;
+
+        let cfd = Cfd::dummy().insert(&mut conn).await;
+        let (loaded, _) = load_cfd(cfd.id(), &mut conn).await.unwrap();
+        assert_eq!(loaded.label, None);
+
+        set_cfd_label(cfd.id(), Some("hedge"), &mut conn)
+            .await
+            .unwrap();
+        let (loaded, _) = load_cfd(cfd.id(), &mut conn).await.unwrap();
+        assert_eq!(loaded.label, Some("hedge".to_owned()));
+
+        set_cfd_label(cfd.id(), None, &mut conn).await.unwrap();
+        let (loaded, _) = load_cfd(cfd.id(), &mut conn).await.unwrap();
+        assert_eq!(loaded.label, None);
+    }
+
+    #[tokio::test]
+    async fn archived_cfd_can_still_be_loaded_and_restored() {
+        let mut conn = setup_test_db().await;
+
+        let cfd = Cfd::dummy().insert(&mut conn).await;
+        let (loaded, _) = load_cfd(cfd.id(), &mut conn).await.unwrap();
+        assert!(!loaded.archived);
+
+        set_cfd_archived(cfd.id(), true, &mut conn).await.unwrap();
+        let (loaded, _) = load_cfd(cfd.id(), &mut conn).await.unwrap();
+        assert_eq!(loaded.id, cfd.id());
+        assert!(loaded.archived);
+        assert_eq!(load_all_cfd_ids(&mut conn).await.unwrap(), vec![cfd.id()]);
+
+        set_cfd_archived(cfd.id(), false, &mut conn).await.unwrap();
+        let (loaded, _) = load_cfd(cfd.id(), &mut conn).await.unwrap();
+        assert!(!loaded.archived);
+    }
+
+    #[tokio::test]
+    async fn setting_label_of_unknown_cfd_fails() {
+        let mut conn = setup_test_db().await;
+
+        let result = set_cfd_label(OrderId::default(), Some("hedge"), &mut conn).await;
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn given_50_cfds_when_paginating_then_windows_are_newest_first() {
+        let mut conn = setup_test_db().await;
+
+        let mut cfds = Vec::new();
+        for seconds in 1..=50 {
+            let cfd = Cfd::dummy()
+                .insert_at(Timestamp::new(seconds), &mut conn)
+                .await;
+            cfds.push(cfd.id());
+        }
+        cfds.reverse();
+
+        let first = load_cfd_ids_page(None, 20, &mut conn).await.unwrap();
+        let (_, cursor) = *first.last().unwrap();
+        let second = load_cfd_ids_page(Some(cursor), 20, &mut conn)
+            .await
+            .unwrap();
+        let (_, cursor) = *second.last().unwrap();
+        let third = load_cfd_ids_page(Some(cursor), 20, &mut conn)
+            .await
+            .unwrap();
+
+        let ids = |page: Vec<(OrderId, CfdCursor)>| {
+            page.into_iter().map(|(id, _)| id).collect::<Vec<_>>()
+        };
+        assert_eq!(ids(first), cfds[0..20]);
+        assert_eq!(ids(second), cfds[20..40]);
+        assert_eq!(ids(third), cfds[40..50]);
+        assert_eq!(cursor.created_at, Timestamp::new(11));
+    }
+
+    #[tokio::test]
+    async fn cfds_created_in_the_same_second_are_not_skipped_between_pages() {
+        let mut conn = setup_test_db().await;
+
+        let mut cfds = Vec::new();
+        for _ in 0..5 {
+            let cfd = Cfd::dummy().insert_at(Timestamp::new(1), &mut conn).await;
+            cfds.push(cfd.id());
+        }
+        cfds.reverse();
+
+        let first = load_cfd_ids_page(None, 2, &mut conn).await.unwrap();
+        let (_, cursor) = *first.last().unwrap();
+        let second = load_cfd_ids_page(Some(cursor), 2, &mut conn).await.unwrap();
+        let (_, cursor) = *second.last().unwrap();
+        let third = load_cfd_ids_page(Some(cursor), 2, &mut conn).await.unwrap();
+
+        let ids = [first, second, third]
+            .into_iter()
+            .flatten()
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, cfds);
+    }
+
     #[tokio::test]
     async fn test_append_events() {
         let mut conn = setup_test_db().await;
@@ -363,7 +906,7 @@ mod tests {
         let event1 = Event {
             timestamp,
             id: cfd.id(),
-            event: CfdEvent::OfferRejected,
+            event: CfdEvent::OfferRejected(None),
         };
 
         append_event(event1.clone(), &mut conn).await.unwrap();
@@ -381,6 +924,168 @@ mod tests {
         assert_eq!(events, vec![event1, event2])
     }
 
+    #[tokio::test]
+    async fn exported_event_log_lists_events_in_order() {
+        let mut conn = setup_test_db().await;
+
+        let cfd = Cfd::dummy().insert(&mut conn).await;
+
+        for event in [
+            CfdEvent::ContractSetupStarted,
+            CfdEvent::ContractSetupFailed,
+            CfdEvent::RevokeConfirmed,
+        ] {
+            append_event(
+                Event {
+                    timestamp: Timestamp::now(),
+                    id: cfd.id(),
+                    event,
+                },
+                &mut conn,
+            )
+            .await
+            .unwrap();
+        }
+
+        let exported = export_cfd_events(cfd.id(), &mut conn).await.unwrap();
+
+        let names = exported
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|event| event["name"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                "ContractSetupStarted",
+                "ContractSetupFailed",
+                "RevokeConfirmed"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn database_with_newer_schema_version_is_refused() {
+        let pool = SqlitePool::connect(":memory:").await.unwrap();
+        run_migrations(&pool).await.unwrap();
+
+        let future_version = expected_schema_version() + 1;
+        sqlx::query(
+            r#"
+            INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time)
+            VALUES ($1, 'from the future', 1, x'00', 0)
+            "#,
+        )
+        .bind(future_version)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let error = run_migrations(&pool).await.unwrap_err();
+
+        assert!(error
+            .to_string()
+            .contains("newer than the supported version"));
+        assert!(error.downcast_ref::<MigrateError>().is_none());
+    }
+
+    #[tokio::test]
+    async fn migrated_database_has_expected_schema_version() {
+        let pool = SqlitePool::connect(":memory:").await.unwrap();
+
+        run_migrations(&pool).await.unwrap();
+
+        assert_eq!(
+            applied_schema_version(&pool).await.unwrap(),
+            Some(expected_schema_version())
+        );
+    }
+
+    #[tokio::test]
+    async fn loading_events_of_a_cfd_uses_index() {
+        let mut conn = setup_test_db().await;
+
+        let details = sqlx::query(
+            "EXPLAIN QUERY PLAN SELECT name, data, created_at FROM events WHERE cfd_id = $1",
+        )
+        .bind(1)
+        .fetch_all(&mut conn)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|row| row.try_get::<String, _>("detail").unwrap())
+        .collect::<Vec<_>>();
+
+        assert!(
+            details.iter().any(
+                |detail| detail.contains("USING COVERING INDEX events_cfd_id")
+                    || detail.contains("USING INDEX events_cfd_id")
+            ),
+            "{details:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn events_index_migration_applies_to_existing_database() {
+        let pool = SqlitePool::connect(":memory:").await.unwrap();
+        let previous_migrations = Migrator {
+            migrations: MIGRATOR
+                .iter()
+                .filter(|migration| migration.version < 20220205000000)
+                .cloned()
+                .collect(),
+        };
+        previous_migrations.run(&pool).await.unwrap();
+
+        // `insert_cfd` writes columns of later migrations, hence insert the row as it was back then
+        let mut conn = pool.acquire().await.unwrap();
+        let cfd = Cfd::dummy();
+        sqlx::query(
+            r#"
+            insert into cfds (
+                uuid,
+                position,
+                initial_price,
+                leverage,
+                settlement_time_interval_hours,
+                quantity_usd,
+                counterparty_network_identity,
+                role,
+                opening_fee,
+                initial_funding_rate,
+                initial_tx_fee_rate,
+                created_at,
+                n_payouts
+            ) values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)"#,
+        )
+        .bind(&cfd.id())
+        .bind(&cfd.position())
+        .bind(&cfd.initial_price())
+        .bind(&cfd.leverage())
+        .bind(&cfd.settlement_time_interval_hours().whole_hours())
+        .bind(&cfd.quantity())
+        .bind(&cfd.counterparty_network_identity())
+        .bind(&cfd.role())
+        .bind(&cfd.opening_fee())
+        .bind(&cfd.initial_funding_rate())
+        .bind(&cfd.initial_tx_fee_rate())
+        .bind(&Timestamp::now())
+        .bind(i64::try_from(cfd.n_payouts()).unwrap())
+        .execute(&mut conn)
+        .await
+        .unwrap();
+        let event = Event::new(cfd.id(), CfdEvent::OfferRejected(None));
+        append_event(event.clone(), &mut conn).await.unwrap();
+        drop(conn);
+
+        run_migrations(&pool).await.unwrap();
+
+        let mut conn = pool.acquire().await.unwrap();
+        let (_, events) = load_cfd(cfd.id(), &mut conn).await.unwrap();
+        assert_eq!(events, vec![event]);
+    }
+
     async fn setup_test_db() -> PoolConnection<Sqlite> {
         let pool = SqlitePool::connect(":memory:").await.unwrap();
 
@@ -405,6 +1110,9 @@ mod tests {
                 OpeningFee::new(Amount::from_sat(2000)),
                 FundingRate::default(),
                 TxFeeRate::default(),
+                // Deliberately not the column defaults to catch them not being persisted
+                100,
+                RoundingStrategy::Bankers,
             )
         }
 
@@ -414,5 +1122,19 @@ mod tests {
 
             self
         }
+
+        /// Insert this [`Cfd`] into the database, pretending it was created at the given time.
+        async fn insert_at(self, created_at: Timestamp, conn: &mut PoolConnection<Sqlite>) -> Self {
+            insert_cfd(&self, conn).await.unwrap();
+
+            sqlx::query("update cfds set created_at = $1 where uuid = $2")
+                .bind(created_at)
+                .bind(self.id())
+                .execute(conn)
+                .await
+                .unwrap();
+
+            self
+        }
     }
 }
@@ -0,0 +1,264 @@
+use crate::future_ext::FutureExt;
+use serde::Serialize;
+use sqlx::SqlitePool;
+use std::future::Future;
+use std::time::Duration;
+use xtra::Address;
+
+/// How long we wait for an actor or the database to respond before considering it down.
+pub const PING_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How often the watchdog checks whether the core actors are responsive.
+pub const WATCHDOG_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How many checks in a row have to fail before the watchdog raises an alert.
+pub const WATCHDOG_MAX_FAILURES: u32 = 3;
+
+/// Message to check whether an actor is still processing its mailbox.
+#[derive(Debug, Clone, Copy)]
+pub struct Ping;
+
+impl xtra::Message for Ping {
+    type Result = ();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Up,
+    Down,
+}
+
+impl Status {
+    pub fn is_up(&self) -> bool {
+        matches!(self, Status::Up)
+    }
+}
+
+/// Aggregated liveness of the components a daemon depends on.
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub cfd_actor: Status,
+    pub connection_actor: Status,
+    pub price_feed: Status,
+    pub db: Status,
+}
+
+impl Report {
+    pub fn is_healthy(&self) -> bool {
+        [
+            self.cfd_actor,
+            self.connection_actor,
+            self.price_feed,
+            self.db,
+        ]
+        .iter()
+        .all(Status::is_up)
+    }
+}
+
+/// Send `msg` to the actor and report it as down if it does not answer within [`PING_TIMEOUT`].
+pub async fn check_actor<A, M>(address: &Address<A>, msg: M) -> Status
+where
+    A: xtra::Handler<M>,
+    M: xtra::Message,
+{
+    match address.send(msg).timeout(PING_TIMEOUT).await {
+        Ok(Ok(_)) => Status::Up,
+        Ok(Err(_)) => {
+            tracing::warn!(actor = %std::any::type_name::<A>(), "Actor is disconnected");
+            Status::Down
+        }
+        Err(_) => {
+            tracing::warn!(actor = %std::any::type_name::<A>(), "Actor did not respond to ping");
+            Status::Down
+        }
+    }
+}
+
+/// Run `check` every `interval` and call `alert` once it reported [`Status::Down`] `max_failures`
+/// times in a row.
+///
+/// The watchdog never resolves. It keeps checking after an alert and alerts again if the actors
+/// recover and get stuck once more. Whether to restart the process is up to the operator, we
+/// don't exit ourselves because an actor may just be slow and killing it could abort a contract
+/// setup half-way through.
+pub async fn watchdog<C, F, A>(interval: Duration, max_failures: u32, mut check: C, mut alert: A)
+where
+    C: FnMut() -> F,
+    F: Future<Output = Status>,
+    A: FnMut(),
+{
+    let mut failures = 0;
+
+    loop {
+        tokio::time::sleep(interval).await;
+
+        match check().await {
+            Status::Up => {
+                if failures >= max_failures {
+                    tracing::info!("Actors are responding to liveness checks again");
+                }
+
+                failures = 0;
+            }
+            Status::Down => {
+                failures += 1;
+                tracing::warn!("Liveness check failed ({failures}/{max_failures})");
+
+                if failures == max_failures {
+                    tracing::error!(
+                        "Actors did not respond to {failures} liveness checks in a row"
+                    );
+                    alert();
+                }
+            }
+        }
+    }
+}
+
+/// Check that we can acquire a connection from the pool and run a trivial query on it.
+pub async fn check_db(db: &SqlitePool) -> Status {
+    match sqlx::query("SELECT 1")
+        .execute(db)
+        .timeout(PING_TIMEOUT)
+        .await
+    {
+        Ok(Ok(_)) => Status::Up,
+        Ok(Err(e)) => {
+            tracing::warn!("Database is not responsive: {e:#}");
+            Status::Down
+        }
+        Err(_) => {
+            tracing::warn!("Database did not respond to ping");
+            Status::Down
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicU32;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use xtra::Actor as _;
+    use xtra_productivity::xtra_productivity;
+
+    #[tokio::test]
+    async fn responsive_actor_is_up() {
+        let (addr, fut) = DummyActor { stall: None }.create(None).run();
+        tokio::spawn(fut);
+
+        let status = check_actor(&addr, Ping).await;
+
+        assert_eq!(status, Status::Up);
+    }
+
+    #[tokio::test]
+    async fn stalled_actor_is_down() {
+        let (addr, fut) = DummyActor {
+            stall: Some(PING_TIMEOUT * 2),
+        }
+        .create(None)
+        .run();
+        tokio::spawn(fut);
+
+        let status = check_actor(&addr, Ping).await;
+
+        assert_eq!(status, Status::Down);
+    }
+
+    #[tokio::test]
+    async fn report_with_stalled_actor_is_unhealthy() {
+        let db = crate::db::memory().await.unwrap();
+        let (addr, fut) = DummyActor {
+            stall: Some(PING_TIMEOUT * 2),
+        }
+        .create(None)
+        .run();
+        tokio::spawn(fut);
+
+        let report = Report {
+            cfd_actor: check_actor(&addr, Ping).await,
+            connection_actor: Status::Up,
+            price_feed: Status::Up,
+            db: check_db(&db).await,
+        };
+
+        assert_eq!(report.db, Status::Up);
+        assert!(!report.is_healthy());
+    }
+
+    #[tokio::test]
+    async fn watchdog_alerts_once_about_stalled_actor() {
+        let (addr, fut) = DummyActor {
+            stall: Some(PING_TIMEOUT * 10),
+        }
+        .create(None)
+        .run();
+        tokio::spawn(fut);
+        let alerts = Arc::new(AtomicU32::new(0));
+
+        let result = watchdog(
+            Duration::from_millis(10),
+            2,
+            move || {
+                let addr = addr.clone();
+                async move { check_actor(&addr, Ping).await }
+            },
+            {
+                let alerts = alerts.clone();
+                move || {
+                    alerts.fetch_add(1, Ordering::SeqCst);
+                }
+            },
+        )
+        .timeout(PING_TIMEOUT * 5)
+        .await;
+
+        assert!(result.is_err(), "watchdog must keep running after an alert");
+        assert_eq!(alerts.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn watchdog_does_not_alert_about_responsive_actor() {
+        let (addr, fut) = DummyActor { stall: None }.create(None).run();
+        tokio::spawn(fut);
+        let alerts = Arc::new(AtomicU32::new(0));
+
+        let _ = watchdog(
+            Duration::from_millis(10),
+            2,
+            move || {
+                let addr = addr.clone();
+                async move { check_actor(&addr, Ping).await }
+            },
+            {
+                let alerts = alerts.clone();
+                move || {
+                    alerts.fetch_add(1, Ordering::SeqCst);
+                }
+            },
+        )
+        .timeout(Duration::from_millis(200))
+        .await;
+
+        assert_eq!(alerts.load(Ordering::SeqCst), 0);
+    }
+
+    struct DummyActor {
+        stall: Option<Duration>,
+    }
+
+    impl xtra::Actor for DummyActor {}
+
+    #[xtra_productivity(message_impl = false)]
+    impl DummyActor {
+        async fn handle_ping(&mut self, _: Ping) {
+            if let Some(stall) = self.stall {
+                tokio::time::sleep(stall).await;
+            }
+        }
+    }
+}
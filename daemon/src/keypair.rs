@@ -13,3 +13,39 @@ where
 
     (sk, pk)
 }
+
+/// Deterministically derive a keypair from the given secret key bytes.
+///
+/// Useful for tests that need stable transactions without wiring a seeded RNG through.
+///
+/// # Panics
+///
+/// If `bytes` are not a valid secret key, i.e. zero or not smaller than the curve order.
+pub fn from_secret_bytes(bytes: [u8; 32]) -> (secp256k1::SecretKey, bitcoin::PublicKey) {
+    let sk = secp256k1::SecretKey::from_slice(&bytes).expect("bytes to be a valid secret key");
+    let pk = bitcoin::PublicKey::new(secp256k1::PublicKey::from_secret_key(SECP256K1, &sk));
+
+    (sk, pk)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_secret_bytes_yield_same_keypair() {
+        let (sk_a, pk_a) = from_secret_bytes([1u8; 32]);
+        let (sk_b, pk_b) = from_secret_bytes([1u8; 32]);
+
+        assert_eq!(sk_a, sk_b);
+        assert_eq!(pk_a, pk_b);
+    }
+
+    #[test]
+    fn different_secret_bytes_yield_different_keypairs() {
+        let (_, pk_a) = from_secret_bytes([1u8; 32]);
+        let (_, pk_b) = from_secret_bytes([2u8; 32]);
+
+        assert_ne!(pk_a, pk_b);
+    }
+}
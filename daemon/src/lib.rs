@@ -2,12 +2,19 @@
 
 use crate::bitcoin::Txid;
 use crate::bitmex_price_feed::QUOTE_INTERVAL_MINUTES;
+use crate::metrics::Metrics;
+use crate::model::cfd::AutoCloseThresholds;
+use crate::model::cfd::ClosingFeeEstimate;
+use crate::model::cfd::CommitTxFee;
 use crate::model::cfd::Order;
 use crate::model::cfd::OrderId;
 use crate::model::cfd::Role;
 use crate::model::Identity;
+use crate::model::Leverage;
 use crate::model::OpeningFee;
+use crate::model::Percent;
 use crate::model::Price;
+use crate::model::RoundingStrategy;
 use crate::model::Usd;
 use crate::oracle::Attestation;
 use anyhow::Context;
@@ -19,12 +26,15 @@ use connection::ConnectionStatus;
 use maia::secp256k1_zkp::schnorrsig;
 use model::FundingRate;
 use model::TxFeeRate;
+use rust_decimal::Decimal;
 use sqlx::SqlitePool;
+use std::future::Future;
 use std::net::SocketAddr;
 use std::time::Duration;
 use time::ext::NumericalDuration;
 use tokio::sync::watch;
 use tokio_tasks::Tasks;
+use xtra::message_channel::MessageChannel;
 use xtra::message_channel::StrongMessageChannel;
 use xtra::Actor;
 use xtra::Address;
@@ -36,6 +46,7 @@ pub use maia;
 
 pub mod sqlx_ext; // Must come first because it is a macro.
 
+pub mod auto_close;
 pub mod auto_rollover;
 pub mod bdk_ext;
 pub mod bitmex_price_feed;
@@ -47,9 +58,11 @@ pub mod connection;
 pub mod db;
 pub mod fan_out;
 mod future_ext;
+pub mod health;
 pub mod keypair;
 pub mod maker_cfd;
 pub mod maker_inc_connections;
+pub mod metrics;
 pub mod model;
 pub mod monitor;
 mod noise;
@@ -75,8 +88,28 @@ pub mod wire;
 /// determine whether the maker is online.
 pub const HEARTBEAT_INTERVAL: std::time::Duration = Duration::from_secs(5);
 
+/// Idle time after which TCP keep-alive probes are sent on the connection between maker and taker.
+///
+/// Prevents NATs from silently dropping the connection, independent of the heartbeats.
+pub const TCP_KEEPALIVE_INTERVAL: std::time::Duration = Duration::from_secs(30);
+
+/// Maximum duration of a contract setup after the take was accepted, before it is considered
+/// failed.
+pub const SETUP_TIMEOUT: std::time::Duration = Duration::from_secs(5 * 60);
+
+/// The number of payouts the maker offers if not configured otherwise for an order.
 pub const N_PAYOUTS: usize = 200;
 
+/// Upper bound on the number of payouts of an order.
+///
+/// Every payout results in at least one CET that both parties have to create and sign.
+pub const MAX_N_PAYOUTS: usize = 1000;
+
+/// How long the maker accepts a settlement proposal after the taker created it.
+///
+/// Accepting a proposal later than this is refused because its price is likely stale.
+pub const SETTLEMENT_PROPOSAL_VALIDITY: time::Duration = time::Duration::minutes(5);
+
 /// The interval until the cfd gets settled, i.e. the attestation happens
 ///
 /// This variable defines at what point in time the oracle event id will be chose to settle the cfd.
@@ -92,7 +125,12 @@ pub const SETTLEMENT_INTERVAL: time::Duration = time::Duration::hours(24);
 pub struct MakerActorSystem<O, W> {
     pub cfd_actor: Address<maker_cfd::Actor<O, maker_inc_connections::Actor, W>>,
     wallet_actor: Address<W>,
+    inc_conn_actor: Address<maker_inc_connections::Actor>,
+    oracle_actor: Address<O>,
+    rescan_channel: Box<dyn MessageChannel<monitor::Rescan>>,
     executor: command::Executor,
+    db: SqlitePool,
+    pub metrics: Metrics,
 
     _tasks: Tasks,
 }
@@ -104,7 +142,10 @@ where
         + xtra::Handler<oracle::Sync>,
     W: xtra::Handler<wallet::BuildPartyParams>
         + xtra::Handler<wallet::Sign>
-        + xtra::Handler<wallet::Withdraw>,
+        + xtra::Handler<wallet::CheckFunds>
+        + xtra::Handler<wallet::Withdraw>
+        + xtra::Handler<wallet::ListUtxos>
+        + xtra::Handler<wallet::NewAddress>,
 {
     #[allow(clippy::too_many_arguments)]
     pub fn new<M>(
@@ -115,16 +156,27 @@ where
         monitor_constructor: impl FnOnce(Box<dyn StrongMessageChannel<monitor::Event>>) -> Result<M>,
         settlement_interval: time::Duration,
         n_payouts: usize,
+        rounding: RoundingStrategy,
+        max_leverage: Leverage,
+        cet_timelock: u32,
+        auto_accept_takes: Option<maker_cfd::AutoAcceptTakes>,
+        require_confirmed_inputs: bool,
+        settlement_proposal_validity: time::Duration,
+        setup_timeout: Duration,
         projection_actor: Address<projection::Actor>,
         identity: x25519_dalek::StaticSecret,
         heartbeat_interval: Duration,
+        tcp_keepalive_interval: Duration,
         p2p_socket: SocketAddr,
+        metrics: Metrics,
     ) -> Result<Self>
     where
         M: xtra::Handler<monitor::StartMonitoring>
             + xtra::Handler<monitor::Sync>
             + xtra::Handler<monitor::CollaborativeSettlement>
             + xtra::Handler<monitor::TryBroadcastTransaction>
+            + xtra::Handler<monitor::Rescan>
+            + xtra::Handler<monitor::GetUnconfirmedInputs>
             + xtra::Handler<oracle::Attestation>,
     {
         let (monitor_addr, monitor_ctx) = xtra::Context::new(None);
@@ -144,18 +196,27 @@ where
             &monitor_addr,
             &monitor_addr,
             &oracle_addr,
+            metrics.clone(),
         )));
 
         let (cfd_actor_addr, cfd_actor_fut) = maker_cfd::Actor::new(
-            db,
+            db.clone(),
             wallet_addr.clone(),
             settlement_interval,
             oracle_pk,
             projection_actor,
             process_manager_addr,
-            inc_conn_addr,
-            oracle_addr,
+            inc_conn_addr.clone(),
+            oracle_addr.clone(),
             n_payouts,
+            rounding,
+            max_leverage,
+            cet_timelock,
+            auto_accept_takes,
+            require_confirmed_inputs.then(|| monitor_addr.clone_channel()),
+            settlement_proposal_validity,
+            setup_timeout,
+            metrics.clone(),
         )
         .create(None)
         .run();
@@ -168,6 +229,7 @@ where
             Box::new(cfd_actor_addr.clone()),
             identity,
             heartbeat_interval,
+            tcp_keepalive_interval,
             p2p_socket,
         )));
 
@@ -186,7 +248,12 @@ where
         Ok(Self {
             cfd_actor: cfd_actor_addr,
             wallet_actor: wallet_addr,
+            inc_conn_actor: inc_conn_addr,
+            oracle_actor: oracle_addr,
+            rescan_channel: Box::new(monitor_addr),
             executor,
+            db,
+            metrics,
             _tasks: tasks,
         })
     }
@@ -199,6 +266,7 @@ where
         fee_rate: Option<TxFeeRate>,
         funding_rate: Option<FundingRate>,
         opening_fee: Option<OpeningFee>,
+        n_payouts: Option<usize>,
     ) -> Result<()> {
         self.cfd_actor
             .send(maker_cfd::NewOrder {
@@ -208,12 +276,21 @@ where
                 tx_fee_rate: fee_rate.unwrap_or_default(),
                 funding_rate: funding_rate.unwrap_or_default(),
                 opening_fee: opening_fee.unwrap_or_default(),
+                n_payouts,
             })
             .await??;
 
         Ok(())
     }
 
+    pub async fn update_order_price(&self, new_price: Price) -> Result<()> {
+        self.cfd_actor
+            .send(maker_cfd::UpdateOrderPrice { new_price })
+            .await??;
+
+        Ok(())
+    }
+
     pub async fn accept_order(&self, order_id: OrderId) -> Result<()> {
         self.cfd_actor
             .send(maker_cfd::AcceptOrder { order_id })
@@ -278,15 +355,119 @@ where
             })
             .await?
     }
+
+    pub async fn list_utxos(&self) -> Result<Vec<wallet::Utxo>> {
+        self.wallet_actor.send(wallet::ListUtxos).await?
+    }
+
+    pub async fn new_address(&self) -> Result<bitcoin::Address> {
+        self.wallet_actor.send(wallet::NewAddress).await?
+    }
+
+    pub async fn estimate_closing_fees(&self, order_id: OrderId) -> Result<ClosingFeeEstimate> {
+        let mut conn = self.db.acquire().await?;
+        let cfd = cfd_actors::load_cfd(order_id, &mut conn).await?;
+
+        cfd.estimate_closing_fees()
+    }
+
+    pub async fn commit_tx_fee(&self, order_id: OrderId) -> Result<CommitTxFee> {
+        let mut conn = self.db.acquire().await?;
+        let cfd = cfd_actors::load_cfd(order_id, &mut conn).await?;
+
+        cfd.commit_tx_fee()
+    }
+
+    /// Re-scan the blockchain for the transactions of the CFD and re-fetch its attestation.
+    ///
+    /// Allows to unstick a CFD that missed an update without restarting the daemon.
+    pub async fn resync(&self, order_id: OrderId) -> Result<()> {
+        let event_id = self
+            .rescan_channel
+            .send(monitor::Rescan { id: order_id })
+            .await
+            .context("Monitor actor disconnected")??;
+
+        if let Some(event_id) = event_id {
+            self.oracle_actor
+                .send(oracle::MonitorAttestation { event_id })
+                .await?;
+            self.oracle_actor.send(oracle::Sync).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Check whether the actors and the database backing the maker are responsive.
+    ///
+    /// The price feed is not owned by the maker actor system, hence its address needs to be
+    /// passed in.
+    pub async fn health<P>(&self, price_feed: &Address<P>) -> health::Report
+    where
+        P: xtra::Handler<bitmex_price_feed::LatestQuote>,
+    {
+        let (cfd_actor, connection_actor, price_feed, db) = tokio::join!(
+            health::check_actor(&self.cfd_actor, health::Ping),
+            health::check_actor(&self.inc_conn_actor, health::Ping),
+            health::check_actor(price_feed, bitmex_price_feed::LatestQuote),
+            health::check_db(&self.db),
+        );
+
+        health::Report {
+            cfd_actor,
+            connection_actor,
+            price_feed,
+            db,
+        }
+    }
+
+    /// Watch the cfd and connection actor, raising an alert if they stop responding to liveness
+    /// checks.
+    ///
+    /// Neither actor blocks in its handlers, a missed ping means it is stuck. See
+    /// [`health::watchdog`].
+    pub fn watchdog(&self) -> impl Future<Output = ()> + Send + 'static {
+        let cfd_actor = self.cfd_actor.clone();
+        let connection_actor = self.inc_conn_actor.clone();
+        let metrics = self.metrics.clone();
+
+        health::watchdog(
+            health::WATCHDOG_INTERVAL,
+            health::WATCHDOG_MAX_FAILURES,
+            move || {
+                let cfd_actor = cfd_actor.clone();
+                let connection_actor = connection_actor.clone();
+
+                async move {
+                    let (cfd_actor, connection_actor) = tokio::join!(
+                        health::check_actor(&cfd_actor, health::Ping),
+                        health::check_actor(&connection_actor, health::Ping),
+                    );
+
+                    if cfd_actor.is_up() && connection_actor.is_up() {
+                        health::Status::Up
+                    } else {
+                        health::Status::Down
+                    }
+                }
+            },
+            move || metrics.record_watchdog_alert(),
+        )
+    }
 }
 
 pub struct TakerActorSystem<O, W, P> {
     pub cfd_actor: Address<taker_cfd::Actor<O, W>>,
     pub connection_actor: Address<connection::Actor>,
     wallet_actor: Address<W>,
+    oracle_actor: Address<O>,
+    rescan_channel: Box<dyn MessageChannel<monitor::Rescan>>,
     pub auto_rollover_actor: Address<auto_rollover::Actor<O>>,
+    pub auto_close_actor: Address<auto_close::Actor>,
     pub price_feed_actor: Address<P>,
     executor: command::Executor,
+    db: SqlitePool,
+    pub metrics: Metrics,
     /// Keep this one around to avoid the supervisor being dropped due to ref-count changes on the
     /// address.
     _price_feed_supervisor: Address<supervisor::Actor<P, bitmex_price_feed::Error>>,
@@ -303,7 +484,10 @@ where
         + xtra::Handler<oracle::Sync>,
     W: xtra::Handler<wallet::BuildPartyParams>
         + xtra::Handler<wallet::Sign>
-        + xtra::Handler<wallet::Withdraw>,
+        + xtra::Handler<wallet::CheckFunds>
+        + xtra::Handler<wallet::Withdraw>
+        + xtra::Handler<wallet::ListUtxos>
+        + xtra::Handler<wallet::NewAddress>,
     P: xtra::Handler<bitmex_price_feed::LatestQuote>,
 {
     #[allow(clippy::too_many_arguments)]
@@ -317,18 +501,21 @@ where
         price_feed_constructor: impl (Fn(Address<supervisor::Actor<P, bitmex_price_feed::Error>>) -> P)
             + Send
             + 'static,
-        n_payouts: usize,
         maker_heartbeat_interval: Duration,
         connect_timeout: Duration,
+        tcp_keepalive_interval: Duration,
+        setup_timeout: Duration,
         projection_actor: Address<projection::Actor>,
         maker_identity: Identity,
+        metrics: Metrics,
     ) -> Result<Self>
     where
         M: xtra::Handler<monitor::StartMonitoring>
             + xtra::Handler<monitor::Sync>
             + xtra::Handler<monitor::CollaborativeSettlement>
             + xtra::Handler<oracle::Attestation>
-            + xtra::Handler<monitor::TryBroadcastTransaction>,
+            + xtra::Handler<monitor::TryBroadcastTransaction>
+            + xtra::Handler<monitor::Rescan>,
     {
         let (maker_online_status_feed_sender, maker_online_status_feed_receiver) =
             watch::channel(ConnectionStatus::Offline { reason: None });
@@ -349,6 +536,7 @@ where
             &monitor_addr,
             &monitor_addr,
             &oracle_addr,
+            metrics.clone(),
         )));
 
         let (connection_actor_addr, connection_actor_ctx) = xtra::Context::new(None);
@@ -360,19 +548,19 @@ where
             process_manager_addr.clone(),
             connection_actor_addr.clone(),
             oracle_addr.clone(),
-            n_payouts,
             maker_identity,
+            setup_timeout,
+            metrics.clone(),
         )
         .create(None)
         .run();
 
         let (auto_rollover_addr, auto_rollover_fut) = auto_rollover::Actor::new(
-            db,
+            db.clone(),
             oracle_pk,
             process_manager_addr,
             connection_actor_addr.clone(),
-            oracle_addr,
-            n_payouts,
+            oracle_addr.clone(),
         )
         .create(None)
         .run();
@@ -391,6 +579,7 @@ where
             identity_sk,
             taker_heartbeat_timeout,
             connect_timeout,
+            tcp_keepalive_interval,
         )));
 
         tasks.add(monitor_ctx.run(monitor_constructor(Box::new(cfd_actor_addr.clone()))?));
@@ -412,28 +601,68 @@ where
         let (price_feed_supervisor, supervisor_fut) = supervisor.create(None).run();
         tasks.add(supervisor_fut);
 
+        let (auto_close_addr, auto_close_fut) =
+            auto_close::Actor::new(db.clone(), &cfd_actor_addr, &price_feed_actor)
+                .create(None)
+                .run();
+        tasks.add(auto_close_fut);
+
         tracing::debug!("Taker actor system ready");
 
         Ok(Self {
             cfd_actor: cfd_actor_addr,
             connection_actor: connection_actor_addr,
             wallet_actor: wallet_actor_addr,
+            oracle_actor: oracle_addr,
+            rescan_channel: Box::new(monitor_addr),
             auto_rollover_actor: auto_rollover_addr,
+            auto_close_actor: auto_close_addr,
             price_feed_actor,
             executor,
+            db,
+            metrics,
             _price_feed_supervisor: price_feed_supervisor,
             _tasks: tasks,
             maker_online_status_feed_receiver,
         })
     }
 
-    pub async fn take_offer(&self, order_id: OrderId, quantity: Usd) -> Result<()> {
+    pub async fn take_offer(
+        &self,
+        order_id: OrderId,
+        quantity: Usd,
+        leverage: Option<Leverage>,
+    ) -> Result<()> {
+        self.cfd_actor
+            .send(taker_cfd::TakeOffer {
+                order_id,
+                quantity,
+                leverage,
+            })
+            .await??;
+        Ok(())
+    }
+
+    /// Withdraw a take request the maker has not accepted yet.
+    pub async fn cancel_order_request(&self, order_id: OrderId) -> Result<()> {
         self.cfd_actor
-            .send(taker_cfd::TakeOffer { order_id, quantity })
+            .send(taker_cfd::CancelOrderRequest { order_id })
             .await??;
         Ok(())
     }
 
+    /// Close the connection to the maker without reconnecting, e.g. when logging out.
+    pub async fn disconnect(&self) -> Result<()> {
+        self.connection_actor.send(connection::Disconnect).await?;
+
+        Ok(())
+    }
+
+    /// The order the maker broadcast most recently, without subscribing to the order feed.
+    pub async fn current_order(&self) -> Result<Option<Order>> {
+        Ok(self.cfd_actor.send(taker_cfd::GetCurrentOrder).await?)
+    }
+
     pub async fn commit(&self, order_id: OrderId) -> Result<()> {
         self.executor
             .execute(order_id, |cfd| cfd.manual_commit_to_blockchain())
@@ -465,6 +694,53 @@ where
             .await?
     }
 
+    /// Propose a collaborative settlement once the profit or loss of the CFD crosses the given
+    /// thresholds.
+    pub async fn set_auto_close_thresholds(
+        &self,
+        order_id: OrderId,
+        thresholds: AutoCloseThresholds,
+    ) -> Result<()> {
+        let is_positive = |percent: Option<Percent>| {
+            percent.map_or(true, |percent| percent.value() > Decimal::ZERO)
+        };
+        anyhow::ensure!(
+            is_positive(thresholds.take_profit) && is_positive(thresholds.stop_loss),
+            "Auto-close thresholds must be positive percentages"
+        );
+
+        let mut conn = self.db.acquire().await?;
+        db::set_auto_close_thresholds(order_id, thresholds, &mut conn).await?;
+
+        Ok(())
+    }
+
+    pub async fn clear_auto_close_thresholds(&self, order_id: OrderId) -> Result<()> {
+        let mut conn = self.db.acquire().await?;
+        db::set_auto_close_thresholds(order_id, AutoCloseThresholds::default(), &mut conn).await?;
+
+        Ok(())
+    }
+
+    pub async fn auto_close_thresholds(&self, order_id: OrderId) -> Result<AutoCloseThresholds> {
+        let mut conn = self.db.acquire().await?;
+        let thresholds = db::load_auto_close_thresholds(&mut conn)
+            .await?
+            .into_iter()
+            .find_map(|(id, thresholds)| (id == order_id).then(|| thresholds))
+            .unwrap_or_default();
+
+        Ok(thresholds)
+    }
+
+    pub async fn rollover(&self, order_id: OrderId) -> Result<()> {
+        self.auto_rollover_actor
+            .send(auto_rollover::Rollover(order_id))
+            .await?;
+
+        Ok(())
+    }
+
     pub async fn withdraw(
         &self,
         amount: Option<Amount>,
@@ -479,4 +755,83 @@ where
             })
             .await?
     }
+
+    pub async fn list_utxos(&self) -> Result<Vec<wallet::Utxo>> {
+        self.wallet_actor.send(wallet::ListUtxos).await?
+    }
+
+    pub async fn new_address(&self) -> Result<bitcoin::Address> {
+        self.wallet_actor.send(wallet::NewAddress).await?
+    }
+
+    pub async fn estimate_closing_fees(&self, order_id: OrderId) -> Result<ClosingFeeEstimate> {
+        let mut conn = self.db.acquire().await?;
+        let cfd = cfd_actors::load_cfd(order_id, &mut conn).await?;
+
+        cfd.estimate_closing_fees()
+    }
+
+    pub async fn commit_tx_fee(&self, order_id: OrderId) -> Result<CommitTxFee> {
+        let mut conn = self.db.acquire().await?;
+        let cfd = cfd_actors::load_cfd(order_id, &mut conn).await?;
+
+        cfd.commit_tx_fee()
+    }
+
+    /// Re-scan the blockchain for the transactions of the CFD and re-fetch its attestation.
+    ///
+    /// Allows to unstick a CFD that missed an update without restarting the daemon.
+    pub async fn resync(&self, order_id: OrderId) -> Result<()> {
+        let event_id = self
+            .rescan_channel
+            .send(monitor::Rescan { id: order_id })
+            .await
+            .context("Monitor actor disconnected")??;
+
+        if let Some(event_id) = event_id {
+            self.oracle_actor
+                .send(oracle::MonitorAttestation { event_id })
+                .await?;
+            self.oracle_actor.send(oracle::Sync).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Check whether the actors and the database backing the taker are responsive.
+    pub async fn health(&self) -> health::Report {
+        let (cfd_actor, connection_actor, price_feed, db) = tokio::join!(
+            health::check_actor(&self.cfd_actor, health::Ping),
+            health::check_actor(&self.connection_actor, health::Ping),
+            health::check_actor(&self.price_feed_actor, bitmex_price_feed::LatestQuote),
+            health::check_db(&self.db),
+        );
+
+        health::Report {
+            cfd_actor,
+            connection_actor,
+            price_feed,
+            db,
+        }
+    }
+
+    /// Watch the cfd actor, raising an alert if it stops responding to liveness checks.
+    ///
+    /// The connection actor is not watched, connecting to the maker blocks it for as long as the
+    /// network timeouts and would be reported as stuck. See [`health::watchdog`].
+    pub fn watchdog(&self) -> impl Future<Output = ()> + Send + 'static {
+        let cfd_actor = self.cfd_actor.clone();
+        let metrics = self.metrics.clone();
+
+        health::watchdog(
+            health::WATCHDOG_INTERVAL,
+            health::WATCHDOG_MAX_FAILURES,
+            move || {
+                let cfd_actor = cfd_actor.clone();
+
+                async move { health::check_actor(&cfd_actor, health::Ping).await }
+            },
+            move || metrics.record_watchdog_alert(),
+        )
+    }
 }
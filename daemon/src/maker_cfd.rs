@@ -2,12 +2,16 @@ use crate::cfd_actors;
 use crate::cfd_actors::insert_cfd_and_update_feed;
 use crate::collab_settlement_maker;
 use crate::command;
+use crate::db;
+use crate::health;
 use crate::maker_inc_connections;
+use crate::metrics::Metrics;
 use crate::model::cfd::Cfd;
 use crate::model::cfd::CollaborativeSettlementCompleted;
 use crate::model::cfd::Order;
 use crate::model::cfd::OrderId;
 use crate::model::cfd::Origin;
+use crate::model::cfd::RejectReason;
 use crate::model::cfd::Role;
 use crate::model::cfd::RolloverCompleted;
 use crate::model::cfd::RolloverProposal;
@@ -15,9 +19,11 @@ use crate::model::cfd::SettlementProposal;
 use crate::model::cfd::SetupCompleted;
 use crate::model::FundingRate;
 use crate::model::Identity;
+use crate::model::Leverage;
 use crate::model::OpeningFee;
 use crate::model::Position;
 use crate::model::Price;
+use crate::model::RoundingStrategy;
 use crate::model::TxFeeRate;
 use crate::model::Usd;
 use crate::monitor;
@@ -37,6 +43,8 @@ use anyhow::Context;
 use anyhow::Result;
 use async_trait::async_trait;
 use bdk::bitcoin::secp256k1::schnorrsig;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
 use std::collections::HashSet;
 use time::Duration;
 use xtra::prelude::*;
@@ -64,6 +72,9 @@ pub struct AcceptRollover {
 pub struct RejectRollover {
     pub order_id: OrderId,
 }
+pub struct UpdateOrderPrice {
+    pub new_price: Price,
+}
 pub struct NewOrder {
     pub price: Price,
     pub min_quantity: Usd,
@@ -71,6 +82,8 @@ pub struct NewOrder {
     pub tx_fee_rate: TxFeeRate,
     pub funding_rate: FundingRate,
     pub opening_fee: OpeningFee,
+    /// Overrides the number of payouts the maker was started with.
+    pub n_payouts: Option<usize>,
 }
 
 pub struct TakerConnected {
@@ -86,6 +99,44 @@ pub struct FromTaker {
     pub msg: wire::TakerToMaker,
 }
 
+/// A module-private message to continue handling a take once the oracle announcement was fetched.
+struct AnnouncementFetched {
+    taker_id: Identity,
+    order: Order,
+    quantity: Usd,
+    leverage: Leverage,
+    announcement: Result<oracle::Announcement>,
+}
+
+/// A message for a setup actor that arrived while the oracle announcement for the take was still
+/// being fetched, i.e. before the setup actor was started.
+enum PendingSetupMsg {
+    Accepted,
+    Rejected(RejectReason),
+    Cancelled(Identity),
+}
+
+/// Limits within which takes are accepted without waiting for an [`AcceptOrder`] from the
+/// operator.
+#[derive(Debug, Clone, Copy)]
+pub struct AutoAcceptTakes {
+    /// Largest quantity of a single take.
+    pub max_quantity: Usd,
+    /// Highest leverage of a single take.
+    pub max_leverage: Leverage,
+    /// Upper bound for the total quantity of all open CFDs, including the ones whose take is still
+    /// pending.
+    pub max_exposure: Usd,
+}
+
+impl AutoAcceptTakes {
+    fn permits(&self, quantity: Usd, leverage: Leverage, exposure: Usd) -> bool {
+        quantity <= self.max_quantity
+            && leverage.get() <= self.max_leverage.get()
+            && exposure + quantity <= self.max_exposure
+    }
+}
+
 pub struct Actor<O, T, W> {
     db: sqlx::SqlitePool,
     wallet: Address<W>,
@@ -98,11 +149,25 @@ pub struct Actor<O, T, W> {
     takers: Address<T>,
     current_order: Option<Order>,
     setup_actors: AddressMap<OrderId, setup_maker::Actor>,
+    /// Takes whose setup actor is started once the oracle announcement was fetched.
+    ///
+    /// Reserves the order id for the take and buffers the messages for the setup actor in the
+    /// meantime.
+    takes_awaiting_announcement: HashMap<OrderId, Vec<PendingSetupMsg>>,
     settlement_actors: AddressMap<OrderId, collab_settlement_maker::Actor>,
     oracle: Address<O>,
     connected_takers: HashSet<Identity>,
     n_payouts: usize,
+    rounding: RoundingStrategy,
+    max_leverage: Leverage,
+    cet_timelock: u32,
+    auto_accept_takes: Option<AutoAcceptTakes>,
+    /// Set if takes funded with unconfirmed inputs are to be rejected during contract setup.
+    require_confirmed_inputs: Option<Box<dyn MessageChannel<monitor::GetUnconfirmedInputs>>>,
+    settlement_proposal_validity: Duration,
+    setup_timeout: std::time::Duration,
     tasks: Tasks,
+    metrics: Metrics,
 }
 
 impl<O, T, W> Actor<O, T, W> {
@@ -117,6 +182,14 @@ impl<O, T, W> Actor<O, T, W> {
         takers: Address<T>,
         oracle: Address<O>,
         n_payouts: usize,
+        rounding: RoundingStrategy,
+        max_leverage: Leverage,
+        cet_timelock: u32,
+        auto_accept_takes: Option<AutoAcceptTakes>,
+        require_confirmed_inputs: Option<Box<dyn MessageChannel<monitor::GetUnconfirmedInputs>>>,
+        settlement_proposal_validity: Duration,
+        setup_timeout: std::time::Duration,
+        metrics: Metrics,
     ) -> Self {
         Self {
             db: db.clone(),
@@ -130,11 +203,20 @@ impl<O, T, W> Actor<O, T, W> {
             takers,
             current_order: None,
             setup_actors: AddressMap::default(),
+            takes_awaiting_announcement: HashMap::default(),
             oracle,
             n_payouts,
+            rounding,
+            max_leverage,
+            cet_timelock,
+            auto_accept_takes,
+            require_confirmed_inputs,
+            settlement_proposal_validity,
+            setup_timeout,
             connected_takers: HashSet::new(),
             settlement_actors: AddressMap::default(),
             tasks: Tasks::default(),
+            metrics,
         }
     }
 
@@ -149,6 +231,40 @@ impl<O, T, W> Actor<O, T, W> {
             .await?;
         Ok(())
     }
+
+    /// Total quantity of all CFDs that are open or whose take is still in progress.
+    async fn open_exposure(&self) -> Result<Usd> {
+        let mut conn = self.db.acquire().await?;
+
+        let mut exposure = Usd::new(Decimal::ZERO);
+        for id in db::load_all_cfd_ids(&mut conn).await? {
+            let cfd = cfd_actors::load_cfd(id, &mut conn).await?;
+
+            if cfd.is_exposed() || self.is_take_in_progress(&id) {
+                exposure = exposure + cfd.quantity();
+            }
+        }
+
+        Ok(exposure)
+    }
+
+    fn is_take_in_progress(&self, order_id: &OrderId) -> bool {
+        self.setup_actors.get_connected(order_id).is_some()
+            || self.takes_awaiting_announcement.contains_key(order_id)
+    }
+
+    /// Buffers `msg` if the setup actor for `order_id` is not started yet.
+    ///
+    /// Returns `false` if there is no take awaiting its announcement for this order.
+    fn buffer_setup_msg(&mut self, order_id: &OrderId, msg: PendingSetupMsg) -> bool {
+        match self.takes_awaiting_announcement.get_mut(order_id) {
+            Some(pending) => {
+                pending.push(msg);
+                true
+            }
+            None => false,
+        }
+    }
 }
 
 impl<O, T, W> Actor<O, T, W>
@@ -156,6 +272,17 @@ where
     T: xtra::Handler<maker_inc_connections::TakerMessage>,
 {
     async fn handle_taker_connected(&mut self, taker_id: Identity) -> Result<()> {
+        tracing::info!(taker_id = %taker_id.short(), "Taker connected");
+
+        // The taker requests the current order once connected, see `RequestCurrentOrder`
+        if !self.connected_takers.insert(taker_id) {
+            tracing::warn!("Taker already connected: {}", taker_id.short());
+        }
+        self.update_connected_takers().await?;
+        Ok(())
+    }
+
+    async fn send_current_order(&mut self, taker_id: Identity) -> Result<()> {
         self.takers
             .send_async_safe(maker_inc_connections::TakerMessage {
                 taker_id,
@@ -163,16 +290,39 @@ where
             })
             .await?;
 
-        if !self.connected_takers.insert(taker_id) {
-            tracing::warn!("Taker already connected: {:?}", &taker_id);
-        }
-        self.update_connected_takers().await?;
+        Ok(())
+    }
+
+    /// Reject a take we cannot set up a contract for, without involving the operator.
+    async fn reject_take(
+        &mut self,
+        taker_id: Identity,
+        order_id: OrderId,
+        reason: RejectReason,
+    ) -> Result<()> {
+        tracing::info!(%order_id, %reason, "Rejecting take");
+
+        self.executor
+            .execute(order_id, |cfd| {
+                cfd.setup_contract(SetupCompleted::rejected_due_to(order_id, reason.into()))
+            })
+            .await?;
+
+        self.takers
+            .send_async_safe(maker_inc_connections::TakerMessage {
+                taker_id,
+                msg: wire::MakerToTaker::RejectOrder { order_id, reason },
+            })
+            .await?;
+
         Ok(())
     }
 
     async fn handle_taker_disconnected(&mut self, taker_id: Identity) -> Result<()> {
+        tracing::info!(taker_id = %taker_id.short(), "Taker disconnected");
+
         if !self.connected_takers.remove(&taker_id) {
-            tracing::warn!("Removed unknown taker: {:?}", &taker_id);
+            tracing::warn!("Removed unknown taker: {}", taker_id.short());
         }
         self.update_connected_takers().await?;
         Ok(())
@@ -199,7 +349,6 @@ where
 
         let (rollover_actor_addr, rollover_actor_future) = rollover_maker::Actor::new(
             order_id,
-            self.n_payouts,
             &self.takers,
             taker_id,
             self.oracle_pk,
@@ -227,23 +376,33 @@ where
         + xtra::Handler<maker_inc_connections::TakerMessage>
         + xtra::Handler<maker_inc_connections::BroadcastOrder>
         + xtra::Handler<Stopping<setup_maker::Actor>>,
-    W: xtra::Handler<wallet::Sign> + xtra::Handler<wallet::BuildPartyParams>,
+    W: xtra::Handler<wallet::Sign>
+        + xtra::Handler<wallet::BuildPartyParams>
+        + xtra::Handler<wallet::CheckFunds>,
 {
     async fn handle_take_order(
         &mut self,
         taker_id: Identity,
         order_id: OrderId,
         quantity: Usd,
+        leverage: Leverage,
         ctx: &mut xtra::Context<Self>,
     ) -> Result<()> {
-        tracing::debug!(%taker_id, %quantity, %order_id, "Taker wants to take an order");
+        tracing::debug!(%taker_id, %quantity, %leverage, %order_id, "Taker wants to take an order");
+        self.metrics.record_take();
 
-        let disconnected = self
-            .setup_actors
-            .get_disconnected(order_id)
-            .with_context(|| {
-                format!("Contract setup for order {order_id} is already in progress")
-            })?;
+        if self.is_take_in_progress(&order_id) {
+            tracing::warn!(%taker_id, %order_id, "Contract setup is already in progress");
+
+            self.takers
+                .send(maker_inc_connections::TakerMessage {
+                    taker_id,
+                    msg: wire::MakerToTaker::TakeOrderAlreadyInProgress(order_id),
+                })
+                .await??;
+
+            return Ok(());
+        }
 
         let mut conn = self.db.acquire().await?;
 
@@ -268,13 +427,16 @@ where
             }
         };
 
-        let cfd = Cfd::from_order(
-            current_order.clone(),
-            Position::Short,
-            quantity,
-            taker_id,
-            Role::Maker,
-        );
+        // We never take an order with a leverage above our cap. A take exceeding it is recorded
+        // with the leverage we offered and rejected below.
+        let exceeds_leverage_cap = leverage > current_order.max_leverage;
+        let order = if exceeds_leverage_cap {
+            current_order.clone()
+        } else {
+            current_order.clone().with_leverage(leverage)
+        };
+
+        let cfd = Cfd::from_order(order, Position::Short, quantity, taker_id, Role::Maker);
 
         // 2. Remove current order
         // The order is removed before we update the state, because the maker might react on the
@@ -291,12 +453,94 @@ where
             .await?;
         insert_cfd_and_update_feed(&cfd, &mut conn, &self.projection).await?;
 
-        // 4. Try to get the oracle announcement, if that fails we should exit prior to changing any
-        // state
-        let announcement = self
-            .oracle
-            .send(oracle::GetAnnouncement(current_order.oracle_event_id))
-            .await??;
+        // 3. Enforce our leverage cap
+        if exceeds_leverage_cap {
+            let max_leverage = current_order.max_leverage;
+            tracing::info!(%order_id, %leverage, %max_leverage, "Leverage exceeds our maximum");
+
+            self.reject_take(taker_id, order_id, RejectReason::LeverageTooHigh)
+                .await?;
+
+            return Ok(());
+        }
+
+        // 4. Fetch the oracle announcement in the background, retrying it must not block the
+        // actor. The take is continued in `handle_announcement_fetched`, until then the order id
+        // stays reserved for this take.
+        self.takes_awaiting_announcement
+            .insert(order_id, Vec::new());
+
+        let this = ctx
+            .address()
+            .expect("actor to be able to give address to itself");
+        let oracle = self.oracle.clone();
+
+        self.tasks.add(async move {
+            let announcement =
+                oracle::get_announcement(&oracle, current_order.oracle_event_id).await;
+
+            let result = this
+                .send(AnnouncementFetched {
+                    taker_id,
+                    order: current_order,
+                    quantity,
+                    leverage,
+                    announcement,
+                })
+                .await;
+
+            match result {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => tracing::error!("Error when handling order take request: {e:#}"),
+                Err(_) => tracing::warn!(%order_id, "Cfd actor stopped before take was handled"),
+            }
+        });
+
+        Ok(())
+    }
+}
+
+#[xtra_productivity]
+impl<O, T, W> Actor<O, T, W>
+where
+    O: xtra::Handler<oracle::GetAnnouncement> + xtra::Handler<oracle::MonitorAttestation>,
+    T: xtra::Handler<maker_inc_connections::ConfirmOrder>
+        + xtra::Handler<maker_inc_connections::TakerMessage>
+        + xtra::Handler<maker_inc_connections::BroadcastOrder>
+        + xtra::Handler<Stopping<setup_maker::Actor>>,
+    W: xtra::Handler<wallet::Sign>
+        + xtra::Handler<wallet::BuildPartyParams>
+        + xtra::Handler<wallet::CheckFunds>,
+{
+    async fn handle_announcement_fetched(
+        &mut self,
+        msg: AnnouncementFetched,
+        ctx: &mut xtra::Context<Self>,
+    ) -> Result<()> {
+        let AnnouncementFetched {
+            taker_id,
+            order,
+            quantity,
+            leverage,
+            announcement,
+        } = msg;
+        let order_id = order.id;
+        let pending_msgs = self
+            .takes_awaiting_announcement
+            .remove(&order_id)
+            .unwrap_or_default();
+
+        // If we cannot get the announcement we cannot set up the contract, reject the take right
+        // away instead of leaving the taker waiting
+        let announcement = match announcement {
+            Ok(announcement) => announcement,
+            Err(e) => {
+                self.reject_take(taker_id, order_id, RejectReason::MakerUnavailable)
+                    .await?;
+
+                return Err(e.context("Rejected take because announcement is unavailable"));
+            }
+        };
 
         // 5. Start up contract setup actor
         let this = ctx
@@ -306,17 +550,62 @@ where
         let (addr, fut) = setup_maker::Actor::new(
             self.db.clone(),
             self.process_manager.clone(),
-            (current_order, cfd.quantity(), self.n_payouts),
+            (order, quantity),
             (self.oracle_pk, announcement),
             &self.wallet,
             &self.wallet,
+            &self.wallet,
+            &self.projection,
+            self.require_confirmed_inputs
+                .as_ref()
+                .map(|channel| channel.clone_channel()),
             (&self.takers, &self.takers, taker_id),
+            self.setup_timeout,
             (&self.takers, &this),
         )
         .create(None)
         .run();
 
-        disconnected.insert(addr);
+        // 6. Deliver what arrived for the setup actor while the announcement was fetched
+        let decided = pending_msgs.iter().any(|msg| {
+            matches!(
+                msg,
+                PendingSetupMsg::Accepted | PendingSetupMsg::Rejected(_)
+            )
+        });
+        for msg in pending_msgs {
+            match msg {
+                PendingSetupMsg::Accepted => {
+                    addr.send_async_safe(setup_maker::Accepted).await?;
+                }
+                PendingSetupMsg::Rejected(reason) => {
+                    addr.send_async_safe(setup_maker::Rejected(reason)).await?;
+                }
+                PendingSetupMsg::Cancelled(taker_id) => {
+                    addr.send_async_safe(setup_maker::Cancelled { taker_id })
+                        .await?;
+                }
+            }
+        }
+
+        // 7. Skip waiting for the operator if the take is within the auto-accept limits
+        if let (Some(policy), false) = (self.auto_accept_takes, decided) {
+            let exposure = self.open_exposure().await?;
+
+            if policy.permits(quantity, leverage, exposure) {
+                tracing::info!(%order_id, %quantity, %exposure, "Automatically accepting take");
+
+                addr.send_async_safe(setup_maker::Accepted).await?;
+            } else {
+                tracing::info!(
+                    %order_id,
+                    %quantity,
+                    "Take exceeds auto-accept limits, waiting for manual decision"
+                );
+            }
+        }
+
+        self.setup_actors.insert(order_id, addr);
 
         self.tasks.add(fut);
 
@@ -331,6 +620,10 @@ impl<O, T, W> Actor<O, T, W> {
 
         tracing::debug!(%order_id, "Maker accepts order");
 
+        if self.buffer_setup_msg(&order_id, PendingSetupMsg::Accepted) {
+            return Ok(());
+        }
+
         if let Err(error) = self
             .setup_actors
             .send(&order_id, setup_maker::Accepted)
@@ -356,9 +649,16 @@ impl<O, T, W> Actor<O, T, W> {
 
         tracing::debug!(%order_id, "Maker rejects order");
 
+        if self.buffer_setup_msg(
+            &order_id,
+            PendingSetupMsg::Rejected(RejectReason::ManualReject),
+        ) {
+            return Ok(());
+        }
+
         if let Err(error) = self
             .setup_actors
-            .send(&order_id, setup_maker::Rejected)
+            .send(&order_id, setup_maker::Rejected(RejectReason::ManualReject))
             .await
         {
             self.executor
@@ -379,21 +679,24 @@ impl<O, T, W> Actor<O, T, W> {
     async fn handle_accept_settlement(&mut self, msg: AcceptSettlement) -> Result<()> {
         let AcceptSettlement { order_id } = msg;
 
-        if let Err(error) = self
+        match self
             .settlement_actors
-            .send(&order_id, collab_settlement_maker::Accepted)
+            .send_fallible(&order_id, collab_settlement_maker::Accepted)
             .await
         {
-            self.executor
-                .execute(order_id, |cfd| {
-                    cfd.settle_collaboratively(CollaborativeSettlementCompleted::Failed {
-                        order_id,
-                        error: anyhow!(error),
+            Ok(result) => result?,
+            Err(error) => {
+                self.executor
+                    .execute(order_id, |cfd| {
+                        cfd.settle_collaboratively(CollaborativeSettlementCompleted::Failed {
+                            order_id,
+                            error: anyhow!(error),
+                        })
                     })
-                })
-                .await?;
+                    .await?;
 
-            bail!("Accept failed: No settlement in progress for order {order_id}")
+                bail!("Accept failed: No settlement in progress for order {order_id}")
+            }
         }
 
         Ok(())
@@ -512,6 +815,8 @@ impl<O, T, W> Actor<O, T, W> {
     async fn handle_rollover_actor_stopping(&mut self, msg: Stopping<rollover_maker::Actor>) {
         self.rollover_actors.gc(msg);
     }
+
+    fn handle_ping(&mut self, _: health::Ping) {}
 }
 
 impl<O, T, W> Actor<O, T, W>
@@ -542,7 +847,7 @@ where
             self.process_manager.clone(),
             (&self.takers, &this),
             self.db.clone(),
-            self.n_payouts,
+            self.settlement_proposal_validity,
         )
         .create(None)
         .run();
@@ -567,6 +872,7 @@ where
             tx_fee_rate,
             funding_rate,
             opening_fee,
+            n_payouts,
         } = msg;
 
         let oracle_event_id = oracle::next_announcement_after(
@@ -579,12 +885,38 @@ where
             max_quantity,
             Origin::Ours,
             oracle_event_id,
+            self.oracle_pk,
             self.settlement_interval,
             tx_fee_rate,
             funding_rate,
             opening_fee,
+            n_payouts.unwrap_or(self.n_payouts),
+            self.rounding,
+            self.max_leverage,
+            self.cet_timelock,
         )?;
 
+        self.publish_order(order).await
+    }
+
+    async fn handle_update_order_price(&mut self, msg: UpdateOrderPrice) -> Result<()> {
+        let order = self
+            .current_order
+            .clone()
+            .context("Cannot update price without current order")?;
+
+        // Takes against the previous order id are rejected as outdated from here on
+        let order = order.with_price(msg.new_price);
+
+        self.publish_order(order).await
+    }
+}
+
+impl<O, T, W> Actor<O, T, W>
+where
+    T: xtra::Handler<maker_inc_connections::BroadcastOrder>,
+{
+    async fn publish_order(&mut self, order: Order) -> Result<()> {
         // 1. Update actor state to current order
         self.current_order.replace(order.clone());
 
@@ -638,7 +970,9 @@ where
         + xtra::Handler<maker_inc_connections::settlement::Response>
         + xtra::Handler<Stopping<collab_settlement_maker::Actor>>
         + xtra::Handler<maker_inc_connections::RegisterRollover>,
-    W: xtra::Handler<wallet::Sign> + xtra::Handler<wallet::BuildPartyParams>,
+    W: xtra::Handler<wallet::Sign>
+        + xtra::Handler<wallet::BuildPartyParams>
+        + xtra::Handler<wallet::CheckFunds>,
 {
     async fn handle(
         &mut self,
@@ -646,14 +980,37 @@ where
         ctx: &mut xtra::Context<Self>,
     ) {
         match msg {
-            wire::TakerToMaker::TakeOrder { order_id, quantity } => {
+            wire::TakerToMaker::RequestCurrentOrder => {
+                if let Err(e) = self.send_current_order(taker_id).await {
+                    tracing::warn!(%taker_id, "Failed to send current order: {:#}", e);
+                }
+            }
+            wire::TakerToMaker::TakeOrder {
+                order_id,
+                quantity,
+                leverage,
+            } => {
                 if let Err(e) = self
-                    .handle_take_order(taker_id, order_id, quantity, ctx)
+                    .handle_take_order(taker_id, order_id, quantity, leverage, ctx)
                     .await
                 {
                     tracing::error!("Error when handling order take request: {:#}", e)
                 }
             }
+            wire::TakerToMaker::CancelOrderRequest { order_id } => {
+                if self.buffer_setup_msg(&order_id, PendingSetupMsg::Cancelled(taker_id)) {
+                    return;
+                }
+
+                if self
+                    .setup_actors
+                    .send(&order_id, setup_maker::Cancelled { taker_id })
+                    .await
+                    .is_err()
+                {
+                    tracing::warn!(%order_id, "No pending order request to cancel");
+                }
+            }
             wire::TakerToMaker::Settlement {
                 order_id,
                 msg:
@@ -711,8 +1068,12 @@ where
             wire::TakerToMaker::Protocol { .. } => {
                 unreachable!("This kind of message should be sent to the `setup_maker::Actor`")
             }
-            TakerToMaker::Hello(_) => {
-                unreachable!("The Hello message is not sent to the cfd actor")
+            TakerToMaker::Hello(_)
+            | TakerToMaker::SupportedCompression(_)
+            | TakerToMaker::Unknown => {
+                // Handled by `maker_inc_connections::Actor`, a message like this reaching us
+                // is a bug there but no reason to crash
+                tracing::warn!(%taker_id, "Dropping unexpected message from taker: {msg}");
             }
         }
     }
@@ -1,5 +1,6 @@
 use crate::collab_settlement_maker;
 use crate::future_ext::FutureExt;
+use crate::health;
 use crate::maker_cfd;
 use crate::maker_cfd::FromTaker;
 use crate::maker_cfd::TakerConnected;
@@ -30,6 +31,7 @@ use std::net::SocketAddr;
 use std::time::Duration;
 use tokio::net::TcpListener;
 use tokio::net::TcpStream;
+use tokio::sync::mpsc;
 use tokio_util::codec::Framed;
 use xtra::prelude::*;
 use xtra_productivity::xtra_productivity;
@@ -38,6 +40,9 @@ use xtras::AddressMap;
 use xtras::SendAsyncSafe;
 use xtras::SendInterval;
 
+/// Maximum number of messages from takers that are queued up for the `maker_cfd::Actor`.
+const TAKER_MSG_QUEUE_CAPACITY: usize = 256;
+
 pub struct BroadcastOrder(pub Option<Order>);
 
 /// Message sent from the `setup_maker::Actor` to the
@@ -94,9 +99,10 @@ pub struct Actor {
     connections: HashMap<Identity, Connection>,
     taker_connected_channel: Box<dyn MessageChannel<TakerConnected>>,
     taker_disconnected_channel: Box<dyn MessageChannel<TakerDisconnected>>,
-    taker_msg_channel: Box<dyn MessageChannel<FromTaker>>,
+    taker_msg_queue: BoundedQueue<FromTaker>,
     noise_priv_key: x25519_dalek::StaticSecret,
     heartbeat_interval: Duration,
+    tcp_keepalive_interval: Duration,
     p2p_socket: SocketAddr,
     setup_actors: AddressMap<OrderId, setup_maker::Actor>,
     settlement_actors: AddressMap<OrderId, collab_settlement_maker::Actor>,
@@ -108,6 +114,7 @@ pub struct Actor {
 struct Connection {
     taker: Identity,
     write: wire::Write<wire::TakerToMaker, wire::MakerToTaker>,
+    compression: wire::CompressionSwitch,
     _tasks: Tasks,
 }
 
@@ -127,6 +134,51 @@ impl Connection {
     }
 }
 
+/// A bounded queue that forwards messages to a [`MessageChannel`] one at a time.
+///
+/// The next message is only forwarded once the previous one was handled. A slow receiver thus
+/// fills up the queue instead of growing its (unbounded) mailbox.
+struct BoundedQueue<M> {
+    sender: mpsc::Sender<M>,
+    capacity: usize,
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("Queue is full")]
+struct QueueFull;
+
+impl<M> BoundedQueue<M>
+where
+    M: Message<Result = ()>,
+{
+    fn new(channel: Box<dyn MessageChannel<M>>, capacity: usize, tasks: &mut Tasks) -> Self {
+        let (sender, mut receiver) = mpsc::channel(capacity);
+
+        tasks.add(async move {
+            while let Some(msg) = receiver.recv().await {
+                if channel.send(msg).await.is_err() {
+                    tracing::warn!("Receiver of queued messages is disconnected");
+                }
+            }
+        });
+
+        Self { sender, capacity }
+    }
+
+    /// Append `msg` to the queue without waiting for a free slot.
+    ///
+    /// Never blocks the caller, which is important because the caller is an actor that must not
+    /// be stalled by a slow receiver.
+    fn try_push(&self, msg: M) -> Result<(), QueueFull> {
+        self.sender.try_send(msg).map_err(|_| QueueFull)
+    }
+
+    /// The number of messages currently waiting in the queue.
+    fn depth(&self) -> usize {
+        self.capacity - self.sender.capacity()
+    }
+}
+
 impl Drop for Connection {
     fn drop(&mut self) {
         let taker_id = self.taker;
@@ -142,20 +194,26 @@ impl Actor {
         taker_msg_channel: Box<dyn MessageChannel<FromTaker>>,
         noise_priv_key: x25519_dalek::StaticSecret,
         heartbeat_interval: Duration,
+        tcp_keepalive_interval: Duration,
         p2p_socket: SocketAddr,
     ) -> Self {
+        let mut tasks = Tasks::default();
+        let taker_msg_queue =
+            BoundedQueue::new(taker_msg_channel, TAKER_MSG_QUEUE_CAPACITY, &mut tasks);
+
         Self {
             connections: HashMap::new(),
             taker_connected_channel: taker_connected_channel.clone_channel(),
             taker_disconnected_channel: taker_disconnected_channel.clone_channel(),
-            taker_msg_channel: taker_msg_channel.clone_channel(),
+            taker_msg_queue,
             noise_priv_key,
             heartbeat_interval,
+            tcp_keepalive_interval,
             p2p_socket,
             setup_actors: AddressMap::default(),
             settlement_actors: AddressMap::default(),
             rollover_actors: AddressMap::default(),
-            tasks: Tasks::default(),
+            tasks,
         }
     }
 
@@ -208,6 +266,7 @@ impl Actor {
         tracing::info!("Listening on {local_address}");
 
         let noise_priv_key = self.noise_priv_key.clone();
+        let tcp_keepalive_interval = self.tcp_keepalive_interval;
 
         self.tasks.add(async move {
             let mut tasks = Tasks::default();
@@ -220,7 +279,12 @@ impl Actor {
 
                 match new_connection {
                     Ok((stream, address)) => {
-                        let upgrade = upgrade(stream, noise_priv_key.clone(), this.clone());
+                        let upgrade = upgrade(
+                            stream,
+                            noise_priv_key.clone(),
+                            tcp_keepalive_interval,
+                            this.clone(),
+                        );
 
                         tasks
                             .add_fallible(
@@ -339,6 +403,7 @@ impl Actor {
         let ConnectionReady {
             mut read,
             write,
+            compression,
             identity,
         } = msg;
         let this = ctx.address().expect("we are alive");
@@ -383,6 +448,7 @@ impl Actor {
             Connection {
                 taker: identity,
                 write,
+                compression,
                 _tasks: tasks,
             },
         );
@@ -440,8 +506,39 @@ impl Actor {
                     tracing::warn!(%order_id, "No active settlement");
                 }
             }
+            SupportedCompression(algorithms) => {
+                let taker_id = msg.taker_id;
+
+                if let Some(conn) = self.connections.get(&taker_id) {
+                    if conn.compression.enable_if_supported(&algorithms) {
+                        tracing::debug!(%taker_id, "Compressing large messages to taker");
+                    }
+                }
+
+                let supported = MakerToTaker::SupportedCompression(wire::Compression::supported());
+                if let Err(e) = self.send_to_taker(&taker_id, supported).await {
+                    tracing::warn!("Failed to reply to compression announcement: {e}");
+                }
+            }
+            Unknown => {
+                tracing::debug!(taker_id = %msg.taker_id, "Ignoring message of unknown type");
+            }
             _ => {
-                let _ = self.taker_msg_channel.send(msg);
+                let taker_id = msg.taker_id;
+
+                // Waiting for a free slot would stall the messages of all other takers and
+                // deadlock if the `maker_cfd::Actor` is waiting on us. Instead, we disconnect the
+                // taker whose message overflowed the queue, it will reconnect once we caught up.
+                if self.taker_msg_queue.try_push(msg).is_err() {
+                    let queue_depth = self.taker_msg_queue.depth();
+                    tracing::warn!(
+                        %taker_id,
+                        %queue_depth,
+                        "Dropping {msg_str} and disconnecting taker because we are overloaded"
+                    );
+
+                    self.drop_taker_connection(&taker_id).await;
+                }
             }
         }
     }
@@ -460,6 +557,8 @@ impl Actor {
     ) {
         self.settlement_actors.gc(message);
     }
+
+    fn handle_ping(&mut self, _: health::Ping) {}
 }
 
 /// Upgrades a TCP stream to an encrypted transport, checking the network version in the process.
@@ -468,9 +567,11 @@ impl Actor {
 async fn upgrade(
     mut stream: TcpStream,
     noise_priv_key: x25519_dalek::StaticSecret,
+    tcp_keepalive_interval: Duration,
     this: xtra::Address<Actor>,
 ) -> Result<()> {
     let taker_address = stream.peer_addr().context("Failed to get peer address")?;
+    wire::enable_tcp_keepalive(&stream, tcp_keepalive_interval)?;
 
     tracing::info!(%taker_address, "Upgrade new connection");
 
@@ -480,8 +581,9 @@ async fn upgrade(
         .context("Failed to complete noise handshake within 20 seconds")??;
     let taker_id = Identity::new(transport_state.get_remote_public_key()?);
 
-    let (mut write, mut read) =
-        Framed::new(stream, EncryptedJsonCodec::new(transport_state)).split();
+    let framed = Framed::new(stream, EncryptedJsonCodec::new(transport_state));
+    let compression = framed.codec().compression_switch();
+    let (mut write, mut read) = framed.split();
 
     let first_message = read
         .try_next()
@@ -496,7 +598,7 @@ async fn upgrade(
             let our_version = Version::current();
             write.send(MakerToTaker::Hello(our_version.clone())).await?;
 
-            if our_version != taker_version {
+            if !our_version.is_compatible_with(&taker_version) {
                 bail!(
                     "Network version mismatch, we are on version {our_version} but taker is on version {taker_version}",
                 );
@@ -513,6 +615,7 @@ async fn upgrade(
         .send(ConnectionReady {
             read,
             write,
+            compression,
             identity: taker_id,
         })
         .await;
@@ -523,6 +626,7 @@ async fn upgrade(
 struct ConnectionReady {
     read: wire::Read<wire::TakerToMaker, wire::MakerToTaker>,
     write: wire::Write<wire::TakerToMaker, wire::MakerToTaker>,
+    compression: wire::CompressionSwitch,
     identity: Identity,
 }
 
@@ -538,3 +642,50 @@ impl xtra::Actor for Actor {
         self.start_listener(ctx).await;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xtra::Actor as _;
+
+    #[tokio::test]
+    async fn flooding_the_queue_caps_its_depth_and_drops_excess_messages() {
+        let (addr, fut) = StalledActor.create(None).run();
+        tokio::spawn(fut);
+
+        let capacity = 10;
+        let mut tasks = Tasks::default();
+        let queue = BoundedQueue::new(Box::new(addr), capacity, &mut tasks);
+
+        let mut dropped = 0;
+        for _ in 0..(capacity * 10) {
+            if queue.try_push(Flood).is_err() {
+                dropped += 1;
+            }
+
+            assert!(queue.depth() <= capacity);
+        }
+
+        assert_eq!(queue.depth(), capacity);
+        // At most one message is in-flight to the actor, the rest has to be dropped.
+        assert!(dropped >= capacity * 10 - capacity - 1);
+    }
+
+    struct Flood;
+
+    impl xtra::Message for Flood {
+        type Result = ();
+    }
+
+    /// An actor that never finishes handling a message.
+    struct StalledActor;
+
+    impl xtra::Actor for StalledActor {}
+
+    #[xtra_productivity(message_impl = false)]
+    impl StalledActor {
+        async fn handle_flood(&mut self, _: Flood) {
+            futures::future::pending::<()>().await;
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use crate::model::cfd::CfdEvent;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+/// Counters of CFD lifecycle events.
+///
+/// Cloning is cheap and all clones share the same counters, which allows a registry to be passed
+/// into the actor system and read from anywhere else.
+#[derive(Clone, Default)]
+pub struct Metrics(Arc<Counters>);
+
+#[derive(Default)]
+struct Counters {
+    takes: AtomicU64,
+    accepts: AtomicU64,
+    rejects: AtomicU64,
+    setups_failed: AtomicU64,
+    settlements: AtomicU64,
+    rollovers: AtomicU64,
+    watchdog_alerts: AtomicU64,
+}
+
+/// The values of all counters at a point in time.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Snapshot {
+    pub takes: u64,
+    pub accepts: u64,
+    pub rejects: u64,
+    pub setups_failed: u64,
+    pub settlements: u64,
+    pub rollovers: u64,
+    pub watchdog_alerts: u64,
+}
+
+impl Metrics {
+    /// Record an order being taken, by us as the taker or by a taker of ours as the maker.
+    pub fn record_take(&self) {
+        self.0.takes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record the state transition of a CFD.
+    pub fn record_event(&self, event: &CfdEvent) {
+        let counter = match event {
+            CfdEvent::ContractSetupStarted => &self.0.accepts,
+            CfdEvent::OfferRejected(_) => &self.0.rejects,
+            CfdEvent::ContractSetupFailed => &self.0.setups_failed,
+            CfdEvent::CollaborativeSettlementCompleted { .. } => &self.0.settlements,
+            CfdEvent::RolloverCompleted { .. } => &self.0.rollovers,
+            _ => return,
+        };
+
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record the watchdog finding the core actors unresponsive.
+    pub fn record_watchdog_alert(&self) {
+        self.0.watchdog_alerts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            takes: self.0.takes.load(Ordering::Relaxed),
+            accepts: self.0.accepts.load(Ordering::Relaxed),
+            rejects: self.0.rejects.load(Ordering::Relaxed),
+            setups_failed: self.0.setups_failed.load(Ordering::Relaxed),
+            settlements: self.0.settlements.load(Ordering::Relaxed),
+            rollovers: self.0.rollovers.load(Ordering::Relaxed),
+            watchdog_alerts: self.0.watchdog_alerts.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Render all counters in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let Snapshot {
+            takes,
+            accepts,
+            rejects,
+            setups_failed,
+            settlements,
+            rollovers,
+            watchdog_alerts,
+        } = self.snapshot();
+
+        [
+            ("itchysats_takes_total", "Orders taken", takes),
+            ("itchysats_accepts_total", "Takes accepted", accepts),
+            ("itchysats_rejects_total", "Takes rejected", rejects),
+            (
+                "itchysats_setups_failed_total",
+                "Contract setups failed",
+                setups_failed,
+            ),
+            (
+                "itchysats_settlements_total",
+                "Collaborative settlements completed",
+                settlements,
+            ),
+            (
+                "itchysats_rollovers_total",
+                "Rollovers completed",
+                rollovers,
+            ),
+            (
+                "itchysats_watchdog_alerts_total",
+                "Times the core actors stopped responding to liveness checks",
+                watchdog_alerts,
+            ),
+        ]
+        .iter()
+        .map(|(name, help, value)| {
+            format!("# HELP {name} {help}\n# TYPE {name} counter\n{name} {value}\n")
+        })
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clones_share_counters() {
+        let metrics = Metrics::default();
+        let clone = metrics.clone();
+
+        clone.record_take();
+        clone.record_event(&CfdEvent::ContractSetupStarted);
+        clone.record_event(&CfdEvent::LockConfirmed);
+
+        assert_eq!(
+            metrics.snapshot(),
+            Snapshot {
+                takes: 1,
+                accepts: 1,
+                ..Snapshot::default()
+            }
+        );
+    }
+
+    #[test]
+    fn renders_counters_in_prometheus_text_format() {
+        let metrics = Metrics::default();
+        metrics.record_event(&CfdEvent::OfferRejected(None));
+
+        let rendered = metrics.render();
+
+        assert!(rendered.contains(
+            "# HELP itchysats_rejects_total Takes rejected\n\
+             # TYPE itchysats_rejects_total counter\n\
+             itchysats_rejects_total 1\n"
+        ));
+        assert!(rendered.contains("itchysats_takes_total 0\n"));
+    }
+}
@@ -11,6 +11,7 @@ use bdk::bitcoin::Denomination;
 use bdk::bitcoin::SignedAmount;
 use chrono::DateTime;
 use parse_display::Display;
+use parse_display::FromStr;
 use reqwest::Url;
 use rust_decimal::prelude::ToPrimitive;
 use rust_decimal::Decimal;
@@ -43,6 +44,10 @@ pub enum Error {
     ZeroPrice,
     #[error("Negative Price is unimplemented.")]
     NegativePrice,
+    #[error("Quantity of zero is not allowed.")]
+    ZeroQuantity,
+    #[error("Negative quantity is not allowed.")]
+    NegativeQuantity,
 }
 
 /// Represents "quantity" or "contract size" in Cfd terms
@@ -50,10 +55,26 @@ pub enum Error {
 pub struct Usd(Decimal);
 
 impl Usd {
+    /// Construct a quantity without any validation.
+    ///
+    /// Meant for arithmetic on quantities, use [`Usd::try_new`] for quantities provided by users.
     pub fn new(value: Decimal) -> Self {
         Self(value)
     }
 
+    /// Construct a quantity that can be traded, i.e. one that is strictly positive.
+    pub fn try_new(value: Decimal) -> Result<Self, Error> {
+        if value == Decimal::ZERO {
+            return Err(Error::ZeroQuantity);
+        }
+
+        if value < Decimal::ZERO {
+            return Err(Error::NegativeQuantity);
+        }
+
+        Ok(Self(value))
+    }
+
     pub fn try_into_u64(&self) -> Result<u64> {
         self.0.to_u64().context("could not fit decimal into u64")
     }
@@ -62,6 +83,11 @@ impl Usd {
     pub fn into_decimal(self) -> Decimal {
         self.0
     }
+
+    /// Format the amount with exactly `dp` decimal places, padding with trailing zeros if needed.
+    pub fn to_string_with_dp(&self, dp: u32) -> String {
+        decimal_to_string_with_dp(self.0, dp)
+    }
 }
 
 impl fmt::Display for Usd {
@@ -111,6 +137,18 @@ impl Price {
     pub fn into_decimal(self) -> Decimal {
         self.0
     }
+
+    /// Format the price with exactly `dp` decimal places, padding with trailing zeros if needed.
+    pub fn to_string_with_dp(&self, dp: u32) -> String {
+        decimal_to_string_with_dp(self.0, dp)
+    }
+
+    /// Subtract `rhs` from this price.
+    ///
+    /// Fails if the difference is not a valid price, i.e. if `rhs` is not smaller than `self`.
+    pub fn checked_sub(self, rhs: Price) -> Result<Price, Error> {
+        Price::new(self.0 - rhs.0)
+    }
 }
 
 impl fmt::Display for Price {
@@ -128,6 +166,12 @@ impl str::FromStr for Price {
     }
 }
 
+/// Round to `dp` decimal places and pad with trailing zeros so that the result always has exactly
+/// `dp` digits after the decimal point.
+fn decimal_to_string_with_dp(value: Decimal, dp: u32) -> String {
+    format!("{:.*}", dp as usize, value.round_dp(dp))
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub struct InversePrice(Decimal);
 
@@ -149,7 +193,13 @@ impl InversePrice {
     }
 }
 
-#[derive(Debug, Copy, Clone, Serialize, Deserialize, PartialEq, sqlx::Type)]
+/// The leverage of a position.
+///
+/// Serialized as its numeric value, [`Display`](fmt::Display) renders it as `x{n}` for the UI.
+#[derive(
+    Debug, Copy, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, sqlx::Type,
+)]
+#[serde(try_from = "u8", into = "u8")]
 #[sqlx(transparent)]
 pub struct Leverage(u8);
 
@@ -164,6 +214,20 @@ impl Leverage {
     }
 }
 
+impl TryFrom<u8> for Leverage {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        Self::new(value)
+    }
+}
+
+impl From<Leverage> for u8 {
+    fn from(leverage: Leverage) -> Self {
+        leverage.0
+    }
+}
+
 impl fmt::Display for Leverage {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let leverage = self.0;
@@ -328,14 +392,6 @@ impl Add<Price> for Price {
     }
 }
 
-impl Sub<Price> for Price {
-    type Output = Price;
-
-    fn sub(self, rhs: Price) -> Self::Output {
-        Self(self.0 - rhs.0)
-    }
-}
-
 impl Add<InversePrice> for InversePrice {
     type Output = InversePrice;
 
@@ -376,14 +432,27 @@ impl Div<Leverage> for Leverage {
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub struct Percent(Decimal);
 
 impl Percent {
+    /// Express `numerator / denominator` in percent, e.g. a ratio of `1 / 4` is `25%`.
+    pub fn from_ratio(numerator: Decimal, denominator: Decimal) -> Result<Self> {
+        let ratio = numerator
+            .checked_div(denominator)
+            .context("Cannot compute percentage with a zero denominator")?;
+
+        Ok(Self(ratio * Decimal::ONE_HUNDRED))
+    }
+
     #[must_use]
     pub fn round_dp(self, digits: u32) -> Self {
         Self(self.0.round_dp(digits))
     }
+
+    pub fn value(&self) -> Decimal {
+        self.0
+    }
 }
 
 impl fmt::Display for Percent {
@@ -430,6 +499,16 @@ impl Identity {
     pub fn pk(&self) -> x25519_dalek::PublicKey {
         self.0
     }
+
+    /// Abbreviated hex representation for logs, e.g. `2a2a…2a2a`.
+    ///
+    /// Use [`Display`](fmt::Display) wherever the identity needs to be parsed again.
+    pub fn short(&self) -> String {
+        let hex = self.to_string();
+        let (head, tail) = (&hex[..4], &hex[hex.len() - 4..]);
+
+        format!("{head}…{tail}")
+    }
 }
 
 impl Serialize for Identity {
@@ -484,6 +563,9 @@ pub struct WalletInfo {
     pub last_updated_at: Timestamp,
 }
 
+const EVENT_ID_PREFIX: &str = "/x/BitMEX/BXBT/";
+const EVENT_ID_DIGITS_SEPARATOR: &str = ".price?n=";
+
 #[derive(
     Debug, Clone, Copy, SerializeDisplay, DeserializeFromStr, PartialEq, Eq, Hash, PartialOrd, Ord,
 )]
@@ -537,7 +619,7 @@ impl fmt::Display for BitMexPriceEventId {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "/x/BitMEX/BXBT/{}.price?n={}",
+            "{EVENT_ID_PREFIX}{}{EVENT_ID_DIGITS_SEPARATOR}{}",
             self.timestamp
                 .format(&olivia::EVENT_TIME_FORMAT)
                 .expect("should always format and we can't return an error here"),
@@ -550,15 +632,25 @@ impl str::FromStr for BitMexPriceEventId {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let remaining = s.trim_start_matches("/x/BitMEX/BXBT/");
-        let (timestamp, rest) = remaining.split_at(19);
-        let digits = rest.trim_start_matches(".price?n=");
+        let remaining = s
+            .strip_prefix(EVENT_ID_PREFIX)
+            .with_context(|| format!("Event id {s} does not start with {EVENT_ID_PREFIX}"))?;
+        let (timestamp, digits) = remaining
+            .split_once(EVENT_ID_DIGITS_SEPARATOR)
+            .with_context(|| {
+                format!("Event id {s} does not contain {EVENT_ID_DIGITS_SEPARATOR}")
+            })?;
+
+        // The timestamp is in UTC, tolerate an explicit designator
+        let timestamp = timestamp.strip_suffix('Z').unwrap_or(timestamp);
 
         Ok(Self {
             timestamp: PrimitiveDateTime::parse(timestamp, &olivia::EVENT_TIME_FORMAT)
                 .with_context(|| format!("Failed to parse {timestamp} as timestamp"))?
                 .assume_utc(),
-            digits: digits.parse()?,
+            digits: digits
+                .parse()
+                .with_context(|| format!("Failed to parse {digits} as number of digits"))?,
         })
     }
 }
@@ -601,6 +693,21 @@ impl Timestamp {
         let out = self.0.try_into().context("Unable to convert i64 to u64")?;
         Ok(out)
     }
+
+    /// Fails if the timestamp is out of the range of [`OffsetDateTime`], e.g. because it was
+    /// received from a counterparty.
+    pub fn to_offset_datetime(&self) -> Result<OffsetDateTime> {
+        let datetime = OffsetDateTime::from_unix_timestamp(self.0)
+            .with_context(|| format!("Timestamp {} is out of range", self.0))?;
+
+        Ok(datetime)
+    }
+}
+
+impl From<OffsetDateTime> for Timestamp {
+    fn from(datetime: OffsetDateTime) -> Self {
+        Self(datetime.unix_timestamp())
+    }
 }
 
 /// Funding rate per SETTLEMENT_INTERVAL
@@ -721,6 +828,7 @@ impl FundingFee {
     }
 }
 
+/// The net fees owed between the parties if the CFD was settled now.
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum FeeFlow {
     LongPaysShort(Amount),
@@ -817,12 +925,63 @@ impl FeeAccount {
     }
 }
 
+/// How fractional satoshis are rounded when computing payouts and funding fees.
+///
+/// Both parties of a CFD have to use the same strategy, otherwise they will not agree on the
+/// transactions of the contract. The maker decides on the strategy and announces it as part of
+/// its order.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, Display, FromStr,
+)]
+#[display(style = "kebab-case")]
+pub enum RoundingStrategy {
+    /// Round funding fees away from zero and payout amounts towards zero.
+    ///
+    /// This is the behaviour all existing contracts have been built with.
+    FeesUpPayoutsDown,
+    /// Round to the nearest satoshi, resolving ties towards the even neighbour.
+    ///
+    /// This avoids systematically favouring one party over the other.
+    Bankers,
+}
+
+impl RoundingStrategy {
+    pub(crate) fn round_fee(self, fee: Decimal) -> Decimal {
+        match self {
+            RoundingStrategy::FeesUpPayoutsDown => {
+                fee.round_dp_with_strategy(0, rust_decimal::RoundingStrategy::AwayFromZero)
+            }
+            RoundingStrategy::Bankers => {
+                fee.round_dp_with_strategy(0, rust_decimal::RoundingStrategy::MidpointNearestEven)
+            }
+        }
+    }
+
+    pub(crate) fn round_payout(self, sats: Decimal) -> Decimal {
+        match self {
+            RoundingStrategy::FeesUpPayoutsDown => {
+                sats.round_dp_with_strategy(0, rust_decimal::RoundingStrategy::ToZero)
+            }
+            RoundingStrategy::Bankers => {
+                sats.round_dp_with_strategy(0, rust_decimal::RoundingStrategy::MidpointNearestEven)
+            }
+        }
+    }
+}
+
+impl Default for RoundingStrategy {
+    fn default() -> Self {
+        RoundingStrategy::FeesUpPayoutsDown
+    }
+}
+
 pub fn calculate_funding_fee(
     price: Price,
     quantity: Usd,
     leverage: Leverage,
     funding_rate: FundingRate,
     hours_to_charge: i64,
+    rounding: RoundingStrategy,
 ) -> Result<FundingFee> {
     if funding_rate.0.is_zero() {
         return Ok(FundingFee::new(Amount::ZERO, funding_rate));
@@ -846,8 +1005,8 @@ pub fn calculate_funding_fee(
     let funding_fee = Decimal::from(margin.as_sat())
         * funding_rate.to_decimal().abs()
         * fraction_of_funding_period;
-    let funding_fee = funding_fee
-        .round_dp_with_strategy(0, rust_decimal::RoundingStrategy::AwayFromZero)
+    let funding_fee = rounding
+        .round_fee(funding_fee)
         .to_u64()
         .context("Failed to represent as u64")?;
 
@@ -903,6 +1062,77 @@ mod tests {
     use rust_decimal_macros::dec;
     use time::macros::datetime;
 
+    #[test]
+    fn usd_to_string_with_dp() {
+        let usd = Usd::new(dec!(1000.125));
+
+        assert_eq!(usd.to_string_with_dp(0), "1000");
+        assert_eq!(usd.to_string_with_dp(2), "1000.12");
+        assert_eq!(usd.to_string_with_dp(3), "1000.125");
+        assert_eq!(usd.to_string_with_dp(5), "1000.12500");
+    }
+
+    #[test]
+    fn usd_try_new_rejects_zero_and_negative_quantities() {
+        assert!(matches!(Usd::try_new(dec!(0)), Err(Error::ZeroQuantity)));
+        assert!(matches!(
+            Usd::try_new(dec!(-10)),
+            Err(Error::NegativeQuantity)
+        ));
+        assert_eq!(Usd::try_new(dec!(10)).unwrap(), Usd::new(dec!(10)));
+    }
+
+    #[test]
+    fn price_subtraction_must_stay_positive() {
+        let high = Price::new(dec!(42000)).unwrap();
+        let low = Price::new(dec!(40000)).unwrap();
+
+        assert_eq!(
+            high.checked_sub(low).unwrap(),
+            Price::new(dec!(2000)).unwrap()
+        );
+        assert!(matches!(low.checked_sub(high), Err(Error::NegativePrice)));
+        assert!(matches!(high.checked_sub(high), Err(Error::ZeroPrice)));
+    }
+
+    #[test]
+    fn price_to_string_with_dp_pads_trailing_zeros() {
+        let price = Price::new(dec!(42000.1)).unwrap();
+
+        assert_eq!(price.to_string_with_dp(0), "42000");
+        assert_eq!(price.to_string_with_dp(1), "42000.1");
+        assert_eq!(price.to_string_with_dp(2), "42000.10");
+        assert_eq!(
+            Price::new(dec!(42000)).unwrap().to_string_with_dp(2),
+            "42000.00"
+        );
+    }
+
+    #[test]
+    fn percent_from_ratio() {
+        assert_eq!(
+            Percent::from_ratio(dec!(1), dec!(4)).unwrap().value(),
+            dec!(25)
+        );
+        assert_eq!(
+            Percent::from_ratio(dec!(-3), dec!(2)).unwrap().value(),
+            dec!(-150)
+        );
+        assert!(Percent::from_ratio(dec!(1), Decimal::ZERO).is_err());
+    }
+
+    #[test]
+    fn percent_ordering() {
+        let loss = Percent::from(dec!(-20));
+        let small_profit = Percent::from(dec!(10.5));
+        let threshold = Percent::from(dec!(50));
+
+        assert!(loss < small_profit);
+        assert!(small_profit < threshold);
+        assert!(Percent::from(dec!(50.01)) > threshold);
+        assert!(Percent::from(dec!(50.00)) >= threshold);
+    }
+
     #[test]
     fn to_olivia_url() {
         let url = BitMexPriceEventId::with_20_digits(datetime!(2021-09-23 10:00:00).assume_utc())
@@ -927,6 +1157,34 @@ mod tests {
         assert_eq!(parsed, expected);
     }
 
+    #[test]
+    fn parse_event_id_with_utc_designator() {
+        let parsed = "/x/BitMEX/BXBT/2021-09-23T10:00:00Z.price?n=20"
+            .parse::<BitMexPriceEventId>()
+            .unwrap();
+        let expected =
+            BitMexPriceEventId::with_20_digits(datetime!(2021-09-23 10:00:00).assume_utc());
+
+        assert_eq!(parsed, expected);
+    }
+
+    #[test]
+    fn parse_malformed_event_id_fails() {
+        for malformed in [
+            "",
+            "/x/BitMEX/BXBT/",
+            "/x/BitMEX/BXBT/2021-09-23T10:00:00",
+            "/x/BitMEX/BXBT/2021-09-23.price?n=20",
+            "/x/BitMEX/BXBT/2021-09-23T10:00:00.price?n=",
+            "/x/BitMEX/ETHUSD/2021-09-23T10:00:00.price?n=20",
+            "/x/BitMEX/BXBT/2021-09-23T10:00:00.price?n=twenty",
+        ] {
+            let result = malformed.parse::<BitMexPriceEventId>();
+
+            assert!(result.is_err(), "{malformed} should not parse");
+        }
+    }
+
     #[test]
     fn new_event_has_no_nanos() {
         let now = BitMexPriceEventId::with_20_digits(OffsetDateTime::now_utc());
@@ -979,6 +1237,28 @@ mod tests {
         assert_eq!(res.0, usd.0);
     }
 
+    #[test]
+    fn roundtrip_leverage_serde() {
+        let leverage = Leverage::new(5).unwrap();
+
+        serde_test::assert_tokens(&leverage, &[serde_test::Token::U8(5)]);
+    }
+
+    #[test]
+    fn leverage_of_zero_fails_to_deserialize() {
+        serde_test::assert_de_tokens_error::<Leverage>(
+            &[serde_test::Token::U8(0)],
+            "Cannot use non-positive values",
+        );
+    }
+
+    #[test]
+    fn leverage_displays_with_x_prefix() {
+        let leverage = Leverage::new(2).unwrap();
+
+        assert_eq!(leverage.to_string(), "x2");
+    }
+
     #[test]
     fn test_algebra_with_types() {
         let usd = Usd::new(dec!(61234.5678));
@@ -1008,6 +1288,31 @@ mod tests {
         assert_eq!(ts_b.seconds() - ts_a.seconds(), -36000);
     }
 
+    #[test]
+    fn timestamp_roundtrips_through_offset_datetime() {
+        let datetime = time::macros::datetime!(2021-09-23 10:00:00 UTC);
+
+        let timestamp = Timestamp::from(datetime);
+
+        assert_eq!(timestamp.seconds(), 1632391200);
+        assert_eq!(timestamp.to_offset_datetime().unwrap(), datetime);
+    }
+
+    #[test]
+    fn pre_epoch_timestamp_roundtrips_through_offset_datetime() {
+        let datetime = time::macros::datetime!(1969-12-31 23:00:00 UTC);
+
+        let timestamp = Timestamp::from(datetime);
+
+        assert_eq!(timestamp.seconds(), -3600);
+        assert_eq!(timestamp.to_offset_datetime().unwrap(), datetime);
+    }
+
+    #[test]
+    fn out_of_range_timestamp_is_an_error() {
+        assert!(Timestamp::new(i64::MAX).to_offset_datetime().is_err());
+    }
+
     #[test]
     fn roundtrip_identity_serde() {
         let id = Identity::new(x25519_dalek::PublicKey::from([42u8; 32]));
@@ -1020,6 +1325,20 @@ mod tests {
         );
     }
 
+    #[test]
+    fn short_identity_keeps_first_and_last_four_hex_chars() {
+        let id = Identity::new(x25519_dalek::PublicKey::from([
+            0xab, 0xcd, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            0, 0, 0, 0x12, 0x34,
+        ]));
+
+        let short = id.short();
+
+        assert_eq!(short, "abcd…1234");
+        assert_eq!(short.chars().count(), 9);
+        assert_eq!(id.to_string().len(), 64);
+    }
+
     #[test]
     fn long_taker_pays_opening_fee_to_maker() {
         let opening_fee = OpeningFee::new(Amount::from_sat(500));
@@ -1114,6 +1433,22 @@ mod tests {
         assert_eq!(short_maker, FeeFlow::ShortPaysLong(Amount::from_sat(1000)));
     }
 
+    #[test]
+    fn fee_flow_serializes_direction_and_amount() {
+        let long_pays_short = FeeFlow::LongPaysShort(Amount::from_sat(500));
+
+        let json = serde_json::to_value(long_pays_short).unwrap();
+
+        assert_eq!(
+            json,
+            serde_json::json!({ "direction": "longPaysShort", "amount": 0.000005 })
+        );
+        assert_eq!(
+            serde_json::to_value(FeeFlow::Nein).unwrap(),
+            serde_json::json!({ "direction": "none" })
+        );
+    }
+
     #[test]
     fn long_taker_short_maker_roundtrip() {
         let opening_fee = OpeningFee::new(Amount::from_sat(100));
@@ -1291,6 +1626,7 @@ mod tests {
             long_leverage,
             funding_rate_pos,
             dummy_settlement_interval(),
+            RoundingStrategy::default(),
         )
         .unwrap();
 
@@ -1301,6 +1637,7 @@ mod tests {
             long_leverage,
             funding_rate_neg,
             dummy_settlement_interval(),
+            RoundingStrategy::default(),
         )
         .unwrap();
 
@@ -1320,12 +1657,53 @@ mod tests {
             dummy_leverage,
             zero_funding_rate,
             dummy_settlement_interval(),
+            RoundingStrategy::default(),
         )
         .unwrap();
 
         assert_eq!(fee.fee, Amount::ZERO)
     }
 
+    #[test]
+    fn rounding_strategy_determines_how_half_satoshis_are_rounded() {
+        // 1 BTC of short margin at 0.0000025% yields a fee of exactly 2.5 sats
+        let fee_with = |rounding| {
+            calculate_funding_fee(
+                Price::new(dec!(1)).unwrap(),
+                Usd::new(dec!(1)),
+                Leverage::new(1).unwrap(),
+                FundingRate::new(dec!(0.000000025)).unwrap(),
+                dummy_settlement_interval(),
+                rounding,
+            )
+            .unwrap()
+            .fee
+        };
+
+        assert_eq!(
+            fee_with(RoundingStrategy::FeesUpPayoutsDown),
+            Amount::from_sat(3)
+        );
+        assert_eq!(fee_with(RoundingStrategy::Bankers), Amount::from_sat(2));
+    }
+
+    #[test]
+    fn rounding_strategy_roundtrips_through_its_name() {
+        for rounding in [
+            RoundingStrategy::FeesUpPayoutsDown,
+            RoundingStrategy::Bankers,
+        ] {
+            assert_eq!(
+                rounding.to_string().parse::<RoundingStrategy>().unwrap(),
+                rounding
+            );
+        }
+        assert_eq!(
+            "fees-up-payouts-down".parse::<RoundingStrategy>().unwrap(),
+            RoundingStrategy::FeesUpPayoutsDown
+        );
+    }
+
     fn dummy_price() -> Price {
         Price::new(dec!(35_000)).expect("to not fail")
     }
@@ -1,6 +1,7 @@
 use crate::model::calculate_funding_fee;
 use crate::model::BitMexPriceEventId;
 use crate::model::FeeAccount;
+use crate::model::FeeFlow;
 use crate::model::FundingFee;
 use crate::model::FundingRate;
 use crate::model::Identity;
@@ -10,6 +11,7 @@ use crate::model::OpeningFee;
 use crate::model::Percent;
 use crate::model::Position;
 use crate::model::Price;
+use crate::model::RoundingStrategy;
 use crate::model::Timestamp;
 use crate::model::TradingPair;
 use crate::model::TxFeeRate;
@@ -18,10 +20,12 @@ use crate::oracle;
 use crate::payout_curve;
 use crate::setup_contract::RolloverParams;
 use crate::setup_contract::SetupParams;
+use crate::MAX_N_PAYOUTS;
 use crate::SETTLEMENT_INTERVAL;
 use anyhow::bail;
 use anyhow::Context;
 use anyhow::Result;
+use bdk::bitcoin::secp256k1::schnorrsig;
 use bdk::bitcoin::secp256k1::SecretKey;
 use bdk::bitcoin::secp256k1::Signature;
 use bdk::bitcoin::Address;
@@ -36,12 +40,16 @@ use bdk::bitcoin::Txid;
 use bdk::descriptor::Descriptor;
 use bdk::miniscript::DescriptorTrait;
 use cached::proc_macro::cached;
+use maia::commit_descriptor;
 use maia::finalize_spend_transaction;
+use maia::lock_descriptor;
 use maia::secp256k1_zkp;
 use maia::secp256k1_zkp::EcdsaAdaptorSignature;
 use maia::secp256k1_zkp::SECP256K1;
 use maia::spending_tx_sighash;
 use maia::TransactionExt;
+use parse_display::Display;
+use parse_display::FromStr;
 use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
 use serde::de::Error as _;
@@ -56,12 +64,22 @@ use time::OffsetDateTime;
 use uuid::adapter::Hyphenated;
 use uuid::Uuid;
 
+/// Default number of blocks the commit transaction has to be confirmed before a CET can spend it.
 pub const CET_TIMELOCK: u32 = 12;
 
+/// The largest CET timelock we agree to, roughly one day of blocks.
+///
+/// A longer timelock gives the counterparty more time to race our CET with an outdated one.
+pub const MAX_CET_TIMELOCK: u32 = 144;
+
+fn default_cet_timelock() -> u32 {
+    CET_TIMELOCK
+}
+
 const CONTRACT_SETUP_COMPLETED_EVENT: &str = "ContractSetupCompleted";
 const ROLLOVER_COMPLETED_EVENT: &str = "RolloverCompleted";
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, sqlx::Type)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, sqlx::Type)]
 #[sqlx(transparent)]
 pub struct OrderId(Hyphenated);
 
@@ -144,6 +162,8 @@ pub struct Order {
     pub max_quantity: Usd,
 
     pub leverage: Leverage,
+    /// The highest leverage the maker accepts when this order is taken.
+    pub max_leverage: Leverage,
 
     // TODO: Remove from order, can be calculated
     pub liquidation_price: Price,
@@ -160,9 +180,28 @@ pub struct Order {
     /// The maker includes this into the Order based on the Oracle announcement to be used.
     pub oracle_event_id: BitMexPriceEventId,
 
+    /// The public key of the oracle attesting to `oracle_event_id`.
+    ///
+    /// Orders of makers that predate this field don't carry it.
+    #[serde(default)]
+    pub oracle_pk: Option<schnorrsig::PublicKey>,
+
     pub tx_fee_rate: TxFeeRate,
     pub funding_rate: FundingRate,
     pub opening_fee: OpeningFee,
+
+    /// The number of payouts on the payout curve, determining the granularity of the CETs.
+    pub n_payouts: usize,
+
+    /// How fractional satoshis of payouts and funding fees are rounded for this order.
+    ///
+    /// Orders of makers that predate this field use the default.
+    #[serde(default)]
+    pub rounding: RoundingStrategy,
+
+    /// Number of blocks the commit transaction has to be confirmed before a CET can spend it.
+    #[serde(default = "default_cet_timelock")]
+    pub cet_timelock: u32,
 }
 
 impl Order {
@@ -173,12 +212,32 @@ impl Order {
         max_quantity: Usd,
         origin: Origin,
         oracle_event_id: BitMexPriceEventId,
+        oracle_pk: schnorrsig::PublicKey,
         settlement_interval: Duration,
         tx_fee_rate: TxFeeRate,
         funding_rate: FundingRate,
         opening_fee: OpeningFee,
+        n_payouts: usize,
+        rounding: RoundingStrategy,
+        max_leverage: Leverage,
+        cet_timelock: u32,
     ) -> Result<Self> {
+        validate_n_payouts(n_payouts)?;
+
+        let min_quantity =
+            Usd::try_new(min_quantity.into_decimal()).context("Invalid minimum quantity")?;
+        let max_quantity =
+            Usd::try_new(max_quantity.into_decimal()).context("Invalid maximum quantity")?;
+
+        if cet_timelock == 0 || cet_timelock > MAX_CET_TIMELOCK {
+            bail!("CET timelock must be between 1 and {MAX_CET_TIMELOCK} blocks")
+        }
+
         let leverage = Leverage::new(2)?;
+        if max_leverage < leverage {
+            bail!("Maximum leverage {max_leverage} must not be lower than the leverage {leverage}")
+        }
+
         let liquidation_price = calculate_long_liquidation_price(leverage, price);
 
         Ok(Order {
@@ -187,6 +246,7 @@ impl Order {
             min_quantity,
             max_quantity,
             leverage,
+            max_leverage,
             trading_pair: TradingPair::BtcUsd,
             liquidation_price,
             position: Position::Short,
@@ -194,11 +254,64 @@ impl Order {
             settlement_interval,
             origin,
             oracle_event_id,
+            oracle_pk: Some(oracle_pk),
             tx_fee_rate,
             funding_rate,
             opening_fee,
+            n_payouts,
+            rounding,
+            cet_timelock,
         })
     }
+
+    /// Re-price this order, keeping everything but the price-dependent fields.
+    ///
+    /// The re-priced order gets a fresh [`OrderId`] so a take can never end up at a price the
+    /// taker did not see.
+    pub fn with_price(self, price: Price) -> Self {
+        Order {
+            id: OrderId::default(),
+            price,
+            liquidation_price: calculate_long_liquidation_price(self.leverage, price),
+            creation_timestamp: Timestamp::now(),
+            ..self
+        }
+    }
+
+    /// Take this order with a different leverage, adjusting the price-dependent fields.
+    ///
+    /// Whether the leverage is acceptable is up to the maker, see [`Order::max_leverage`].
+    pub fn with_leverage(self, leverage: Leverage) -> Self {
+        Order {
+            leverage,
+            liquidation_price: calculate_long_liquidation_price(leverage, self.price),
+            ..self
+        }
+    }
+
+    /// The margin a taker has to lock up to take this order with its minimum quantity.
+    ///
+    /// The taker always takes the counter position of the maker.
+    pub fn min_taker_margin(&self) -> Amount {
+        match self.position.counter_position() {
+            Position::Long => calculate_long_margin(self.price, self.min_quantity, self.leverage),
+            Position::Short => calculate_short_margin(self.price, self.min_quantity),
+        }
+    }
+}
+
+/// The payout curve needs at least this many segments to model both tails.
+const MIN_N_PAYOUTS: usize = 3;
+
+/// Ensure an order does not ask for more CETs than we are willing to create and sign.
+pub fn validate_n_payouts(n_payouts: usize) -> Result<()> {
+    if !(MIN_N_PAYOUTS..=MAX_N_PAYOUTS).contains(&n_payouts) {
+        bail!(
+            "Number of payouts must be between {MIN_N_PAYOUTS} and {MAX_N_PAYOUTS}, got {n_payouts}"
+        )
+    }
+
+    Ok(())
 }
 
 /// Proposed collaborative settlement
@@ -226,6 +339,68 @@ pub enum SettlementKind {
     Outgoing,
 }
 
+/// Commands a user can trigger on a CFD.
+#[derive(Debug, Clone, Display, FromStr, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "camelCase")]
+#[display(style = "camelCase")]
+pub enum CfdAction {
+    AcceptOrder,
+    RejectOrder,
+    Commit,
+    Settle,
+    Rollover,
+    AcceptSettlement,
+    RejectSettlement,
+    AcceptRollover,
+    RejectRollover,
+    CancelOrder,
+}
+
+/// Profit and loss thresholds at which a CFD is closed automatically by proposing a collaborative
+/// settlement.
+///
+/// Both thresholds are positive percentages of the margin, i.e. a `stop_loss` of 20 triggers once
+/// the CFD has lost 20% or more.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct AutoCloseThresholds {
+    pub take_profit: Option<Percent>,
+    pub stop_loss: Option<Percent>,
+}
+
+impl AutoCloseThresholds {
+    pub fn is_set(&self) -> bool {
+        self.take_profit.is_some() || self.stop_loss.is_some()
+    }
+
+    pub fn is_breached(&self, profit: Percent) -> bool {
+        let take_profit = self
+            .take_profit
+            .map_or(false, |take_profit| profit >= take_profit);
+        let stop_loss = self
+            .stop_loss
+            .map_or(false, |stop_loss| profit.value() <= -stop_loss.value());
+
+        take_profit || stop_loss
+    }
+}
+
+/// Reasons why a take of an order was rejected.
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum RejectReason {
+    #[error("The maker rejected the take")]
+    ManualReject,
+    #[error("The maker is unable to process the take at the moment")]
+    MakerUnavailable,
+    #[error("The quantity is not within the limits of the order")]
+    QuantityOutOfRange,
+    #[error("The maker does not know the order")]
+    InvalidOrderId,
+    #[error("The order is already being taken")]
+    AlreadyInProgress,
+    #[error("The leverage exceeds the maximum leverage of the order")]
+    LeverageTooHigh,
+}
+
 /// Reasons why we cannot rollover a CFD.
 #[derive(thiserror::Error, Debug, PartialEq)]
 pub enum NoRolloverReason {
@@ -243,14 +418,30 @@ pub enum NoRolloverReason {
     Closed,
 }
 
-/// Errors that can happen when handling the expiry of the refund
-/// timelock on the commit transaciton.
-#[derive(thiserror::Error, Debug)]
-pub enum RefundTimelockExpiryError {
+/// Reasons why a transaction of the CFD cannot be produced in its current state.
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq)]
+pub enum TxUnavailable {
     #[error("CFD is already final")]
     AlreadyFinal,
     #[error("CFD does not have a DLC")]
     NoDlc,
+}
+
+/// The transactions that can spend from the DLC of a CFD.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Display)]
+pub enum SpendableTx {
+    Commit,
+    Refund,
+    Cet,
+    CollaborativeSettlement,
+}
+
+/// Errors that can happen when handling the expiry of the refund
+/// timelock on the commit transaciton.
+#[derive(thiserror::Error, Debug)]
+pub enum RefundTimelockExpiryError {
+    #[error(transparent)]
+    Unavailable(#[from] TxUnavailable),
     #[error("Failed to sign refund transaction")]
     Signing(#[from] anyhow::Error),
 }
@@ -282,7 +473,9 @@ pub enum CfdEvent {
     },
 
     ContractSetupFailed,
-    OfferRejected,
+    /// Not a struct variant so that events without a reason, recorded before the reason was
+    /// introduced, can still be deserialized.
+    OfferRejected(Option<RejectReason>),
 
     RolloverStarted,
     RolloverAccepted,
@@ -310,6 +503,8 @@ pub enum CfdEvent {
 
     // TODO: The monitoring events should move into the monitor once we use multiple
     // aggregates in different actors
+    /// The lock transaction was seen in the mempool, it is not final yet.
+    LockSeen,
     LockConfirmed,
     /// The lock transaction is confirmed after CFD was closed
     ///
@@ -323,6 +518,10 @@ pub enum CfdEvent {
     RefundConfirmed,
     RevokeConfirmed,
     CollaborativeSettlementConfirmed,
+    /// The lock transaction was reorged out of the chain after it had been confirmed.
+    LockReorged,
+    /// The commit transaction was reorged out of the chain after it had been confirmed.
+    CommitReorged,
 
     CetTimelockExpiredPriorOracleAttestation,
     CetTimelockExpiredPostOracleAttestation {
@@ -431,6 +630,8 @@ pub struct Cfd {
     role: Role,
     opening_fee: OpeningFee,
     initial_tx_fee_rate: TxFeeRate,
+    n_payouts: usize,
+    rounding: RoundingStrategy,
     // dynamic (based on events)
     fee_account: FeeAccount,
 
@@ -452,6 +653,11 @@ pub struct Cfd {
     collaborative_settlement_spend_tx: Option<Transaction>,
     refund_tx: Option<Transaction>,
 
+    /// The price attested by the oracle, if we have received a relevant attestation.
+    attestation_price: Option<Price>,
+    /// The price the CFD was collaboratively settled at.
+    collaborative_settlement_price: Option<Price>,
+
     lock_finality: bool,
 
     commit_finality: bool,
@@ -483,6 +689,8 @@ impl Cfd {
         opening_fee: OpeningFee,
         initial_funding_rate: FundingRate,
         initial_tx_fee_rate: TxFeeRate,
+        n_payouts: usize,
+        rounding: RoundingStrategy,
     ) -> Self {
         let initial_funding_fee = calculate_funding_fee(
             initial_price,
@@ -490,6 +698,7 @@ impl Cfd {
             leverage,
             initial_funding_rate,
             SETTLEMENT_INTERVAL.whole_hours(),
+            rounding,
         )
         .expect("values from db to be sane");
 
@@ -506,11 +715,15 @@ impl Cfd {
             initial_funding_rate,
             opening_fee,
             initial_tx_fee_rate,
+            n_payouts,
+            rounding,
             dlc: None,
             cet: None,
             commit_tx: None,
             collaborative_settlement_spend_tx: None,
             refund_tx: None,
+            attestation_price: None,
+            collaborative_settlement_price: None,
             lock_finality: false,
             commit_finality: false,
             refund_finality: false,
@@ -547,6 +760,8 @@ impl Cfd {
             order.opening_fee,
             order.funding_rate,
             order.tx_fee_rate,
+            order.n_payouts,
+            order.rounding,
         )
     }
 
@@ -564,6 +779,8 @@ impl Cfd {
         opening_fee: OpeningFee,
         initial_funding_rate: FundingRate,
         initial_tx_fee_rate: TxFeeRate,
+        n_payouts: usize,
+        rounding: RoundingStrategy,
         events: Vec<Event>,
     ) -> Self {
         let cfd = Self::new(
@@ -578,6 +795,8 @@ impl Cfd {
             opening_fee,
             initial_funding_rate,
             initial_tx_fee_rate,
+            n_payouts,
+            rounding,
         );
         events.into_iter().fold(cfd, Cfd::apply)
     }
@@ -588,7 +807,7 @@ impl Cfd {
             .map(|dlc| dlc.settlement_event_id.timestamp)
     }
 
-    fn margin(&self) -> Amount {
+    pub fn margin(&self) -> Amount {
         match self.position {
             Position::Long => {
                 calculate_long_margin(self.initial_price, self.quantity, self.leverage)
@@ -597,6 +816,13 @@ impl Cfd {
         }
     }
 
+    /// Our margin including the fees accumulated in `fee_account`.
+    ///
+    /// See [`calculate_margin_with_fees`].
+    pub fn margin_with_fees(&self, fee_account: &FeeAccount) -> Amount {
+        calculate_margin_with_fees(self.margin(), self.position, fee_account)
+    }
+
     fn counterparty_margin(&self) -> Amount {
         match self.position {
             Position::Short => {
@@ -610,6 +836,11 @@ impl Cfd {
         self.settlement_proposal.is_some()
     }
 
+    /// Whether a rollover was started and neither completed, rejected nor failed yet.
+    pub fn is_rollover_in_progress(&self) -> bool {
+        self.during_rollover
+    }
+
     fn is_in_force_close(&self) -> bool {
         self.commit_tx.is_some()
     }
@@ -652,6 +883,13 @@ impl Cfd {
         Ok(())
     }
 
+    fn can_commit(&self) -> bool {
+        self.lock_finality
+            && !self.is_closed()
+            && !self.is_in_force_close()
+            && !self.commit_finality
+    }
+
     fn can_settle_collaboratively(&self) -> bool {
         !self.is_closed()
             && !self.commit_finality
@@ -692,17 +930,172 @@ impl Cfd {
             || self.is_refunded()
     }
 
-    pub fn start_contract_setup(&self) -> Result<(Event, SetupParams)> {
+    /// The actions that can currently be taken on this CFD.
+    ///
+    /// This consolidates the eligibility checks of the individual commands so that consumers do not
+    /// have to derive them from the state. Publishing the refund transaction is never a user
+    /// action, it happens automatically once the refund timelock expires.
+    pub fn available_actions(&self) -> Vec<CfdAction> {
+        let mut actions = Vec::new();
+
+        if self.can_commit() {
+            actions.push(CfdAction::Commit);
+        }
+
+        match self.role {
+            Role::Maker => {
+                if self.version == 0 {
+                    actions.push(CfdAction::AcceptOrder);
+                    actions.push(CfdAction::RejectOrder);
+                }
+
+                if self.is_in_collaborative_settlement() {
+                    actions.push(CfdAction::AcceptSettlement);
+                    actions.push(CfdAction::RejectSettlement);
+                }
+
+                if self.during_rollover {
+                    actions.push(CfdAction::AcceptRollover);
+                    actions.push(CfdAction::RejectRollover);
+                }
+            }
+            Role::Taker => {
+                if self.version == 0 {
+                    actions.push(CfdAction::CancelOrder);
+                }
+
+                if self.lock_finality
+                    && !self.is_in_collaborative_settlement()
+                    && !self.during_rollover
+                    && self.can_settle_collaboratively()
+                {
+                    actions.push(CfdAction::Settle);
+                }
+
+                if !self.during_rollover && self.can_rollover().is_ok() {
+                    actions.push(CfdAction::Rollover);
+                }
+            }
+        }
+
+        actions
+    }
+
+    /// Estimate the on-chain fees of closing this CFD collaboratively vs. force-closing it.
+    ///
+    /// Uses the fee rate the CFD was set up with.
+    pub fn estimate_closing_fees(&self) -> Result<ClosingFeeEstimate> {
+        let dlc = self
+            .dlc
+            .as_ref()
+            .context("Cannot estimate closing fees before the contract is set up")?;
+
+        dlc.estimate_closing_fees(self.initial_tx_fee_rate)
+    }
+
+    /// Fee and size of the commit transaction that would be broadcast when force-closing this CFD.
+    pub fn commit_tx_fee(&self) -> Result<CommitTxFee> {
+        let dlc = self
+            .dlc
+            .as_ref()
+            .context("Cannot compute commit transaction fee before the contract is set up")?;
+
+        dlc.commit_tx_fee()
+    }
+
+    /// The price this CFD closes at.
+    ///
+    /// The price of a collaborative settlement or oracle attestation is final, only if neither
+    /// happened yet we fall back to `current_price`.
+    pub fn closing_price(&self, current_price: Price) -> Result<Price> {
+        Ok(self.final_closing_price()?.unwrap_or(current_price))
+    }
+
+    /// The price this CFD closes at, if it was settled collaboratively or attested already.
+    pub fn final_closing_price(&self) -> Result<Option<Price>> {
+        let closing_price = match (self.collaborative_settlement_price, self.attestation_price) {
+            (None, None) => None,
+            (Some(settlement_price), None) => Some(settlement_price),
+            (None, Some(attestation_price)) => Some(attestation_price),
+            (Some(_), Some(_)) => {
+                bail!(
+                    "CFD {} was both settled collaboratively and attested",
+                    self.id
+                )
+            }
+        };
+
+        Ok(closing_price)
+    }
+
+    /// Our refund, if the refund transaction was published.
+    fn refund(&self) -> Option<Amount> {
+        if !self.refund_timelock_expired && !self.refund_finality {
+            return None;
+        }
+
+        Some(self.dlc.as_ref()?.refund_amount(self.role))
+    }
+
+    /// Profit in percent of our margin at the [closing price](Self::closing_price).
+    ///
+    /// A refunded CFD has no closing price, its profit is our refund minus our margin.
+    pub fn profit(&self, current_price: Price) -> Result<Percent> {
+        if let Some(refund) = self.refund() {
+            let (_, profit_in_percent) = calculate_profit(
+                refund
+                    .to_signed()
+                    .context("Refund to fit into signed amount")?,
+                self.margin()
+                    .to_signed()
+                    .context("Margin to fit into signed amount")?,
+            );
+
+            return Ok(profit_in_percent);
+        }
+
+        let (_, profit_in_percent, _) = calculate_profit_at_price(
+            self.initial_price,
+            self.closing_price(current_price)?,
+            self.quantity,
+            self.leverage,
+            self.fee_account,
+        )?;
+
+        Ok(profit_in_percent)
+    }
+
+    /// Whether the CFD is open and not being closed yet, i.e. whether it makes sense to
+    /// automatically propose a collaborative settlement.
+    pub fn can_auto_close(&self) -> bool {
+        self.lock_finality
+            && self.can_settle_collaboratively()
+            && !self.is_in_collaborative_settlement()
+            && !self.during_rollover
+    }
+
+    /// Whether the CFD binds collateral, i.e. its contract is being set up or it is set up and
+    /// not closed yet.
+    pub fn is_exposed(&self) -> bool {
+        (self.during_contract_setup || self.dlc.is_some()) && !self.is_closed()
+    }
+
+    /// Start setting up the contract, the CETs become spendable `cet_timelock` blocks after the
+    /// commit transaction confirmed.
+    pub fn start_contract_setup(&self, cet_timelock: u32) -> Result<(Event, SetupParams)> {
         if self.version > 0 {
             bail!("Start contract not allowed in version {}", self.version)
         }
 
+        self.validate_cet_timelock(cet_timelock)?;
+
         let margin = self.margin();
         let counterparty_margin = self.counterparty_margin();
 
         Ok((
             Event::new(self.id(), CfdEvent::ContractSetupStarted),
             SetupParams::new(
+                self.id,
                 margin,
                 counterparty_margin,
                 self.counterparty_network_identity,
@@ -710,8 +1103,11 @@ impl Cfd {
                 self.quantity,
                 self.leverage,
                 self.refund_timelock_in_blocks(),
+                cet_timelock,
                 self.initial_tx_fee_rate(),
                 self.fee_account,
+                self.n_payouts,
+                self.rounding,
             )?,
         ))
     }
@@ -747,6 +1143,7 @@ impl Cfd {
             self.leverage,
             funding_rate,
             hours_to_charge,
+            self.rounding,
         )?;
 
         Ok((
@@ -759,6 +1156,8 @@ impl Cfd {
                 tx_fee_rate,
                 self.fee_account,
                 funding_fee,
+                self.n_payouts,
+                self.rounding,
             ),
             self.dlc.clone().context("No DLC present")?,
             self.settlement_interval,
@@ -789,6 +1188,7 @@ impl Cfd {
             self.leverage,
             funding_rate,
             hours_to_charge,
+            self.rounding,
         )?;
 
         Ok((
@@ -801,6 +1201,8 @@ impl Cfd {
                 tx_fee_rate,
                 self.fee_account,
                 funding_fee,
+                self.n_payouts,
+                self.rounding,
             ),
             self.dlc.clone().context("No DLC present")?,
         ))
@@ -825,39 +1227,49 @@ impl Cfd {
         Ok(settlement)
     }
 
-    pub fn propose_collaborative_settlement(
-        &self,
-        current_price: Price,
-        n_payouts: usize,
-    ) -> Result<Event> {
-        anyhow::ensure!(
-            !self.is_in_collaborative_settlement()
-                && self.role == Role::Taker
-                && self.can_settle_collaboratively(),
-            "Failed to propose collaborative settlement"
-        );
-
+    /// The amounts the taker and the maker receive, in that order, when settling at `price`.
+    ///
+    /// The payout curve is shifted by the fees settled through our fee account, hence the split
+    /// reflects the opening fee and all funding fees accumulated so far.
+    pub fn settlement_amounts(&self, price: Price) -> Result<(Amount, Amount)> {
         let payout_curve = payout_curve::calculate(
             self.initial_price,
             self.quantity,
             self.leverage,
-            n_payouts,
+            self.n_payouts,
             self.fee_account.settle(),
+            self.rounding,
         )?;
 
-        let payout = {
-            let current_price = current_price.try_into_u64()?;
-            payout_curve
-                .iter()
-                .find(|&x| x.digits().range().contains(&current_price))
-                .context("find current price on the payout curve")?
-        };
+        let price = price.try_into_u64()?;
+        let payout = payout_curve
+            .iter()
+            .find(|&x| x.digits().range().contains(&price))
+            .context("find current price on the payout curve")?;
+
+        Ok((*payout.taker_amount(), *payout.maker_amount()))
+    }
+
+    pub fn propose_collaborative_settlement(&self, current_price: Price) -> Result<Event> {
+        // Rollover and collaborative settlement are mutually exclusive, both update the DLC
+        if self.is_rollover_in_progress() {
+            bail!("Cannot settle collaboratively while the CFD is being rolled over");
+        }
+
+        anyhow::ensure!(
+            !self.is_in_collaborative_settlement()
+                && self.role == Role::Taker
+                && self.can_settle_collaboratively(),
+            "Failed to propose collaborative settlement"
+        );
+
+        let (taker, maker) = self.settlement_amounts(current_price)?;
 
         let proposal = SettlementProposal {
             order_id: self.id,
             timestamp: Timestamp::now(),
-            taker: *payout.taker_amount(),
-            maker: *payout.maker_amount(),
+            taker,
+            maker,
             price: current_price,
         };
 
@@ -870,8 +1282,11 @@ impl Cfd {
     pub fn receive_collaborative_settlement_proposal(
         self,
         proposal: SettlementProposal,
-        n_payouts: usize,
     ) -> Result<Event> {
+        if self.is_rollover_in_progress() {
+            bail!("Cannot settle collaboratively while the CFD is being rolled over");
+        }
+
         anyhow::ensure!(
             !self.is_in_collaborative_settlement()
                 && self.role == Role::Maker
@@ -882,24 +1297,10 @@ impl Cfd {
 
         // Validate that the amounts sent by the taker are sane according to the payout curve
 
-        let payout_curve_long = payout_curve::calculate(
-            self.initial_price,
-            self.quantity,
-            self.leverage,
-            n_payouts,
-            self.fee_account.settle(),
-        )?;
-
-        let payout = {
-            let proposal_price = proposal.price.try_into_u64()?;
-            payout_curve_long
-                .iter()
-                .find(|&x| x.digits().range().contains(&proposal_price))
-                .context("find current price on the payout curve")?
-        };
+        let (taker, maker) = self.settlement_amounts(proposal.price)?;
 
-        if proposal.maker != *payout.maker_amount() || proposal.taker != *payout.taker_amount() {
-            bail!("The settlement amounts sent by the taker are not according to the agreed payout curve. Expected taker {} and maker {} but received taker {} and maker {}", payout.taker_amount(), payout.maker_amount(), proposal.taker, proposal.maker);
+        if proposal.maker != maker || proposal.taker != taker {
+            bail!("The settlement amounts sent by the taker are not according to the agreed payout curve. Expected taker {} and maker {} but received taker {} and maker {}", taker, maker, proposal.taker, proposal.maker);
         }
 
         Ok(Event::new(
@@ -937,7 +1338,9 @@ impl Cfd {
             SetupCompleted::Succeeded {
                 payload: (dlc, _), ..
             } => CfdEvent::ContractSetupCompleted { dlc },
-            SetupCompleted::Rejected { .. } => CfdEvent::OfferRejected,
+            SetupCompleted::Rejected { reason, .. } => {
+                CfdEvent::OfferRejected(reason.downcast_ref::<RejectReason>().copied())
+            }
             SetupCompleted::Failed { error, .. } => {
                 tracing::error!("Contract setup failed: {:#}", error);
 
@@ -1063,13 +1466,11 @@ impl Cfd {
     }
 
     pub fn handle_refund_timelock_expired(self) -> Result<Event, RefundTimelockExpiryError> {
-        use RefundTimelockExpiryError::*;
-
         if self.is_closed() {
-            return Err(AlreadyFinal);
+            return Err(TxUnavailable::AlreadyFinal.into());
         }
 
-        let dlc = self.dlc.as_ref().ok_or(NoDlc)?;
+        let dlc = self.dlc.as_ref().ok_or(TxUnavailable::NoDlc)?;
         let refund_tx = dlc.signed_refund_tx()?;
 
         let event = self.event(CfdEvent::RefundTimelockExpired { refund_tx });
@@ -1077,6 +1478,18 @@ impl Cfd {
         Ok(event)
     }
 
+    /// Record that the lock transaction was published, unless it is already final.
+    ///
+    /// Returns `None` if the event would not tell us anything new, e.g. because the CFD was
+    /// closed while the lock transaction was still pending.
+    pub fn handle_lock_seen(self) -> Option<Event> {
+        if self.lock_finality || self.is_closed() || self.is_in_force_close() {
+            return None;
+        }
+
+        Some(self.event(CfdEvent::LockSeen))
+    }
+
     pub fn handle_lock_confirmed(self) -> Event {
         // For the special case where we close when lock is still pending
         if self.is_closed() || self.is_in_force_close() {
@@ -1106,16 +1519,118 @@ impl Cfd {
         self.event(CfdEvent::RevokeConfirmed)
     }
 
+    /// Revert the finality of the transaction with the given `txid` after it was reorged out of
+    /// the chain.
+    pub fn handle_reorg(self, txid: Txid) -> Result<Event> {
+        let dlc = self
+            .dlc
+            .as_ref()
+            .context("Cannot handle reorg without a DLC")?;
+
+        if txid == dlc.lock.0.txid() {
+            return Ok(self.event(CfdEvent::LockReorged));
+        }
+
+        if txid == dlc.commit.0.txid() {
+            return Ok(self.event(CfdEvent::CommitReorged));
+        }
+
+        bail!("Reorged transaction {txid} is neither the lock nor the commit transaction")
+    }
+
+    /// Fails with [`TxUnavailable`] if the CFD cannot be committed in its current state.
     pub fn manual_commit_to_blockchain(&self) -> Result<Event> {
-        anyhow::ensure!(!self.is_closed());
+        if self.is_closed() {
+            return Err(TxUnavailable::AlreadyFinal.into());
+        }
 
-        let dlc = self.dlc.as_ref().context("Cannot commit without a DLC")?;
+        let dlc = self.dlc.as_ref().ok_or(TxUnavailable::NoDlc)?;
 
         Ok(self.event(CfdEvent::ManualCommit {
             tx: dlc.signed_commit_tx()?,
         }))
     }
 
+    /// List the transactions spending from the DLC together with whether they can currently be
+    /// broadcast.
+    ///
+    /// Transactions that cannot be broadcast in the current state carry the reason why not, which
+    /// allows presenting all recovery options to the user, e.g. when force closing.
+    pub fn spendable_transactions(&self) -> Vec<(SpendableTx, Result<Transaction>)> {
+        vec![
+            (SpendableTx::Commit, self.spendable_commit_tx()),
+            (SpendableTx::Refund, self.spendable_refund_tx()),
+            (SpendableTx::Cet, self.spendable_cet()),
+            (
+                SpendableTx::CollaborativeSettlement,
+                self.spendable_collaborative_settlement_tx(),
+            ),
+        ]
+    }
+
+    fn spendable_commit_tx(&self) -> Result<Transaction> {
+        let dlc = self.dlc.as_ref().ok_or(TxUnavailable::NoDlc)?;
+
+        if self.is_final() || self.is_collaboratively_closed() {
+            return Err(TxUnavailable::AlreadyFinal.into());
+        }
+        if self.commit_finality {
+            bail!("Commit transaction is already confirmed");
+        }
+
+        dlc.signed_commit_tx()
+    }
+
+    fn spendable_refund_tx(&self) -> Result<Transaction> {
+        let dlc = self.dlc.as_ref().ok_or(TxUnavailable::NoDlc)?;
+
+        if self.is_final() || self.is_collaboratively_closed() {
+            return Err(TxUnavailable::AlreadyFinal.into());
+        }
+        if !self.commit_finality {
+            bail!("Refund transaction requires the commit transaction to be confirmed");
+        }
+        if !self.refund_timelock_expired {
+            bail!("Refund timelock has not expired yet");
+        }
+
+        dlc.signed_refund_tx()
+    }
+
+    fn spendable_cet(&self) -> Result<Transaction> {
+        if self.dlc.is_none() {
+            return Err(TxUnavailable::NoDlc.into());
+        }
+
+        if self.is_final() || self.is_collaboratively_closed() {
+            return Err(TxUnavailable::AlreadyFinal.into());
+        }
+
+        let cet = self
+            .cet
+            .clone()
+            .context("CET requires an attestation of the settlement event")?;
+
+        if !self.commit_finality {
+            bail!("CET requires the commit transaction to be confirmed");
+        }
+        if !self.cet_timelock_expired {
+            bail!("CET timelock has not expired yet");
+        }
+
+        Ok(cet)
+    }
+
+    fn spendable_collaborative_settlement_tx(&self) -> Result<Transaction> {
+        if self.is_final() {
+            return Err(TxUnavailable::AlreadyFinal.into());
+        }
+
+        self.collaborative_settlement_spend_tx
+            .clone()
+            .context("CFD is not being settled collaboratively")
+    }
+
     fn event(&self, event: CfdEvent) -> Event {
         Event::new(self.id, event)
     }
@@ -1142,6 +1657,26 @@ impl Cfd {
             .ceil() as u32
     }
 
+    /// Ensure the CETs become spendable before the refund transaction and not unreasonably late.
+    ///
+    /// Both timelocks are relative to the confirmation of the commit transaction. A CET timelock
+    /// that is not below the refund timelock would let the refund transaction win over every CET.
+    pub fn validate_cet_timelock(&self, cet_timelock: u32) -> Result<()> {
+        let refund_timelock = self.refund_timelock_in_blocks();
+
+        if cet_timelock == 0 {
+            bail!("CET timelock must be at least one block")
+        }
+        if cet_timelock >= refund_timelock {
+            bail!("CET timelock {cet_timelock} must be below the refund timelock {refund_timelock}")
+        }
+        if cet_timelock > MAX_CET_TIMELOCK {
+            bail!("CET timelock {cet_timelock} exceeds the maximum of {MAX_CET_TIMELOCK} blocks")
+        }
+
+        Ok(())
+    }
+
     pub fn id(&self) -> OrderId {
         self.id
     }
@@ -1186,6 +1721,14 @@ impl Cfd {
         self.opening_fee
     }
 
+    pub fn n_payouts(&self) -> usize {
+        self.n_payouts
+    }
+
+    pub fn rounding(&self) -> RoundingStrategy {
+        self.rounding
+    }
+
     pub fn sign_collaborative_settlement_taker(
         &self,
         proposal: &SettlementProposal,
@@ -1201,6 +1744,16 @@ impl Cfd {
         Ok((tx, sig, script_pk))
     }
 
+    /// The script pubkeys of the maker and the taker payout addresses, in that order.
+    pub fn payout_script_pubkeys(&self) -> Result<(Script, Script)> {
+        let dlc = self.dlc.as_ref().context("No DLC present")?;
+
+        Ok((
+            dlc.script_pubkey_for(Role::Maker),
+            dlc.script_pubkey_for(Role::Taker),
+        ))
+    }
+
     pub fn version(&self) -> u64 {
         self.version
     }
@@ -1216,9 +1769,17 @@ impl Cfd {
                 self.dlc = Some(dlc);
                 self.during_contract_setup = false;
             }
-            OracleAttestedPostCetTimelock { cet, .. } => self.cet = Some(cet),
-            OracleAttestedPriorCetTimelock { timelocked_cet, .. } => {
+            OracleAttestedPostCetTimelock { cet, price } => {
+                self.cet = Some(cet);
+                self.attestation_price = Some(price);
+            }
+            OracleAttestedPriorCetTimelock {
+                timelocked_cet,
+                price,
+                ..
+            } => {
                 self.cet = Some(timelocked_cet);
+                self.attestation_price = Some(price);
             }
             ContractSetupFailed { .. } => {
                 // TODO: Deal with failed contract setup
@@ -1244,9 +1805,12 @@ impl Cfd {
                 self.settlement_proposal = Some(proposal)
             }
             CollaborativeSettlementProposalAccepted { .. } => {}
-            CollaborativeSettlementCompleted { spend_tx, .. } => {
+            CollaborativeSettlementCompleted {
+                spend_tx, price, ..
+            } => {
                 self.settlement_proposal = None;
                 self.collaborative_settlement_spend_tx = Some(spend_tx);
+                self.collaborative_settlement_price = Some(price);
             }
             CollaborativeSettlementRejected | CollaborativeSettlementFailed => {
                 self.settlement_proposal = None;
@@ -1255,14 +1819,17 @@ impl Cfd {
             RefundConfirmed => self.refund_finality = true,
             CollaborativeSettlementConfirmed => self.collaborative_settlement_finality = true,
             RefundTimelockExpired { .. } => self.refund_timelock_expired = true,
+            LockSeen => {}
             LockConfirmed => self.lock_finality = true,
             LockConfirmedAfterFinality => self.lock_finality = true,
             CommitConfirmed => self.commit_finality = true,
+            LockReorged => self.lock_finality = false,
+            CommitReorged => self.commit_finality = false,
             CetTimelockExpiredPriorOracleAttestation
             | CetTimelockExpiredPostOracleAttestation { .. } => {
                 self.cet_timelock_expired = true;
             }
-            OfferRejected => {
+            OfferRejected(_) => {
                 // nothing to do here? A rejection means it should be impossible to issue any
                 // commands
             }
@@ -1303,6 +1870,26 @@ pub fn calculate_long_margin(price: Price, quantity: Usd, leverage: Leverage) ->
     quantity / (price * leverage)
 }
 
+/// Calculates the margin of the given position including accumulated fees
+///
+/// Fees we owe the counterparty are added on top of our margin, whereas fees the counterparty owes
+/// us reduce it. This represents the amount effectively locked up in the CFD.
+pub fn calculate_margin_with_fees(
+    margin: Amount,
+    position: Position,
+    fee_account: &FeeAccount,
+) -> Amount {
+    match (fee_account.settle(), position) {
+        (FeeFlow::LongPaysShort(fee), Position::Long)
+        | (FeeFlow::ShortPaysLong(fee), Position::Short) => margin + fee,
+        (FeeFlow::LongPaysShort(fee), Position::Short)
+        | (FeeFlow::ShortPaysLong(fee), Position::Long) => {
+            margin.checked_sub(fee).unwrap_or(Amount::ZERO)
+        }
+        (FeeFlow::Nein, _) => margin,
+    }
+}
+
 /// Calculates the shorts's margin in BTC
 ///
 /// The short margin is represented as the quantity of the contract given the
@@ -1316,12 +1903,18 @@ pub fn calculate_long_liquidation_price(leverage: Leverage, price: Price) -> Pri
     price * leverage / (leverage + 1)
 }
 
+/// Number of decimal places the profit percentage is rounded to.
+///
+/// Dividing the profit by the margin in sats rarely terminates, rounding avoids reporting values
+/// like `31.999997984000016127999870976`.
+const PROFIT_PERCENT_DECIMAL_PLACES: u32 = 4;
+
 pub fn calculate_profit(payout: SignedAmount, margin: SignedAmount) -> (SignedAmount, Percent) {
     let profit = payout - margin;
 
     let profit_sats = Decimal::from(profit.as_sat());
     let margin_sats = Decimal::from(margin.as_sat());
-    let percent = dec!(100) * profit_sats / margin_sats;
+    let percent = (dec!(100) * profit_sats / margin_sats).round_dp(PROFIT_PERCENT_DECIMAL_PLACES);
 
     (profit, Percent(percent))
 }
@@ -1437,12 +2030,13 @@ impl Cet {
         (commit_tx, commit_descriptor): (&Transaction, &Descriptor<PublicKey>),
         maker_address: &Address,
         taker_address: &Address,
+        cet_timelock: u32,
     ) -> Result<Transaction> {
         let tx = Transaction {
             version: 2,
             input: vec![TxIn {
                 previous_output: commit_tx.outpoint(&commit_descriptor.script_pubkey())?,
-                sequence: CET_TIMELOCK,
+                sequence: cet_timelock,
                 ..Default::default()
             }],
             lock_time: 0,
@@ -1500,14 +2094,178 @@ pub struct Dlc {
     // and create an internal structure that depicts this properly and avoids duplication.
     pub settlement_event_id: BitMexPriceEventId,
     pub refund_timelock: u32,
+    /// Number of blocks the commit transaction has to be confirmed before a CET can spend it.
+    #[serde(default = "default_cet_timelock")]
+    pub cet_timelock: u32,
 }
 
-impl Dlc {
-    /// Create a close transaction based on the current contract and a settlement proposals
-    pub fn close_transaction(
-        &self,
-        proposal: &crate::model::cfd::SettlementProposal,
-    ) -> Result<(Transaction, Signature)> {
+/// On-chain fees of the different ways of closing a CFD.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct ClosingFeeEstimate {
+    /// Fee of the collaborative settlement transaction.
+    #[serde(with = "::bdk::bitcoin::util::amount::serde::as_btc")]
+    pub collaborative_settlement: Amount,
+    /// Combined fee of the commit transaction and the CET spending from it.
+    #[serde(with = "::bdk::bitcoin::util::amount::serde::as_btc")]
+    pub commit_and_cet: Amount,
+}
+
+/// Fee burden of force-closing a CFD by broadcasting its commit transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct CommitTxFee {
+    #[serde(with = "::bdk::bitcoin::util::amount::serde::as_btc")]
+    pub fee: Amount,
+    /// Virtual size of the signed commit transaction in vbytes.
+    pub vsize: u64,
+}
+
+impl Dlc {
+    /// Check that the transactions and descriptors of the DLC fit together.
+    ///
+    /// A corrupted DLC would otherwise only fail deep inside signing or broadcasting. We check
+    /// that the lock output funds the commit transaction, that the commit output funds the refund
+    /// transaction and that the lock and commit descriptors are built from our keys and the keys of
+    /// the counterparty. The CETs are not stored as transactions, they are built from the same
+    /// commit output as the refund transaction.
+    pub fn verify_integrity(&self) -> Result<()> {
+        let (lock_tx, lock_desc) = &self.lock;
+        let (commit_tx, _, commit_desc) = &self.commit;
+        let (refund_tx, _) = &self.refund;
+
+        let lock_outpoint = lock_tx
+            .outpoint(&lock_desc.script_pubkey())
+            .context("Lock transaction does not contain the lock output")?;
+        let lock_amount = lock_tx.output[lock_outpoint.vout as usize].value;
+        let expected_lock_amount = self.maker_lock_amount + self.taker_lock_amount;
+        anyhow::ensure!(
+            lock_amount == expected_lock_amount.as_sat(),
+            "Lock output of {lock_amount} sat does not match combined margin {expected_lock_amount}"
+        );
+
+        match commit_tx.input.as_slice() {
+            [input] if input.previous_output == lock_outpoint => {}
+            _ => bail!("Commit transaction does not spend the lock output {lock_outpoint}"),
+        }
+        let commit_amount = commit_tx
+            .output
+            .iter()
+            .map(|output| output.value)
+            .sum::<u64>();
+        anyhow::ensure!(
+            commit_amount <= lock_amount,
+            "Commit outputs of {commit_amount} sat exceed lock output of {lock_amount} sat"
+        );
+
+        let commit_outpoint = commit_tx
+            .outpoint(&commit_desc.script_pubkey())
+            .context("Commit transaction does not contain the commit output")?;
+        match refund_tx.input.as_slice() {
+            [input] if input.previous_output == commit_outpoint => {}
+            _ => bail!("Refund transaction does not spend the commit output {commit_outpoint}"),
+        }
+
+        let own_pk = |sk: &SecretKey| {
+            PublicKey::new(bdk::bitcoin::secp256k1::PublicKey::from_secret_key(
+                SECP256K1, sk,
+            ))
+        };
+        let own_keys = (
+            own_pk(&self.identity),
+            own_pk(&self.revocation),
+            own_pk(&self.publish),
+        );
+        let counterparty_keys = (
+            self.identity_counterparty,
+            self.revocation_pk_counterparty,
+            self.publish_pk_counterparty,
+        );
+
+        let lock_desc_matches = [
+            lock_descriptor(own_keys.0, counterparty_keys.0),
+            lock_descriptor(counterparty_keys.0, own_keys.0),
+        ]
+        .contains(lock_desc);
+        anyhow::ensure!(
+            lock_desc_matches,
+            "Lock descriptor does not match our and the counterparty's identity"
+        );
+
+        let commit_desc_matches = [
+            commit_descriptor(own_keys, counterparty_keys),
+            commit_descriptor(counterparty_keys, own_keys),
+        ]
+        .contains(commit_desc);
+        anyhow::ensure!(
+            commit_desc_matches,
+            "Commit descriptor does not match our and the counterparty's keys"
+        );
+
+        Ok(())
+    }
+
+    /// Estimate the fees for closing the contract via the collaborative settlement transaction and
+    /// via the commit transaction followed by a CET.
+    ///
+    /// The stored transactions are not signed yet, hence we add the maximum satisfaction weight of
+    /// the output they spend. The collaborative settlement transaction and the CETs have the same
+    /// shape as the refund transaction (one input, one output per party), so we use the refund
+    /// transaction's size for them.
+    pub fn estimate_closing_fees(&self, fee_rate: TxFeeRate) -> Result<ClosingFeeEstimate> {
+        let (_, lock_desc) = &self.lock;
+        let (commit_tx, _, commit_desc) = &self.commit;
+        let (refund_tx, _) = &self.refund;
+
+        let lock_satisfaction_weight = lock_desc.max_satisfaction_weight()?;
+        let commit_satisfaction_weight = commit_desc.max_satisfaction_weight()?;
+
+        let fee = |weight: usize| {
+            let vbytes = (weight as u64 + 3) / 4;
+            Amount::from_sat(vbytes * fee_rate.to_u32() as u64)
+        };
+
+        let collaborative_settlement = fee(refund_tx.weight() + lock_satisfaction_weight);
+        let commit = fee(commit_tx.weight() + lock_satisfaction_weight);
+        let cet = fee(refund_tx.weight() + commit_satisfaction_weight);
+
+        Ok(ClosingFeeEstimate {
+            collaborative_settlement,
+            commit_and_cet: commit + cet,
+        })
+    }
+
+    /// Compute the absolute fee and the size of the fully signed commit transaction.
+    ///
+    /// The fee is the difference between the lock output spent by the commit transaction and the
+    /// commit transaction's outputs.
+    pub fn commit_tx_fee(&self) -> Result<CommitTxFee> {
+        let (lock_tx, lock_desc) = &self.lock;
+        let commit_tx = self.signed_commit_tx()?;
+
+        let lock_outpoint = lock_tx
+            .outpoint(&lock_desc.script_pubkey())
+            .context("Lock script not found in lock transaction")?;
+        let lock_amount = lock_tx.output[lock_outpoint.vout as usize].value;
+        let commit_amount = commit_tx
+            .output
+            .iter()
+            .map(|output| output.value)
+            .sum::<u64>();
+
+        let fee = lock_amount
+            .checked_sub(commit_amount)
+            .context("Commit transaction spends more than the lock output")?;
+
+        Ok(CommitTxFee {
+            fee: Amount::from_sat(fee),
+            vsize: (commit_tx.weight() as u64 + 3) / 4,
+        })
+    }
+
+    /// Create a close transaction based on the current contract and a settlement proposals
+    pub fn close_transaction(
+        &self,
+        proposal: &crate::model::cfd::SettlementProposal,
+    ) -> Result<(Transaction, Signature)> {
         let (lock_tx, lock_desc) = &self.lock;
         let (lock_outpoint, lock_amount) = {
             let outpoint = lock_tx
@@ -1554,16 +2312,19 @@ impl Dlc {
     }
 
     pub fn refund_amount(&self, role: Role) -> Amount {
-        let our_script_pubkey = match role {
-            Role::Taker => self.taker_address.script_pubkey(),
-            Role::Maker => self.maker_address.script_pubkey(),
-        };
+        self.output_amount_for(&self.refund.0, role)
+    }
 
-        self.refund
-            .0
-            .output
+    /// The amount paid to the payout address of `role` by `tx`.
+    ///
+    /// Returns zero if `tx` does not pay to that address, e.g. a CET in which the party was
+    /// liquidated.
+    pub fn output_amount_for(&self, tx: &Transaction, role: Role) -> Amount {
+        let script_pubkey = self.script_pubkey_for(role);
+
+        tx.output
             .iter()
-            .find(|output| output.script_pubkey == our_script_pubkey)
+            .find(|output| output.script_pubkey == script_pubkey)
             .map(|output| Amount::from_sat(output.value))
             .unwrap_or_default()
     }
@@ -1653,6 +2414,7 @@ impl Dlc {
                 (&self.commit.0, &self.commit.2),
                 &self.maker_address,
                 &self.taker_address,
+                self.cet_timelock,
             )
             .context("Failed to reconstruct CET")?;
 
@@ -1719,6 +2481,9 @@ pub struct CollaborativeSettlement {
     price: Price,
 }
 
+/// The maximum amount each party contributes to the fee of a collaborative settlement.
+const MAX_SETTLEMENT_FEE_SHARE_SAT: u64 = 1_000;
+
 impl CollaborativeSettlement {
     pub fn new(tx: Transaction, own_script_pubkey: Script, price: Price) -> Result<Self> {
         // Falls back to Amount::ZERO in case we don't find an output that matches out script pubkey
@@ -1750,6 +2515,52 @@ impl CollaborativeSettlement {
     pub fn payout(&self) -> Amount {
         self.payout
     }
+
+    /// Verify that the settlement transaction pays out what was agreed on in the proposal.
+    ///
+    /// Each party contributes to the transaction fee, hence we accept outputs that are short of
+    /// the agreed amount by at most `MAX_SETTLEMENT_FEE_SHARE_SAT`. A missing output is treated
+    /// as a zero payout.
+    pub fn verify(
+        &self,
+        proposal: &SettlementProposal,
+        maker_script_pubkey: &Script,
+        taker_script_pubkey: &Script,
+    ) -> Result<()> {
+        if let Some(output) = self.tx.output.iter().find(|output| {
+            output.script_pubkey != *maker_script_pubkey
+                && output.script_pubkey != *taker_script_pubkey
+        }) {
+            bail!(
+                "Settlement transaction pays {} to unknown script {}",
+                Amount::from_sat(output.value),
+                output.script_pubkey
+            );
+        }
+
+        for (party, script_pubkey, agreed) in [
+            ("maker", maker_script_pubkey, proposal.maker),
+            ("taker", taker_script_pubkey, proposal.taker),
+        ] {
+            let paid = self
+                .tx
+                .output
+                .iter()
+                .find(|output| output.script_pubkey == *script_pubkey)
+                .map(|output| Amount::from_sat(output.value))
+                .unwrap_or(Amount::ZERO);
+
+            let fee_share = agreed.checked_sub(paid).with_context(|| {
+                format!("Settlement transaction pays {party} {paid} but {agreed} was agreed")
+            })?;
+
+            if fee_share.as_sat() > MAX_SETTLEMENT_FEE_SHARE_SAT {
+                bail!("Settlement transaction pays {party} {paid} but {agreed} was agreed");
+            }
+        }
+
+        Ok(())
+    }
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -2078,7 +2889,7 @@ mod tests {
             Leverage::new(2).unwrap(),
             empty_fee_long,
             SignedAmount::from_sat(3_174_603),
-            dec!(31.999997984000016127999870976).into(),
+            dec!(32).into(),
             "long position should make a profit when price goes up",
         );
 
@@ -2089,7 +2900,7 @@ mod tests {
             Leverage::new(2).unwrap(),
             empty_fee_short,
             SignedAmount::from_sat(-3_174_603),
-            dec!(-15.999998992000008063999935488).into(),
+            dec!(-16).into(),
             "short position should make a loss when price goes up",
         );
     }
@@ -2162,6 +2973,425 @@ mod tests {
         );
     }
 
+    #[test]
+    fn closing_price_without_attestation_or_settlement_is_current_price() {
+        let current_price = Price::new(dec!(40_000)).unwrap();
+        let cfd = Cfd::taker_long().dummy_open(dummy_event_id());
+
+        let closing_price = cfd.closing_price(current_price).unwrap();
+
+        assert_eq!(closing_price, current_price);
+    }
+
+    #[test]
+    fn closing_price_with_attestation_is_attested_price() {
+        let attested_price = Price::new(dec!(45_000)).unwrap();
+        let cfd = Cfd::taker_long()
+            .dummy_open(dummy_event_id())
+            .apply(Event::dummy_attestation(attested_price));
+
+        let closing_price = cfd
+            .closing_price(Price::new(dec!(40_000)).unwrap())
+            .unwrap();
+
+        assert_eq!(closing_price, attested_price);
+    }
+
+    #[test]
+    fn closing_price_with_collaborative_settlement_is_settlement_price() {
+        let settlement_price = Price::new(dec!(42_000)).unwrap();
+        let cfd = Cfd::taker_long()
+            .dummy_open(dummy_event_id())
+            .apply(Event::dummy_collab_settlement_completed(settlement_price));
+
+        let closing_price = cfd
+            .closing_price(Price::new(dec!(40_000)).unwrap())
+            .unwrap();
+
+        assert_eq!(closing_price, settlement_price);
+    }
+
+    #[test]
+    fn final_closing_price_is_only_known_once_attested_or_settled() {
+        let attested_price = Price::new(dec!(45_000)).unwrap();
+        let cfd = Cfd::taker_long().dummy_open(dummy_event_id());
+
+        assert_eq!(cfd.final_closing_price().unwrap(), None);
+
+        let cfd = cfd.apply(Event::dummy_attestation(attested_price));
+
+        assert_eq!(cfd.final_closing_price().unwrap(), Some(attested_price));
+    }
+
+    #[test]
+    fn closing_price_with_attestation_and_settlement_is_error() {
+        let cfd = Cfd::taker_long()
+            .dummy_open(dummy_event_id())
+            .apply(Event::dummy_collab_settlement_completed(
+                Price::new(dec!(42_000)).unwrap(),
+            ))
+            .apply(Event::dummy_attestation(Price::new(dec!(45_000)).unwrap()));
+
+        let result = cfd.closing_price(Price::new(dec!(40_000)).unwrap());
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn profit_uses_closing_price() {
+        let attested_price = Price::new(dec!(45_000)).unwrap();
+        let cfd = Cfd::taker_long()
+            .dummy_open(dummy_event_id())
+            .apply(Event::dummy_attestation(attested_price));
+        let (_, expected_profit, _) = calculate_profit_at_price(
+            cfd.initial_price,
+            attested_price,
+            cfd.quantity,
+            cfd.leverage,
+            cfd.fee_account,
+        )
+        .unwrap();
+
+        let current_price = Price::new(dec!(40_000)).unwrap();
+
+        assert_eq!(cfd.profit(current_price).unwrap(), expected_profit);
+    }
+
+    #[test]
+    fn profit_of_refunded_cfd_uses_refund_amount() {
+        let mut cfd = Cfd::taker_long().dummy_open(dummy_event_id());
+        let dlc = cfd.dlc.as_mut().unwrap();
+        dlc.taker_address = Address::from_str("1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2").unwrap();
+        dlc.refund.0.output = vec![TxOut {
+            value: 40_000,
+            script_pubkey: dlc.taker_address.script_pubkey(),
+        }];
+        let refund_tx = dlc.refund.0.clone();
+        let refund_timelock_expired =
+            Event::new(cfd.id(), CfdEvent::RefundTimelockExpired { refund_tx });
+        let cfd = cfd.apply(refund_timelock_expired);
+
+        let refund = SignedAmount::from_sat(40_000);
+        let margin = cfd.margin().to_signed().unwrap();
+        let (profit_btc, expected_profit) = calculate_profit(refund, margin);
+
+        let current_price = Price::new(dec!(60_000)).unwrap();
+
+        assert_eq!(profit_btc, refund - margin);
+        assert_eq!(cfd.profit(current_price).unwrap(), expected_profit);
+    }
+
+    #[test]
+    fn profit_percent_is_rounded_and_symmetric_at_equal_leverage() {
+        let initial_price = Price::new(dec!(50_400)).unwrap();
+        let closing_price = Price::new(dec!(60_000)).unwrap();
+        let quantity = Usd::new(dec!(10_000));
+        let leverage = Leverage::new(1).unwrap();
+
+        let (_, long_percent, _) = calculate_profit_at_price(
+            initial_price,
+            closing_price,
+            quantity,
+            leverage,
+            FeeAccount::new(Position::Long, Role::Taker),
+        )
+        .unwrap();
+        let (_, short_percent, _) = calculate_profit_at_price(
+            initial_price,
+            closing_price,
+            quantity,
+            leverage,
+            FeeAccount::new(Position::Short, Role::Maker),
+        )
+        .unwrap();
+
+        assert_eq!(long_percent, dec!(16).into());
+        assert!(long_percent.0.scale() <= PROFIT_PERCENT_DECIMAL_PLACES);
+        assert_eq!(
+            long_percent.0.checked_add(short_percent.0).unwrap(),
+            Decimal::ZERO
+        );
+    }
+
+    #[test]
+    fn auto_close_thresholds_are_breached_at_or_beyond_their_limits() {
+        let thresholds = AutoCloseThresholds {
+            take_profit: Some(dec!(50).into()),
+            stop_loss: Some(dec!(20).into()),
+        };
+
+        assert!(!thresholds.is_breached(dec!(0).into()));
+        assert!(!thresholds.is_breached(dec!(49.99).into()));
+        assert!(!thresholds.is_breached(dec!(-19.99).into()));
+        assert!(thresholds.is_breached(dec!(50).into()));
+        assert!(thresholds.is_breached(dec!(75).into()));
+        assert!(thresholds.is_breached(dec!(-20).into()));
+        assert!(thresholds.is_breached(dec!(-100).into()));
+        assert!(!AutoCloseThresholds::default().is_breached(dec!(-100).into()));
+    }
+
+    #[test]
+    fn min_taker_margin_of_short_order_is_long_margin_of_min_quantity() {
+        let order = Order::new_short(
+            Price::new(dec!(10_000)).unwrap(),
+            Usd::new(dec!(100)),
+            Usd::new(dec!(1_000)),
+            Origin::Ours,
+            dummy_event_id(),
+            dummy_oracle_pk(),
+            Duration::hours(24),
+            TxFeeRate::default(),
+            FundingRate::default(),
+            OpeningFee::default(),
+            N_PAYOUTS,
+            RoundingStrategy::default(),
+            Leverage::new(2).unwrap(),
+            CET_TIMELOCK,
+        )
+        .unwrap();
+
+        // 100 USD at 10_000 USD/BTC with leverage 2
+        assert_eq!(order.min_taker_margin(), Amount::from_sat(500_000));
+    }
+
+    #[test]
+    fn order_of_older_maker_without_optional_fields_deserializes() {
+        let order = Order::new_short(
+            Price::new(dec!(10_000)).unwrap(),
+            Usd::new(dec!(100)),
+            Usd::new(dec!(1_000)),
+            Origin::Theirs,
+            dummy_event_id(),
+            dummy_oracle_pk(),
+            Duration::hours(24),
+            TxFeeRate::default(),
+            FundingRate::default(),
+            OpeningFee::default(),
+            N_PAYOUTS,
+            RoundingStrategy::Bankers,
+            Leverage::new(2).unwrap(),
+            CET_TIMELOCK,
+        )
+        .unwrap();
+        let mut old_order = serde_json::to_value(&order).unwrap();
+        let fields = old_order.as_object_mut().unwrap();
+        fields.remove("oracle_pk").unwrap();
+        fields.remove("cet_timelock").unwrap();
+        fields.remove("rounding").unwrap();
+
+        let deserialized = serde_json::from_value::<Order>(old_order).unwrap();
+
+        assert_eq!(
+            deserialized,
+            Order {
+                oracle_pk: None,
+                cet_timelock: default_cet_timelock(),
+                rounding: RoundingStrategy::FeesUpPayoutsDown,
+                ..order
+            }
+        );
+    }
+
+    #[test]
+    fn order_with_excessive_n_payouts_is_rejected() {
+        let order = Order::new_short(
+            Price::new(dec!(10_000)).unwrap(),
+            Usd::new(dec!(100)),
+            Usd::new(dec!(1_000)),
+            Origin::Ours,
+            dummy_event_id(),
+            dummy_oracle_pk(),
+            Duration::hours(24),
+            TxFeeRate::default(),
+            FundingRate::default(),
+            OpeningFee::default(),
+            MAX_N_PAYOUTS + 1,
+            RoundingStrategy::default(),
+            Leverage::new(2).unwrap(),
+            CET_TIMELOCK,
+        );
+
+        assert!(order.is_err());
+        assert!(validate_n_payouts(0).is_err());
+        assert!(validate_n_payouts(MAX_N_PAYOUTS).is_ok());
+    }
+
+    #[test]
+    fn cet_timelock_must_be_positive_bounded_and_below_refund_timelock() {
+        let cfd = Cfd::dummy_not_open_yet();
+        let refund_timelock = cfd.refund_timelock_in_blocks();
+
+        assert!(cfd.validate_cet_timelock(0).is_err());
+        assert!(cfd.validate_cet_timelock(CET_TIMELOCK).is_ok());
+        assert!(cfd.validate_cet_timelock(MAX_CET_TIMELOCK).is_ok());
+        assert!(cfd.validate_cet_timelock(MAX_CET_TIMELOCK + 1).is_err());
+        assert!(cfd.validate_cet_timelock(refund_timelock).is_err());
+        assert!(cfd.start_contract_setup(0).is_err());
+    }
+
+    #[test]
+    fn contract_setup_uses_n_payouts_of_order() {
+        let order = Order {
+            n_payouts: 50,
+            ..Order::dummy_model()
+        };
+
+        let cfd = Cfd::from_order(
+            order,
+            Position::Long,
+            Usd::new(dec!(10)),
+            dummy_identity(),
+            Role::Taker,
+        );
+        let (_, setup_params) = cfd.start_contract_setup(CET_TIMELOCK).unwrap();
+
+        assert_eq!(cfd.n_payouts(), 50);
+        assert_eq!(setup_params.n_payouts(), 50);
+    }
+
+    #[test]
+    fn contract_setup_uses_rounding_of_order() {
+        let order = Order {
+            rounding: RoundingStrategy::Bankers,
+            ..Order::dummy_model()
+        };
+
+        let cfd = Cfd::from_order(
+            order,
+            Position::Long,
+            Usd::new(dec!(10)),
+            dummy_identity(),
+            Role::Taker,
+        );
+        let (_, setup_params) = cfd.start_contract_setup(CET_TIMELOCK).unwrap();
+
+        assert_eq!(cfd.rounding(), RoundingStrategy::Bankers);
+        assert_eq!(setup_params.rounding(), RoundingStrategy::Bankers);
+    }
+
+    #[test]
+    fn margin_with_fees_includes_fees_owed_and_deducts_fees_received() {
+        let opening_fee = OpeningFee::new(Amount::from_sat(1000));
+        let funding_fee = FundingFee::new(
+            Amount::from_sat(500),
+            FundingRate::new(dec!(0.001)).unwrap(),
+        );
+
+        let taker_long = Cfd::taker_long();
+        let taker_fees = FeeAccount::new(Position::Long, Role::Taker)
+            .add_opening_fee(opening_fee)
+            .add_funding_fee(funding_fee);
+
+        let maker_short = Cfd::maker_short();
+        let maker_fees = FeeAccount::new(Position::Short, Role::Maker)
+            .add_opening_fee(opening_fee)
+            .add_funding_fee(funding_fee);
+
+        assert_eq!(taker_long.margin(), Amount::from_sat(50_000_000));
+        assert_eq!(
+            taker_long.margin_with_fees(&taker_fees),
+            Amount::from_sat(50_001_500)
+        );
+        assert_eq!(maker_short.margin(), Amount::from_sat(100_000_000));
+        assert_eq!(
+            maker_short.margin_with_fees(&maker_fees),
+            Amount::from_sat(99_998_500)
+        );
+    }
+
+    #[test]
+    fn order_with_max_leverage_below_leverage_is_rejected() {
+        let order = Order::new_short(
+            Price::new(dec!(10_000)).unwrap(),
+            Usd::new(dec!(100)),
+            Usd::new(dec!(1_000)),
+            Origin::Ours,
+            dummy_event_id(),
+            dummy_oracle_pk(),
+            Duration::hours(24),
+            TxFeeRate::default(),
+            FundingRate::default(),
+            OpeningFee::default(),
+            N_PAYOUTS,
+            RoundingStrategy::default(),
+            Leverage::new(1).unwrap(),
+            CET_TIMELOCK,
+        );
+
+        assert!(order.is_err());
+    }
+
+    #[test]
+    fn order_with_zero_or_negative_quantity_is_rejected() {
+        for (min_quantity, max_quantity) in [(dec!(0), dec!(1_000)), (dec!(-100), dec!(1_000))] {
+            let order = Order::new_short(
+                Price::new(dec!(10_000)).unwrap(),
+                Usd::new(min_quantity),
+                Usd::new(max_quantity),
+                Origin::Ours,
+                dummy_event_id(),
+                dummy_oracle_pk(),
+                Duration::hours(24),
+                TxFeeRate::default(),
+                FundingRate::default(),
+                OpeningFee::default(),
+                N_PAYOUTS,
+                RoundingStrategy::default(),
+                Leverage::new(2).unwrap(),
+                CET_TIMELOCK,
+            );
+
+            assert!(order.is_err());
+        }
+    }
+
+    #[test]
+    fn order_taken_with_leverage_recalculates_liquidation_price() {
+        let leverage = Leverage::new(5).unwrap();
+
+        let order = Order::dummy_model().with_leverage(leverage);
+
+        assert_eq!(order.leverage, leverage);
+        assert_eq!(
+            order.liquidation_price,
+            calculate_long_liquidation_price(leverage, order.price)
+        );
+    }
+
+    #[test]
+    fn repriced_order_has_new_id_and_same_quantities() {
+        let order = Order::new_short(
+            Price::new(dec!(10_000)).unwrap(),
+            Usd::new(dec!(100)),
+            Usd::new(dec!(1_000)),
+            Origin::Ours,
+            dummy_event_id(),
+            dummy_oracle_pk(),
+            Duration::hours(24),
+            TxFeeRate::default(),
+            FundingRate::default(),
+            OpeningFee::default(),
+            N_PAYOUTS,
+            RoundingStrategy::default(),
+            Leverage::new(2).unwrap(),
+            CET_TIMELOCK,
+        )
+        .unwrap();
+        let new_price = Price::new(dec!(20_000)).unwrap();
+
+        let repriced = order.clone().with_price(new_price);
+
+        assert_ne!(repriced.id, order.id);
+        assert_eq!(repriced.price, new_price);
+        assert_eq!(
+            repriced.liquidation_price,
+            calculate_long_liquidation_price(order.leverage, new_price)
+        );
+        assert_eq!(repriced.min_quantity, order.min_quantity);
+        assert_eq!(repriced.max_quantity, order.max_quantity);
+        assert_eq!(repriced.oracle_event_id, order.oracle_event_id);
+    }
+
     #[test]
     fn margin_remains_constant() {
         let initial_price = Price::new(dec!(15_000)).unwrap();
@@ -2268,7 +3498,35 @@ mod tests {
 
         let event = CfdEvent::from_json(name, data).unwrap();
 
-        assert_eq!(event, CfdEvent::OfferRejected);
+        assert_eq!(event, CfdEvent::OfferRejected(None));
+    }
+
+    #[test]
+    fn offer_rejected_reason_roundtrips_through_json() {
+        let event = CfdEvent::OfferRejected(Some(RejectReason::ManualReject));
+
+        let (name, data) = event.to_json();
+        let deserialized = CfdEvent::from_json(name, data).unwrap();
+
+        assert_eq!(deserialized, event);
+    }
+
+    #[test]
+    fn rejected_setup_records_reason() {
+        let cfd = Cfd::taker_long();
+        let order_id = cfd.id();
+
+        let event = cfd
+            .setup_contract(SetupCompleted::rejected_due_to(
+                order_id,
+                RejectReason::AlreadyInProgress.into(),
+            ))
+            .unwrap();
+
+        assert_eq!(
+            event.event,
+            CfdEvent::OfferRejected(Some(RejectReason::AlreadyInProgress))
+        );
     }
 
     #[test]
@@ -2382,9 +3640,18 @@ mod tests {
             datetime!(2021-11-19 10:00:00).assume_utc(),
         ));
 
-        let cannot_roll_over = cfd.can_rollover().unwrap_err();
+        let cannot_roll_over = cfd.can_rollover().unwrap_err();
+
+        assert!(matches!(cannot_roll_over, NoRolloverReason::Closed))
+    }
+
+    #[test]
+    fn only_open_cfds_are_exposed() {
+        let event_id = dummy_event_id();
 
-        assert!(matches!(cannot_roll_over, NoRolloverReason::Closed))
+        assert!(!Cfd::dummy_not_open_yet().is_exposed());
+        assert!(Cfd::dummy_not_open_yet().dummy_open(event_id).is_exposed());
+        assert!(!Cfd::dummy_final(event_id).is_exposed());
     }
 
     #[test]
@@ -2396,6 +3663,7 @@ mod tests {
             Leverage::new(1).unwrap(),
             funding_rate,
             SETTLEMENT_INTERVAL.whole_hours(),
+            RoundingStrategy::default(),
         )
         .unwrap();
 
@@ -2426,12 +3694,114 @@ mod tests {
         assert_eq!(no_rollover_reason, NoRolloverReason::Closed);
     }
 
-    /// Cover scenario where trigger a collab settlement during ongoing rollover
+    /// The fee account is not stored on its own. It is rebuilt from the opening fee of the CFD
+    /// and the funding fees of the persisted rollover events.
+    #[tokio::test]
+    async fn accumulated_fees_survive_reopening_the_database() {
+        let path = std::env::temp_dir().join(format!("fee-account-{}.sqlite", OrderId::default()));
+        let cfd = Cfd::taker_long();
+        let events = Event::dummy_open(dummy_event_id())
+            .into_iter()
+            .chain(Event::dummy_rollover(1000, dec!(0.001)))
+            .chain(Event::dummy_rollover(500, dec!(0.001)))
+            .map(|event| Event {
+                id: cfd.id(),
+                ..event
+            })
+            .collect::<Vec<_>>();
+        let expected = events
+            .iter()
+            .cloned()
+            .fold(cfd.clone(), Cfd::apply)
+            .fee_account;
+
+        let pool = crate::db::connect(path.clone()).await.unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        crate::db::insert_cfd(&cfd, &mut conn).await.unwrap();
+        for event in events {
+            crate::db::append_event(event, &mut conn).await.unwrap();
+        }
+        drop(conn);
+        pool.close().await;
+
+        let pool = crate::db::connect(path.clone()).await.unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        let loaded = crate::cfd_actors::load_cfd(cfd.id(), &mut conn)
+            .await
+            .unwrap();
+        drop(conn);
+        pool.close().await;
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(loaded.fee_account, expected);
+        assert_ne!(loaded.fee_account, cfd.fee_account);
+    }
+
+    #[test]
+    fn funding_fees_shift_settlement_amounts_from_taker_to_maker() {
+        let price = Price::new(dec!(10000)).unwrap();
+        let quantity = Usd::new(dec!(10));
+        let leverage = Leverage::new(2).unwrap();
+        let taker_long = Cfd::taker_long()
+            .with_quantity(quantity)
+            .with_opening_price(price)
+            .with_leverage(leverage)
+            .dummy_open(dummy_event_id());
+
+        let (taker_before, maker_before) = taker_long.clone().settlement_amounts(price).unwrap();
+        let (taker_after, maker_after) = taker_long
+            .dummy_rollovers(1000, dec!(0.0001), 2)
+            .settlement_amounts(price)
+            .unwrap();
+
+        assert_eq!(taker_before - taker_after, Amount::from_sat(2000));
+        assert_eq!(maker_after - maker_before, Amount::from_sat(2000));
+    }
+
+    #[test]
+    fn settlement_not_paying_proposed_amounts_fails_verification() {
+        let opening_price = Price::new(dec!(10000)).unwrap();
+
+        let taker_keys = crate::keypair::new(&mut rand::thread_rng());
+        let maker_keys = crate::keypair::new(&mut rand::thread_rng());
+
+        let cfd = Cfd::taker_long()
+            .with_quantity(Usd::new(dec!(10)))
+            .with_opening_price(opening_price)
+            .with_leverage(Leverage::new(2).unwrap())
+            .dummy_open(dummy_event_id())
+            .with_lock(taker_keys, maker_keys);
+
+        let proposal = match cfd
+            .propose_collaborative_settlement(opening_price)
+            .unwrap()
+            .event
+        {
+            CfdEvent::CollaborativeSettlementStarted { proposal } => proposal,
+            _ => panic!("Proposal not created!"),
+        };
+        let (tx, _, taker_script) = cfd.sign_collaborative_settlement_taker(&proposal).unwrap();
+        let settlement = CollaborativeSettlement::new(tx, taker_script, opening_price).unwrap();
+        let (maker_script, taker_script) = cfd.payout_script_pubkeys().unwrap();
+
+        settlement
+            .verify(&proposal, &maker_script, &taker_script)
+            .unwrap();
+
+        let mismatched = SettlementProposal {
+            taker: proposal.taker + Amount::from_sat(10_000),
+            ..proposal
+        };
+        let result = settlement.verify(&mismatched, &maker_script, &taker_script);
+
+        assert!(result.is_err());
+    }
+
+    /// Cover scenario where a rollover finishes after a collab settlement
     ///
-    /// In this scenario the collab settlement finished before the rollover finished.
-    /// If we trigger a collaborative settlement during rollover, the settlement will have priority
-    /// over the rollover. Upon finishing the rollover we fail because the cfd was already
-    /// settled.
+    /// In this scenario the collab settlement finished before the rollover finished, which is only
+    /// possible for CFDs whose events were recorded before both were made mutually exclusive.
+    /// Upon finishing the rollover we fail because the cfd was already settled.
     #[test]
     fn given_collab_settlement_finished_then_cannot_finish_rollover() {
         let quantity = Usd::new(dec!(10));
@@ -2459,12 +3829,11 @@ mod tests {
         assert_eq!(no_rollover_reason, NoRolloverReason::Closed);
     }
 
-    /// Cover scenario where trigger a collab settlement during ongoing rollover
+    /// Cover scenario where a rollover finishes during an ongoing collab settlement
     ///
-    /// In this scenario the collab settlement is still ongoing when the rollover finishes.
-    /// If we trigger a collaborative settlement during rollover, the settlement will have priority
-    /// over the rollover. Upon finishing the rollover we fail because the cfd was already
-    /// settled.
+    /// In this scenario the collab settlement is still ongoing when the rollover finishes, which is
+    /// only possible for CFDs whose events were recorded before both were made mutually exclusive.
+    /// Upon finishing the rollover we fail because the cfd is being settled.
     #[test]
     fn given_ongoing_collab_settlement_then_cannot_finish_rollover() {
         let cfd = Cfd::taker_long()
@@ -2512,45 +3881,179 @@ mod tests {
     }
 
     #[test]
-    fn given_ongoing_rollover_then_can_start_collaborative_settlement() {
-        let quantity = Usd::new(dec!(10));
-        let leverage = Leverage::new(2).unwrap();
-        let opening_price = Price::new(dec!(10000)).unwrap();
-        let order_id = OrderId::default();
-
+    fn given_ongoing_rollover_then_cannot_start_collaborative_settlement() {
         let taker_keys = crate::keypair::new(&mut rand::thread_rng());
         let maker_keys = crate::keypair::new(&mut rand::thread_rng());
+        let price = Price::new(dec!(10000)).unwrap();
 
         let taker_long = Cfd::taker_long()
-            .with_id(order_id)
-            .with_quantity(quantity)
-            .with_opening_price(opening_price)
-            .with_leverage(leverage)
             .dummy_open(dummy_event_id())
-            .dummy_start_rollover()
-            .with_lock(taker_keys, maker_keys);
+            .with_lock(taker_keys, maker_keys)
+            .dummy_start_rollover();
+        assert!(taker_long.is_rollover_in_progress());
+
+        let error = taker_long
+            .propose_collaborative_settlement(price)
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Cannot settle collaboratively while the CFD is being rolled over"
+        );
+        assert!(!taker_long.available_actions().contains(&CfdAction::Settle));
 
+        let (_, proposal, _, _) = Cfd::taker_long()
+            .dummy_open(dummy_event_id())
+            .with_lock(taker_keys, maker_keys)
+            .dummy_collab_settlement_taker(price);
         let maker_short = Cfd::maker_short()
-            .with_id(order_id)
-            .with_quantity(quantity)
-            .with_opening_price(opening_price)
-            .with_leverage(leverage)
             .dummy_open(dummy_event_id())
             .with_lock(taker_keys, maker_keys)
             .dummy_start_rollover();
 
-        let (taker_long, proposal, taker_sig, _) =
-            taker_long.dummy_collab_settlement_taker(opening_price);
+        let error = maker_short
+            .receive_collaborative_settlement_proposal(proposal)
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Cannot settle collaboratively while the CFD is being rolled over"
+        );
+    }
 
-        let (maker_short, _) = maker_short.dummy_collab_settlement_maker(proposal, taker_sig);
+    #[test]
+    fn given_open_cfd_without_attestation_then_settle_and_rollover_available() {
+        let cfd = Cfd::taker_long().dummy_open(dummy_event_id());
 
-        assert!(
-            taker_long.collaborative_settlement_spend_tx.is_some(),
-            "No settlement tx even though the settlement passed"
+        let actions = cfd.available_actions();
+
+        assert_eq!(
+            actions,
+            vec![CfdAction::Commit, CfdAction::Settle, CfdAction::Rollover],
+            "refund is never available as an action"
+        );
+    }
+
+    #[test]
+    fn given_attested_cfd_then_neither_settle_nor_rollover_available() {
+        let cfd = Cfd::dummy_with_attestation(dummy_event_id());
+
+        let actions = cfd.available_actions();
+
+        assert!(!actions.contains(&CfdAction::Settle));
+        assert!(!actions.contains(&CfdAction::Rollover));
+    }
+
+    #[test]
+    fn collaborative_settlement_is_cheaper_than_commit_and_cet() {
+        let taker_keys = crate::keypair::new(&mut rand::thread_rng());
+        let maker_keys = crate::keypair::new(&mut rand::thread_rng());
+
+        let cfd = Cfd::taker_long()
+            .dummy_open(dummy_event_id())
+            .with_lock(taker_keys, maker_keys);
+
+        let estimate = cfd.estimate_closing_fees().unwrap();
+
+        assert!(estimate.collaborative_settlement > Amount::ZERO);
+        assert!(estimate.collaborative_settlement < estimate.commit_and_cet);
+    }
+
+    #[test]
+    fn open_cfd_can_be_committed_but_not_refunded() {
+        let taker_keys = crate::keypair::new(&mut rand::thread_rng());
+        let maker_keys = crate::keypair::new(&mut rand::thread_rng());
+
+        let mut cfd = Cfd::taker_long()
+            .dummy_open(dummy_event_id())
+            .with_lock(taker_keys, maker_keys);
+        let dlc = cfd.dlc.as_mut().unwrap();
+        let lock_outpoint = dlc.lock.0.outpoint(&dlc.lock.1.script_pubkey()).unwrap();
+        dlc.commit.0.input = vec![TxIn {
+            previous_output: lock_outpoint,
+            ..Default::default()
+        }];
+
+        let spendable = cfd
+            .spendable_transactions()
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+
+        assert!(spendable[&SpendableTx::Commit].is_ok());
+        assert!(spendable[&SpendableTx::Refund].is_err());
+        assert!(spendable[&SpendableTx::Cet].is_err());
+        assert!(spendable[&SpendableTx::CollaborativeSettlement].is_err());
+    }
+
+    #[test]
+    fn commit_tx_fee_is_difference_between_lock_output_and_commit_outputs() {
+        let taker_keys = crate::keypair::new(&mut rand::thread_rng());
+        let maker_keys = crate::keypair::new(&mut rand::thread_rng());
+
+        let mut cfd = Cfd::taker_long()
+            .dummy_open(dummy_event_id())
+            .with_lock(taker_keys, maker_keys);
+        let dlc = cfd.dlc.as_mut().unwrap();
+
+        let lock_outpoint = dlc.lock.0.outpoint(&dlc.lock.1.script_pubkey()).unwrap();
+        let lock_amount = dlc.lock.0.output[lock_outpoint.vout as usize].value;
+        dlc.commit.0 = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: lock_outpoint,
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: lock_amount - 1_000,
+                script_pubkey: dlc.commit.2.script_pubkey(),
+            }],
+        };
+        let signed_commit_tx = dlc.signed_commit_tx().unwrap();
+
+        let commit_tx_fee = cfd.commit_tx_fee().unwrap();
+
+        let output_amount = signed_commit_tx
+            .output
+            .iter()
+            .map(|output| output.value)
+            .sum::<u64>();
+        assert_eq!(
+            commit_tx_fee.fee,
+            Amount::from_sat(lock_amount - output_amount)
+        );
+        assert_eq!(commit_tx_fee.fee, Amount::from_sat(1_000));
+        assert_eq!(
+            commit_tx_fee.vsize,
+            (signed_commit_tx.weight() as u64 + 3) / 4
         );
+    }
+
+    #[test]
+    fn dlc_with_commit_tx_not_spending_lock_output_is_inconsistent() {
+        let taker_keys = crate::keypair::new(&mut rand::thread_rng());
+        let maker_keys = crate::keypair::new(&mut rand::thread_rng());
+
+        let cfd = Cfd::taker_long()
+            .dummy_open(dummy_event_id())
+            .with_lock(taker_keys, maker_keys);
+
+        let error = cfd.dlc.unwrap().verify_integrity().unwrap_err();
+
+        let message = format!("{error:#}");
         assert!(
-            maker_short.collaborative_settlement_spend_tx.is_some(),
-            "No settlement tx even though the settlement passed"
+            message.contains("Commit transaction does not spend the lock output"),
+            "{message}"
+        );
+    }
+
+    #[test]
+    fn given_maker_cfd_pending_setup_then_can_accept_or_reject() {
+        let cfd = Cfd::maker_short();
+
+        let actions = cfd.available_actions();
+
+        assert_eq!(
+            actions,
+            vec![CfdAction::AcceptOrder, CfdAction::RejectOrder]
         );
     }
 
@@ -2572,10 +4075,11 @@ mod tests {
 
         let (cfd, _, _, _) = cfd.dummy_collab_settlement_taker(opening_price);
 
-        // TODO: Assert on the error string
-        assert!(
-            cfd.manual_commit_to_blockchain().is_err(),
-            "Manual commit to blockchain did not error"
+        let error = cfd.manual_commit_to_blockchain().unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<TxUnavailable>(),
+            Some(&TxUnavailable::AlreadyFinal),
+            "Committing a settled CFD should be unavailable"
         );
         assert!(
             cfd.decrypt_cet(&Attestation::dummy()).unwrap().is_none(),
@@ -2583,6 +4087,35 @@ mod tests {
         );
     }
 
+    #[test]
+    fn commit_and_refund_are_unavailable_without_dlc() {
+        let cfd = Cfd::taker_long();
+
+        let commit_error = cfd.manual_commit_to_blockchain().unwrap_err();
+        let refund_error = cfd.handle_refund_timelock_expired().unwrap_err();
+
+        assert_eq!(
+            commit_error.downcast_ref::<TxUnavailable>(),
+            Some(&TxUnavailable::NoDlc)
+        );
+        assert!(matches!(
+            refund_error,
+            RefundTimelockExpiryError::Unavailable(TxUnavailable::NoDlc)
+        ));
+    }
+
+    #[test]
+    fn refund_is_unavailable_once_final() {
+        let cfd = Cfd::dummy_final(dummy_event_id());
+
+        let error = cfd.handle_refund_timelock_expired().unwrap_err();
+
+        assert!(matches!(
+            error,
+            RefundTimelockExpiryError::Unavailable(TxUnavailable::AlreadyFinal)
+        ));
+    }
+
     #[test]
     fn given_commit_when_lock_confirmed_then_lock_confirmed_after_finality() {
         let taker_long = Cfd::taker_long()
@@ -2654,6 +4187,70 @@ mod tests {
         assert_eq!(maker_event.event, CfdEvent::LockConfirmedAfterFinality);
     }
 
+    #[test]
+    fn given_rejected_or_failed_settlement_then_can_propose_settlement_again() {
+        let price = Price::new(dec!(1000)).unwrap();
+
+        for outcome in [
+            CfdEvent::CollaborativeSettlementRejected,
+            CfdEvent::CollaborativeSettlementFailed,
+        ] {
+            let taker_long = Cfd::taker_long()
+                .dummy_open(dummy_event_id())
+                .dummy_start_collab_settlement();
+            let order_id = taker_long.id();
+            assert!(taker_long.propose_collaborative_settlement(price).is_err());
+
+            let taker_long = taker_long.apply(Event::new(order_id, outcome));
+
+            assert!(!taker_long.is_closed());
+            assert!(taker_long.can_settle_collaboratively());
+            assert!(taker_long.propose_collaborative_settlement(price).is_ok());
+        }
+    }
+
+    #[test]
+    fn given_manual_commit_then_commit_tx_is_stored_and_cfd_is_in_force_close() {
+        let taker_long = Cfd::taker_long()
+            .dummy_open(dummy_event_id())
+            .dummy_commit();
+
+        assert_eq!(taker_long.commit_tx, Some(dummy_transaction()));
+        assert!(taker_long.is_in_force_close());
+        assert!(!taker_long.is_closed());
+        assert!(!taker_long.can_commit());
+        assert_eq!(taker_long.can_rollover(), Err(NoRolloverReason::Committed));
+    }
+
+    #[test]
+    fn in_force_close_only_if_we_emitted_the_commit_tx() {
+        let manually_committed = Cfd::taker_long()
+            .dummy_open(dummy_event_id())
+            .dummy_commit();
+        let committed_by_counterparty = Cfd::taker_long()
+            .dummy_open(dummy_event_id())
+            .apply(Event::new(OrderId::default(), CfdEvent::CommitConfirmed));
+
+        assert!(manually_committed.is_in_force_close());
+        assert!(!committed_by_counterparty.is_in_force_close());
+    }
+
+    #[test]
+    fn given_rollover_rejected_then_cfd_stays_open_and_can_roll_over_again() {
+        let taker_long = Cfd::taker_long()
+            .dummy_open(dummy_event_id())
+            .dummy_start_rollover();
+        let order_id = taker_long.id();
+        assert!(taker_long.start_rollover().is_err());
+
+        let taker_long = taker_long.apply(Event::new(order_id, CfdEvent::RolloverRejected));
+
+        assert!(!taker_long.during_rollover);
+        assert!(!taker_long.is_closed());
+        assert_eq!(taker_long.can_rollover(), Ok(()));
+        assert!(taker_long.start_rollover().is_ok());
+    }
+
     #[test]
     fn given_commit_then_cannot_collab_close() {
         let taker_long = Cfd::taker_long()
@@ -2664,14 +4261,90 @@ mod tests {
             .dummy_open(dummy_event_id())
             .dummy_commit();
 
-        let result_taker = taker_long.propose_collaborative_settlement(Price::dummy(), N_PAYOUTS);
-        let result_maker = maker_short
-            .receive_collaborative_settlement_proposal(SettlementProposal::dummy(), N_PAYOUTS);
+        let result_taker = taker_long.propose_collaborative_settlement(Price::dummy());
+        let result_maker =
+            maker_short.receive_collaborative_settlement_proposal(SettlementProposal::dummy());
 
         assert!(result_taker.is_err(), "When having commit tx available we should not be able to trigger collaborative settlement");
         assert!(result_maker.is_err(), "When having commit tx available we should not be able to trigger collaborative settlement");
     }
 
+    #[test]
+    fn given_commit_confirmed_when_commit_reorged_then_can_rollover_again() {
+        let cfd = Cfd::taker_long().dummy_open(dummy_event_id());
+        let order_id = cfd.id();
+
+        let event = cfd.clone().handle_commit_confirmed();
+        let cfd = cfd.apply(event);
+        assert_eq!(cfd.can_rollover(), Err(NoRolloverReason::Committed));
+
+        let cfd = cfd.apply(Event::new(order_id, CfdEvent::CommitReorged));
+
+        assert!(!cfd.commit_finality);
+        assert_eq!(cfd.can_rollover(), Ok(()));
+    }
+
+    #[test]
+    fn cet_is_reconstructed_with_the_dlc_cet_timelock() {
+        let cet_timelock = 20;
+        let dlc = Dlc::dummy(None);
+        let commit_descriptor = dlc.commit.2.clone();
+        let commit_tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![],
+            output: vec![TxOut {
+                value: 1_000,
+                script_pubkey: commit_descriptor.script_pubkey(),
+            }],
+        };
+        let expected_cet = Transaction {
+            version: 2,
+            input: vec![TxIn {
+                previous_output: bdk::bitcoin::OutPoint::new(commit_tx.txid(), 0),
+                sequence: cet_timelock,
+                ..Default::default()
+            }],
+            lock_time: 0,
+            output: vec![
+                TxOut {
+                    value: 600,
+                    script_pubkey: dlc.maker_address.script_pubkey(),
+                },
+                TxOut {
+                    value: 400,
+                    script_pubkey: dlc.taker_address.script_pubkey(),
+                },
+            ],
+        };
+        let cet = Cet {
+            maker_amount: Amount::from_sat(600),
+            taker_amount: Amount::from_sat(400),
+            adaptor_sig: dlc.cets.values().next().unwrap()[0].adaptor_sig,
+            range: RangeInclusive::new(0, 1),
+            n_bits: 0,
+            txid: expected_cet.txid(),
+        };
+        let commit = (&commit_tx, &commit_descriptor);
+
+        let reconstructed = cet
+            .to_tx(commit, &dlc.maker_address, &dlc.taker_address, cet_timelock)
+            .unwrap();
+
+        assert_eq!(reconstructed, expected_cet);
+        assert!(cet
+            .to_tx(commit, &dlc.maker_address, &dlc.taker_address, CET_TIMELOCK)
+            .is_err());
+    }
+
+    #[test]
+    fn given_lock_already_final_then_lock_seen_is_ignored() {
+        let cfd = Cfd::taker_long().dummy_open(dummy_event_id());
+
+        assert!(cfd.lock_finality);
+        assert!(cfd.handle_lock_seen().is_none());
+    }
+
     #[test]
     fn ensure_collaborative_settlement_takes_rollover_fees_into_account() {
         let quantity = Usd::new(dec!(10));
@@ -2815,9 +4488,9 @@ mod tests {
             let funding_fee_for_whole_interval =
                 calculate_funding_fee(
                     price,
-                    quantity, leverage , funding_rate, SETTLEMENT_INTERVAL.whole_hours()).unwrap();
+                    quantity, leverage , funding_rate, SETTLEMENT_INTERVAL.whole_hours(), RoundingStrategy::default()).unwrap();
             let funding_fee_for_one_hour =
-                calculate_funding_fee(price, quantity, leverage, funding_rate, 1).unwrap();
+                calculate_funding_fee(price, quantity, leverage, funding_rate, 1, RoundingStrategy::default()).unwrap();
             let fee_account = FeeAccount::new(Position::Long, Role::Taker);
 
             let fee_account_whole_interval = fee_account.add_funding_fee(funding_fee_for_whole_interval);
@@ -2920,6 +4593,29 @@ mod tests {
             open
         }
 
+        fn dummy_attestation(price: Price) -> Self {
+            Event {
+                timestamp: Timestamp::now(),
+                id: Default::default(),
+                event: CfdEvent::OracleAttestedPostCetTimelock {
+                    cet: dummy_transaction(),
+                    price,
+                },
+            }
+        }
+
+        fn dummy_collab_settlement_completed(price: Price) -> Self {
+            Event {
+                timestamp: Timestamp::now(),
+                id: Default::default(),
+                event: CfdEvent::CollaborativeSettlementCompleted {
+                    spend_tx: dummy_transaction(),
+                    script: Script::new(),
+                    price,
+                },
+            }
+        }
+
         fn dummy_manual_commit() -> Vec<Self> {
             vec![Event {
                 timestamp: Timestamp::now(),
@@ -3008,12 +4704,14 @@ mod tests {
             };
 
             let taker_params = BuildPartyParams {
+                order_id: self.id,
                 amount: taker_margin,
                 identity_pk: pk_taker,
                 fee_rate: Default::default(),
             };
 
             let maker_params = BuildPartyParams {
+                order_id: self.id,
                 amount: maker_margin,
                 identity_pk: pk_maker,
                 fee_rate: Default::default(),
@@ -3070,9 +4768,7 @@ mod tests {
         ) -> (Self, SettlementProposal, Signature, Script) {
             let mut events = Vec::new();
 
-            let propose = self
-                .propose_collaborative_settlement(price, N_PAYOUTS)
-                .unwrap();
+            let propose = self.propose_collaborative_settlement(price).unwrap();
             events.push(propose.clone());
 
             let settlement_proposal =
@@ -3112,7 +4808,7 @@ mod tests {
             let mut events = Vec::new();
             let incoming_settlement = self
                 .clone()
-                .receive_collaborative_settlement_proposal(proposal.clone(), N_PAYOUTS)
+                .receive_collaborative_settlement_proposal(proposal.clone())
                 .unwrap();
             events.push(incoming_settlement);
 
@@ -3214,10 +4910,15 @@ mod tests {
                 Usd::new(dec!(1000)),
                 Origin::Ours,
                 dummy_event_id(),
+                dummy_oracle_pk(),
                 time::Duration::hours(24),
                 TxFeeRate::default(),
                 FundingRate::default(),
                 OpeningFee::default(),
+                N_PAYOUTS,
+                RoundingStrategy::default(),
+                Leverage::new(10).unwrap(),
+                CET_TIMELOCK,
             )
             .unwrap()
         }
@@ -3302,6 +5003,7 @@ mod tests {
                     None => dummy_event_id(),
                 },
                 refund_timelock: 0,
+                cet_timelock: CET_TIMELOCK,
             }
         }
     }
@@ -3310,6 +5012,45 @@ mod tests {
         dummy_partially_signed_transaction().extract_tx()
     }
 
+    #[test]
+    fn output_amount_for_finds_each_partys_output() {
+        let maker_address = Address::from_str("132F25rTsvBdp9JzLLBHP5mvGY66i1xdiM").unwrap();
+        let taker_address = Address::from_str("1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2").unwrap();
+        let mut dlc = Dlc::dummy(None);
+        dlc.maker_address = maker_address.clone();
+        dlc.taker_address = taker_address.clone();
+        dlc.refund.0.output = vec![
+            TxOut {
+                value: 60_000,
+                script_pubkey: maker_address.script_pubkey(),
+            },
+            TxOut {
+                value: 40_000,
+                script_pubkey: taker_address.script_pubkey(),
+            },
+        ];
+        let liquidation_cet = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![],
+            output: vec![TxOut {
+                value: 100_000,
+                script_pubkey: maker_address.script_pubkey(),
+            }],
+        };
+
+        assert_eq!(dlc.refund_amount(Role::Maker), Amount::from_sat(60_000));
+        assert_eq!(dlc.refund_amount(Role::Taker), Amount::from_sat(40_000));
+        assert_eq!(
+            dlc.output_amount_for(&liquidation_cet, Role::Maker),
+            Amount::from_sat(100_000)
+        );
+        assert_eq!(
+            dlc.output_amount_for(&liquidation_cet, Role::Taker),
+            Amount::ZERO
+        );
+    }
+
     pub fn dummy_partially_signed_transaction() -> PartiallySignedTransaction {
         // very simple dummy psbt that does not contain anything
         // pulled in from github.com-1ecc6299db9ec823/bitcoin-0.27.1/src/util/psbt/mod.rs:238
@@ -3340,6 +5081,13 @@ mod tests {
         BitMexPriceEventId::with_20_digits(OffsetDateTime::now_utc())
     }
 
+    fn dummy_oracle_pk() -> schnorrsig::PublicKey {
+        schnorrsig::PublicKey::from_str(
+            "ddd4636845a90185991826be5a494cde9f4a6947b1727217afedc6292fa4caf7",
+        )
+        .unwrap()
+    }
+
     fn extract_payout_amount(tx: Transaction, script: Script) -> Amount {
         tx.output
             .into_iter()
@@ -6,7 +6,6 @@ use crate::model::cfd;
 use crate::model::cfd::CfdEvent;
 use crate::model::cfd::Dlc;
 use crate::model::cfd::OrderId;
-use crate::model::cfd::CET_TIMELOCK;
 use crate::model::BitMexPriceEventId;
 use crate::oracle;
 use crate::oracle::Attestation;
@@ -16,7 +15,9 @@ use crate::Tasks;
 use anyhow::Context;
 use anyhow::Result;
 use async_trait::async_trait;
+use bdk::bitcoin::util::psbt::PartiallySignedTransaction;
 use bdk::bitcoin::Address;
+use bdk::bitcoin::OutPoint;
 use bdk::bitcoin::PublicKey;
 use bdk::bitcoin::Script;
 use bdk::bitcoin::Txid;
@@ -26,11 +27,14 @@ use bdk::electrum_client::ElectrumApi;
 use bdk::electrum_client::GetHistoryRes;
 use bdk::electrum_client::HeaderNotification;
 use bdk::miniscript::DescriptorTrait;
+use serde::Deserialize;
+use serde::Serialize;
 use serde_json::Value;
 use sqlx::SqlitePool;
 use std::collections::hash_map::Entry;
 use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::convert::TryFrom;
 use std::convert::TryInto;
 use std::fmt;
@@ -44,6 +48,12 @@ use xtras::SendInterval;
 
 const FINALITY_CONFIRMATIONS: u32 = 1;
 
+/// Number of confirmations after which we no longer expect a transaction to be reorged out.
+///
+/// Until a transaction that triggered a revertable finality event reaches this depth, we keep
+/// watching it so we can emit [`Event::Reorg`] if it disappears from the chain.
+const REORG_SAFETY_CONFIRMATIONS: u32 = 6;
+
 pub struct StartMonitoring {
     pub id: OrderId,
     pub params: MonitorParams,
@@ -54,12 +64,27 @@ pub struct CollaborativeSettlement {
     pub tx: (Txid, Script),
 }
 
+/// Rebuild the monitoring of a CFD from its events and sync with the blockchain right away.
+///
+/// Returns the oracle event the CFD settles on, if it is still being monitored.
+pub struct Rescan {
+    pub id: OrderId,
+}
+
+/// Find the inputs of a transaction that spend outputs which are not confirmed yet.
+///
+/// Returns the outpoints of all inputs whose funding transaction is not in a block.
+pub struct GetUnconfirmedInputs {
+    pub psbt: PartiallySignedTransaction,
+}
+
 // TODO: The design of this struct causes a lot of marshalling und unmarshelling that is quite
 // unnecessary. Should be taken apart so we can handle all cases individually!
 #[derive(Clone)]
 pub struct MonitorParams {
     lock: (Txid, Descriptor<PublicKey>),
     commit: (Txid, Descriptor<PublicKey>),
+    cet_timelock: u32,
     cets: HashMap<BitMexPriceEventId, Vec<Cet>>,
     refund: (Txid, Script, u32),
     revoked_commits: Vec<(Txid, Script)>,
@@ -128,6 +153,21 @@ struct RpcError {
     message: String,
 }
 
+impl RpcError {
+    /// Whether the error tells us that the transaction is already in the mempool or on-chain.
+    fn is_already_published(&self) -> bool {
+        if self.code == i64::from(RpcErrorCode::RpcVerifyAlreadyInChain) {
+            return true;
+        }
+
+        self.code == i64::from(RpcErrorCode::RpcVerifyRejected)
+            && matches!(
+                self.message.as_str(),
+                "txn-already-known" | "txn-already-in-mempool"
+            )
+    }
+}
+
 pub struct Sync;
 
 // TODO: Send messages to the projection actor upon finality events so we send out updates.
@@ -146,6 +186,9 @@ struct State {
     latest_block_height: BlockHeight,
     current_status: BTreeMap<(Txid, Script), ScriptStatus>,
     awaiting_status: HashMap<(Txid, Script), Vec<(ScriptStatus, Event)>>,
+    /// Finality events that would have to be reverted if their transaction got reorged out,
+    /// together with the height of the block the transaction was included in.
+    reached_finality: HashMap<(Txid, Script), (BlockHeight, Vec<Event>)>,
 }
 
 impl State {
@@ -154,10 +197,69 @@ impl State {
             latest_block_height,
             current_status: BTreeMap::default(),
             awaiting_status: HashMap::default(),
+            reached_finality: HashMap::default(),
+        }
+    }
+
+    /// All scripts we need to fetch the history for on the next sync.
+    fn scripts(&self) -> impl Iterator<Item = &Script> {
+        self.awaiting_status
+            .keys()
+            .chain(self.reached_finality.keys())
+            .map(|(_, script)| script)
+    }
+
+    /// Everything we are still waiting for on behalf of the given CFD.
+    fn watched_scripts(&self, order_id: OrderId) -> Vec<WatchedScript> {
+        self.awaiting_status
+            .iter()
+            .flat_map(|((txid, script), targets)| {
+                targets
+                    .iter()
+                    .filter(move |(_, event)| event.order_id() == order_id)
+                    .map(move |(status, event)| WatchedScript {
+                        txid: *txid,
+                        script: script.clone(),
+                        status: *status,
+                        event: event.clone(),
+                    })
+            })
+            .collect()
+    }
+
+    /// Resume waiting for scripts that were watched before.
+    ///
+    /// Scripts we are already waiting for are skipped, otherwise their event would be emitted
+    /// twice.
+    fn watch(&mut self, scripts: Vec<WatchedScript>) {
+        for WatchedScript {
+            txid,
+            script,
+            status,
+            event,
+        } in scripts
+        {
+            let targets = self.awaiting_status.entry((txid, script)).or_default();
+
+            if !targets.contains(&(status, event.clone())) {
+                targets.push((status, event));
+            }
         }
     }
 }
 
+/// A status we wait for a transaction to reach, and the event to emit once it does.
+///
+/// These are persisted per CFD as a cache of what the monitor watched. The CFD's events remain the
+/// source of truth, on startup the cache is reconciled with what they tell us to watch.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct WatchedScript {
+    txid: Txid,
+    script: Script,
+    status: ScriptStatus,
+    event: Event,
+}
+
 /// Read-model of the CFD for the monitoring actor.
 #[derive(Default)]
 struct Cfd {
@@ -231,7 +333,7 @@ impl Cfd {
                     ..self
                 }
             }
-            ContractSetupStarted | ContractSetupFailed | OfferRejected | RolloverRejected => {
+            ContractSetupStarted | ContractSetupFailed | OfferRejected(_) | RolloverRejected => {
                 Self::default() // all false / empty
             }
             LockConfirmed => Self {
@@ -244,6 +346,14 @@ impl Cfd {
                 commit_tx: None,
                 ..self
             },
+            LockReorged => Self {
+                monitor_lock_finality: true,
+                ..self
+            },
+            CommitReorged => Self {
+                monitor_commit_finality: true,
+                ..self
+            },
             // final states, don't monitor anything
             CetConfirmed
             | RefundConfirmed
@@ -266,7 +376,8 @@ impl Cfd {
                 cet: Some(cet),
                 ..self
             },
-            RolloverStarted { .. }
+            LockSeen
+            | RolloverStarted { .. }
             | RolloverAccepted
             | RolloverFailed
             | ManualCommit { .. }
@@ -281,6 +392,23 @@ impl Cfd {
             }
         }
     }
+
+    /// Whether the CFD's events still tell us to wait for `event`.
+    fn still_awaits(&self, event: &Event) -> bool {
+        match event {
+            Event::LockSeen(_) | Event::LockFinality(_) => self.monitor_lock_finality,
+            Event::CommitFinality(_) => self.monitor_commit_finality,
+            Event::CloseFinality(_) => self.monitor_collaborative_settlement_finality.is_some(),
+            Event::CetTimelockExpired(_) => self.monitor_cet_timelock,
+            // We start watching the CET once the oracle attests, which is not reflected in the
+            // flags. Keep watching it unless the CFD is closed.
+            Event::CetFinality(_) => self.params.is_some(),
+            Event::RefundTimelockExpired(_) => self.monitor_refund_timelock,
+            Event::RefundFinality(_) => self.monitor_refund_finality,
+            Event::RevokedTransactionFound(_) => self.monitor_revoked_commit_transactions,
+            Event::Reorg { .. } => false,
+        }
+    }
 }
 
 impl Actor<bdk::electrum_client::Client> {
@@ -310,6 +438,60 @@ impl Actor<bdk::electrum_client::Client> {
 }
 
 impl State {
+    fn reinit(&mut self, msg: &ReinitMonitoring) {
+        let ReinitMonitoring {
+            id,
+            params,
+            monitor_lock_finality,
+            monitor_commit_finality,
+            monitor_cet_timelock,
+            monitor_refund_timelock,
+            monitor_refund_finality,
+            monitor_revoked_commit_transactions,
+            monitor_collaborative_settlement_finality,
+        } = msg;
+        let id = *id;
+
+        if *monitor_lock_finality {
+            self.monitor_lock_finality(params, id);
+        }
+
+        if *monitor_commit_finality {
+            self.monitor_commit_finality(params, id)
+        }
+
+        if *monitor_cet_timelock {
+            self.monitor_commit_cet_timelock(params, id);
+        }
+
+        if *monitor_refund_timelock {
+            self.monitor_commit_refund_timelock(params, id);
+        }
+
+        if *monitor_refund_finality {
+            self.monitor_refund_finality(params, id);
+        }
+
+        if *monitor_revoked_commit_transactions {
+            self.monitor_revoked_commit_transactions(params, id);
+        }
+
+        if let Some(params) = monitor_collaborative_settlement_finality {
+            self.monitor_close_finality(params.clone(), id);
+        }
+    }
+
+    /// Stop waiting for any status on behalf of the given CFD.
+    ///
+    /// Transactions that already reached finality are still watched for reorgs.
+    fn forget(&mut self, order_id: OrderId) {
+        self.awaiting_status.retain(|_, targets| {
+            targets.retain(|(_, event)| event.order_id() != order_id);
+
+            !targets.is_empty()
+        });
+    }
+
     fn monitor_all(&mut self, params: &MonitorParams, order_id: OrderId) {
         self.monitor_lock_finality(params, order_id);
         self.monitor_commit_finality(params, order_id);
@@ -320,10 +502,15 @@ impl State {
     }
 
     fn monitor_lock_finality(&mut self, params: &MonitorParams, order_id: OrderId) {
-        self.awaiting_status
-            .entry((params.lock.0, params.lock.1.script_pubkey()))
-            .or_default()
-            .push((ScriptStatus::finality(), Event::LockFinality(order_id)));
+        self.monitor_lock((params.lock.0, params.lock.1.script_pubkey()), order_id);
+    }
+
+    /// Watch the lock transaction until it is final, reporting when it first shows up in the
+    /// mempool so users get feedback early.
+    fn monitor_lock(&mut self, lock: (Txid, Script), order_id: OrderId) {
+        let targets = self.awaiting_status.entry(lock).or_default();
+        targets.push((ScriptStatus::InMempool, Event::LockSeen(order_id)));
+        targets.push((ScriptStatus::finality(), Event::LockFinality(order_id)));
     }
 
     fn monitor_commit_finality(&mut self, params: &MonitorParams, order_id: OrderId) {
@@ -341,13 +528,24 @@ impl State {
     }
 
     fn monitor_commit_cet_timelock(&mut self, params: &MonitorParams, order_id: OrderId) {
-        self.awaiting_status
-            .entry((params.commit.0, params.commit.1.script_pubkey()))
-            .or_default()
-            .push((
-                ScriptStatus::with_confirmations(CET_TIMELOCK),
-                Event::CetTimelockExpired(order_id),
-            ));
+        self.monitor_cet_timelock(
+            (params.commit.0, params.commit.1.script_pubkey()),
+            params.cet_timelock,
+            order_id,
+        );
+    }
+
+    /// Watch the commit transaction until the CETs spending from it can be published.
+    fn monitor_cet_timelock(
+        &mut self,
+        commit: (Txid, Script),
+        cet_timelock: u32,
+        order_id: OrderId,
+    ) {
+        self.awaiting_status.entry(commit).or_default().push((
+            ScriptStatus::with_confirmations(cet_timelock),
+            Event::CetTimelockExpired(order_id),
+        ));
     }
 
     fn monitor_commit_refund_timelock(&mut self, params: &MonitorParams, order_id: OrderId) {
@@ -427,17 +625,22 @@ where
             .context("Failed to subscribe to header notifications")?
             .try_into()?;
 
-        let num_transactions = self.state.awaiting_status.len();
+        let num_transactions = self.state.awaiting_status.len() + self.state.reached_finality.len();
 
         tracing::trace!("Updating status of {num_transactions} transactions",);
 
         let histories = self
             .client
-            .batch_script_get_history(self.state.awaiting_status.keys().map(|(_, script)| script))
+            .batch_script_get_history(self.state.scripts())
             .context("Failed to get script histories")?;
 
         let mut ready_events = self.state.update(latest_block_height, histories);
 
+        let order_ids = ready_events
+            .iter()
+            .map(Event::order_id)
+            .collect::<HashSet<_>>();
+
         while let Some(event) = ready_events.pop() {
             match self.event_channel.send(event).await {
                 Ok(()) => {}
@@ -447,10 +650,16 @@ where
             }
         }
 
+        // Only persist once the events were handed over. Should we crash before, the events we no
+        // longer wait for are derived again from the CFD's events on startup.
+        self.persist_watched_scripts(order_ids).await?;
+
         Ok(())
     }
 
-    async fn handle_oracle_attestation(&mut self, attestation: oracle::Attestation) {
+    async fn handle_oracle_attestation(&mut self, attestation: oracle::Attestation) -> Result<()> {
+        let mut order_ids = Vec::new();
+
         for (order_id, MonitorParams { cets, .. }) in self
             .cfds
             .clone()
@@ -459,8 +668,30 @@ where
         {
             try_continue!(self
                 .state
-                .monitor_cet_finality(cets, attestation.clone(), order_id))
+                .monitor_cet_finality(cets, attestation.clone(), order_id));
+
+            order_ids.push(order_id);
         }
+
+        self.persist_watched_scripts(order_ids).await
+    }
+
+    /// Store what we are waiting for on behalf of the given CFDs so we can resume after a restart.
+    async fn persist_watched_scripts(
+        &mut self,
+        order_ids: impl IntoIterator<Item = OrderId>,
+    ) -> Result<()> {
+        let mut conn = self.db.acquire().await?;
+
+        for order_id in order_ids {
+            let scripts = self.state.watched_scripts(order_id);
+
+            db::save_monitored_scripts(order_id, &scripts, &mut conn)
+                .await
+                .with_context(|| format!("Failed to persist watched scripts of CFD {order_id}"))?;
+        }
+
+        Ok(())
     }
 }
 
@@ -473,6 +704,7 @@ impl State {
         let txid_to_script = self
             .awaiting_status
             .keys()
+            .chain(self.reached_finality.keys())
             .cloned()
             .collect::<HashMap<_, _>>();
 
@@ -576,14 +808,97 @@ impl State {
 
                     for (target_status, event) in reached_monitoring_target {
                         tracing::info!(%txid, target = %target_status, current = %status, "Bitcoin transaction reached monitoring target");
+
+                        match status {
+                            ScriptStatus::Confirmed(confirmed) if event.is_revertable() => {
+                                self.reached_finality
+                                    .entry((*txid, script.clone()))
+                                    .or_insert_with(|| {
+                                        (
+                                            confirmed.inclusion_height(self.latest_block_height),
+                                            Vec::new(),
+                                        )
+                                    })
+                                    .1
+                                    .push(event.clone());
+                            }
+                            _ => {}
+                        }
+
                         ready_events.push(event);
                     }
                 }
             }
         }
 
+        // 5. check whether any transaction we considered final got reorged out
+        ready_events.extend(self.detect_reorgs(&histories));
+
         ready_events
     }
+
+    /// Detect transactions that reached finality but are no longer confirmed.
+    ///
+    /// For every finality event of such a transaction, we emit an [`Event::Reorg`] and start
+    /// monitoring for its finality again. Transactions that are buried deep enough are no longer
+    /// watched.
+    fn detect_reorgs(&mut self, histories: &HashMap<(Txid, Script), GetHistoryRes>) -> Vec<Event> {
+        let mut reorg_events = Vec::new();
+
+        for ((txid, script), (inclusion_height, events)) in
+            std::mem::take(&mut self.reached_finality)
+        {
+            let key = (txid, script);
+
+            match histories.get(&key) {
+                Some(history_entry) if history_entry.height > 0 => {
+                    let new_inclusion_height = BlockHeight(
+                        u32::try_from(history_entry.height).expect("we checked that height is > 0"),
+                    );
+
+                    if new_inclusion_height != inclusion_height {
+                        tracing::debug!(
+                            %txid,
+                            old_height = u32::from(inclusion_height),
+                            new_height = u32::from(new_inclusion_height),
+                            "Bitcoin transaction was included in a different block after reorg"
+                        );
+                    }
+
+                    let confirmations = Confirmed::from_inclusion_and_latest_block(
+                        new_inclusion_height.into(),
+                        self.latest_block_height.into(),
+                    )
+                    .confirmations();
+
+                    if confirmations < REORG_SAFETY_CONFIRMATIONS {
+                        self.reached_finality
+                            .insert(key, (new_inclusion_height, events));
+                    }
+                }
+                _ => {
+                    tracing::warn!(
+                        %txid,
+                        height = u32::from(inclusion_height),
+                        "Final Bitcoin transaction is no longer confirmed, reverting finality"
+                    );
+
+                    for event in events {
+                        reorg_events.push(Event::Reorg {
+                            order_id: event.order_id(),
+                            tx: txid,
+                        });
+                        self.awaiting_status
+                            .entry(key.clone())
+                            .or_default()
+                            .push((ScriptStatus::finality(), event));
+                    }
+                }
+            }
+        }
+
+        reorg_events
+    }
 }
 
 fn print_status_change(txid: Txid, old: Option<&ScriptStatus>, new: &ScriptStatus) {
@@ -598,7 +913,7 @@ fn print_status_change(txid: Txid, old: Option<&ScriptStatus>, new: &ScriptStatu
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
 struct Confirmed {
     /// The depth of this transaction within the blockchain.
     ///
@@ -626,9 +941,14 @@ impl Confirmed {
     fn confirmations(&self) -> u32 {
         self.depth + 1
     }
+
+    /// The height of the block the transaction was included in, based on the latest known block.
+    fn inclusion_height(&self, latest_block: BlockHeight) -> BlockHeight {
+        BlockHeight(latest_block.0.saturating_sub(self.depth))
+    }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
 enum ScriptStatus {
     Unseen,
     InMempool,
@@ -691,8 +1011,10 @@ impl Add<u32> for BlockHeight {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Event {
+    /// The lock transaction was seen in the mempool or in a block for the first time.
+    LockSeen(OrderId),
     LockFinality(OrderId),
     CommitFinality(OrderId),
     CloseFinality(OrderId),
@@ -701,11 +1023,17 @@ pub enum Event {
     RefundTimelockExpired(OrderId),
     RefundFinality(OrderId),
     RevokedTransactionFound(OrderId),
+    /// A transaction for which we previously emitted a finality event is no longer confirmed.
+    Reorg {
+        order_id: OrderId,
+        tx: Txid,
+    },
 }
 
 impl Event {
     pub fn order_id(&self) -> OrderId {
         let order_id = match self {
+            Event::LockSeen(order_id) => order_id,
             Event::LockFinality(order_id) => order_id,
             Event::CommitFinality(order_id) => order_id,
             Event::CloseFinality(order_id) => order_id,
@@ -714,10 +1042,18 @@ impl Event {
             Event::RefundFinality(order_id) => order_id,
             Event::CetFinality(order_id) => order_id,
             Event::RevokedTransactionFound(order_id) => order_id,
+            Event::Reorg { order_id, .. } => order_id,
         };
 
         *order_id
     }
+
+    /// Whether this event has to be reverted if its transaction gets reorged out of the chain.
+    ///
+    /// Only finality events that do not close the CFD can be reverted.
+    fn is_revertable(&self) -> bool {
+        matches!(self, Event::LockFinality(_) | Event::CommitFinality(_))
+    }
 }
 
 impl MonitorParams {
@@ -726,6 +1062,7 @@ impl MonitorParams {
         MonitorParams {
             lock: (dlc.lock.0.txid(), dlc.lock.1),
             commit: (dlc.commit.0.txid(), dlc.commit.2),
+            cet_timelock: dlc.cet_timelock,
             cets: map_cets(dlc.cets, &dlc.maker_address),
             refund: (dlc.refund.0.txid(), script_pubkey, dlc.refund_timelock),
             revoked_commits: dlc
@@ -855,36 +1192,28 @@ where
 
                     for id in db::load_all_cfd_ids(&mut conn).await? {
                         let (_, events) = db::load_cfd(id, &mut conn).await?;
+                        let cfd = events.into_iter().fold(Cfd::default(), Cfd::apply);
 
-                        let Cfd {
-                            params,
-                            monitor_lock_finality,
-                            monitor_commit_finality,
-                            monitor_cet_timelock,
-                            monitor_refund_timelock,
-                            monitor_refund_finality,
-                            monitor_revoked_commit_transactions,
-                            monitor_collaborative_settlement_finality,
-                            ..
-                        } = events.into_iter().fold(Cfd::default(), Cfd::apply);
+                        // The persisted scripts may be stale, only resume those the CFD's events
+                        // still tell us to wait for
+                        let scripts =
+                            db::load_monitored_scripts::<Vec<WatchedScript>>(id, &mut conn)
+                                .await?
+                                .unwrap_or_default()
+                                .into_iter()
+                                .filter(|script| cfd.still_awaits(&script.event))
+                                .collect::<Vec<_>>();
 
-                        let params = match params {
+                        let reinit = match ReinitMonitoring::new(id, cfd) {
                             None => continue,
-                            Some(params) => params,
+                            Some(reinit) => reinit,
                         };
 
-                        this.send(ReinitMonitoring {
-                            id,
-                            params,
-                            monitor_lock_finality,
-                            monitor_commit_finality,
-                            monitor_cet_timelock,
-                            monitor_refund_timelock,
-                            monitor_refund_finality,
-                            monitor_revoked_commit_transactions,
-                            monitor_collaborative_settlement_finality,
-                        })
-                        .await?;
+                        this.send(reinit).await??;
+
+                        if !scripts.is_empty() {
+                            this.send(RestoreMonitoring { id, scripts }).await??;
+                        }
                     }
 
                     anyhow::Ok(())
@@ -906,24 +1235,28 @@ where
         &mut self,
         msg: StartMonitoring,
         _ctx: &mut xtra::Context<Self>,
-    ) {
+    ) -> Result<()> {
         let StartMonitoring { id, params } = msg;
 
         self.state.monitor_all(&params, id);
         self.cfds.insert(id, params);
+
+        self.persist_watched_scripts([id]).await
     }
 
-    fn handle_collaborative_settlement(
+    async fn handle_collaborative_settlement(
         &mut self,
         collaborative_settlement: CollaborativeSettlement,
-    ) {
-        self.state.monitor_close_finality(
-            collaborative_settlement.tx,
-            collaborative_settlement.order_id,
-        );
+    ) -> Result<()> {
+        let order_id = collaborative_settlement.order_id;
+
+        self.state
+            .monitor_close_finality(collaborative_settlement.tx, order_id);
+
+        self.persist_watched_scripts([order_id]).await
     }
 
-    async fn handle_try_broadcast_transaction(&self, msg: TryBroadcastTransaction) -> Result<()> {
+    async fn handle_try_broadcast_transaction(&self, msg: TryBroadcastTransaction) -> Result<Txid> {
         let TryBroadcastTransaction { tx, kind } = msg;
 
         let result = self.client.transaction_broadcast(&tx);
@@ -932,13 +1265,13 @@ where
             let rpc_error = parse_rpc_protocol_error(value)
                 .with_context(|| format!("Failed to parse electrum error response '{value:?}'"))?;
 
-            if rpc_error.code == i64::from(RpcErrorCode::RpcVerifyAlreadyInChain) {
+            if rpc_error.is_already_published() {
                 let txid = tx.txid();
                 tracing::trace!(
-                    %txid, "Attempted to broadcast {kind} that was already on-chain",
+                    %txid, "Attempted to broadcast {kind} that was already published",
                 );
 
-                return Ok(());
+                return Ok(txid);
             }
 
             // We do this check because electrum sometimes returns an RpcVerifyError when it should
@@ -951,7 +1284,7 @@ where
                     tracing::trace!(
                         %txid, "Attempted to broadcast {kind} that was already on-chain",
                     );
-                    return Ok(());
+                    return Ok(txid);
                 }
             }
         }
@@ -965,54 +1298,119 @@ where
 
         tracing::info!(%txid, "{kind:#} published on chain");
 
-        Ok(())
+        Ok(txid)
     }
 
-    async fn handle_reinit_monitoring(&mut self, msg: ReinitMonitoring) {
-        let ReinitMonitoring {
-            id,
-            params,
-            monitor_lock_finality,
-            monitor_commit_finality,
-            monitor_cet_timelock,
-            monitor_refund_timelock,
-            monitor_refund_finality,
-            monitor_revoked_commit_transactions,
-            monitor_collaborative_settlement_finality,
-        } = msg;
+    async fn handle_get_unconfirmed_inputs(
+        &self,
+        msg: GetUnconfirmedInputs,
+    ) -> Result<Vec<OutPoint>> {
+        let inputs = spent_outputs(&msg.psbt)?;
 
-        self.cfds.insert(id, params.clone());
+        let histories = self
+            .client
+            .batch_script_get_history(inputs.iter().map(|(_, script)| script))
+            .context("Failed to get script histories of inputs")?;
 
-        if monitor_lock_finality {
-            self.state.monitor_lock_finality(&params, id);
-        }
+        Ok(unconfirmed_inputs(&inputs, histories))
+    }
 
-        if monitor_commit_finality {
-            self.state.monitor_commit_finality(&params, id)
-        }
+    async fn handle_reinit_monitoring(&mut self, msg: ReinitMonitoring) -> Result<()> {
+        self.state.reinit(&msg);
+        self.cfds.insert(msg.id, msg.params);
 
-        if monitor_cet_timelock {
-            self.state.monitor_commit_cet_timelock(&params, id);
-        }
+        self.persist_watched_scripts([msg.id]).await
+    }
 
-        if monitor_refund_timelock {
-            self.state.monitor_commit_refund_timelock(&params, id);
-        }
+    async fn handle_restore_monitoring(&mut self, msg: RestoreMonitoring) -> Result<()> {
+        let RestoreMonitoring { id, scripts } = msg;
 
-        if monitor_refund_finality {
-            self.state.monitor_refund_finality(&params, id);
-        }
+        self.state.watch(scripts);
 
-        if monitor_revoked_commit_transactions {
-            self.state.monitor_revoked_commit_transactions(&params, id);
-        }
+        self.persist_watched_scripts([id]).await
+    }
 
-        if let Some(params) = monitor_collaborative_settlement_finality {
-            self.state.monitor_close_finality(params, id);
-        }
+    async fn handle_rescan(&mut self, msg: Rescan) -> Result<Option<BitMexPriceEventId>> {
+        let Rescan { id } = msg;
+
+        let mut conn = self.db.acquire().await?;
+        let (_, events) = db::load_cfd(id, &mut conn).await?;
+        let cfd = events.into_iter().fold(Cfd::default(), Cfd::apply);
+
+        // Drop whatever we are still waiting for on behalf of this CFD, otherwise re-initialising
+        // would emit every event twice.
+        self.state.forget(id);
+        self.cfds.remove(&id);
+
+        let event_id = match ReinitMonitoring::new(id, cfd) {
+            None => None,
+            Some(reinit) => {
+                let event_id = reinit.params.event_id;
+
+                self.state.reinit(&reinit);
+                self.cfds.insert(id, reinit.params);
+
+                Some(event_id)
+            }
+        };
+
+        self.persist_watched_scripts([id]).await?;
+
+        self.sync().await.context("Failed to sync after rescan")?;
+
+        Ok(event_id)
     }
 }
 
+/// The outputs spent by the inputs of `psbt`, together with their script.
+fn spent_outputs(psbt: &PartiallySignedTransaction) -> Result<Vec<(OutPoint, Script)>> {
+    psbt.global
+        .unsigned_tx
+        .input
+        .iter()
+        .zip(&psbt.inputs)
+        .map(|(txin, input)| {
+            let outpoint = txin.previous_output;
+            let script = match (&input.witness_utxo, &input.non_witness_utxo) {
+                (Some(txout), _) => Some(txout.script_pubkey.clone()),
+                (None, Some(tx)) => tx
+                    .output
+                    .get(outpoint.vout as usize)
+                    .map(|txout| txout.script_pubkey.clone()),
+                (None, None) => None,
+            }
+            .with_context(|| format!("Input {outpoint} does not specify the output it spends"))?;
+
+            Ok((outpoint, script))
+        })
+        .collect()
+}
+
+/// Electrum reports transactions in the mempool with a height of 0, or -1 if they have unconfirmed
+/// parents.
+fn unconfirmed_inputs(
+    inputs: &[(OutPoint, Script)],
+    histories: Vec<Vec<GetHistoryRes>>,
+) -> Vec<OutPoint> {
+    inputs
+        .iter()
+        .zip(histories)
+        .filter(|((outpoint, _), history)| {
+            !history
+                .iter()
+                .any(|entry| entry.tx_hash == outpoint.txid && entry.height > 0)
+        })
+        .map(|((outpoint, _), _)| *outpoint)
+        .collect()
+}
+
+/// Resume watching the scripts persisted for a CFD, on top of what [`ReinitMonitoring`] derived
+/// from its events.
+struct RestoreMonitoring {
+    id: OrderId,
+    scripts: Vec<WatchedScript>,
+}
+
 // TODO: Re-model this by tearing apart `MonitorParams`.
 struct ReinitMonitoring {
     id: OrderId,
@@ -1030,6 +1428,35 @@ struct ReinitMonitoring {
     monitor_collaborative_settlement_finality: Option<(Txid, Script)>,
 }
 
+impl ReinitMonitoring {
+    /// Returns `None` if there is nothing to monitor for the CFD.
+    fn new(id: OrderId, cfd: Cfd) -> Option<Self> {
+        let Cfd {
+            params,
+            monitor_lock_finality,
+            monitor_commit_finality,
+            monitor_cet_timelock,
+            monitor_refund_timelock,
+            monitor_refund_finality,
+            monitor_revoked_commit_transactions,
+            monitor_collaborative_settlement_finality,
+            ..
+        } = cfd;
+
+        Some(Self {
+            id,
+            params: params?,
+            monitor_lock_finality,
+            monitor_commit_finality,
+            monitor_cet_timelock,
+            monitor_refund_timelock,
+            monitor_refund_finality,
+            monitor_revoked_commit_transactions,
+            monitor_collaborative_settlement_finality,
+        })
+    }
+}
+
 #[async_trait]
 impl<C> xtra::Handler<Sync> for Actor<C>
 where
@@ -1045,7 +1472,9 @@ where
 #[async_trait]
 impl xtra::Handler<oracle::Attestation> for Actor {
     async fn handle(&mut self, msg: oracle::Attestation, _ctx: &mut xtra::Context<Self>) {
-        self.handle_oracle_attestation(msg).await
+        if let Err(e) = self.handle_oracle_attestation(msg).await {
+            tracing::warn!("Failed to handle oracle attestation: {e:#}");
+        }
     }
 }
 
@@ -1053,8 +1482,171 @@ impl xtra::Handler<oracle::Attestation> for Actor {
 mod tests {
     use super::*;
     use crate::model::cfd::CET_TIMELOCK;
+    use rust_decimal_macros::dec;
     use tracing_subscriber::prelude::*;
 
+    #[test]
+    fn already_in_chain_electrum_error_counts_as_published() {
+        let response = Value::String(
+            r#"sendrawtransaction RPC error: {"code":-27,"message":"Transaction already in block chain"}"#
+                .to_owned(),
+        );
+
+        let rpc_error = parse_rpc_protocol_error(&response).unwrap();
+
+        assert!(rpc_error.is_already_published());
+    }
+
+    #[test]
+    fn already_in_mempool_electrum_error_counts_as_published() {
+        let response = Value::String(
+            r#"sendrawtransaction RPC error: {"code":-26,"message":"txn-already-in-mempool"}"#
+                .to_owned(),
+        );
+
+        let rpc_error = parse_rpc_protocol_error(&response).unwrap();
+
+        assert!(rpc_error.is_already_published());
+    }
+
+    #[test]
+    fn rejected_electrum_error_does_not_count_as_published() {
+        let response = Value::String(
+            r#"sendrawtransaction RPC error: {"code":-26,"message":"min relay fee not met"}"#
+                .to_owned(),
+        );
+
+        let rpc_error = parse_rpc_protocol_error(&response).unwrap();
+
+        assert!(!rpc_error.is_already_published());
+    }
+
+    #[test]
+    fn inputs_spending_outputs_not_in_a_block_are_unconfirmed() {
+        let confirmed = OutPoint::new(txid1(), 0);
+        let in_mempool = OutPoint::new(txid2(), 0);
+        let unknown = OutPoint::new(txid2(), 1);
+        let inputs = vec![
+            (confirmed, script1()),
+            (in_mempool, script1()),
+            (unknown, script1()),
+        ];
+
+        let histories = vec![
+            vec![GetHistoryRes {
+                height: 5,
+                tx_hash: txid1(),
+                fee: None,
+            }],
+            vec![
+                GetHistoryRes {
+                    height: 5,
+                    tx_hash: txid1(),
+                    fee: None,
+                },
+                GetHistoryRes {
+                    height: 0,
+                    tx_hash: txid2(),
+                    fee: None,
+                },
+            ],
+            vec![],
+        ];
+
+        assert_eq!(
+            unconfirmed_inputs(&inputs, histories),
+            vec![in_mempool, unknown]
+        );
+    }
+
+    #[tokio::test]
+    async fn watched_scripts_survive_restart() {
+        let db = db::memory().await.unwrap();
+        let mut conn = db.acquire().await.unwrap();
+
+        let cfd = model::cfd::Cfd::new(
+            OrderId::default(),
+            model::Position::Long,
+            model::Price::new(dec!(60_000)).unwrap(),
+            model::Leverage::new(2).unwrap(),
+            time::Duration::hours(24),
+            model::cfd::Role::Taker,
+            model::Usd::new(dec!(1_000)),
+            "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF"
+                .parse()
+                .unwrap(),
+            model::OpeningFee::new(bdk::bitcoin::Amount::from_sat(2000)),
+            model::FundingRate::default(),
+            model::TxFeeRate::default(),
+            100,
+            model::RoundingStrategy::default(),
+        );
+        db::insert_cfd(&cfd, &mut conn).await.unwrap();
+        let order_id = cfd.id();
+
+        let mut state = State::new(BlockHeight(0));
+        state.monitor_lock((txid1(), script1()), order_id);
+        state.monitor_cet_timelock((txid2(), script1()), CET_TIMELOCK, order_id);
+        state.monitor_close_finality((txid2(), script1()), order_id);
+
+        db::save_monitored_scripts(order_id, &state.watched_scripts(order_id), &mut conn)
+            .await
+            .unwrap();
+
+        let mut restarted = State::new(BlockHeight(0));
+        let scripts = db::load_monitored_scripts(order_id, &mut conn)
+            .await
+            .unwrap()
+            .unwrap();
+        restarted.watch(scripts);
+
+        assert_eq!(restarted.awaiting_status, state.awaiting_status);
+        assert_eq!(restarted.awaiting_status.len(), 2);
+    }
+
+    #[test]
+    fn restored_scripts_are_reconciled_with_cfd_events() {
+        let order_id = OrderId::default();
+        let cfd = Cfd {
+            monitor_commit_finality: true,
+            ..Cfd::default()
+        };
+        let lock_finality = WatchedScript {
+            txid: txid1(),
+            script: script1(),
+            status: ScriptStatus::finality(),
+            event: Event::LockFinality(order_id),
+        };
+        let commit_finality = WatchedScript {
+            txid: txid2(),
+            script: script1(),
+            status: ScriptStatus::finality(),
+            event: Event::CommitFinality(order_id),
+        };
+
+        // What re-initialising from the CFD's events already watches
+        let mut state = State::new(BlockHeight(0));
+        state.awaiting_status.insert(
+            (txid2(), script1()),
+            vec![(ScriptStatus::finality(), Event::CommitFinality(order_id))],
+        );
+
+        state.watch(
+            vec![lock_finality, commit_finality]
+                .into_iter()
+                .filter(|script| cfd.still_awaits(&script.event))
+                .collect(),
+        );
+
+        assert_eq!(
+            state.awaiting_status,
+            HashMap::from_iter([(
+                (txid2(), script1()),
+                vec![(ScriptStatus::finality(), Event::CommitFinality(order_id))]
+            )])
+        );
+    }
+
     #[tokio::test]
     async fn can_handle_multiple_subscriptions_on_the_same_transaction() {
         let _guard = tracing_subscriber::fmt()
@@ -1162,6 +1754,213 @@ mod tests {
         assert!(state.awaiting_status.is_empty());
     }
 
+    #[tokio::test]
+    async fn cet_timelock_expires_after_configured_number_of_blocks() {
+        let _guard = tracing_subscriber::fmt()
+            .with_env_filter("trace")
+            .with_test_writer()
+            .set_default();
+
+        let order_id = OrderId::default();
+        let cet_timelock = 20;
+        let commit_confirmed_at = || GetHistoryRes {
+            height: 10,
+            tx_hash: txid1(),
+            fee: None,
+        };
+
+        let mut state = State::new(BlockHeight(0));
+        state.monitor_cet_timelock((txid1(), script1()), cet_timelock, order_id);
+
+        // The default timelock would have expired here already
+        let ready_events = state.update(
+            BlockHeight(10 + CET_TIMELOCK),
+            vec![vec![commit_confirmed_at()]],
+        );
+        assert!(ready_events.is_empty());
+
+        let ready_events = state.update(
+            BlockHeight(10 + cet_timelock - 1),
+            vec![vec![commit_confirmed_at()]],
+        );
+        assert_eq!(ready_events, vec![Event::CetTimelockExpired(order_id)]);
+    }
+
+    #[tokio::test]
+    async fn lock_seen_in_mempool_is_reported_before_finality() {
+        let _guard = tracing_subscriber::fmt()
+            .with_env_filter("trace")
+            .with_test_writer()
+            .set_default();
+
+        let order_id = OrderId::default();
+
+        let mut state = State::new(BlockHeight(0));
+        state.monitor_lock((txid1(), script1()), order_id);
+
+        let ready_events = state.update(BlockHeight(10), vec![vec![]]);
+        assert!(ready_events.is_empty());
+
+        let ready_events = state.update(
+            BlockHeight(10),
+            vec![vec![GetHistoryRes {
+                height: 0,
+                tx_hash: txid1(),
+                fee: None,
+            }]],
+        );
+        assert_eq!(ready_events, vec![Event::LockSeen(order_id)]);
+
+        let ready_events = state.update(
+            BlockHeight(11),
+            vec![vec![GetHistoryRes {
+                height: 11,
+                tx_hash: txid1(),
+                fee: None,
+            }]],
+        );
+        assert_eq!(ready_events, vec![Event::LockFinality(order_id)]);
+    }
+
+    #[tokio::test]
+    async fn reorg_of_final_commit_tx_reverts_finality() {
+        let _guard = tracing_subscriber::fmt()
+            .with_env_filter("trace")
+            .with_test_writer()
+            .set_default();
+
+        let order_id = OrderId::default();
+        let commit_finality = Event::CommitFinality(order_id);
+
+        let mut state = State::new(BlockHeight(0));
+        state.awaiting_status = HashMap::from_iter([(
+            (txid1(), script1()),
+            vec![(ScriptStatus::finality(), commit_finality.clone())],
+        )]);
+
+        let ready_events = state.update(
+            BlockHeight(10),
+            vec![vec![GetHistoryRes {
+                height: 10,
+                tx_hash: txid1(),
+                fee: None,
+            }]],
+        );
+        assert_eq!(ready_events, vec![commit_finality.clone()]);
+        assert!(state.awaiting_status.is_empty());
+
+        // The block including the commit transaction got replaced, the transaction is back in
+        // the mempool.
+        let ready_events = state.update(
+            BlockHeight(10),
+            vec![vec![GetHistoryRes {
+                height: 0,
+                tx_hash: txid1(),
+                fee: None,
+            }]],
+        );
+        assert_eq!(
+            ready_events,
+            vec![Event::Reorg {
+                order_id,
+                tx: txid1()
+            }]
+        );
+
+        // Once the transaction confirms again, we emit the finality event again.
+        let ready_events = state.update(
+            BlockHeight(11),
+            vec![vec![GetHistoryRes {
+                height: 11,
+                tx_hash: txid1(),
+                fee: None,
+            }]],
+        );
+        assert_eq!(ready_events, vec![commit_finality]);
+    }
+
+    #[tokio::test]
+    async fn stop_watching_for_reorgs_once_buried_deep_enough() {
+        let _guard = tracing_subscriber::fmt()
+            .with_env_filter("trace")
+            .with_test_writer()
+            .set_default();
+
+        let commit_finality = Event::CommitFinality(OrderId::default());
+
+        let mut state = State::new(BlockHeight(0));
+        state.awaiting_status = HashMap::from_iter([(
+            (txid1(), script1()),
+            vec![(ScriptStatus::finality(), commit_finality.clone())],
+        )]);
+
+        let history = || {
+            vec![vec![GetHistoryRes {
+                height: 10,
+                tx_hash: txid1(),
+                fee: None,
+            }]]
+        };
+
+        let ready_events = state.update(BlockHeight(10), history());
+        assert_eq!(ready_events, vec![commit_finality]);
+        assert_eq!(state.reached_finality.len(), 1);
+
+        let ready_events = state.update(BlockHeight(10 + REORG_SAFETY_CONFIRMATIONS), history());
+        assert!(ready_events.is_empty());
+        assert!(state.reached_finality.is_empty());
+    }
+
+    #[test]
+    fn rescan_reports_confirmed_commit_exactly_once() {
+        let order_id = OrderId::default();
+        let reinit = ReinitMonitoring {
+            id: order_id,
+            params: dummy_monitor_params(),
+            monitor_lock_finality: false,
+            monitor_commit_finality: true,
+            monitor_cet_timelock: false,
+            monitor_refund_timelock: false,
+            monitor_refund_finality: false,
+            monitor_revoked_commit_transactions: false,
+            monitor_collaborative_settlement_finality: None,
+        };
+
+        let mut state = State::new(BlockHeight(0));
+        state.reinit(&reinit);
+        state.forget(order_id);
+        state.reinit(&reinit);
+
+        let ready_events = state.update(
+            BlockHeight(10),
+            vec![vec![GetHistoryRes {
+                height: 5,
+                tx_hash: txid1(),
+                fee: None,
+            }]],
+        );
+
+        assert_eq!(ready_events, vec![Event::CommitFinality(order_id)]);
+    }
+
+    fn dummy_monitor_params() -> MonitorParams {
+        let descriptor = Descriptor::new_pk(
+            "0317b7e1ce1f9f94c32a43739229f88c0b0333296fb46e8f72865849c6ae34b84e"
+                .parse()
+                .unwrap(),
+        );
+
+        MonitorParams {
+            lock: (txid2(), descriptor.clone()),
+            commit: (txid1(), descriptor),
+            cet_timelock: CET_TIMELOCK,
+            cets: HashMap::new(),
+            refund: (txid2(), script1(), 0),
+            revoked_commits: Vec::new(),
+            event_id: BitMexPriceEventId::with_20_digits(time::OffsetDateTime::now_utc()),
+        }
+    }
+
     fn txid1() -> Txid {
         "1278ef8104c2f63c03d4d52bace29bed28bd5e664e67543735ddc95a39bfdc0f"
             .parse()
@@ -7,6 +7,9 @@ use crate::Tasks;
 use anyhow::Context;
 use anyhow::Result;
 use async_trait::async_trait;
+use bdk::bitcoin::secp256k1::SECP256K1;
+use maia::compute_adaptor_pk;
+use maia::secp256k1_zkp;
 use maia::secp256k1_zkp::schnorrsig;
 use maia::secp256k1_zkp::SecretKey;
 use serde::Deserialize;
@@ -24,8 +27,15 @@ use xtras::SendAsyncSafe;
 use xtras::SendInterval;
 
 pub struct Actor {
+    oracle_pk: schnorrsig::PublicKey,
     announcements: HashMap<BitMexPriceEventId, (OffsetDateTime, Vec<schnorrsig::PublicKey>)>,
+    /// Events whose attestation we are waiting for.
+    ///
+    /// Many CFDs may settle on the same event, we only monitor it once and the attestation is
+    /// dispatched to all CFDs by the receiver of the `attestation_channel`.
     pending_attestations: HashSet<BitMexPriceEventId>,
+    /// Events whose attestation is currently being fetched from the oracle.
+    attestations_in_flight: HashSet<BitMexPriceEventId>,
     attestation_channel: Box<dyn StrongMessageChannel<Attestation>>,
     announcement_lookahead: Duration,
     tasks: Tasks,
@@ -34,6 +44,9 @@ pub struct Actor {
 
 pub struct Sync;
 
+/// Monitor the oracle for the attestation of the given event.
+///
+/// Monitoring an event that is already monitored is a no-op.
 pub struct MonitorAttestation {
     pub event_id: BitMexPriceEventId,
 }
@@ -55,6 +68,57 @@ pub struct Attestation {
     pub scalars: Vec<SecretKey>,
 }
 
+impl Attestation {
+    /// Verify that the scalars are the oracle's signatures on the digits of the attested price.
+    ///
+    /// Each scalar has to be the secret key of the attestation point of its announced nonce and
+    /// the digit it attests to, which only the oracle can compute.
+    pub fn verify(
+        &self,
+        oracle_pk: &schnorrsig::PublicKey,
+        nonce_pks: &[schnorrsig::PublicKey],
+    ) -> Result<()> {
+        let id = self.id;
+        let n_digits = nonce_pks.len();
+
+        if self.scalars.len() != n_digits {
+            anyhow::bail!(
+                "Attestation {id} has {} scalars but {n_digits} nonces were announced",
+                self.scalars.len()
+            );
+        }
+
+        if n_digits < 64 && self.price >> n_digits != 0 {
+            anyhow::bail!(
+                "Price {} of attestation {id} exceeds {n_digits} digits",
+                self.price
+            );
+        }
+
+        // The most significant digit comes first
+        let digits = (0..n_digits).rev().map(|position| {
+            self.price
+                .checked_shr(position as u32)
+                .map_or(0, |shifted| (shifted & 1) as usize)
+        });
+
+        for (index, ((scalar, nonce_pk), digit)) in
+            self.scalars.iter().zip(nonce_pks).zip(digits).enumerate()
+        {
+            let attestation_pk = compute_adaptor_pk(oracle_pk, &[(digit, *nonce_pk)])
+                .context("Failed to compute attestation point")?;
+
+            if secp256k1_zkp::PublicKey::from_secret_key(SECP256K1, scalar) != attestation_pk {
+                anyhow::bail!(
+                    "Scalar {index} of attestation {id} does not match the announced nonce"
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
 /// A module-private message to allow parallelization of fetching announcements.
 #[derive(Debug)]
 struct NewAnnouncementFetched {
@@ -68,6 +132,14 @@ struct NewAnnouncementFetched {
 struct NewAttestationFetched {
     id: BitMexPriceEventId,
     attestation: Attestation,
+    /// The nonces announced alongside the attestation, in case we didn't cache the announcement.
+    nonce_pks: Vec<schnorrsig::PublicKey>,
+}
+
+/// A module-private message to allow fetching an attestation again after a failed attempt.
+#[derive(Debug)]
+struct AttestationFetchFailed {
+    id: BitMexPriceEventId,
 }
 
 #[derive(Default)]
@@ -100,10 +172,13 @@ impl Actor {
         db: SqlitePool,
         attestation_channel: Box<dyn StrongMessageChannel<Attestation>>,
         announcement_lookahead: Duration,
+        oracle_pk: schnorrsig::PublicKey,
     ) -> Self {
         Self {
+            oracle_pk,
             announcements: HashMap::new(),
             pending_attestations: HashSet::new(),
+            attestations_in_flight: HashSet::new(),
             attestation_channel,
             announcement_lookahead,
             tasks: Tasks::default(),
@@ -125,45 +200,53 @@ impl Actor {
             if self.announcements.get(&event_id).is_some() {
                 continue;
             }
-            let this = ctx.address().expect("self to be alive");
 
-            self.tasks.add_fallible(
-                async move {
-                    let url = event_id.to_olivia_url();
+            self.fetch_announcement(event_id, ctx);
+        }
+    }
 
-                    tracing::debug!("Fetching announcement for {event_id}");
+    fn fetch_announcement(&mut self, event_id: BitMexPriceEventId, ctx: &mut xtra::Context<Self>) {
+        let this = ctx.address().expect("self to be alive");
 
-                    let response = reqwest::get(url.clone())
-                        .await
-                        .with_context(|| format!("Failed to GET {url}"))?;
+        self.tasks.add_fallible(
+            async move {
+                let url = event_id.to_olivia_url();
 
-                    let code = response.status();
-                    if !code.is_success() {
-                        anyhow::bail!("GET {url} responded with {code}");
-                    }
+                tracing::debug!("Fetching announcement for {event_id}");
 
-                    let announcement = response
-                        .json::<Announcement>()
-                        .await
-                        .context("Failed to deserialize as Announcement")?;
+                let response = reqwest::get(url.clone())
+                    .await
+                    .with_context(|| format!("Failed to GET {url}"))?;
 
-                    this.send(NewAnnouncementFetched {
-                        id: event_id,
-                        nonce_pks: announcement.nonce_pks,
-                        expected_outcome_time: announcement.expected_outcome_time,
-                    })
-                    .await?;
+                let code = response.status();
+                if !code.is_success() {
+                    anyhow::bail!("GET {url} responded with {code}");
+                }
 
-                    Ok(())
-                },
-                |e| async move {
-                    tracing::debug!("Failed to fetch announcement: {:#}", e);
-                },
-            );
-        }
+                let announcement = response
+                    .json::<Announcement>()
+                    .await
+                    .context("Failed to deserialize as Announcement")?;
+
+                this.send(NewAnnouncementFetched {
+                    id: event_id,
+                    nonce_pks: announcement.nonce_pks,
+                    expected_outcome_time: announcement.expected_outcome_time,
+                })
+                .await?;
+
+                Ok(())
+            },
+            |e| async move {
+                tracing::debug!("Failed to fetch announcement: {:#}", e);
+            },
+        );
     }
 
-    fn update_pending_attestations(&mut self, ctx: &mut xtra::Context<Self>) {
+    /// The pending attestations that are due and not being fetched yet, marking them as in flight.
+    fn attestations_to_fetch(&mut self) -> Vec<BitMexPriceEventId> {
+        let mut to_fetch = Vec::new();
+
         for event_id in self.pending_attestations.iter().copied() {
             if !event_id.has_likely_occured() {
                 tracing::trace!("Skipping {event_id} because it likely hasn't occurred yet");
@@ -171,7 +254,24 @@ impl Actor {
                 continue;
             }
 
+            if !self.attestations_in_flight.insert(event_id) {
+                tracing::trace!(
+                    "Skipping {event_id} because its attestation is already being fetched"
+                );
+
+                continue;
+            }
+
+            to_fetch.push(event_id);
+        }
+
+        to_fetch
+    }
+
+    fn update_pending_attestations(&mut self, ctx: &mut xtra::Context<Self>) {
+        for event_id in self.attestations_to_fetch() {
             let this = ctx.address().expect("self to be alive");
+            let on_failure = this.clone();
 
             self.tasks.add_fallible(
                 async move {
@@ -188,21 +288,27 @@ impl Actor {
                         anyhow::bail!("GET {url} responded with {code}");
                     }
 
-                    let attestation = response
-                        .json::<Attestation>()
-                        .await
+                    let body = response.bytes().await.context("Failed to read response")?;
+                    let attestation = serde_json::from_slice::<Attestation>(&body)
                         .context("Failed to deserialize as Attestation")?;
+                    let announcement = serde_json::from_slice::<Announcement>(&body)
+                        .context("Failed to deserialize as Announcement")?;
 
                     this.send(NewAttestationFetched {
                         id: event_id,
                         attestation,
+                        nonce_pks: announcement.nonce_pks,
                     })
                     .await?;
 
                     Ok(())
                 },
-                |e| async move {
+                move |e| async move {
                     tracing::debug!("Failed to fetch attestation: {:#}", e);
+
+                    let _: Result<(), xtra::Disconnected> = on_failure
+                        .send(AttestationFetchFailed { id: event_id })
+                        .await;
                 },
             )
         }
@@ -226,16 +332,25 @@ impl Actor {
     fn handle_get_announcement(
         &mut self,
         msg: GetAnnouncement,
-        _ctx: &mut xtra::Context<Self>,
+        ctx: &mut xtra::Context<Self>,
     ) -> Result<Announcement, NoAnnouncement> {
-        self.announcements
-            .get_key_value(&msg.0)
-            .map(|(id, (time, nonce_pks))| Announcement {
+        let GetAnnouncement(event_id) = msg;
+
+        match self.announcements.get_key_value(&event_id) {
+            Some((id, (time, nonce_pks))) => Ok(Announcement {
                 id: *id,
                 expected_outcome_time: *time,
                 nonce_pks: nonce_pks.clone(),
-            })
-            .ok_or(NoAnnouncement(msg.0))
+            }),
+            None if !event_id.has_likely_occured() => {
+                // The oracle may publish the announcement any moment, fetch it right away
+                // instead of waiting for the next sync.
+                self.fetch_announcement(event_id, ctx);
+
+                Err(NoAnnouncement::NotYetPublished(event_id))
+            }
+            None => Err(NoAnnouncement::Unavailable(event_id)),
+        }
     }
 
     fn handle_new_announcement_fetched(
@@ -252,8 +367,31 @@ impl Actor {
         self.update_pending_attestations(ctx);
     }
 
+    fn handle_attestation_fetch_failed(&mut self, msg: AttestationFetchFailed) {
+        self.attestations_in_flight.remove(&msg.id);
+    }
+
     async fn handle_new_attestation_fetched(&mut self, msg: NewAttestationFetched) {
-        let NewAttestationFetched { id, attestation } = msg;
+        let NewAttestationFetched {
+            id,
+            attestation,
+            nonce_pks,
+        } = msg;
+
+        self.attestations_in_flight.remove(&id);
+
+        // Prefer the nonces we learned before the event occurred, those are what our CETs are
+        // built upon.
+        let nonce_pks = match self.announcements.get(&id) {
+            Some((_, announced_nonce_pks)) => announced_nonce_pks,
+            None => &nonce_pks,
+        };
+
+        // The attestation stays pending, we will fetch it again on the next sync.
+        if let Err(e) = attestation.verify(&self.oracle_pk, nonce_pks) {
+            tracing::error!("Rejecting attestation for {id}: {e:#}");
+            return;
+        }
 
         tracing::info!("Fetched new attestation for {id}");
 
@@ -264,8 +402,50 @@ impl Actor {
 }
 
 #[derive(Debug, Clone, thiserror::Error)]
-#[error("Announcement {0} not found")]
-pub struct NoAnnouncement(pub BitMexPriceEventId);
+pub enum NoAnnouncement {
+    /// The event lies in the future, the announcement may still be published.
+    #[error("Announcement {0} not yet published")]
+    NotYetPublished(BitMexPriceEventId),
+    #[error("Announcement {0} not found")]
+    Unavailable(BitMexPriceEventId),
+}
+
+/// How often we ask again for an announcement that is not yet published before giving up.
+const ANNOUNCEMENT_RETRIES: u32 = 5;
+const ANNOUNCEMENT_RETRY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Get the announcement for `event_id` from the oracle actor.
+///
+/// The announcement of an event in the near future may not be published yet but appear shortly,
+/// in which case we retry a bounded number of times before failing.
+pub async fn get_announcement<O>(
+    oracle: &xtra::Address<O>,
+    event_id: BitMexPriceEventId,
+) -> Result<Announcement>
+where
+    O: xtra::Handler<GetAnnouncement>,
+{
+    let mut retries = 0;
+
+    loop {
+        match oracle
+            .send(GetAnnouncement(event_id))
+            .await
+            .context("Oracle actor disconnected")?
+        {
+            Ok(announcement) => return Ok(announcement),
+            Err(NoAnnouncement::NotYetPublished(_)) if retries < ANNOUNCEMENT_RETRIES => {
+                retries += 1;
+                tracing::debug!(
+                    "Announcement {event_id} not yet published, retrying ({retries}/{ANNOUNCEMENT_RETRIES})"
+                );
+
+                tokio::time::sleep(ANNOUNCEMENT_RETRY_INTERVAL).await;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
 
 pub fn next_announcement_after(timestamp: OffsetDateTime) -> Result<BitMexPriceEventId> {
     let adjusted = ceil_to_next_hour(timestamp)?;
@@ -457,16 +637,21 @@ mod olivia_api {
     }
 
     #[cfg(test)]
-    mod tests {
+    pub(crate) mod tests {
         use std::vec;
 
         use crate::model::BitMexPriceEventId;
         use crate::oracle;
         use time::macros::datetime;
 
+        pub(crate) const RESPONSE: &str = r#"{"announcement":{"oracle_event":{"encoding":"json","data":"{\"id\":\"/x/BitMEX/BXBT/2021-10-04T22:00:00.price?n=20\",\"expected-outcome-time\":\"2021-10-04T22:00:00\",\"descriptor\":{\"type\":\"digit-decomposition\",\"is_signed\":false,\"n_digits\":20,\"unit\":null},\"schemes\":{\"olivia-v1\":{\"nonces\":[\"8d72028eeaf4b85aec0f750f05a4a320cac193f5d8494bfe05cd4b29f3df4239\",\"77240f79a0042adae35ad24284b18b906f17a979fcec3c90d11ed682c6b9261e\",\"e42332407b58f7c6e860b886acfe8d19636fb21a1e20722522206b30a2424d89\",\"ce1158e02dc265751887edae9bdcf8d06ad40489c7643324ccb6a46e4e740f5a\",\"52a5751a43046217bcf009df917c24e400c6da645474a654a5f89499df7154d4\",\"e7b97360a952c2b239d1bfeaade73da4a38e83d20f5deb5b054bcbbc78c91e40\",\"612ce13fd61be10e8de77976c6d479865bc3d2ebdc212946f1e5d93e3f504d2e\",\"e40decd0ea27003b873dde9b6be02f1b344e7e74bc5299144fa0f37b1cf12e90\",\"281a829e05d5f8b96eaf620c7b26115bfb29013d503b6bb40068cdb413a87197\",\"3c87eed0a3852953b0f3ac8a47ff194de66c7229c42e6578e0f6464ba240f033\",\"29028525277cb39adab9ac145d6ce61f2e10306e7b6ce95970a22ea3b201a5d9\",\"20971b4d2069d8b9b5c5678290ab7624821cf32ffe32a20d58428ca90da02523\",\"667a9af33ed45bfb5c4fc7adacea15bbe26df90e0df7dd5b8235e14dfd0da38f\",\"224df2d2706b5c629173b84927e2b206dad7a72e132eb86912d9464dad4b41d1\",\"85296962b9d1f7699c248467ce94ce4aa6e00d26fe01af3a507bcd3a303855d4\",\"96813c9f4d136f0f64be79e73d657fecc43d8b6c463163913b4fa31f96b1ae6b\",\"9d5971aa596923560b12f367fb2f4e192d8906bf6ed3a58b093f50d3cad27493\",\"b7f2c135db80cee02b4436557c78dc1dd2343c1a3688ba736c6c40e9531547b6\",\"bd6236fc18f1dc96f9755cc5c435adaf3952ff810d3ad5b96a03464a61eecfde\",\"20b2922ce326e5e2f4ed683723a879e467edd1068bf5a3c4f331525216227abe\"]},\"ecdsa-v1\":{}}}"},"signature":"743ed9900aba5a1ba3ba9d862628cdc5cca27974c40c4ab64618709021b3fbb13216a3efc733be260025da487ae9b63a8290d555bdc8da6324deff149fc7b110"},"attestation":{"outcome":"48935","schemes":{"olivia-v1":{"scalars":["1327b3bd0f1faf45d6fed6c96d0c158da22a2033a6fed98bed036df0a4eef484","72659c6beebd45e299bc4260a1c1ffd708ed33771459563502f25fc4f537cef6","051eec45417e2493f36b13f4fdf83fb981be42901bf876e4ac594ff2daa4c30e","847d8c7204335b1dbc2078cfb56118b1977162e7b997f2029f490929bbd603c7","5b695846292b6d69d9beedcc7dd2b7e49fd49ec4fcf262d9357f52b049fa8998","368a1f2206fcedcde37381b272fa5a400f55ef720ee2b8fff558e3b0dce729ee","9e1c015c0e827037f18681937764f4973ef22d6fbbd82f6bde3bf5198f6b8999","fe9620c9ad9862b5615f8cf3e20e8d9f422e7410914ce8af2b8bad8937b75738","44297ae831898f8f5c7e57720f233a717e9034a5b41d6c89cce6d9058c4ee086","587fc9b71f1920df825138f00bc625e6610e61b1fec0a64e2800fc05b3a2e96d","010377f6b885ae48d62e7863c8038240aafe0a7fb97d58ac6173186c95335955","5243782226739f59b0ac01a56a63537289ffe81b87b33eca42f89f7848623520","06184cb8e46b5d520cd9b5829feeb73b688d61e5f37b91ff88d3f9b8664a5cdd","fe48f4b568bb501732c4e8f1919940c9bca0ad909f4624658b14664af823ccfe","0841f121e7a54f88a844227cd0ae62171b49d004120c16d1a1d619f0b76f7068","c4ac3c8751a63f7c40062b9b84f2bb953b0e6bd8f2cf3b2bcaf711321e92df8f","86a2b1a31bf80f17c00ab28420c636c1ed604d0b1f0a33adda99a0cf1e510269","fb892eba992b723a06bccad6a2a1bb875d548a275a987266fceed097b9fd88db","41991fb15fdb013ccab3e6674b91546a0e1e56a1e212c8795c76d0b43f4c884d","ab6a4368d2e5e7cea23fd648662769facc1c37f1d1613225e9010af07cd74711"]},"ecdsa-v1":{"signature":"1d9a5e2336883cc6b440ff40e16ee44f8af2ba9313e46f1e4cd417f7dba7686279b0216e4b0b5fcf0c650dbad98fdefcf5ef16b49d63651a87f80caddd472384"}},"time":"2021-10-04T22:00:15"}}"#;
+
+        pub(crate) const OLIVIA_PK: &str =
+            "ddd4636845a90185991826be5a494cde9f4a6947b1727217afedc6292fa4caf7";
+
         #[test]
         fn deserialize_announcement() {
-            let json = r#"{"announcement":{"oracle_event":{"encoding":"json","data":"{\"id\":\"/x/BitMEX/BXBT/2021-10-04T22:00:00.price?n=20\",\"expected-outcome-time\":\"2021-10-04T22:00:00\",\"descriptor\":{\"type\":\"digit-decomposition\",\"is_signed\":false,\"n_digits\":20,\"unit\":null},\"schemes\":{\"olivia-v1\":{\"nonces\":[\"8d72028eeaf4b85aec0f750f05a4a320cac193f5d8494bfe05cd4b29f3df4239\",\"77240f79a0042adae35ad24284b18b906f17a979fcec3c90d11ed682c6b9261e\",\"e42332407b58f7c6e860b886acfe8d19636fb21a1e20722522206b30a2424d89\",\"ce1158e02dc265751887edae9bdcf8d06ad40489c7643324ccb6a46e4e740f5a\",\"52a5751a43046217bcf009df917c24e400c6da645474a654a5f89499df7154d4\",\"e7b97360a952c2b239d1bfeaade73da4a38e83d20f5deb5b054bcbbc78c91e40\",\"612ce13fd61be10e8de77976c6d479865bc3d2ebdc212946f1e5d93e3f504d2e\",\"e40decd0ea27003b873dde9b6be02f1b344e7e74bc5299144fa0f37b1cf12e90\",\"281a829e05d5f8b96eaf620c7b26115bfb29013d503b6bb40068cdb413a87197\",\"3c87eed0a3852953b0f3ac8a47ff194de66c7229c42e6578e0f6464ba240f033\",\"29028525277cb39adab9ac145d6ce61f2e10306e7b6ce95970a22ea3b201a5d9\",\"20971b4d2069d8b9b5c5678290ab7624821cf32ffe32a20d58428ca90da02523\",\"667a9af33ed45bfb5c4fc7adacea15bbe26df90e0df7dd5b8235e14dfd0da38f\",\"224df2d2706b5c629173b84927e2b206dad7a72e132eb86912d9464dad4b41d1\",\"85296962b9d1f7699c248467ce94ce4aa6e00d26fe01af3a507bcd3a303855d4\",\"96813c9f4d136f0f64be79e73d657fecc43d8b6c463163913b4fa31f96b1ae6b\",\"9d5971aa596923560b12f367fb2f4e192d8906bf6ed3a58b093f50d3cad27493\",\"b7f2c135db80cee02b4436557c78dc1dd2343c1a3688ba736c6c40e9531547b6\",\"bd6236fc18f1dc96f9755cc5c435adaf3952ff810d3ad5b96a03464a61eecfde\",\"20b2922ce326e5e2f4ed683723a879e467edd1068bf5a3c4f331525216227abe\"]},\"ecdsa-v1\":{}}}"},"signature":"743ed9900aba5a1ba3ba9d862628cdc5cca27974c40c4ab64618709021b3fbb13216a3efc733be260025da487ae9b63a8290d555bdc8da6324deff149fc7b110"},"attestation":{"outcome":"48935","schemes":{"olivia-v1":{"scalars":["1327b3bd0f1faf45d6fed6c96d0c158da22a2033a6fed98bed036df0a4eef484","72659c6beebd45e299bc4260a1c1ffd708ed33771459563502f25fc4f537cef6","051eec45417e2493f36b13f4fdf83fb981be42901bf876e4ac594ff2daa4c30e","847d8c7204335b1dbc2078cfb56118b1977162e7b997f2029f490929bbd603c7","5b695846292b6d69d9beedcc7dd2b7e49fd49ec4fcf262d9357f52b049fa8998","368a1f2206fcedcde37381b272fa5a400f55ef720ee2b8fff558e3b0dce729ee","9e1c015c0e827037f18681937764f4973ef22d6fbbd82f6bde3bf5198f6b8999","fe9620c9ad9862b5615f8cf3e20e8d9f422e7410914ce8af2b8bad8937b75738","44297ae831898f8f5c7e57720f233a717e9034a5b41d6c89cce6d9058c4ee086","587fc9b71f1920df825138f00bc625e6610e61b1fec0a64e2800fc05b3a2e96d","010377f6b885ae48d62e7863c8038240aafe0a7fb97d58ac6173186c95335955","5243782226739f59b0ac01a56a63537289ffe81b87b33eca42f89f7848623520","06184cb8e46b5d520cd9b5829feeb73b688d61e5f37b91ff88d3f9b8664a5cdd","fe48f4b568bb501732c4e8f1919940c9bca0ad909f4624658b14664af823ccfe","0841f121e7a54f88a844227cd0ae62171b49d004120c16d1a1d619f0b76f7068","c4ac3c8751a63f7c40062b9b84f2bb953b0e6bd8f2cf3b2bcaf711321e92df8f","86a2b1a31bf80f17c00ab28420c636c1ed604d0b1f0a33adda99a0cf1e510269","fb892eba992b723a06bccad6a2a1bb875d548a275a987266fceed097b9fd88db","41991fb15fdb013ccab3e6674b91546a0e1e56a1e212c8795c76d0b43f4c884d","ab6a4368d2e5e7cea23fd648662769facc1c37f1d1613225e9010af07cd74711"]},"ecdsa-v1":{"signature":"1d9a5e2336883cc6b440ff40e16ee44f8af2ba9313e46f1e4cd417f7dba7686279b0216e4b0b5fcf0c650dbad98fdefcf5ef16b49d63651a87f80caddd472384"}},"time":"2021-10-04T22:00:15"}}"#;
+            let json = RESPONSE;
 
             let deserialized = serde_json::from_str::<oracle::Announcement>(json).unwrap();
             let expected = oracle::Announcement {
@@ -539,9 +724,37 @@ mod olivia_api {
             assert_eq!(deserialized, expected)
         }
 
+        #[test]
+        fn attestation_verifies_against_announced_nonces() {
+            let oracle_pk = OLIVIA_PK.parse().unwrap();
+            let announcement = serde_json::from_str::<oracle::Announcement>(RESPONSE).unwrap();
+            let attestation = serde_json::from_str::<oracle::Attestation>(RESPONSE).unwrap();
+
+            attestation
+                .verify(&oracle_pk, &announcement.nonce_pks)
+                .unwrap();
+        }
+
+        #[test]
+        fn attestation_with_mismatched_scalar_is_rejected() {
+            let oracle_pk = OLIVIA_PK.parse().unwrap();
+            let announcement = serde_json::from_str::<oracle::Announcement>(RESPONSE).unwrap();
+            let mut attestation = serde_json::from_str::<oracle::Attestation>(RESPONSE).unwrap();
+            attestation.scalars.swap(0, 1);
+
+            let error = attestation
+                .verify(&oracle_pk, &announcement.nonce_pks)
+                .unwrap_err();
+
+            assert!(
+                error.to_string().contains("Scalar 0"),
+                "unexpected error: {error:#}"
+            );
+        }
+
         #[test]
         fn deserialize_attestation() {
-            let json = r#"{"announcement":{"oracle_event":{"encoding":"json","data":"{\"id\":\"/x/BitMEX/BXBT/2021-10-04T22:00:00.price?n=20\",\"expected-outcome-time\":\"2021-10-04T22:00:00\",\"descriptor\":{\"type\":\"digit-decomposition\",\"is_signed\":false,\"n_digits\":20,\"unit\":null},\"schemes\":{\"olivia-v1\":{\"nonces\":[\"8d72028eeaf4b85aec0f750f05a4a320cac193f5d8494bfe05cd4b29f3df4239\",\"77240f79a0042adae35ad24284b18b906f17a979fcec3c90d11ed682c6b9261e\",\"e42332407b58f7c6e860b886acfe8d19636fb21a1e20722522206b30a2424d89\",\"ce1158e02dc265751887edae9bdcf8d06ad40489c7643324ccb6a46e4e740f5a\",\"52a5751a43046217bcf009df917c24e400c6da645474a654a5f89499df7154d4\",\"e7b97360a952c2b239d1bfeaade73da4a38e83d20f5deb5b054bcbbc78c91e40\",\"612ce13fd61be10e8de77976c6d479865bc3d2ebdc212946f1e5d93e3f504d2e\",\"e40decd0ea27003b873dde9b6be02f1b344e7e74bc5299144fa0f37b1cf12e90\",\"281a829e05d5f8b96eaf620c7b26115bfb29013d503b6bb40068cdb413a87197\",\"3c87eed0a3852953b0f3ac8a47ff194de66c7229c42e6578e0f6464ba240f033\",\"29028525277cb39adab9ac145d6ce61f2e10306e7b6ce95970a22ea3b201a5d9\",\"20971b4d2069d8b9b5c5678290ab7624821cf32ffe32a20d58428ca90da02523\",\"667a9af33ed45bfb5c4fc7adacea15bbe26df90e0df7dd5b8235e14dfd0da38f\",\"224df2d2706b5c629173b84927e2b206dad7a72e132eb86912d9464dad4b41d1\",\"85296962b9d1f7699c248467ce94ce4aa6e00d26fe01af3a507bcd3a303855d4\",\"96813c9f4d136f0f64be79e73d657fecc43d8b6c463163913b4fa31f96b1ae6b\",\"9d5971aa596923560b12f367fb2f4e192d8906bf6ed3a58b093f50d3cad27493\",\"b7f2c135db80cee02b4436557c78dc1dd2343c1a3688ba736c6c40e9531547b6\",\"bd6236fc18f1dc96f9755cc5c435adaf3952ff810d3ad5b96a03464a61eecfde\",\"20b2922ce326e5e2f4ed683723a879e467edd1068bf5a3c4f331525216227abe\"]},\"ecdsa-v1\":{}}}"},"signature":"743ed9900aba5a1ba3ba9d862628cdc5cca27974c40c4ab64618709021b3fbb13216a3efc733be260025da487ae9b63a8290d555bdc8da6324deff149fc7b110"},"attestation":{"outcome":"48935","schemes":{"olivia-v1":{"scalars":["1327b3bd0f1faf45d6fed6c96d0c158da22a2033a6fed98bed036df0a4eef484","72659c6beebd45e299bc4260a1c1ffd708ed33771459563502f25fc4f537cef6","051eec45417e2493f36b13f4fdf83fb981be42901bf876e4ac594ff2daa4c30e","847d8c7204335b1dbc2078cfb56118b1977162e7b997f2029f490929bbd603c7","5b695846292b6d69d9beedcc7dd2b7e49fd49ec4fcf262d9357f52b049fa8998","368a1f2206fcedcde37381b272fa5a400f55ef720ee2b8fff558e3b0dce729ee","9e1c015c0e827037f18681937764f4973ef22d6fbbd82f6bde3bf5198f6b8999","fe9620c9ad9862b5615f8cf3e20e8d9f422e7410914ce8af2b8bad8937b75738","44297ae831898f8f5c7e57720f233a717e9034a5b41d6c89cce6d9058c4ee086","587fc9b71f1920df825138f00bc625e6610e61b1fec0a64e2800fc05b3a2e96d","010377f6b885ae48d62e7863c8038240aafe0a7fb97d58ac6173186c95335955","5243782226739f59b0ac01a56a63537289ffe81b87b33eca42f89f7848623520","06184cb8e46b5d520cd9b5829feeb73b688d61e5f37b91ff88d3f9b8664a5cdd","fe48f4b568bb501732c4e8f1919940c9bca0ad909f4624658b14664af823ccfe","0841f121e7a54f88a844227cd0ae62171b49d004120c16d1a1d619f0b76f7068","c4ac3c8751a63f7c40062b9b84f2bb953b0e6bd8f2cf3b2bcaf711321e92df8f","86a2b1a31bf80f17c00ab28420c636c1ed604d0b1f0a33adda99a0cf1e510269","fb892eba992b723a06bccad6a2a1bb875d548a275a987266fceed097b9fd88db","41991fb15fdb013ccab3e6674b91546a0e1e56a1e212c8795c76d0b43f4c884d","ab6a4368d2e5e7cea23fd648662769facc1c37f1d1613225e9010af07cd74711"]},"ecdsa-v1":{"signature":"1d9a5e2336883cc6b440ff40e16ee44f8af2ba9313e46f1e4cd417f7dba7686279b0216e4b0b5fcf0c650dbad98fdefcf5ef16b49d63651a87f80caddd472384"}},"time":"2021-10-04T22:00:15"}}"#;
+            let json = RESPONSE;
 
             let deserialized = serde_json::from_str::<oracle::Attestation>(json).unwrap();
             let expected = oracle::Attestation {
@@ -619,7 +832,11 @@ mod olivia_api {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use olivia_api::tests::OLIVIA_PK;
+    use olivia_api::tests::RESPONSE;
     use time::macros::datetime;
+    use xtra::Actor as _;
+    use xtra::Handler;
 
     #[test]
     fn next_event_id_after_timestamp() {
@@ -642,4 +859,99 @@ mod tests {
             "/x/BitMEX/BXBT/2021-09-24T00:00:00.price?n=20"
         );
     }
+
+    #[tokio::test]
+    async fn cfds_sharing_an_event_are_monitored_once_and_attested_once() {
+        let (recorder, recorder_fut) = AttestationRecorder::default().create(None).run();
+        tokio::spawn(recorder_fut);
+        let mut oracle = Actor::new(
+            db::memory().await.unwrap(),
+            Box::new(recorder.clone()),
+            Duration::hours(24),
+            OLIVIA_PK.parse().unwrap(),
+        );
+        let (_, mut ctx) = xtra::Context::<Actor>::new(None);
+        let attestation = serde_json::from_str::<Attestation>(RESPONSE).unwrap();
+        let announcement = serde_json::from_str::<Announcement>(RESPONSE).unwrap();
+
+        // One `MonitorAttestation` per CFD settling on the event
+        for _ in 0..2 {
+            oracle
+                .handle(
+                    MonitorAttestation {
+                        event_id: attestation.id,
+                    },
+                    &mut ctx,
+                )
+                .await;
+        }
+        assert_eq!(oracle.pending_attestations, HashSet::from([attestation.id]));
+
+        oracle
+            .handle(
+                NewAttestationFetched {
+                    id: attestation.id,
+                    attestation: attestation.clone(),
+                    nonce_pks: announcement.nonce_pks,
+                },
+                &mut ctx,
+            )
+            .await;
+
+        assert!(oracle.pending_attestations.is_empty());
+        assert_eq!(recorder.send(Recorded).await.unwrap(), vec![attestation]);
+    }
+
+    #[tokio::test]
+    async fn attestation_is_fetched_once_while_in_flight_and_again_after_failure() {
+        let (recorder, recorder_fut) = AttestationRecorder::default().create(None).run();
+        tokio::spawn(recorder_fut);
+        let mut oracle = Actor::new(
+            db::memory().await.unwrap(),
+            Box::new(recorder),
+            Duration::hours(24),
+            OLIVIA_PK.parse().unwrap(),
+        );
+        let (_, mut ctx) = xtra::Context::<Actor>::new(None);
+        let event_id = serde_json::from_str::<Attestation>(RESPONSE).unwrap().id;
+
+        oracle
+            .handle(MonitorAttestation { event_id }, &mut ctx)
+            .await;
+
+        assert_eq!(oracle.attestations_to_fetch(), vec![event_id]);
+        assert!(
+            oracle.attestations_to_fetch().is_empty(),
+            "attestation in flight must not be fetched again"
+        );
+
+        oracle
+            .handle(AttestationFetchFailed { id: event_id }, &mut ctx)
+            .await;
+
+        assert_eq!(oracle.attestations_to_fetch(), vec![event_id]);
+    }
+
+    /// Stands in for the actor dispatching attestations to the CFDs.
+    #[derive(Default)]
+    struct AttestationRecorder(Vec<Attestation>);
+
+    impl xtra::Actor for AttestationRecorder {}
+
+    struct Recorded;
+
+    impl xtra::Message for Recorded {
+        type Result = Vec<Attestation>;
+    }
+
+    #[xtra_productivity(message_impl = false)]
+    impl AttestationRecorder {
+        fn handle_attestation(&mut self, msg: Attestation) {
+            self.0.push(msg);
+        }
+
+        fn handle_recorded(&mut self, _: Recorded) -> Vec<Attestation> {
+            self.0.clone()
+        }
+    }
 }
@@ -1,11 +1,13 @@
 use crate::model::FeeFlow;
 use crate::model::Leverage;
 use crate::model::Price;
+use crate::model::RoundingStrategy;
 use crate::model::Usd;
 use crate::payout_curve::curve::Curve;
 use anyhow::Context;
 use anyhow::Result;
 use bdk::bitcoin;
+use cached::proc_macro::cached;
 use itertools::Itertools;
 use maia::generate_payouts;
 use maia::Payout;
@@ -55,8 +57,9 @@ pub fn calculate(
     leverage: Leverage,
     n_payouts: usize,
     fee: FeeFlow,
+    rounding: RoundingStrategy,
 ) -> Result<Vec<Payout>> {
-    let payouts = calculate_payout_parameters(price, quantity, leverage, n_payouts, fee)?
+    let payouts = calculate_payout_parameters(price, quantity, leverage, n_payouts, fee, rounding)?
         .into_iter()
         .map(PayoutParameter::into_payouts)
         .flatten_ok()
@@ -78,25 +81,11 @@ fn calculate_payout_parameters(
     long_leverage: Leverage,
     n_payouts: usize,
     fee: FeeFlow,
+    rounding: RoundingStrategy,
 ) -> Result<Vec<PayoutParameter>> {
-    let initial_rate = price
-        .try_into_f64()
-        .context("Cannot convert price to f64")?;
-    let quantity = quantity
-        .try_into_u64()
-        .context("Cannot convert quantity to u64")? as usize;
-
-    let payout_curve = PayoutCurve::new(
-        initial_rate,
-        long_leverage.get() as usize,
-        SHORT_LEVERAGE,
-        quantity,
-        CONTRACT_VALUE,
-        None,
-    )?;
+    let (payout_scheme, total_value) = payout_scheme(price, quantity, long_leverage, n_payouts)?;
 
-    let payout_parameters = payout_curve
-        .generate_payout_scheme(n_payouts)?
+    let payout_parameters = payout_scheme
         .rows()
         .into_iter()
         .map(|row| {
@@ -104,7 +93,7 @@ fn calculate_payout_parameters(
             let right_bound = row[1] as u64;
             let long_amount_btc = row[2];
 
-            let long_amount = to_sats(long_amount_btc)?;
+            let long_amount = to_sats(long_amount_btc, rounding)?;
 
             let long_amount_adjusted = match fee {
                 FeeFlow::LongPaysShort(fee) => long_amount.saturating_sub(fee.as_sat()),
@@ -114,7 +103,7 @@ fn calculate_payout_parameters(
 
             let adjustment = long_amount - long_amount_adjusted;
 
-            let short_amount = to_sats(payout_curve.total_value - long_amount_btc)?;
+            let short_amount = to_sats(total_value - long_amount_btc, rounding)?;
             let short_amount_adjusted = short_amount + adjustment;
 
             Ok(PayoutParameter {
@@ -129,6 +118,47 @@ fn calculate_payout_parameters(
     Ok(payout_parameters)
 }
 
+/// Generate the payout scheme of the curve for the given inputs, together with its total value.
+///
+/// Fitting the curve is expensive for a high number of payouts and settlement and rollover previews
+/// keep asking for the same scheme. Fees and rounding are applied on top of the scheme, hence they
+/// are not part of the cache key.
+#[cached(
+    size = 100,
+    key = "(Decimal, Decimal, u8, usize)",
+    convert = r#"{ (price.into_decimal(), quantity.into_decimal(), long_leverage.get(), n_payouts) }"#,
+    result = true
+)]
+fn payout_scheme(
+    price: Price,
+    quantity: Usd,
+    long_leverage: Leverage,
+    n_payouts: usize,
+) -> Result<(Array2<f64>, f64)> {
+    #[cfg(test)]
+    tests::PAYOUT_SCHEME_COMPUTATIONS.with(|computations| computations.set(computations.get() + 1));
+
+    let initial_rate = price
+        .try_into_f64()
+        .context("Cannot convert price to f64")?;
+    let quantity = quantity
+        .try_into_u64()
+        .context("Cannot convert quantity to u64")? as usize;
+
+    let payout_curve = PayoutCurve::new(
+        initial_rate,
+        long_leverage.get() as usize,
+        SHORT_LEVERAGE,
+        quantity,
+        CONTRACT_VALUE,
+        None,
+    )?;
+
+    let payout_scheme = payout_curve.generate_payout_scheme(n_payouts)?;
+
+    Ok((payout_scheme, payout_curve.total_value))
+}
+
 #[derive(PartialEq, Clone, Copy)]
 struct PayoutParameter {
     left_bound: u64,
@@ -159,12 +189,15 @@ impl fmt::Debug for PayoutParameter {
 }
 
 /// Converts a float with any precision to a [`bitcoin::Amount`].
-fn to_sats(btc: f64) -> Result<u64> {
+fn to_sats(btc: f64, rounding: RoundingStrategy) -> Result<u64> {
     let sats_per_btc = Decimal::from(100_000_000);
 
     let btc = Decimal::from_f64(btc).context("Cannot create decimal from float")?;
     let sats = btc * sats_per_btc;
-    let sats = sats.to_u64().context("Cannot fit sats into u64")?;
+    let sats = rounding
+        .round_payout(sats)
+        .to_u64()
+        .context("Cannot fit sats into u64")?;
 
     Ok(sats)
 }
@@ -471,8 +504,16 @@ mod tests {
     use super::*;
     use bdk::bitcoin::Amount;
     use rust_decimal_macros::dec;
+    use std::cell::Cell;
     use std::ops::RangeInclusive;
 
+    thread_local! {
+        /// How often the payout scheme was computed instead of taken from the cache.
+        ///
+        /// Thread local because tests run in parallel.
+        pub(super) static PAYOUT_SCHEME_COMPUTATIONS: Cell<usize> = Cell::new(0);
+    }
+
     #[test]
     fn test_bounded() {
         let initial_rate = 40000.0;
@@ -528,6 +569,7 @@ mod tests {
             Leverage::new(5).unwrap(),
             200,
             FeeFlow::Nein,
+            RoundingStrategy::default(),
         )
         .unwrap();
 
@@ -749,14 +791,21 @@ mod tests {
             Leverage::new(5).unwrap(),
             200,
             FeeFlow::Nein,
+            RoundingStrategy::default(),
         )
         .unwrap();
 
         let fee = FeeFlow::LongPaysShort(Amount::from_sat(100));
 
-        let payouts_with_fee =
-            calculate_payout_parameters(price, quantity, Leverage::new(5).unwrap(), 200, fee)
-                .unwrap();
+        let payouts_with_fee = calculate_payout_parameters(
+            price,
+            quantity,
+            Leverage::new(5).unwrap(),
+            200,
+            fee,
+            RoundingStrategy::default(),
+        )
+        .unwrap();
         assert_eq!(payouts.len(), payouts_with_fee.len());
 
         let total_per_payout = payouts
@@ -806,6 +855,7 @@ mod tests {
             Leverage::new(5).unwrap(),
             200,
             FeeFlow::Nein,
+            RoundingStrategy::default(),
         )
         .unwrap();
 
@@ -816,6 +866,111 @@ mod tests {
         pretty_assertions::assert_eq!(actual_payouts.last().unwrap(), &upper_tail);
     }
 
+    #[test]
+    fn n_payouts_determines_number_of_cets() {
+        let price = Price::new(dec!(54000.00)).unwrap();
+        let quantity = Usd::new(dec!(3500.00));
+        let leverage = Leverage::new(5).unwrap();
+
+        let coarse = calculate_payout_parameters(
+            price,
+            quantity,
+            leverage,
+            50,
+            FeeFlow::Nein,
+            RoundingStrategy::default(),
+        )
+        .unwrap();
+        let default = calculate_payout_parameters(
+            price,
+            quantity,
+            leverage,
+            200,
+            FeeFlow::Nein,
+            RoundingStrategy::default(),
+        )
+        .unwrap();
+
+        // One segment per payout plus the lower tail
+        assert_eq!(coarse.len(), 51);
+        assert_eq!(default.len(), 201);
+
+        let coarse_cets = calculate(
+            price,
+            quantity,
+            leverage,
+            50,
+            FeeFlow::Nein,
+            RoundingStrategy::default(),
+        )
+        .unwrap();
+        let default_cets = calculate(
+            price,
+            quantity,
+            leverage,
+            200,
+            FeeFlow::Nein,
+            RoundingStrategy::default(),
+        )
+        .unwrap();
+
+        assert!(coarse_cets.len() < default_cets.len());
+    }
+
+    #[test]
+    fn identical_inputs_reuse_cached_payout_scheme() {
+        // Inputs no other test uses, otherwise the cache might already be populated
+        let price = Price::new(dec!(43210.12)).unwrap();
+        let quantity = Usd::new(dec!(1234));
+        let leverage = Leverage::new(3).unwrap();
+        let computations = || PAYOUT_SCHEME_COMPUTATIONS.with(Cell::get);
+
+        let first = calculate_payout_parameters(
+            price,
+            quantity,
+            leverage,
+            20,
+            FeeFlow::Nein,
+            RoundingStrategy::default(),
+        )
+        .unwrap();
+        assert_eq!(computations(), 1);
+
+        let second = calculate_payout_parameters(
+            price,
+            quantity,
+            leverage,
+            20,
+            FeeFlow::LongPaysShort(Amount::from_sat(1000)),
+            RoundingStrategy::default(),
+        )
+        .unwrap();
+        assert_eq!(computations(), 1, "fees should not invalidate the cache");
+        assert_ne!(first, second);
+
+        calculate_payout_parameters(
+            price,
+            quantity,
+            leverage,
+            21,
+            FeeFlow::Nein,
+            RoundingStrategy::default(),
+        )
+        .unwrap();
+        assert_eq!(computations(), 2);
+    }
+
+    #[test]
+    fn rounding_strategy_determines_payout_amounts() {
+        let btc = 0.000_000_019;
+
+        assert_eq!(
+            to_sats(btc, RoundingStrategy::FeesUpPayoutsDown).unwrap(),
+            1
+        );
+        assert_eq!(to_sats(btc, RoundingStrategy::Bankers).unwrap(), 2);
+    }
+
     fn payout(range: RangeInclusive<u64>, short: u64, long: u64) -> PayoutParameter {
         PayoutParameter {
             left_bound: *range.start(),
@@ -1,17 +1,33 @@
-use crate::db::append_event;
+use crate::cfd_actors::append_cfd_state;
+use crate::metrics::Metrics;
 use crate::model::cfd;
 use crate::model::cfd::CfdEvent;
+use crate::model::cfd::OrderId;
 use crate::model::cfd::Role;
 use crate::monitor;
 use crate::monitor::MonitorParams;
 use crate::monitor::TransactionKind;
 use crate::oracle;
 use crate::projection;
+use crate::Tasks;
+use anyhow::Context;
 use anyhow::Result;
+use bdk::bitcoin::Transaction;
+use bdk::electrum_client;
+use std::time::Duration;
 use xtra::prelude::MessageChannel;
 use xtra_productivity::xtra_productivity;
 use xtras::SendAsyncSafe;
 
+/// How often we try to broadcast the lock transaction after contract setup.
+///
+/// If all attempts fail, the CFD stays in `PendingOpen`. Its events still contain the lock
+/// transaction, hence the monitor re-broadcasts it on the next startup.
+const LOCK_BROADCAST_ATTEMPTS: u32 = 3;
+
+/// How long we wait before retrying to broadcast the lock transaction, doubled on every retry.
+const LOCK_BROADCAST_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
 pub struct Actor {
     db: sqlx::SqlitePool,
     role: Role,
@@ -20,6 +36,8 @@ pub struct Actor {
     start_monitoring: Box<dyn MessageChannel<monitor::StartMonitoring>>,
     monitor_collaborative_settlement: Box<dyn MessageChannel<monitor::CollaborativeSettlement>>,
     monitor_attestation: Box<dyn MessageChannel<oracle::MonitorAttestation>>,
+    metrics: Metrics,
+    tasks: Tasks,
 }
 
 pub struct Event(cfd::Event);
@@ -40,6 +58,7 @@ impl Actor {
         monitor_collaborative_settlement: &(impl MessageChannel<monitor::CollaborativeSettlement>
               + 'static),
         monitor_attestation: &(impl MessageChannel<oracle::MonitorAttestation> + 'static),
+        metrics: Metrics,
     ) -> Self {
         Self {
             db,
@@ -49,6 +68,8 @@ impl Actor {
             start_monitoring: start_monitoring.clone_channel(),
             monitor_collaborative_settlement: monitor_collaborative_settlement.clone_channel(),
             monitor_attestation: monitor_attestation.clone_channel(),
+            metrics,
+            tasks: Tasks::default(),
         }
     }
 }
@@ -58,9 +79,18 @@ impl Actor {
     fn handle(&mut self, msg: Event) -> Result<()> {
         let event = msg.0;
 
+        // A DLC never changes once stored, hence it is enough to verify it once
+        if let CfdEvent::ContractSetupCompleted { dlc } | CfdEvent::RolloverCompleted { dlc, .. } =
+            &event.event
+        {
+            dlc.verify_integrity()
+                .with_context(|| format!("DLC of CFD {} is inconsistent", event.id))?;
+        }
+
         // 1. Safe in DB
         let mut conn = self.db.acquire().await?;
-        append_event(event.clone(), &mut conn).await?;
+        append_cfd_state(event.clone(), self.role, &mut conn).await?;
+        self.metrics.record_event(&event.event);
 
         // 2. Post process event
         use CfdEvent::*;
@@ -69,12 +99,11 @@ impl Actor {
                 tracing::info!("Setup complete, publishing on chain now");
 
                 let lock_tx = dlc.lock.0.clone();
-                self.try_broadcast_transaction
-                    .send_async_safe(monitor::TryBroadcastTransaction {
-                        tx: lock_tx,
-                        kind: TransactionKind::Lock,
-                    })
-                    .await?;
+                self.tasks.add(broadcast_lock_tx(
+                    self.try_broadcast_transaction.clone_channel(),
+                    event.id,
+                    lock_tx,
+                ));
 
                 self.start_monitoring
                     .send_async_safe(monitor::StartMonitoring {
@@ -94,14 +123,31 @@ impl Actor {
             } => {
                 let txid = spend_tx.txid();
 
+                // Watch for the close transaction before attempting to broadcast it: the
+                // counterparty has the fully signed transaction too and may publish it first.
+                self.monitor_collaborative_settlement
+                    .send_async_safe(monitor::CollaborativeSettlement {
+                        order_id: event.id,
+                        tx: (txid, script),
+                    })
+                    .await?;
+
                 match self.role {
                     Role::Maker => {
-                        self.try_broadcast_transaction
+                        if let Err(e) = self
+                            .try_broadcast_transaction
                             .send(monitor::TryBroadcastTransaction {
                                 tx: spend_tx,
                                 kind: TransactionKind::CollaborativeClose,
                             })
-                            .await??;
+                            .await?
+                        {
+                            // The counterparty may still publish it, keep waiting for finality
+                            tracing::warn!(
+                                order_id = %event.id,
+                                "Failed to broadcast collaborative close {txid}: {e:#}"
+                            );
+                        }
                     }
                     Role::Taker => {
                         // TODO: Publish the tx once the collaborative settlement is symmetric,
@@ -110,13 +156,6 @@ impl Actor {
                         tracing::info!(order_id=%event.id, "Collaborative settlement completed successfully {txid}");
                     }
                 };
-
-                self.monitor_collaborative_settlement
-                    .send_async_safe(monitor::CollaborativeSettlement {
-                        order_id: event.id,
-                        tx: (txid, script),
-                    })
-                    .await?;
             }
             OracleAttestedPostCetTimelock { cet, .. }
             | CetTimelockExpiredPostOracleAttestation { cet } => {
@@ -175,18 +214,21 @@ impl Actor {
             CollaborativeSettlementStarted { .. }
             | ContractSetupStarted
             | ContractSetupFailed
-            | OfferRejected
+            | OfferRejected(_)
             | RolloverStarted
             | RolloverAccepted
             | RolloverRejected
             | RolloverFailed
             | CollaborativeSettlementProposalAccepted
+            | LockSeen
             | LockConfirmed
             | LockConfirmedAfterFinality
             | CommitConfirmed
             | CetConfirmed
             | RevokeConfirmed
             | CollaborativeSettlementConfirmed
+            | LockReorged
+            | CommitReorged
             | CollaborativeSettlementRejected
             | CollaborativeSettlementFailed
             | CetTimelockExpiredPriorOracleAttestation => {}
@@ -202,3 +244,172 @@ impl Actor {
 }
 
 impl xtra::Actor for Actor {}
+
+/// Broadcast the lock transaction, retrying with exponential backoff on transient failures.
+///
+/// Errors that are not caused by the connection to the electrum server, e.g. the transaction being
+/// rejected, will not go away by retrying.
+async fn broadcast_lock_tx(
+    try_broadcast_transaction: Box<dyn MessageChannel<monitor::TryBroadcastTransaction>>,
+    order_id: OrderId,
+    tx: Transaction,
+) {
+    let mut backoff = LOCK_BROADCAST_INITIAL_BACKOFF;
+
+    for attempt in 1..=LOCK_BROADCAST_ATTEMPTS {
+        let result = try_broadcast_transaction
+            .send(monitor::TryBroadcastTransaction {
+                tx: tx.clone(),
+                kind: TransactionKind::Lock,
+            })
+            .await;
+
+        let error = match result {
+            Ok(Ok(_)) => return,
+            Ok(Err(e)) => e,
+            Err(_) => {
+                tracing::warn!(%order_id, "Monitor actor disconnected, lock transaction not sent");
+                return;
+            }
+        };
+
+        if !is_transient(&error) {
+            tracing::error!(%order_id, "Failed to broadcast lock transaction: {error:#}");
+            return;
+        }
+
+        if attempt == LOCK_BROADCAST_ATTEMPTS {
+            tracing::error!(
+                %order_id,
+                %attempt,
+                "Giving up broadcasting lock transaction until next startup: {error:#}"
+            );
+            return;
+        }
+
+        tracing::warn!(
+            %order_id,
+            "Failed to broadcast lock transaction, retrying in {}s: {error:#}",
+            backoff.as_secs()
+        );
+
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+    }
+}
+
+/// Whether broadcasting failed because we could not reach the electrum server.
+fn is_transient(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<electrum_client::Error>(),
+        Some(
+            electrum_client::Error::IOError(_)
+                | electrum_client::Error::SharedIOError(_)
+                | electrum_client::Error::AllAttemptsErrored(_)
+        )
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bdk::bitcoin::Txid;
+    use std::collections::VecDeque;
+    use std::io;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use xtra::Actor as _;
+
+    #[tokio::test(start_paused = true)]
+    async fn lock_broadcast_is_retried_on_transient_errors() {
+        let (monitor, attempts) = FailingMonitor::start(vec![unreachable(), unreachable()]);
+
+        broadcast_lock_tx(monitor, OrderId::default(), dummy_tx()).await;
+
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn lock_broadcast_gives_up_after_all_attempts() {
+        let (monitor, attempts) = FailingMonitor::start(vec![
+            unreachable(),
+            unreachable(),
+            unreachable(),
+            unreachable(),
+        ]);
+
+        broadcast_lock_tx(monitor, OrderId::default(), dummy_tx()).await;
+
+        assert_eq!(
+            attempts.load(Ordering::SeqCst),
+            LOCK_BROADCAST_ATTEMPTS as usize
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn lock_broadcast_is_not_retried_if_rejected() {
+        let rejected = electrum_client::Error::Protocol(serde_json::Value::String(
+            r#"sendrawtransaction RPC error: {"code":-26,"message":"min relay fee not met"}"#
+                .to_owned(),
+        ));
+        let (monitor, attempts) = FailingMonitor::start(vec![rejected, unreachable()]);
+
+        broadcast_lock_tx(monitor, OrderId::default(), dummy_tx()).await;
+
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    fn unreachable() -> electrum_client::Error {
+        electrum_client::Error::IOError(io::Error::from(io::ErrorKind::ConnectionRefused))
+    }
+
+    fn dummy_tx() -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![],
+            output: vec![],
+        }
+    }
+
+    /// Fails to broadcast with the given errors, in order, and succeeds afterwards.
+    struct FailingMonitor {
+        errors: VecDeque<electrum_client::Error>,
+        attempts: Arc<AtomicUsize>,
+    }
+
+    impl FailingMonitor {
+        fn start(
+            errors: Vec<electrum_client::Error>,
+        ) -> (
+            Box<dyn MessageChannel<monitor::TryBroadcastTransaction>>,
+            Arc<AtomicUsize>,
+        ) {
+            let attempts = Arc::new(AtomicUsize::new(0));
+            let (addr, fut) = FailingMonitor {
+                errors: errors.into(),
+                attempts: attempts.clone(),
+            }
+            .create(None)
+            .run();
+            tokio::spawn(fut);
+
+            (addr.clone_channel(), attempts)
+        }
+    }
+
+    impl xtra::Actor for FailingMonitor {}
+
+    #[xtra_productivity(message_impl = false)]
+    impl FailingMonitor {
+        fn handle(&mut self, msg: monitor::TryBroadcastTransaction) -> Result<Txid> {
+            self.attempts.fetch_add(1, Ordering::SeqCst);
+
+            match self.errors.pop_front() {
+                Some(error) => Err(anyhow::Error::new(error).context("Broadcasting failed")),
+                None => Ok(msg.tx.txid()),
+            }
+        }
+    }
+}
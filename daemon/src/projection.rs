@@ -1,19 +1,24 @@
 use crate::bitmex_price_feed;
+use crate::cfd_actors;
 use crate::db;
 use crate::model;
 use crate::model::calculate_funding_fee;
 use crate::model::cfd::calculate_long_liquidation_price;
 use crate::model::cfd::calculate_long_margin;
+use crate::model::cfd::calculate_margin_with_fees;
 use crate::model::cfd::calculate_profit;
 use crate::model::cfd::calculate_profit_at_price;
 use crate::model::cfd::calculate_short_margin;
+pub use crate::model::cfd::CfdAction;
 use crate::model::cfd::CfdEvent;
 use crate::model::cfd::Dlc;
 use crate::model::cfd::Event;
 use crate::model::cfd::OrderId;
 use crate::model::cfd::Origin;
+use crate::model::cfd::RejectReason;
 use crate::model::cfd::Role;
 use crate::model::FeeAccount;
+use crate::model::FeeFlow;
 use crate::model::FundingRate;
 use crate::model::Identity;
 use crate::model::Leverage;
@@ -22,13 +27,16 @@ use crate::model::Price;
 use crate::model::Timestamp;
 use crate::model::TradingPair;
 use crate::model::Usd;
+use crate::try_continue;
 use crate::Order;
 use crate::Tasks;
 use crate::SETTLEMENT_INTERVAL;
+use anyhow::bail;
 use anyhow::Context;
 use anyhow::Result;
 use async_trait::async_trait;
 use bdk::bitcoin::Amount;
+use bdk::bitcoin::Denomination;
 use bdk::bitcoin::Network;
 use bdk::bitcoin::Script;
 use bdk::bitcoin::SignedAmount;
@@ -43,11 +51,16 @@ use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
 use serde::Deserialize;
 use serde::Serialize;
+use sqlx::pool::PoolConnection;
+use sqlx::Sqlite;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::sync::Arc;
 use std::time::Duration;
 use time::OffsetDateTime;
 use tokio::sync::watch;
+use tokio::sync::Notify;
 use xtra::prelude::MessageChannel;
 use xtra_productivity::xtra_productivity;
 
@@ -58,57 +71,127 @@ pub struct Update<T>(pub T);
 /// Indicates that the CFD with the given order ID changed.
 pub struct CfdChanged(pub OrderId);
 
+/// Indicates that the contract setup of the CFD with the given order ID reached a milestone.
+pub struct SetupProgressed {
+    pub order_id: OrderId,
+    pub progress: SetupProgress,
+}
+
+/// Milestones of a contract setup, in the order they are reached.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum SetupProgress {
+    ParametersExchanged,
+    TransactionsCreated,
+    SignaturesVerified,
+    LockSigned,
+}
+
+/// Set the label of the CFD with the given order ID, or clear it if `label` is `None`.
+///
+/// Labels only help the user to organise their CFDs, they are never sent to the counterparty.
+/// They are limited to [`MAX_LABEL_LENGTH`] characters.
+pub struct SetLabel {
+    pub order_id: OrderId,
+    pub label: Option<String>,
+}
+
+/// The maximum number of characters of a CFD label.
+pub const MAX_LABEL_LENGTH: usize = 64;
+
+/// Archive the CFD with the given order ID, or restore it if `archived` is `false`.
+///
+/// Archived CFDs are hidden from the feed by default but stay in the database.
+pub struct SetArchived {
+    pub order_id: OrderId,
+    pub archived: bool,
+}
+
+/// Load at most `limit` CFDs positioned after `before` from the database, newest first.
+pub struct LoadCfds {
+    pub before: Option<db::CfdCursor>,
+    pub limit: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CfdPage {
+    pub cfds: Vec<Cfd>,
+    /// Cursor to pass as `before` to load the next page, `None` if this is the last page.
+    pub next: Option<db::CfdCursor>,
+}
+
+/// Default time window in which rapid CFD updates, e.g. during contract setup, are coalesced into
+/// a single publish.
+pub const DEBOUNCE_INTERVAL: Duration = Duration::from_millis(100);
+
 pub struct Actor {
     db: sqlx::SqlitePool,
     tx: Tx,
     state: State,
     price_feed: Box<dyn MessageChannel<bitmex_price_feed::LatestQuote>>,
+    /// How long CFD updates are collected before they are published, zero publishes immediately.
+    debounce: Duration,
+    /// Whether there are CFD updates that have not been published yet.
+    cfds_dirty: bool,
+    /// Wakes the task that publishes pending CFD updates once the debounce window elapsed.
+    publish_pending: Arc<Notify>,
     tasks: Tasks,
 }
 
+/// Publish the CFD updates collected during the debounce window.
+struct PublishCfds;
+
+#[derive(Clone)]
 pub struct Feeds {
     pub quote: watch::Receiver<Option<Quote>>,
     pub order: watch::Receiver<Option<CfdOrder>>,
     pub connected_takers: watch::Receiver<Vec<Identity>>,
     pub cfds: watch::Receiver<Vec<Cfd>>,
+    pub proposals: watch::Receiver<Vec<PendingProposal>>,
+    /// Only the maker publishes its exposure, hence this is `None` for the taker.
+    pub exposure: Option<watch::Receiver<Exposure>>,
 }
 
 impl Actor {
     pub fn new(
         db: sqlx::SqlitePool,
-        _role: Role,
+        role: Role,
         network: Network,
         price_feed: &(impl MessageChannel<bitmex_price_feed::LatestQuote> + 'static),
+        debounce: Duration,
     ) -> (Self, Feeds) {
-        let (tx_cfds, rx_cfds) = watch::channel(Vec::new());
-        let (tx_order, rx_order) = watch::channel(None);
-        let (tx_quote, rx_quote) = watch::channel(None);
-        let (tx_connected_takers, rx_connected_takers) = watch::channel(Vec::new());
+        let (tx, feeds) = Tx::new(role);
 
         let actor = Self {
             db,
-            tx: Tx {
-                cfds: tx_cfds,
-                order: tx_order,
-                quote: tx_quote,
-                connected_takers: tx_connected_takers,
-            },
+            tx,
             state: State::new(network),
             price_feed: price_feed.clone_channel(),
+            debounce,
+            cfds_dirty: false,
+            publish_pending: Arc::new(Notify::new()),
             tasks: Tasks::default(),
         };
-        let feeds = Feeds {
-            cfds: rx_cfds,
-            order: rx_order,
-            quote: rx_quote,
-            connected_takers: rx_connected_takers,
-        };
 
         (actor, feeds)
     }
+
+    /// Publish the CFDs, coalescing updates that happen within the debounce window.
+    ///
+    /// The window starts with the first update, hence an update is delayed by at most the
+    /// debounce interval.
+    fn publish_cfds(&mut self) {
+        if self.debounce.is_zero() {
+            self.tx
+                .send_cfds_update(self.state.cfds.clone(), self.state.quote);
+            return;
+        }
+
+        self.cfds_dirty = true;
+        self.publish_pending.notify_one();
+    }
 }
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct Cfd {
     pub order_id: OrderId,
     #[serde(with = "round_to_two_dp")]
@@ -117,8 +200,9 @@ pub struct Cfd {
     /// Sum of all costs
     ///
     /// Includes the opening fee and all fees that were already charged.
-    #[serde(with = "::bdk::bitcoin::util::amount::serde::as_btc")]
-    pub accumulated_fees: SignedAmount,
+    pub accumulated_fees: Denominated<SignedAmount>,
+    /// Net fees owed to either party if the CFD was settled now.
+    pub fee_flow: Denominated<FeeFlow>,
 
     pub leverage: Leverage,
     pub trading_pair: TradingPair,
@@ -129,15 +213,14 @@ pub struct Cfd {
     #[serde(with = "round_to_two_dp")]
     pub quantity_usd: Usd,
 
-    #[serde(with = "::bdk::bitcoin::util::amount::serde::as_btc")]
-    pub margin: Amount,
-    #[serde(with = "::bdk::bitcoin::util::amount::serde::as_btc")]
-    pub margin_counterparty: Amount,
+    pub margin: Denominated<Amount>,
+    pub margin_counterparty: Denominated<Amount>,
+    /// Our margin including the fees accumulated so far.
+    pub margin_with_fees: Denominated<Amount>,
     pub role: Role,
 
     /// Projected or final profit amount
-    #[serde(with = "::bdk::bitcoin::util::amount::serde::as_btc::opt")]
-    pub profit_btc: Option<SignedAmount>,
+    pub profit_btc: Option<Denominated<SignedAmount>>,
     /// Projected or final profit percent
     pub profit_percent: Option<String>,
 
@@ -149,11 +232,13 @@ pub struct Cfd {
     /// If we don't have a current price in this scenario we don't know the payout, hence it is
     /// represented as option. If we already know the final payout (based on CET or
     /// collborative close) then this is the final payout.
-    #[serde(with = "::bdk::bitcoin::util::amount::serde::as_btc::opt")]
-    pub payout: Option<SignedAmount>,
+    pub payout: Option<Denominated<SignedAmount>>,
+    #[serde(with = "round_to_two_dp::opt")]
     pub closing_price: Option<Price>,
 
     pub state: CfdState,
+    /// Why the take was rejected, if the CFD is in state [`CfdState::Rejected`] and we know it.
+    pub rejection_reason: Option<RejectReason>,
     pub actions: HashSet<CfdAction>,
 
     // TODO: This `CfdDetails` wrapper is useless and could be removed, but that would be a
@@ -162,12 +247,35 @@ pub struct Cfd {
 
     #[serde(with = "::time::serde::timestamp::option")]
     pub expiry_timestamp: Option<OffsetDateTime>,
+    /// Estimate of when the refund transaction can be published.
+    ///
+    /// The refund timelock is relative to the confirmation of the commit transaction, hence this
+    /// is only known once the commit transaction confirmed. Assumes an average block time of
+    /// 10 minutes.
+    #[serde(with = "::time::serde::timestamp::option")]
+    pub refund_timelock_expiry_estimate: Option<OffsetDateTime>,
+    /// Whether the commit transaction was broadcast by us or by the counterparty.
+    ///
+    /// `None` until the commit transaction was published by either party. If this is `Some(true)`
+    /// we forced the close and will publish the CET once its timelock expired.
+    pub commit_broadcast_by_us: Option<bool>,
 
     pub counterparty: Identity,
 
     #[serde(with = "round_to_two_dp::opt")]
     pub pending_settlement_proposal_price: Option<Price>,
 
+    /// Milestones reached during contract setup so far.
+    ///
+    /// Only kept in memory, hence empty for CFDs set up before the last restart.
+    pub setup_progress: Vec<SetupProgress>,
+
+    /// User-defined label, e.g. "hedge".
+    pub label: Option<String>,
+
+    /// Whether the user archived the CFD to hide it from the feed.
+    pub archived: bool,
+
     #[serde(skip)]
     aggregated: Aggregated,
 }
@@ -180,7 +288,7 @@ pub struct Cfd {
 /// - It serves as an aggregate that is hydrated from events.
 ///
 /// This dual-role motivates the existence of this struct.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 struct Aggregated {
     fee_account: FeeAccount,
 
@@ -195,7 +303,11 @@ struct Aggregated {
     timelocked_cet: Option<Transaction>,
 
     commit_published: bool,
+    /// Whether we broadcast the commit transaction ourselves.
+    commit_broadcast_by_us: bool,
     refund_published: bool,
+
+    commit_confirmed_at: Option<OffsetDateTime>,
 }
 
 impl Aggregated {
@@ -208,7 +320,9 @@ impl Aggregated {
             cet: None,
             timelocked_cet: None,
             commit_published: false,
+            commit_broadcast_by_us: false,
             refund_published: false,
+            commit_confirmed_at: None,
         }
     }
 
@@ -217,17 +331,31 @@ impl Aggregated {
             return Some(extract_payout_amount(tx, script));
         }
 
+        if self.refund_published {
+            return Some(self.latest_dlc?.refund_amount(role));
+        }
+
         let tx = self.cet.or(self.timelocked_cet)?;
         let dlc = self
             .latest_dlc
             .as_ref()
             .expect("dlc to be present when we have a cet");
-        let script = dlc.script_pubkey_for(role);
 
-        Some(extract_payout_amount(tx, script))
+        Some(dlc.output_amount_for(&tx, role))
     }
 }
 
+/// The average time between two blocks.
+const AVERAGE_BLOCK_TIME: Duration = Duration::from_secs(10 * 60);
+
+/// Estimate the wall-clock time at which a refund timelock of `refund_timelock_in_blocks` expires.
+fn estimate_refund_timelock_expiry(
+    commit_confirmed_at: OffsetDateTime,
+    refund_timelock_in_blocks: u32,
+) -> OffsetDateTime {
+    commit_confirmed_at + AVERAGE_BLOCK_TIME * refund_timelock_in_blocks
+}
+
 /// Returns output if it can be found or zero amount
 ///
 /// If we cannot find an output for our script we assume that we were liquidated.
@@ -239,6 +367,55 @@ fn extract_payout_amount(tx: Transaction, script: Script) -> Amount {
         .unwrap_or(Amount::ZERO)
 }
 
+/// Load all CFDs from the database without running the projection actor.
+///
+/// The profit of CFDs whose payout is not known yet is calculated at `price`, if given.
+pub async fn load_all_cfds(
+    db: &sqlx::SqlitePool,
+    network: Network,
+    price: Option<Price>,
+) -> Result<Vec<Cfd>> {
+    let quote = price.map(|price| bitmex_price_feed::Quote {
+        timestamp: Timestamp::now(),
+        bid: price,
+        ask: price,
+    });
+
+    let mut conn = db.acquire().await?;
+    let mut cfds = Vec::new();
+
+    for id in db::load_all_cfd_ids(&mut conn).await? {
+        let cfd = load_cfd(id, network, &mut conn).await?;
+
+        cfds.push(cfd.with_current_quote(quote));
+    }
+
+    Ok(cfds)
+}
+
+/// Load a CFD from the database and hydrate it from its events.
+async fn load_cfd(id: OrderId, network: Network, conn: &mut PoolConnection<Sqlite>) -> Result<Cfd> {
+    let (cfd, events) = db::load_cfd(id, conn).await?;
+
+    // The aggregate is the single source of truth for which commands are allowed and which price
+    // the CFD closes at
+    let aggregate = cfd_actors::rehydrate(cfd.clone(), events.clone());
+    let actions = aggregate.available_actions().into_iter().collect();
+    let closing_price = aggregate.final_closing_price().unwrap_or_else(|e| {
+        tracing::warn!(order_id = %id, "Unable to determine closing price: {e:#}");
+
+        None
+    });
+
+    let mut cfd = events
+        .into_iter()
+        .fold(Cfd::new(cfd), |cfd, event| cfd.apply(event, network));
+    cfd.actions = actions;
+    cfd.closing_price = closing_price;
+
+    Ok(cfd)
+}
+
 impl Cfd {
     fn new(
         db::Cfd {
@@ -251,6 +428,9 @@ impl Cfd {
             role,
             opening_fee,
             initial_funding_rate,
+            rounding,
+            label,
+            archived,
             ..
         }: db::Cfd,
     ) -> Self {
@@ -269,6 +449,7 @@ impl Cfd {
             leverage,
             initial_funding_rate,
             SETTLEMENT_INTERVAL.whole_hours(),
+            rounding,
         )
         .expect("values from db to be sane");
 
@@ -276,23 +457,19 @@ impl Cfd {
             .add_opening_fee(opening_fee)
             .add_funding_fee(initial_funding_fee);
 
-        let initial_actions = if role == Role::Maker {
-            HashSet::from([CfdAction::AcceptOrder, CfdAction::RejectOrder])
-        } else {
-            HashSet::new()
-        };
-
         Self {
             order_id: id,
             initial_price,
-            accumulated_fees: fee_account.balance(),
+            accumulated_fees: fee_account.balance().into(),
+            fee_flow: fee_account.settle().into(),
+            margin_with_fees: calculate_margin_with_fees(margin, position, &fee_account).into(),
             leverage,
             trading_pair: TradingPair::BtcUsd,
             position,
             liquidation_price,
             quantity_usd,
-            margin,
-            margin_counterparty,
+            margin: margin.into(),
+            margin_counterparty: margin_counterparty.into(),
             role,
 
             profit_btc: None,
@@ -301,156 +478,137 @@ impl Cfd {
             closing_price: None,
 
             state: CfdState::PendingSetup,
-            actions: initial_actions,
+            rejection_reason: None,
+            actions: HashSet::new(),
             details: CfdDetails {
                 tx_url_list: HashSet::new(),
             },
             expiry_timestamp: None,
+            refund_timelock_expiry_estimate: None,
+            commit_broadcast_by_us: None,
             counterparty: counterparty_network_identity,
             pending_settlement_proposal_price: None,
+            setup_progress: Vec::new(),
+            label,
+            archived,
             aggregated: Aggregated::new(fee_account),
         }
     }
 
     fn apply(mut self, event: Event, network: Network) -> Self {
-        // First, try to set state based on event.
+        // First, set state based on event.
+        self.state = CfdState::after(&event.event, self.role);
+
+        // Then, record whatever the event carries for display purposes.
         use CfdEvent::*;
         match event.event {
-            ContractSetupStarted => {
-                self.state = CfdState::ContractSetup;
-            }
             ContractSetupCompleted { dlc } => {
                 self.expiry_timestamp = Some(dlc.settlement_event_id.timestamp());
                 self.aggregated.latest_dlc = Some(dlc);
-
-                self.state = CfdState::PendingOpen;
-            }
-            ContractSetupFailed => {
-                self.state = CfdState::SetupFailed;
-            }
-            OfferRejected => {
-                self.state = CfdState::Rejected;
             }
             RolloverCompleted { dlc, funding_fee } => {
                 self.expiry_timestamp = Some(dlc.settlement_event_id.timestamp());
                 self.aggregated.latest_dlc = Some(dlc);
                 self.aggregated.fee_account =
                     self.aggregated.fee_account.add_funding_fee(funding_fee);
-                self.accumulated_fees = self.aggregated.fee_account.balance();
-
-                self.state = CfdState::Open;
-            }
-            RolloverRejected => {
-                self.state = CfdState::Open;
+                self.accumulated_fees = self.aggregated.fee_account.balance().into();
+                self.fee_flow = self.aggregated.fee_account.settle().into();
+                self.margin_with_fees = calculate_margin_with_fees(
+                    self.margin.value(),
+                    self.position,
+                    &self.aggregated.fee_account,
+                )
+                .into();
             }
-            RolloverFailed => {
-                self.state = CfdState::Open;
-            }
-            CollaborativeSettlementStarted { proposal } => match self.role {
-                Role::Maker => {
+            CollaborativeSettlementStarted { proposal } => {
+                if let Role::Maker = self.role {
                     self.pending_settlement_proposal_price = Some(proposal.price);
-
-                    self.state = CfdState::IncomingSettlementProposal;
                 }
-                Role::Taker => {
-                    self.state = CfdState::OutgoingSettlementProposal;
-                }
-            },
-            CollaborativeSettlementProposalAccepted => {
+            }
+            CollaborativeSettlementProposalAccepted
+            | CollaborativeSettlementRejected
+            | CollaborativeSettlementFailed => {
                 self.pending_settlement_proposal_price = None;
-
-                self.state = CfdState::PendingClose;
             }
             CollaborativeSettlementCompleted {
-                spend_tx,
-                script,
-                price,
+                spend_tx, script, ..
             } => {
                 self.aggregated.collab_settlement_tx = Some((spend_tx, script));
-                self.closing_price = Some(price);
-
-                self.state = CfdState::PendingClose;
-            }
-            CollaborativeSettlementRejected => {
-                self.pending_settlement_proposal_price = None;
-
-                self.state = CfdState::Open;
-            }
-            CollaborativeSettlementFailed => {
-                self.pending_settlement_proposal_price = None;
-                self.state = CfdState::Open;
-            }
-            LockConfirmed => {
-                self.state = CfdState::Open;
             }
             CommitConfirmed => {
                 // Commit can be published by either party, meaning it being confirmed might be the
                 // first time we hear about it!
                 self.aggregated.commit_published = true;
 
-                self.state = CfdState::OpenCommitted;
-            }
-            CetConfirmed => {
-                self.state = CfdState::Closed;
-            }
-            RefundConfirmed => {
-                self.state = CfdState::Refunded;
-            }
-            LockConfirmedAfterFinality | CollaborativeSettlementConfirmed => {
-                self.state = CfdState::Closed;
+                if self.aggregated.commit_confirmed_at.is_none() {
+                    self.aggregated.commit_confirmed_at = event.timestamp.to_offset_datetime().ok();
+                }
             }
-            CetTimelockExpiredPriorOracleAttestation => {
-                self.state = CfdState::OpenCommitted;
+            CommitReorged => {
+                self.aggregated.commit_confirmed_at = None;
             }
             CetTimelockExpiredPostOracleAttestation { cet } => {
                 self.aggregated.cet = Some(cet);
-
-                self.state = CfdState::PendingCet;
             }
             RefundTimelockExpired { .. } => {
                 self.aggregated.refund_published = true;
-
-                self.state = CfdState::PendingRefund;
             }
             OracleAttestedPriorCetTimelock {
                 timelocked_cet,
-                price,
+                commit_tx,
                 ..
             } => {
                 self.aggregated.timelocked_cet = Some(timelocked_cet);
-                self.closing_price = Some(price);
 
-                self.state = CfdState::PendingCommit;
+                // The commit transaction is emitted whenever we haven't broadcast it before, even
+                // if the counterparty's commit transaction already confirmed.
+                if commit_tx.is_some() && !self.aggregated.commit_published {
+                    self.aggregated.commit_broadcast_by_us = true;
+                }
             }
-            OracleAttestedPostCetTimelock { cet, price, .. } => {
+            OracleAttestedPostCetTimelock { cet, .. } => {
                 self.aggregated.cet = Some(cet);
-                self.closing_price = Some(price);
-
-                self.state = CfdState::PendingCet;
             }
             ManualCommit { .. } => {
                 self.aggregated.commit_published = true;
-
-                self.state = CfdState::PendingCommit;
+                self.aggregated.commit_broadcast_by_us = true;
             }
             RevokeConfirmed => {
                 tracing::error!(order_id = %self.order_id, "Revoked logic not implemented");
-                self.state = CfdState::OpenCommitted;
             }
-            RolloverStarted { .. } => match self.role {
-                Role::Maker => {
-                    self.state = CfdState::IncomingRolloverProposal;
-                }
-                Role::Taker => {
-                    self.state = CfdState::OutgoingRolloverProposal;
-                }
-            },
-            RolloverAccepted => {
-                self.state = CfdState::ContractSetup;
+            OfferRejected(reason) => {
+                self.rejection_reason = reason;
             }
+            ContractSetupStarted
+            | ContractSetupFailed
+            | RolloverRejected
+            | RolloverFailed
+            | LockSeen
+            | CetConfirmed
+            | RefundConfirmed
+            | CollaborativeSettlementConfirmed
+            | LockReorged
+            | LockConfirmed
+            | LockConfirmedAfterFinality
+            | CetTimelockExpiredPriorOracleAttestation
+            | RolloverStarted { .. }
+            | RolloverAccepted => {}
+        };
+
+        self.refund_timelock_expiry_estimate = match (
+            self.aggregated.commit_confirmed_at,
+            self.aggregated.latest_dlc.as_ref(),
+        ) {
+            (Some(commit_confirmed_at), Some(dlc)) => Some(estimate_refund_timelock_expiry(
+                commit_confirmed_at,
+                dlc.refund_timelock,
+            )),
+            _ => None,
         };
 
-        self.actions = self.derive_actions();
+        if self.aggregated.commit_published {
+            self.commit_broadcast_by_us = Some(self.aggregated.commit_broadcast_by_us);
+        }
 
         if let Some(lock_tx_url) = self.lock_tx_url(network) {
             self.details.tx_url_list.insert(lock_tx_url);
@@ -481,13 +639,14 @@ impl Cfd {
             let (profit_btc, profit_percent) = calculate_profit(
                 payout,
                 self.margin
+                    .value()
                     .to_signed()
                     .expect("Amount to fit into signed amount"),
             );
 
             return Self {
-                payout: Some(payout),
-                profit_btc: Some(profit_btc),
+                payout: Some(payout.into()),
+                profit_btc: Some(profit_btc.into()),
                 profit_percent: Some(profit_percent.to_string()),
                 ..self
             };
@@ -509,7 +668,7 @@ impl Cfd {
                     None
                 }
             }
-        }).map(|(in_btc, in_percent, payout)| (Some(in_btc), Some(in_percent.round_dp(1).to_string()), Some(payout)))
+        }).map(|(in_btc, in_percent, payout)| (Some(Denominated::new(in_btc)), Some(in_percent.round_dp(1).to_string()), Some(Denominated::new(payout))))
             .unwrap_or_else(|| {
                 tracing::debug!(order_id = %self.order_id, "Unable to calculate profit/loss without current price");
 
@@ -524,34 +683,21 @@ impl Cfd {
         }
     }
 
-    fn derive_actions(&self) -> HashSet<CfdAction> {
-        match (self.state, self.role) {
-            (CfdState::PendingSetup, Role::Maker) => {
-                HashSet::from([CfdAction::AcceptOrder, CfdAction::RejectOrder])
-            }
-            (CfdState::PendingSetup, Role::Taker) => HashSet::new(),
-            (CfdState::ContractSetup, _) => HashSet::new(),
-            (CfdState::Rejected, _) => HashSet::new(),
-            (CfdState::PendingOpen, _) => HashSet::new(),
-            (CfdState::Open, _) => HashSet::from([CfdAction::Commit, CfdAction::Settle]),
-            (CfdState::PendingCommit, _) => HashSet::new(),
-            (CfdState::PendingCet, _) => HashSet::new(),
-            (CfdState::PendingClose, _) => HashSet::new(),
-            (CfdState::OpenCommitted, _) => HashSet::new(),
-            (CfdState::IncomingSettlementProposal, Role::Maker) => {
-                HashSet::from([CfdAction::AcceptSettlement, CfdAction::RejectSettlement])
-            }
-            (CfdState::IncomingSettlementProposal, Role::Taker) => HashSet::new(),
-            (CfdState::OutgoingSettlementProposal, _) => HashSet::new(),
-            (CfdState::IncomingRolloverProposal, Role::Maker) => {
-                HashSet::from([CfdAction::AcceptRollover, CfdAction::RejectRollover])
-            }
-            (CfdState::IncomingRolloverProposal, Role::Taker) => HashSet::new(),
-            (CfdState::OutgoingRolloverProposal, _) => HashSet::new(),
-            (CfdState::Closed, _) => HashSet::new(),
-            (CfdState::PendingRefund, _) => HashSet::new(),
-            (CfdState::Refunded, _) => HashSet::new(),
-            (CfdState::SetupFailed, _) => HashSet::new(),
+    /// Render all bitcoin amounts in `denomination`.
+    pub fn in_denomination(self, denomination: Denomination) -> Self {
+        Self {
+            accumulated_fees: self.accumulated_fees.in_denomination(denomination),
+            fee_flow: self.fee_flow.in_denomination(denomination),
+            margin: self.margin.in_denomination(denomination),
+            margin_counterparty: self.margin_counterparty.in_denomination(denomination),
+            margin_with_fees: self.margin_with_fees.in_denomination(denomination),
+            profit_btc: self
+                .profit_btc
+                .map(|profit| profit.in_denomination(denomination)),
+            payout: self
+                .payout
+                .map(|payout| payout.in_denomination(denomination)),
+            ..self
         }
     }
 
@@ -624,17 +770,67 @@ struct Tx {
     // TODO: Use this channel to communicate maker status as well with generic
     // ID of connected counterparties
     pub connected_takers: watch::Sender<Vec<Identity>>,
+    proposals: watch::Sender<Vec<PendingProposal>>,
+    /// Only present for the maker, the taker does not publish its exposure.
+    exposure: Option<watch::Sender<Exposure>>,
 }
 
 impl Tx {
+    fn new(role: Role) -> (Self, Feeds) {
+        let (tx_cfds, rx_cfds) = watch::channel(Vec::new());
+        let (tx_order, rx_order) = watch::channel(None);
+        let (tx_quote, rx_quote) = watch::channel(None);
+        let (tx_connected_takers, rx_connected_takers) = watch::channel(Vec::new());
+        let (tx_proposals, rx_proposals) = watch::channel(Vec::new());
+        let (tx_exposure, rx_exposure) = match role {
+            Role::Maker => {
+                let (tx, rx) = watch::channel(Exposure::default());
+                (Some(tx), Some(rx))
+            }
+            Role::Taker => (None, None),
+        };
+
+        let tx = Self {
+            cfds: tx_cfds,
+            order: tx_order,
+            quote: tx_quote,
+            connected_takers: tx_connected_takers,
+            proposals: tx_proposals,
+            exposure: tx_exposure,
+        };
+        let feeds = Feeds {
+            cfds: rx_cfds,
+            order: rx_order,
+            quote: rx_quote,
+            connected_takers: rx_connected_takers,
+            proposals: rx_proposals,
+            exposure: rx_exposure,
+        };
+
+        (tx, feeds)
+    }
+
+    /// Publish all CFDs, ordered by their [`OrderId`].
     fn send_cfds_update(
         &self,
-        cfds: HashMap<OrderId, Cfd>,
+        cfds: BTreeMap<OrderId, Cfd>,
         quote: Option<bitmex_price_feed::Quote>,
     ) {
+        let proposals = pending_proposals(cfds.values());
+        if *self.proposals.borrow() != proposals {
+            let _ = self.proposals.send(proposals);
+        }
+
+        if let Some(tx_exposure) = &self.exposure {
+            let exposure = Exposure::of(cfds.values());
+            if *tx_exposure.borrow() != exposure {
+                let _ = tx_exposure.send(exposure);
+            }
+        }
+
         let cfds_with_quote = cfds
-            .into_iter()
-            .map(|(_, cfd)| cfd.with_current_quote(quote))
+            .into_values()
+            .map(|cfd| cfd.with_current_quote(quote))
             .collect();
 
         let _ = self.cfds.send(cfds_with_quote);
@@ -660,12 +856,110 @@ impl Tx {
     }
 }
 
+/// A settlement or rollover proposal that is awaiting a decision.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct PendingProposal {
+    pub order_id: OrderId,
+    pub kind: ProposalKind,
+    pub direction: ProposalDirection,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProposalKind {
+    Settlement,
+    Rollover,
+}
+
+/// Whether the proposal was sent by the counterparty or by us.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProposalDirection {
+    Incoming,
+    Outgoing,
+}
+
+/// Collect the proposals of all CFDs that are currently awaiting a decision.
+///
+/// Sorted by order ID so the feed only changes if the set of proposals changes.
+fn pending_proposals<'a>(cfds: impl Iterator<Item = &'a Cfd>) -> Vec<PendingProposal> {
+    let mut proposals = cfds
+        .filter_map(|cfd| {
+            let (kind, direction) = cfd.state.pending_proposal()?;
+
+            Some(PendingProposal {
+                order_id: cfd.order_id,
+                kind,
+                direction,
+            })
+        })
+        .collect::<Vec<_>>();
+    proposals.sort_by_key(|proposal| proposal.order_id);
+
+    proposals
+}
+
+/// Notional exposure and margin locked across all open CFDs.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Exposure {
+    #[serde(with = "round_to_two_dp")]
+    pub total_quantity_usd: Usd,
+    pub total_margin: Denominated<Amount>,
+    /// Long minus short notional, negative if we are net short.
+    #[serde(with = "round_to_two_dp")]
+    pub net_quantity_usd: Usd,
+}
+
+impl Default for Exposure {
+    fn default() -> Self {
+        Self {
+            total_quantity_usd: Usd::new(Decimal::ZERO),
+            total_margin: Denominated::new(Amount::ZERO),
+            net_quantity_usd: Usd::new(Decimal::ZERO),
+        }
+    }
+}
+
+impl Exposure {
+    /// Sum up the CFDs whose margin is locked, i.e. that are neither still being set up nor
+    /// closed.
+    fn of<'a>(cfds: impl Iterator<Item = &'a Cfd>) -> Self {
+        cfds.filter(|cfd| {
+            cfd.state.category() == CfdStateCategory::Open
+                && !matches!(cfd.state, CfdState::PendingSetup | CfdState::ContractSetup)
+        })
+        .fold(Exposure::default(), |exposure, cfd| {
+            let net_quantity_usd = match cfd.position {
+                Position::Long => exposure.net_quantity_usd + cfd.quantity_usd,
+                Position::Short => exposure.net_quantity_usd - cfd.quantity_usd,
+            };
+
+            Exposure {
+                total_quantity_usd: exposure.total_quantity_usd + cfd.quantity_usd,
+                total_margin: Denominated::new(exposure.total_margin.value() + cfd.margin.value()),
+                net_quantity_usd,
+            }
+        })
+    }
+
+    /// Render all bitcoin amounts in `denomination`.
+    pub fn in_denomination(self, denomination: Denomination) -> Self {
+        Self {
+            total_margin: self.total_margin.in_denomination(denomination),
+            ..self
+        }
+    }
+}
+
 /// Internal struct to keep state in one place
 struct State {
     network: Network,
     quote: Option<bitmex_price_feed::Quote>,
-    /// All hydrated CFDs.
-    cfds: HashMap<OrderId, Cfd>,
+    /// All hydrated CFDs, keyed by order id so updating one is a `O(log n)` lookup instead of a
+    /// scan.
+    cfds: BTreeMap<OrderId, Cfd>,
+    /// Milestones of contract setups, kept separately because they are not part of the events.
+    setup_progress: HashMap<OrderId, Vec<SetupProgress>>,
 }
 
 impl State {
@@ -673,27 +967,91 @@ impl State {
         Self {
             network,
             quote: None,
-            cfds: HashMap::new(),
+            cfds: BTreeMap::new(),
+            setup_progress: HashMap::new(),
         }
     }
 
+    fn record_setup_progress(&mut self, order_id: OrderId, progress: SetupProgress) {
+        let setup_progress = self.setup_progress.entry(order_id).or_default();
+        setup_progress.push(progress);
+
+        if let Some(cfd) = self.cfds.get_mut(&order_id) {
+            cfd.setup_progress = setup_progress.clone();
+        }
+    }
+
+    /// Hydrate all CFDs in the database, skipping the ones that fail to load.
+    async fn backfill(&mut self, db: sqlx::SqlitePool) -> Result<()> {
+        let mut conn = db
+            .acquire()
+            .await
+            .context("Failed to acquire DB connection")?;
+
+        for id in db::load_all_cfd_ids(&mut conn).await? {
+            let cfd = try_continue!(self
+                .load_cfd(id, &mut conn)
+                .await
+                .with_context(|| format!("Failed to rehydrate CFD {id}")));
+
+            self.cfds.insert(id, cfd);
+        }
+
+        Ok(())
+    }
+
     async fn update_cfd(&mut self, db: sqlx::SqlitePool, id: OrderId) -> Result<()> {
         let mut conn = db
             .acquire()
             .await
             .context("Failed to acquire DB connection")?;
 
-        let (cfd, events) = db::load_cfd(id, &mut conn).await?;
+        let cfd = self.load_cfd(id, &mut conn).await?;
 
-        let cfd = events
-            .into_iter()
-            .fold(Cfd::new(cfd), |cfd, event| cfd.apply(event, self.network));
+        // Milestones are only of interest while the setup is running
+        if !matches!(cfd.state, CfdState::PendingSetup | CfdState::ContractSetup) {
+            self.setup_progress.remove(&id);
+        }
 
         self.cfds.insert(id, cfd);
 
         Ok(())
     }
 
+    async fn load_page(
+        &self,
+        db: sqlx::SqlitePool,
+        before: Option<db::CfdCursor>,
+        limit: u32,
+    ) -> Result<CfdPage> {
+        let mut conn = db
+            .acquire()
+            .await
+            .context("Failed to acquire DB connection")?;
+
+        let ids = db::load_cfd_ids_page(before, limit, &mut conn).await?;
+
+        let next = match ids.last() {
+            Some((_, cursor)) if ids.len() == limit as usize => Some(*cursor),
+            _ => None,
+        };
+
+        let mut cfds = Vec::with_capacity(ids.len());
+        for (id, _) in ids {
+            let cfd = self.load_cfd(id, &mut conn).await?;
+            cfds.push(cfd.with_current_quote(self.quote));
+        }
+
+        Ok(CfdPage { cfds, next })
+    }
+
+    async fn load_cfd(&self, id: OrderId, conn: &mut PoolConnection<Sqlite>) -> Result<Cfd> {
+        let mut cfd = load_cfd(id, self.network, conn).await?;
+        cfd.setup_progress = self.setup_progress.get(&id).cloned().unwrap_or_default();
+
+        Ok(cfd)
+    }
+
     fn update_quote(&mut self, quote: Option<bitmex_price_feed::Quote>) {
         self.quote = quote;
     }
@@ -707,8 +1065,73 @@ impl Actor {
             return;
         };
 
-        self.tx
-            .send_cfds_update(self.state.cfds.clone(), self.state.quote);
+        self.publish_cfds();
+    }
+
+    fn handle(&mut self, msg: SetupProgressed) {
+        self.state.record_setup_progress(msg.order_id, msg.progress);
+
+        self.publish_cfds();
+    }
+
+    async fn handle(&mut self, msg: SetLabel) -> Result<()> {
+        let SetLabel { order_id, label } = msg;
+        let label = label
+            .map(|label| label.trim().to_owned())
+            .filter(|label| !label.is_empty());
+
+        if let Some(label) = &label {
+            let length = label.chars().count();
+            if length > MAX_LABEL_LENGTH {
+                bail!("Label must not be longer than {MAX_LABEL_LENGTH} characters, got {length}");
+            }
+        }
+
+        let mut conn = self
+            .db
+            .acquire()
+            .await
+            .context("Failed to acquire DB connection")?;
+        db::set_cfd_label(order_id, label.as_deref(), &mut conn).await?;
+
+        self.state.update_cfd(self.db.clone(), order_id).await?;
+        self.publish_cfds();
+
+        Ok(())
+    }
+
+    async fn handle(&mut self, msg: SetArchived) -> Result<()> {
+        let SetArchived { order_id, archived } = msg;
+
+        if archived {
+            let cfd = self
+                .state
+                .cfds
+                .get(&order_id)
+                .with_context(|| format!("No CFD with id {order_id}"))?;
+
+            if cfd.state.category() == CfdStateCategory::Open {
+                bail!("Cannot archive CFD {order_id} because it is still open");
+            }
+        }
+
+        let mut conn = self
+            .db
+            .acquire()
+            .await
+            .context("Failed to acquire DB connection")?;
+        db::set_cfd_archived(order_id, archived, &mut conn).await?;
+
+        self.state.update_cfd(self.db.clone(), order_id).await?;
+        self.publish_cfds();
+
+        Ok(())
+    }
+
+    async fn handle(&mut self, msg: LoadCfds) -> Result<CfdPage> {
+        self.state
+            .load_page(self.db.clone(), msg.before, msg.limit)
+            .await
     }
 
     fn handle(&mut self, msg: Update<Option<Order>>) {
@@ -718,42 +1141,55 @@ impl Actor {
     fn handle(&mut self, msg: Update<Option<bitmex_price_feed::Quote>>) {
         self.state.update_quote(msg.0);
 
-        let hydrated_cfds = self.state.cfds.clone();
-
         self.tx.send_quote_update(msg.0);
-        self.tx.send_cfds_update(hydrated_cfds, msg.0);
+        self.publish_cfds();
     }
 
     fn handle(&mut self, msg: Update<Vec<model::Identity>>) {
         let _ = self.tx.connected_takers.send(msg.0);
     }
+
+    fn handle(&mut self, _: PublishCfds) {
+        if !std::mem::take(&mut self.cfds_dirty) {
+            return;
+        }
+
+        self.tx
+            .send_cfds_update(self.state.cfds.clone(), self.state.quote);
+    }
 }
 
 #[async_trait]
 impl xtra::Actor for Actor {
     async fn started(&mut self, ctx: &mut xtra::Context<Self>) {
         let this = ctx.address().expect("we just started");
-        let pool = self.db.clone();
 
-        self.tasks.add_fallible(
-            {
+        // Seed the feed before handling any message so a freshly started UI immediately shows all
+        // existing CFDs, published as a single update.
+        if let Err(e) = self.state.backfill(self.db.clone()).await {
+            tracing::warn!("Failed to do initial rehydration of CFDs: {e:#}");
+        }
+        self.tx
+            .send_cfds_update(self.state.cfds.clone(), self.state.quote);
+
+        if !self.debounce.is_zero() {
+            self.tasks.add({
                 let this = this.clone();
+                let publish_pending = self.publish_pending.clone();
+                let debounce = self.debounce;
 
                 async move {
-                    let mut conn = pool.acquire().await?;
-                    let vec = db::load_all_cfd_ids(&mut conn).await?;
+                    loop {
+                        publish_pending.notified().await;
+                        tokio::time::sleep(debounce).await;
 
-                    for id in vec {
-                        let _: Result<(), xtra::Disconnected> = this.send(CfdChanged(id)).await;
+                        if this.send(PublishCfds).await.is_err() {
+                            return;
+                        }
                     }
-
-                    anyhow::Ok(())
                 }
-            },
-            |e| async move {
-                tracing::warn!("Failed to do initial rehydratation of CFDs: {e:#}");
-            },
-        );
+            });
+        }
 
         self.tasks.add({
             let price_feed = self.price_feed.clone_channel();
@@ -778,7 +1214,9 @@ impl xtra::Actor for Actor {
 
 #[derive(Debug, Clone, Serialize)]
 pub struct Quote {
+    #[serde(with = "round_to_two_dp")]
     bid: Price,
+    #[serde(with = "round_to_two_dp")]
     ask: Price,
     last_updated_at: Timestamp,
 }
@@ -807,8 +1245,7 @@ pub struct CfdOrder {
     /// Fee charged by the maker for opening a position
     ///
     /// Note: It's a flat fee on top of the fee calculated based on funding rate
-    #[serde(with = "::bdk::bitcoin::util::amount::serde::as_btc::opt")]
-    pub opening_fee: Option<Amount>,
+    pub opening_fee: Option<Denominated<Amount>>,
 
     /// The interest as annualized percentage
     ///
@@ -833,13 +1270,18 @@ pub struct CfdOrder {
     #[serde(with = "round_to_two_dp")]
     pub parcel_size: Usd,
 
-    #[serde(with = "::bdk::bitcoin::util::amount::serde::as_btc")]
-    pub margin_per_parcel: Amount,
+    pub margin_per_parcel: Denominated<Amount>,
+
+    /// The margin a taker has to lock up to take the order's `min_quantity`.
+    ///
+    /// Allows the UI to grey out orders the taker cannot afford.
+    pub min_taker_margin: Denominated<Amount>,
 
-    #[serde(with = "::bdk::bitcoin::util::amount::serde::as_btc")]
-    pub initial_funding_fee_per_parcel: Amount,
+    pub initial_funding_fee_per_parcel: Denominated<Amount>,
 
     pub leverage: Leverage,
+    /// The highest leverage the maker accepts when taking this order.
+    pub max_leverage: Leverage,
     #[serde(with = "round_to_two_dp")]
     pub liquidation_price: Price,
 
@@ -847,6 +1289,23 @@ pub struct CfdOrder {
     pub settlement_time_interval_in_secs: u64,
 }
 
+impl CfdOrder {
+    /// Render all bitcoin amounts in `denomination`.
+    pub fn in_denomination(self, denomination: Denomination) -> Self {
+        Self {
+            opening_fee: self
+                .opening_fee
+                .map(|fee| fee.in_denomination(denomination)),
+            margin_per_parcel: self.margin_per_parcel.in_denomination(denomination),
+            min_taker_margin: self.min_taker_margin.in_denomination(denomination),
+            initial_funding_fee_per_parcel: self
+                .initial_funding_fee_per_parcel
+                .in_denomination(denomination),
+            ..self
+        }
+    }
+}
+
 impl TryFrom<Order> for CfdOrder {
     type Error = anyhow::Error;
 
@@ -868,8 +1327,11 @@ impl TryFrom<Order> for CfdOrder {
                 (Origin::Ours, Position::Short) | (Origin::Theirs, Position::Long) => {
                     calculate_short_margin(order.price, parcel_size)
                 }
-            },
+            }
+            .into(),
+            min_taker_margin: order.min_taker_margin().into(),
             leverage: order.leverage,
+            max_leverage: order.max_leverage,
             liquidation_price: order.liquidation_price,
             creation_timestamp: order.creation_timestamp,
             settlement_time_interval_in_secs: order
@@ -877,7 +1339,7 @@ impl TryFrom<Order> for CfdOrder {
                 .whole_seconds()
                 .try_into()
                 .context("unable to convert settlement interval")?,
-            opening_fee: Some(order.opening_fee.to_inner()),
+            opening_fee: Some(order.opening_fee.to_inner().into()),
             funding_rate_annualized_percent: AnnualisedFundingPercent::from(order.funding_rate)
                 .to_string(),
             funding_rate_hourly_percent: HourlyFundingPercent::from(order.funding_rate).to_string(),
@@ -887,9 +1349,11 @@ impl TryFrom<Order> for CfdOrder {
                 order.leverage,
                 order.funding_rate,
                 SETTLEMENT_INTERVAL.whole_hours(),
+                order.rounding,
             )
             .context("unable to calcualte initial funding fee")?
-            .to_inner(),
+            .to_inner()
+            .into(),
         })
     }
 }
@@ -900,6 +1364,7 @@ pub enum CfdState {
     ContractSetup,
     Rejected,
     PendingOpen,
+    LockSeen,
     Open,
     PendingCommit,
     PendingCet,
@@ -915,59 +1380,156 @@ pub enum CfdState {
     SetupFailed,
 }
 
-#[derive(Debug, Clone, Serialize)]
-pub struct CfdDetails {
-    tx_url_list: HashSet<TxUrl>,
+impl CfdState {
+    /// The state a CFD of the given `role` is in after `event` was applied.
+    ///
+    /// Every event fully determines the state, hence we don't need to know the previous one.
+    pub fn after(event: &CfdEvent, role: Role) -> Self {
+        use CfdEvent::*;
+        match event {
+            ContractSetupStarted => CfdState::ContractSetup,
+            ContractSetupCompleted { .. } => CfdState::PendingOpen,
+            ContractSetupFailed => CfdState::SetupFailed,
+            OfferRejected(_) => CfdState::Rejected,
+            RolloverCompleted { .. } | RolloverRejected | RolloverFailed => CfdState::Open,
+            CollaborativeSettlementStarted { .. } => match role {
+                Role::Maker => CfdState::IncomingSettlementProposal,
+                Role::Taker => CfdState::OutgoingSettlementProposal,
+            },
+            CollaborativeSettlementProposalAccepted | CollaborativeSettlementCompleted { .. } => {
+                CfdState::PendingClose
+            }
+            CollaborativeSettlementRejected | CollaborativeSettlementFailed => CfdState::Open,
+            // Still pending open, but we can tell the user that the lock transaction was published
+            LockSeen => CfdState::LockSeen,
+            LockConfirmed => CfdState::Open,
+            CommitConfirmed => CfdState::OpenCommitted,
+            // After a reorg the transaction is back in the mempool, waiting to be confirmed again.
+            LockReorged => CfdState::PendingOpen,
+            CommitReorged => CfdState::PendingCommit,
+            CetConfirmed => CfdState::Closed,
+            RefundConfirmed => CfdState::Refunded,
+            LockConfirmedAfterFinality | CollaborativeSettlementConfirmed => CfdState::Closed,
+            CetTimelockExpiredPriorOracleAttestation => CfdState::OpenCommitted,
+            CetTimelockExpiredPostOracleAttestation { .. } => CfdState::PendingCet,
+            RefundTimelockExpired { .. } => CfdState::PendingRefund,
+            OracleAttestedPriorCetTimelock { .. } => CfdState::PendingCommit,
+            OracleAttestedPostCetTimelock { .. } => CfdState::PendingCet,
+            ManualCommit { .. } => CfdState::PendingCommit,
+            RevokeConfirmed => CfdState::OpenCommitted,
+            RolloverStarted { .. } => match role {
+                Role::Maker => CfdState::IncomingRolloverProposal,
+                Role::Taker => CfdState::OutgoingRolloverProposal,
+            },
+            RolloverAccepted => CfdState::ContractSetup,
+        }
+    }
+
+    /// The kind and direction of the proposal if the CFD is awaiting a decision on one.
+    pub fn pending_proposal(&self) -> Option<(ProposalKind, ProposalDirection)> {
+        match self {
+            CfdState::IncomingSettlementProposal => {
+                Some((ProposalKind::Settlement, ProposalDirection::Incoming))
+            }
+            CfdState::OutgoingSettlementProposal => {
+                Some((ProposalKind::Settlement, ProposalDirection::Outgoing))
+            }
+            CfdState::IncomingRolloverProposal => {
+                Some((ProposalKind::Rollover, ProposalDirection::Incoming))
+            }
+            CfdState::OutgoingRolloverProposal => {
+                Some((ProposalKind::Rollover, ProposalDirection::Outgoing))
+            }
+            _ => None,
+        }
+    }
+
+    /// Coarse grouping of the state for filtering CFDs.
+    pub fn category(&self) -> CfdStateCategory {
+        match self {
+            CfdState::PendingSetup
+            | CfdState::ContractSetup
+            | CfdState::PendingOpen
+            | CfdState::LockSeen
+            | CfdState::Open
+            | CfdState::PendingCommit
+            | CfdState::PendingCet
+            | CfdState::PendingClose
+            | CfdState::OpenCommitted
+            | CfdState::IncomingSettlementProposal
+            | CfdState::OutgoingSettlementProposal
+            | CfdState::IncomingRolloverProposal
+            | CfdState::OutgoingRolloverProposal
+            | CfdState::PendingRefund => CfdStateCategory::Open,
+            CfdState::Closed | CfdState::Refunded => CfdStateCategory::Closed,
+            CfdState::Rejected | CfdState::SetupFailed => CfdStateCategory::Failed,
+        }
+    }
 }
 
-#[derive(Debug, Clone, Display, FromStr, Serialize, Deserialize, PartialEq, Eq, Hash)]
-#[serde(rename_all = "camelCase")]
-#[display(style = "camelCase")]
-pub enum CfdAction {
-    AcceptOrder,
-    RejectOrder,
-    Commit,
-    Settle,
-    AcceptSettlement,
-    RejectSettlement,
-    AcceptRollover,
-    RejectRollover,
+/// Whether a CFD is still ongoing, has been closed or never got off the ground.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Display, FromStr)]
+#[serde(rename_all = "lowercase")]
+#[display(style = "lowercase")]
+pub enum CfdStateCategory {
+    Open,
+    Closed,
+    Failed,
+}
+
+/// Retain only the CFDs whose state falls into the given category.
+pub fn filter_by_state(cfds: Vec<Cfd>, category: CfdStateCategory) -> Vec<Cfd> {
+    cfds.into_iter()
+        .filter(|cfd| cfd.state.category() == category)
+        .collect()
+}
+
+/// Drop archived CFDs, unless `include_archived` is set.
+pub fn filter_archived(cfds: Vec<Cfd>, include_archived: bool) -> Vec<Cfd> {
+    cfds.into_iter()
+        .filter(|cfd| include_archived || !cfd.archived)
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CfdDetails {
+    tx_url_list: HashSet<TxUrl>,
 }
 
 mod round_to_two_dp {
     use super::*;
     use serde::Serializer;
 
-    pub trait ToDecimal {
-        fn to_decimal(&self) -> Decimal;
+    /// The number of decimal places used for all prices and quantities shown to the user.
+    const DECIMAL_PLACES: u32 = 2;
+
+    pub trait ToStringWithDp {
+        fn to_string_with_dp(&self, dp: u32) -> String;
     }
 
-    impl ToDecimal for Usd {
-        fn to_decimal(&self) -> Decimal {
-            self.into_decimal()
+    impl ToStringWithDp for Usd {
+        fn to_string_with_dp(&self, dp: u32) -> String {
+            Usd::to_string_with_dp(self, dp)
         }
     }
 
-    impl ToDecimal for Price {
-        fn to_decimal(&self) -> Decimal {
-            self.into_decimal()
+    impl ToStringWithDp for Price {
+        fn to_string_with_dp(&self, dp: u32) -> String {
+            Price::to_string_with_dp(self, dp)
         }
     }
 
-    pub fn serialize<D: ToDecimal, S: Serializer>(
+    pub fn serialize<D: ToStringWithDp, S: Serializer>(
         value: &D,
         serializer: S,
     ) -> Result<S::Ok, S::Error> {
-        let decimal = value.to_decimal();
-        let decimal = decimal.round_dp(2);
-
-        Serialize::serialize(&decimal, serializer)
+        serializer.serialize_str(&value.to_string_with_dp(DECIMAL_PLACES))
     }
 
     pub mod opt {
         use super::*;
 
-        pub fn serialize<D: ToDecimal, S: Serializer>(
+        pub fn serialize<D: ToStringWithDp, S: Serializer>(
             value: &Option<D>,
             serializer: S,
         ) -> Result<S::Ok, S::Error> {
@@ -987,7 +1549,7 @@ mod round_to_two_dp {
 
         #[derive(Serialize)]
         #[serde(transparent)]
-        struct WithOnlyTwoDecimalPlaces<I: ToDecimal> {
+        struct WithOnlyTwoDecimalPlaces<I: ToStringWithDp> {
             #[serde(with = "super")]
             inner: I,
         }
@@ -1009,6 +1571,94 @@ mod round_to_two_dp {
 
             assert_ser_tokens(&price, &[Token::Str("1000.12")]);
         }
+
+        #[test]
+        fn whole_price_serializes_with_trailing_zeros() {
+            let price = WithOnlyTwoDecimalPlaces {
+                inner: model::Price::new(dec!(42000)).unwrap(),
+            };
+
+            assert_ser_tokens(&price, &[Token::Str("42000.00")]);
+        }
+    }
+}
+
+/// A value whose bitcoin amounts are rendered in the given denomination on the feed.
+///
+/// Amounts are rendered in BTC unless rendered [in another
+/// denomination](Denominated::in_denomination) explicitly.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Denominated<T> {
+    value: T,
+    denomination: Denomination,
+}
+
+impl<T> Denominated<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            value,
+            denomination: Denomination::Bitcoin,
+        }
+    }
+
+    pub fn in_denomination(self, denomination: Denomination) -> Self {
+        Self {
+            denomination,
+            ..self
+        }
+    }
+
+    pub fn value(&self) -> T
+    where
+        T: Copy,
+    {
+        self.value
+    }
+}
+
+impl<T> From<T> for Denominated<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl Serialize for Denominated<Amount> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.denomination {
+            Denomination::Satoshi => serializer.serialize_u64(self.value.as_sat()),
+            denomination => serializer.serialize_f64(self.value.to_float_in(denomination)),
+        }
+    }
+}
+
+impl Serialize for Denominated<SignedAmount> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.denomination {
+            Denomination::Satoshi => serializer.serialize_i64(self.value.as_sat()),
+            denomination => serializer.serialize_f64(self.value.to_float_in(denomination)),
+        }
+    }
+}
+
+impl Serialize for Denominated<FeeFlow> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        #[serde(tag = "direction", content = "amount", rename_all = "camelCase")]
+        enum FeeFlowOnTheFeed {
+            LongPaysShort(Denominated<Amount>),
+            ShortPaysLong(Denominated<Amount>),
+            #[serde(rename = "none")]
+            Nein,
+        }
+
+        let amount = |amount| Denominated::new(amount).in_denomination(self.denomination);
+        let fee_flow = match self.value {
+            FeeFlow::LongPaysShort(fee) => FeeFlowOnTheFeed::LongPaysShort(amount(fee)),
+            FeeFlow::ShortPaysLong(fee) => FeeFlowOnTheFeed::ShortPaysLong(amount(fee)),
+            FeeFlow::Nein => FeeFlowOnTheFeed::Nein,
+        };
+
+        fee_flow.serialize(serializer)
     }
 }
 
@@ -1117,6 +1767,37 @@ impl fmt::Display for HourlyFundingPercent {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use serde_json::json;
+    use xtra::Actor as _;
+
+    #[test]
+    fn amounts_serialize_in_btc_unless_rendered_in_sats() {
+        let amount = Denominated::new(SignedAmount::from_sat(-10_000));
+
+        assert_eq!(serde_json::to_value(amount).unwrap(), json!(-0.0001));
+        assert_eq!(
+            serde_json::to_value(amount.in_denomination(Denomination::Satoshi)).unwrap(),
+            json!(-10_000)
+        );
+    }
+
+    #[test]
+    fn fee_flow_serializes_its_amount_in_the_given_denomination() {
+        let fee_flow = Denominated::new(FeeFlow::LongPaysShort(Amount::from_sat(500)));
+
+        assert_eq!(
+            serde_json::to_value(fee_flow).unwrap(),
+            json!({ "direction": "longPaysShort", "amount": 0.000005 })
+        );
+        assert_eq!(
+            serde_json::to_value(fee_flow.in_denomination(Denomination::Satoshi)).unwrap(),
+            json!({ "direction": "longPaysShort", "amount": 500 })
+        );
+        assert_eq!(
+            serde_json::to_value(Denominated::new(FeeFlow::Nein)).unwrap(),
+            json!({ "direction": "none" })
+        );
+    }
 
     #[test]
     fn state_snapshot_test() {
@@ -1141,4 +1822,545 @@ mod tests {
         let json = serde_json::to_string(&CfdState::SetupFailed).unwrap();
         assert_eq!(json, "\"SetupFailed\"");
     }
+
+    #[test]
+    fn filter_by_state_returns_only_matching_cfds() {
+        let cfds = vec![
+            Cfd::dummy(CfdState::Open),
+            Cfd::dummy(CfdState::Closed),
+            Cfd::dummy(CfdState::PendingSetup),
+            Cfd::dummy(CfdState::Rejected),
+            Cfd::dummy(CfdState::Refunded),
+            Cfd::dummy(CfdState::SetupFailed),
+        ];
+
+        let states = |category| {
+            filter_by_state(cfds.clone(), category)
+                .into_iter()
+                .map(|cfd| cfd.state)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            states(CfdStateCategory::Open),
+            vec![CfdState::Open, CfdState::PendingSetup]
+        );
+        assert_eq!(
+            states(CfdStateCategory::Closed),
+            vec![CfdState::Closed, CfdState::Refunded]
+        );
+        assert_eq!(
+            states(CfdStateCategory::Failed),
+            vec![CfdState::Rejected, CfdState::SetupFailed]
+        );
+    }
+
+    #[test]
+    fn archived_cfds_are_hidden_unless_requested() {
+        let archived = Cfd {
+            archived: true,
+            ..Cfd::dummy(CfdState::Closed)
+        };
+        let cfds = vec![Cfd::dummy(CfdState::Open), archived];
+
+        let states = |include_archived| {
+            filter_archived(cfds.clone(), include_archived)
+                .into_iter()
+                .map(|cfd| cfd.state)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(states(false), vec![CfdState::Open]);
+        assert_eq!(states(true), vec![CfdState::Open, CfdState::Closed]);
+    }
+
+    #[test]
+    fn state_category_round_trips_through_query_param() {
+        for category in [
+            CfdStateCategory::Open,
+            CfdStateCategory::Closed,
+            CfdStateCategory::Failed,
+        ] {
+            assert_eq!(
+                category.to_string().parse::<CfdStateCategory>().unwrap(),
+                category
+            );
+        }
+        assert_eq!(CfdStateCategory::Open.to_string(), "open");
+    }
+
+    #[test]
+    fn exposure_sums_up_open_cfds() {
+        let long = Cfd::dummy_maker(Position::Long, Usd::new(dec!(1_000)), CfdState::Open);
+        let short = Cfd::dummy_maker(
+            Position::Short,
+            Usd::new(dec!(3_000)),
+            CfdState::IncomingSettlementProposal,
+        );
+        let closed = Cfd::dummy_maker(Position::Long, Usd::new(dec!(500)), CfdState::Closed);
+        let in_setup =
+            Cfd::dummy_maker(Position::Long, Usd::new(dec!(500)), CfdState::ContractSetup);
+
+        let exposure = Exposure::of([&long, &short, &closed, &in_setup].into_iter());
+
+        assert_eq!(
+            exposure,
+            Exposure {
+                total_quantity_usd: Usd::new(dec!(4_000)),
+                total_margin: Denominated::new(long.margin.value() + short.margin.value()),
+                net_quantity_usd: Usd::new(dec!(-2_000)),
+            }
+        );
+    }
+
+    #[test]
+    fn exposure_without_cfds_is_zero() {
+        let exposure = Exposure::of(std::iter::empty());
+
+        assert_eq!(exposure, Exposure::default());
+    }
+
+    #[test]
+    fn only_the_maker_publishes_its_exposure() {
+        let (_, maker_feeds) = Tx::new(Role::Maker);
+        let (_, taker_feeds) = Tx::new(Role::Taker);
+
+        assert!(maker_feeds.exposure.is_some());
+        assert!(taker_feeds.exposure.is_none());
+    }
+
+    #[test]
+    fn updating_a_cfd_only_touches_it_and_preserves_order() {
+        let mut state = State::new(Network::Testnet);
+        for _ in 0..100 {
+            let cfd = Cfd::dummy(CfdState::Open);
+            state.cfds.insert(cfd.order_id, cfd);
+        }
+        let (tx, feeds) = Tx::new(Role::Maker);
+
+        tx.send_cfds_update(state.cfds.clone(), None);
+        let before = feeds.cfds.borrow().clone();
+
+        let target = before[42].order_id;
+        let updated = Cfd {
+            state: CfdState::PendingClose,
+            ..state.cfds[&target].clone()
+        };
+        state.cfds.insert(target, updated);
+        tx.send_cfds_update(state.cfds.clone(), None);
+        let after = feeds.cfds.borrow().clone();
+
+        assert_eq!(
+            before.iter().map(|cfd| cfd.order_id).collect::<Vec<_>>(),
+            after.iter().map(|cfd| cfd.order_id).collect::<Vec<_>>(),
+            "same CFDs in the same order"
+        );
+        assert!(after
+            .windows(2)
+            .all(|pair| pair[0].order_id < pair[1].order_id));
+        for (before, after) in before.iter().zip(&after) {
+            if after.order_id == target {
+                assert_eq!(after.state, CfdState::PendingClose);
+            } else {
+                assert_eq!(after.state, before.state);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn cfds_in_db_are_published_on_start() {
+        let db = db::memory().await.unwrap();
+        let order_ids = [OrderId::default(), OrderId::default()];
+        {
+            let mut conn = db.acquire().await.unwrap();
+            for id in order_ids {
+                db::insert_cfd(&dummy_model_cfd(id), &mut conn)
+                    .await
+                    .unwrap();
+            }
+        }
+
+        let (price_feed, price_feed_fut) = DummyPriceFeed.create(None).run();
+        tokio::spawn(price_feed_fut);
+        let (actor, mut feeds) = Actor::new(
+            db,
+            Role::Taker,
+            Network::Testnet,
+            &price_feed,
+            Duration::ZERO,
+        );
+        let (_addr, actor_fut) = actor.create(None).run();
+        tokio::spawn(actor_fut);
+
+        tokio::time::timeout(Duration::from_secs(5), feeds.cfds.changed())
+            .await
+            .unwrap()
+            .unwrap();
+
+        let published = feeds
+            .cfds
+            .borrow()
+            .iter()
+            .map(|cfd| cfd.order_id)
+            .collect::<HashSet<_>>();
+        assert_eq!(published, HashSet::from(order_ids));
+    }
+
+    #[tokio::test]
+    async fn updates_within_debounce_window_are_published_once() {
+        let debounce = Duration::from_millis(500);
+        let db = db::memory().await.unwrap();
+        let order_id = OrderId::default();
+        {
+            let mut conn = db.acquire().await.unwrap();
+            db::insert_cfd(&dummy_model_cfd(order_id), &mut conn)
+                .await
+                .unwrap();
+        }
+
+        let (price_feed, price_feed_fut) = DummyPriceFeed.create(None).run();
+        tokio::spawn(price_feed_fut);
+        let (actor, mut feeds) =
+            Actor::new(db, Role::Taker, Network::Testnet, &price_feed, debounce);
+        let (addr, actor_fut) = actor.create(None).run();
+        tokio::spawn(actor_fut);
+
+        // The initial backfill is published right away
+        tokio::time::timeout(Duration::from_secs(5), feeds.cfds.changed())
+            .await
+            .unwrap()
+            .unwrap();
+
+        for progress in [
+            SetupProgress::ParametersExchanged,
+            SetupProgress::TransactionsCreated,
+            SetupProgress::SignaturesVerified,
+        ] {
+            addr.send(SetupProgressed { order_id, progress })
+                .await
+                .unwrap();
+        }
+        assert!(!feeds.cfds.has_changed().unwrap());
+
+        tokio::time::timeout(debounce * 4, feeds.cfds.changed())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(feeds.cfds.borrow()[0].setup_progress.len(), 3);
+
+        let next_publish = tokio::time::timeout(debounce * 2, feeds.cfds.changed()).await;
+        assert!(next_publish.is_err(), "expected a single coalesced publish");
+    }
+
+    #[tokio::test]
+    async fn loading_all_cfds_includes_label_and_archived_status() {
+        let db = db::memory().await.unwrap();
+        let order_id = OrderId::default();
+        {
+            let mut conn = db.acquire().await.unwrap();
+            db::insert_cfd(&dummy_model_cfd(order_id), &mut conn)
+                .await
+                .unwrap();
+            db::set_cfd_label(order_id, Some("hedge"), &mut conn)
+                .await
+                .unwrap();
+            db::set_cfd_archived(order_id, true, &mut conn)
+                .await
+                .unwrap();
+        }
+
+        let cfds = load_all_cfds(&db, Network::Testnet, None).await.unwrap();
+
+        assert_eq!(cfds[0].label, Some("hedge".to_owned()));
+        assert!(cfds[0].archived);
+    }
+
+    #[tokio::test]
+    async fn labels_longer_than_the_limit_are_rejected() {
+        let db = db::memory().await.unwrap();
+        let order_id = OrderId::default();
+        {
+            let mut conn = db.acquire().await.unwrap();
+            db::insert_cfd(&dummy_model_cfd(order_id), &mut conn)
+                .await
+                .unwrap();
+        }
+
+        let (price_feed, price_feed_fut) = DummyPriceFeed.create(None).run();
+        tokio::spawn(price_feed_fut);
+        let (actor, feeds) = Actor::new(
+            db,
+            Role::Taker,
+            Network::Testnet,
+            &price_feed,
+            Duration::ZERO,
+        );
+        let (addr, actor_fut) = actor.create(None).run();
+        tokio::spawn(actor_fut);
+
+        let too_long = addr
+            .send(SetLabel {
+                order_id,
+                label: Some("a".repeat(MAX_LABEL_LENGTH + 1)),
+            })
+            .await
+            .unwrap();
+        assert!(too_long.is_err());
+
+        addr.send(SetLabel {
+            order_id,
+            label: Some("a".repeat(MAX_LABEL_LENGTH)),
+        })
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            feeds.cfds.borrow()[0].label,
+            Some("a".repeat(MAX_LABEL_LENGTH))
+        );
+    }
+
+    #[tokio::test]
+    async fn setup_progress_is_dropped_once_the_setup_ended() {
+        let db = db::memory().await.unwrap();
+        let order_id = OrderId::default();
+        {
+            let mut conn = db.acquire().await.unwrap();
+            db::insert_cfd(&dummy_model_cfd(order_id), &mut conn)
+                .await
+                .unwrap();
+        }
+        let mut state = State::new(Network::Testnet);
+        state.update_cfd(db.clone(), order_id).await.unwrap();
+
+        state.record_setup_progress(order_id, SetupProgress::ParametersExchanged);
+        {
+            let mut conn = db.acquire().await.unwrap();
+            db::append_event(
+                Event {
+                    timestamp: Timestamp::now(),
+                    id: order_id,
+                    event: CfdEvent::ContractSetupFailed,
+                },
+                &mut conn,
+            )
+            .await
+            .unwrap();
+        }
+        state.update_cfd(db, order_id).await.unwrap();
+
+        assert!(state.setup_progress.is_empty());
+    }
+
+    #[tokio::test]
+    async fn quote_updates_are_published_on_quote_feed() {
+        let db = db::memory().await.unwrap();
+        let (price_feed, price_feed_fut) = DummyPriceFeed.create(None).run();
+        tokio::spawn(price_feed_fut);
+        let (actor, mut feeds) = Actor::new(
+            db,
+            Role::Taker,
+            Network::Testnet,
+            &price_feed,
+            Duration::ZERO,
+        );
+        let (addr, actor_fut) = actor.create(None).run();
+        tokio::spawn(actor_fut);
+
+        addr.send(Update(Some(bitmex_price_feed::Quote {
+            timestamp: Timestamp::new(1_600_000_000),
+            bid: Price::new(dec!(60_000)).unwrap(),
+            ask: Price::new(dec!(60_010)).unwrap(),
+        })))
+        .await
+        .unwrap();
+
+        tokio::time::timeout(Duration::from_secs(5), feeds.quote.changed())
+            .await
+            .unwrap()
+            .unwrap();
+        let quote = feeds.quote.borrow().clone().unwrap();
+        assert_eq!(quote.bid, Price::new(dec!(60_000)).unwrap());
+        assert_eq!(quote.ask, Price::new(dec!(60_010)).unwrap());
+        assert_eq!(quote.last_updated_at, Timestamp::new(1_600_000_000));
+    }
+
+    #[test]
+    fn cet_without_our_output_is_a_zero_payout() {
+        let ours = Script::from(vec![0x51]);
+        let theirs = Script::from(vec![0x52]);
+        let liquidation_cet = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![],
+            output: vec![bdk::bitcoin::TxOut {
+                value: 100_000,
+                script_pubkey: theirs.clone(),
+            }],
+        };
+
+        assert_eq!(
+            extract_payout_amount(liquidation_cet.clone(), ours),
+            Amount::ZERO
+        );
+        assert_eq!(
+            extract_payout_amount(liquidation_cet, theirs),
+            Amount::from_sat(100_000)
+        );
+    }
+
+    #[test]
+    fn refund_timelock_expiry_is_estimated_from_commit_confirmation() {
+        let commit_confirmed_at = Timestamp::new(1_600_000_000).to_offset_datetime().unwrap();
+
+        let estimate = estimate_refund_timelock_expiry(commit_confirmed_at, 216);
+
+        assert_eq!(
+            estimate,
+            Timestamp::new(1_600_000_000 + 216 * 10 * 60)
+                .to_offset_datetime()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn commit_broadcast_origin_distinguishes_us_from_counterparty() {
+        let committed_by_us = [
+            CfdEvent::ManualCommit {
+                tx: dummy_transaction(),
+            },
+            CfdEvent::CommitConfirmed,
+        ]
+        .into_iter()
+        .fold(Cfd::dummy(CfdState::Open), |cfd, event| {
+            cfd.apply(Event::new(OrderId::default(), event), Network::Testnet)
+        });
+        let committed_by_counterparty = Cfd::dummy(CfdState::Open).apply(
+            Event::new(OrderId::default(), CfdEvent::CommitConfirmed),
+            Network::Testnet,
+        );
+
+        assert_eq!(Cfd::dummy(CfdState::Open).commit_broadcast_by_us, None);
+        assert_eq!(committed_by_us.state, CfdState::OpenCommitted);
+        assert_eq!(committed_by_us.commit_broadcast_by_us, Some(true));
+        assert_eq!(committed_by_counterparty.state, CfdState::OpenCommitted);
+        assert_eq!(
+            committed_by_counterparty.commit_broadcast_by_us,
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn attestation_after_counterparty_commit_confirmed_does_not_claim_the_commit() {
+        let cfd = [
+            CfdEvent::CommitConfirmed,
+            CfdEvent::OracleAttestedPriorCetTimelock {
+                timelocked_cet: dummy_transaction(),
+                commit_tx: Some(dummy_transaction()),
+                price: Price::new(dec!(60_000)).unwrap(),
+            },
+        ]
+        .into_iter()
+        .fold(Cfd::dummy(CfdState::Open), |cfd, event| {
+            cfd.apply(Event::new(OrderId::default(), event), Network::Testnet)
+        });
+
+        assert_eq!(cfd.commit_broadcast_by_us, Some(false));
+    }
+
+    #[test]
+    fn attestation_before_commit_confirmed_broadcasts_the_commit_by_us() {
+        let cfd = [
+            CfdEvent::OracleAttestedPriorCetTimelock {
+                timelocked_cet: dummy_transaction(),
+                commit_tx: Some(dummy_transaction()),
+                price: Price::new(dec!(60_000)).unwrap(),
+            },
+            CfdEvent::CommitConfirmed,
+        ]
+        .into_iter()
+        .fold(Cfd::dummy(CfdState::Open), |cfd, event| {
+            cfd.apply(Event::new(OrderId::default(), event), Network::Testnet)
+        });
+
+        assert_eq!(cfd.commit_broadcast_by_us, Some(true));
+    }
+
+    fn dummy_transaction() -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![],
+            output: vec![],
+        }
+    }
+
+    fn dummy_model_cfd(id: OrderId) -> model::cfd::Cfd {
+        model::cfd::Cfd::new(
+            id,
+            Position::Long,
+            Price::new(dec!(60_000)).unwrap(),
+            Leverage::new(2).unwrap(),
+            time::Duration::hours(24),
+            Role::Taker,
+            Usd::new(dec!(1_000)),
+            "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF"
+                .parse()
+                .unwrap(),
+            model::OpeningFee::default(),
+            FundingRate::default(),
+            model::TxFeeRate::default(),
+            crate::N_PAYOUTS,
+            model::RoundingStrategy::default(),
+        )
+    }
+
+    struct DummyPriceFeed;
+
+    impl xtra::Actor for DummyPriceFeed {}
+
+    #[xtra_productivity(message_impl = false)]
+    impl DummyPriceFeed {
+        fn handle(
+            &mut self,
+            _: bitmex_price_feed::LatestQuote,
+        ) -> Option<bitmex_price_feed::Quote> {
+            None
+        }
+    }
+
+    impl Cfd {
+        fn dummy(state: CfdState) -> Self {
+            Cfd::dummy_with(Position::Long, Usd::new(dec!(1_000)), Role::Taker, state)
+        }
+
+        fn dummy_maker(position: Position, quantity_usd: Usd, state: CfdState) -> Self {
+            Cfd::dummy_with(position, quantity_usd, Role::Maker, state)
+        }
+
+        fn dummy_with(position: Position, quantity_usd: Usd, role: Role, state: CfdState) -> Self {
+            let cfd = Cfd::new(db::Cfd {
+                id: OrderId::default(),
+                position,
+                initial_price: Price::new(dec!(60_000)).unwrap(),
+                leverage: Leverage::new(2).unwrap(),
+                settlement_interval: time::Duration::hours(24),
+                quantity_usd,
+                counterparty_network_identity:
+                    "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF"
+                        .parse()
+                        .unwrap(),
+                role,
+                opening_fee: model::OpeningFee::default(),
+                initial_funding_rate: FundingRate::default(),
+                initial_tx_fee_rate: model::TxFeeRate::default(),
+                n_payouts: crate::N_PAYOUTS,
+                rounding: model::RoundingStrategy::default(),
+                label: None,
+                archived: false,
+            });
+
+            Cfd { state, ..cfd }
+        }
+    }
 }
@@ -59,7 +59,6 @@ struct RolloverFailed {
 pub struct Actor {
     order_id: OrderId,
     send_to_taker_actor: Box<dyn MessageChannel<TakerMessage>>,
-    n_payouts: usize,
     taker_id: Identity,
     oracle_pk: schnorrsig::PublicKey,
     sent_from_taker: Option<UnboundedSender<RolloverMsg>>,
@@ -74,7 +73,6 @@ impl Actor {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         order_id: OrderId,
-        n_payouts: usize,
         send_to_taker_actor: &(impl MessageChannel<TakerMessage> + 'static),
         taker_id: Identity,
         oracle_pk: schnorrsig::PublicKey,
@@ -89,7 +87,6 @@ impl Actor {
     ) -> Self {
         Self {
             order_id,
-            n_payouts,
             send_to_taker_actor: send_to_taker_actor.clone_channel(),
             taker_id,
             oracle_pk,
@@ -180,7 +177,6 @@ impl Actor {
             rollover_params,
             Role::Maker,
             dlc,
-            self.n_payouts,
         );
 
         let this = ctx.address().expect("self to be alive");
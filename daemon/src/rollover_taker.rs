@@ -36,7 +36,6 @@ const MAKER_RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
 
 pub struct Actor {
     id: OrderId,
-    n_payouts: usize,
     oracle_pk: schnorrsig::PublicKey,
     maker: xtra::Address<connection::Actor>,
     get_announcement: Box<dyn MessageChannel<GetAnnouncement>>,
@@ -50,7 +49,6 @@ impl Actor {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         id: OrderId,
-        n_payouts: usize,
         oracle_pk: schnorrsig::PublicKey,
         maker: xtra::Address<connection::Actor>,
         get_announcement: &(impl MessageChannel<GetAnnouncement> + 'static),
@@ -63,7 +61,6 @@ impl Actor {
     ) -> Self {
         Self {
             id,
-            n_payouts,
             oracle_pk,
             maker,
             get_announcement: get_announcement.clone_channel(),
@@ -137,7 +134,6 @@ impl Actor {
             rollover_params,
             Role::Taker,
             dlc,
-            self.n_payouts,
         );
 
         let this = ctx.address().expect("self to be alive");
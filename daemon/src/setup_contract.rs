@@ -1,18 +1,22 @@
 use crate::future_ext::FutureExt;
 use crate::model::cfd::Cet;
 use crate::model::cfd::Dlc;
+use crate::model::cfd::OrderId;
 use crate::model::cfd::RevokedCommit;
 use crate::model::cfd::Role;
-use crate::model::cfd::CET_TIMELOCK;
 use crate::model::FeeAccount;
 use crate::model::FundingFee;
 use crate::model::Identity;
 use crate::model::Leverage;
 use crate::model::Price;
+use crate::model::RoundingStrategy;
 use crate::model::TxFeeRate;
 use crate::model::Usd;
+use crate::monitor;
 use crate::oracle;
 use crate::payout_curve;
+use crate::projection;
+use crate::projection::SetupProgress;
 use crate::transaction_ext::TransactionExt;
 use crate::wallet;
 use crate::wire::Msg0;
@@ -53,16 +57,43 @@ use maia::Announcement;
 use maia::PartyParams;
 use maia::PunishParams;
 use std::collections::HashMap;
+use std::collections::HashSet;
+use std::future::Future;
 use std::iter::FromIterator;
 use std::ops::RangeInclusive;
 use std::time::Duration;
 use xtra::prelude::MessageChannel;
+use xtras::SendAsyncSafe;
 
 /// How long protocol waits for the next message before giving up
 // TODO: Analyse why event sourcing refactor made us change the timeout from 60 to 70 secs.
 const MSG_TIMEOUT: Duration = Duration::from_secs(70);
 
+/// Fail the contract `setup` if it does not complete within `timeout`.
+///
+/// Protects against a counterparty that goes quiet in between messages, which would otherwise leave
+/// the CFD in contract setup for as long as the per-message timeouts keep getting reset.
+pub async fn with_timeout(
+    setup: impl Future<Output = Result<Dlc>>,
+    timeout: Duration,
+) -> Result<Dlc> {
+    let seconds = timeout.as_secs();
+
+    setup
+        .timeout(timeout)
+        .await
+        .with_context(|| format!("Contract setup did not complete within {seconds} seconds"))?
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ContractSetupError {
+    /// The setup failed before we signed the lock transaction, hence no funds are at stake.
+    #[error("Contract setup failed before signing the lock transaction")]
+    FailedBeforeLockSignature(#[source] anyhow::Error),
+}
+
 pub struct SetupParams {
+    order_id: OrderId,
     margin: Amount,
     counterparty_margin: Amount,
     counterparty_identity: Identity,
@@ -70,13 +101,17 @@ pub struct SetupParams {
     quantity: Usd,
     leverage: Leverage,
     refund_timelock: u32,
+    cet_timelock: u32,
     tx_fee_rate: TxFeeRate,
     fee_account: FeeAccount,
+    n_payouts: usize,
+    rounding: RoundingStrategy,
 }
 
 impl SetupParams {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
+        order_id: OrderId,
         margin: Amount,
         counterparty_margin: Amount,
         counterparty_identity: Identity,
@@ -84,10 +119,14 @@ impl SetupParams {
         quantity: Usd,
         leverage: Leverage,
         refund_timelock: u32,
+        cet_timelock: u32,
         tx_fee_rate: TxFeeRate,
         fee_account: FeeAccount,
+        n_payouts: usize,
+        rounding: RoundingStrategy,
     ) -> Result<Self> {
         Ok(Self {
+            order_id,
             margin,
             counterparty_margin,
             counterparty_identity,
@@ -95,14 +134,25 @@ impl SetupParams {
             quantity,
             leverage,
             refund_timelock,
+            cet_timelock,
             tx_fee_rate,
             fee_account,
+            n_payouts,
+            rounding,
         })
     }
 
     pub fn counterparty_identity(&self) -> Identity {
         self.counterparty_identity
     }
+
+    pub fn n_payouts(&self) -> usize {
+        self.n_payouts
+    }
+
+    pub fn rounding(&self) -> RoundingStrategy {
+        self.rounding
+    }
 }
 
 /// Given an initial set of parameters, sets up the CFD contract with
@@ -113,24 +163,42 @@ pub async fn new(
     mut stream: impl FusedStream<Item = SetupMsg> + Unpin,
     (oracle_pk, announcement): (schnorrsig::PublicKey, oracle::Announcement),
     setup_params: SetupParams,
+    check_funds_channel: Box<dyn MessageChannel<wallet::CheckFunds>>,
     build_party_params_channel: Box<dyn MessageChannel<wallet::BuildPartyParams>>,
     sign_channel: Box<dyn MessageChannel<wallet::Sign>>,
+    progress_channel: Box<dyn MessageChannel<projection::SetupProgressed>>,
+    unconfirmed_inputs_channel: Option<Box<dyn MessageChannel<monitor::GetUnconfirmedInputs>>>,
     role: Role,
-    n_payouts: usize,
 ) -> Result<Dlc> {
+    let order_id = setup_params.order_id;
+
+    // Don't start talking to the counterparty if we cannot fund our side of the lock transaction
+    check_funds_channel
+        .send(wallet::CheckFunds {
+            order_id,
+            amount: setup_params.margin,
+            fee_rate: setup_params.tx_fee_rate,
+        })
+        .await
+        .context("Failed to send message to wallet actor")
+        .and_then(|checked| checked.context("Cannot fund the lock transaction"))
+        .map_err(ContractSetupError::FailedBeforeLockSignature)?;
+
     let (sk, pk) = crate::keypair::new(&mut rand::thread_rng());
     let (rev_sk, rev_pk) = crate::keypair::new(&mut rand::thread_rng());
     let (publish_sk, publish_pk) = crate::keypair::new(&mut rand::thread_rng());
 
     let own_params = build_party_params_channel
         .send(wallet::BuildPartyParams {
+            order_id: setup_params.order_id,
             amount: setup_params.margin,
             identity_pk: pk,
             fee_rate: setup_params.tx_fee_rate,
         })
         .await
-        .context("Failed to send message to wallet actor")?
-        .context("Failed to build party params")?;
+        .context("Failed to send message to wallet actor")
+        .and_then(|params| params.context("Failed to build party params"))
+        .map_err(ContractSetupError::FailedBeforeLockSignature)?;
 
     let own_punish = PunishParams {
         revocation_pk: rev_pk,
@@ -149,6 +217,12 @@ pub async fn new(
         .context("Failed to read Msg0")?;
 
     tracing::info!("Exchanged setup parameters");
+    report_progress(
+        &progress_channel,
+        order_id,
+        SetupProgress::ParametersExchanged,
+    )
+    .await;
 
     let (other, other_punish) = msg0.into();
 
@@ -163,6 +237,30 @@ pub async fn new(
         )
     }
 
+    // Unconfirmed inputs could be double-spent by the counterparty, invalidating the lock
+    // transaction after we already committed to the contract
+    if let Some(unconfirmed_inputs_channel) = unconfirmed_inputs_channel {
+        let unconfirmed_inputs = unconfirmed_inputs_channel
+            .send(monitor::GetUnconfirmedInputs {
+                psbt: params.other.lock_psbt.clone(),
+            })
+            .await
+            .context("Failed to send message to monitor actor")?
+            .context("Failed to check confirmation of counterparty inputs")?;
+
+        if !unconfirmed_inputs.is_empty() {
+            let unconfirmed_inputs = unconfirmed_inputs
+                .iter()
+                .map(|outpoint| outpoint.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            anyhow::bail!(
+                "Counterparty funds the lock transaction with unconfirmed inputs: {unconfirmed_inputs}"
+            )
+        }
+    }
+
     let settlement_event_id = announcement.id;
     let payouts = HashMap::from_iter([(
         announcement.into(),
@@ -170,8 +268,9 @@ pub async fn new(
             setup_params.price,
             setup_params.quantity,
             setup_params.leverage,
-            n_payouts,
+            setup_params.n_payouts,
             setup_params.fee_account.settle(),
+            setup_params.rounding,
         )?,
     )]);
 
@@ -186,7 +285,7 @@ pub async fn new(
                 (maker_params, maker_punish),
                 (taker_params, taker_punish),
                 oracle_pk,
-                (CET_TIMELOCK, setup_params.refund_timelock),
+                (setup_params.cet_timelock, setup_params.refund_timelock),
                 payouts,
                 sk,
                 setup_params.tx_fee_rate.to_u32(),
@@ -197,8 +296,17 @@ pub async fn new(
     .context("Failed to create CFD transactions")?;
 
     tracing::info!("Created CFD transactions");
+    report_progress(
+        &progress_channel,
+        order_id,
+        SetupProgress::TransactionsCreated,
+    )
+    .await;
+
+    let own_msg1 = Msg1::from(own_cfd_txs.clone());
+    let own_cet_ranges = cet_ranges(&own_msg1.cets);
 
-    sink.send(SetupMsg::Msg1(Msg1::from(own_cfd_txs.clone())))
+    sink.send(SetupMsg::Msg1(own_msg1))
         .await
         .context("Failed to send Msg1")?;
 
@@ -210,6 +318,8 @@ pub async fn new(
         .try_into_msg1()
         .context("Failed to read Msg1")?;
 
+    ensure_cet_sets_match(&own_cet_ranges, &cet_ranges(&msg1.cets))?;
+
     tracing::info!("Exchanged CFD transactions");
 
     let lock_desc = lock_descriptor(params.maker().identity_pk, params.taker().identity_pk);
@@ -276,6 +386,12 @@ pub async fn new(
     .context("Refund signature does not verify")?;
 
     tracing::info!("Verified all signatures");
+    report_progress(
+        &progress_channel,
+        order_id,
+        SetupProgress::SignaturesVerified,
+    )
+    .await;
 
     let mut signed_lock_tx = sign_channel
         .send(wallet::Sign { psbt: lock_tx })
@@ -299,6 +415,7 @@ pub async fn new(
         .context("Failed to merge lock PSBTs")?;
 
     tracing::info!("Exchanged signed lock transaction");
+    report_progress(&progress_channel, order_id, SetupProgress::LockSigned).await;
 
     // TODO: In case we sign+send but never receive (the signed lock_tx from the other party) we
     // need some fallback handling (after x time) to spend the outputs in a different way so the
@@ -382,6 +499,7 @@ pub async fn new(
         revoked_commit: Vec::new(),
         settlement_event_id,
         refund_timelock: setup_params.refund_timelock,
+        cet_timelock: setup_params.cet_timelock,
     })
 }
 
@@ -394,9 +512,12 @@ pub struct RolloverParams {
     fee_rate: TxFeeRate,
     fee_account: FeeAccount,
     current_fee: FundingFee,
+    n_payouts: usize,
+    rounding: RoundingStrategy,
 }
 
 impl RolloverParams {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         price: Price,
         quantity: Usd,
@@ -405,6 +526,8 @@ impl RolloverParams {
         fee_rate: TxFeeRate,
         fee_account: FeeAccount,
         current_fee: FundingFee,
+        n_payouts: usize,
+        rounding: RoundingStrategy,
     ) -> Self {
         Self {
             price,
@@ -414,6 +537,8 @@ impl RolloverParams {
             fee_rate,
             fee_account,
             current_fee,
+            n_payouts,
+            rounding,
         }
     }
 
@@ -429,10 +554,11 @@ pub async fn roll_over(
     rollover_params: RolloverParams,
     our_role: Role,
     dlc: Dlc,
-    n_payouts: usize,
 ) -> Result<Dlc> {
     let sk = dlc.identity;
     let pk = PublicKey::new(secp256k1_zkp::PublicKey::from_secret_key(SECP256K1, &sk));
+    // The CET timelock was agreed on during contract setup and stays the same across rollovers
+    let cet_timelock = dlc.cet_timelock;
 
     let (rev_sk, rev_pk) = crate::keypair::new(&mut rand::thread_rng());
     let (publish_sk, publish_pk) = crate::keypair::new(&mut rand::thread_rng());
@@ -467,8 +593,9 @@ pub async fn roll_over(
             rollover_params.price,
             rollover_params.quantity,
             rollover_params.leverage,
-            n_payouts,
+            rollover_params.n_payouts,
             rollover_params.fee_account.settle(),
+            rollover_params.rounding,
         )?,
     )]);
 
@@ -516,7 +643,7 @@ pub async fn roll_over(
                     taker_punish_params,
                 ),
                 oracle_pk,
-                (CET_TIMELOCK, rollover_params.refund_timelock),
+                (cet_timelock, rollover_params.refund_timelock),
                 payouts,
                 sk,
                 rollover_params.fee_rate.to_u32(),
@@ -526,7 +653,10 @@ pub async fn roll_over(
     .await?
     .context("Failed to create new CFD transactions")?;
 
-    sink.send(RolloverMsg::Msg1(RolloverMsg1::from(own_cfd_txs.clone())))
+    let own_msg1 = RolloverMsg1::from(own_cfd_txs.clone());
+    let own_cet_ranges = cet_ranges(&own_msg1.cets);
+
+    sink.send(RolloverMsg::Msg1(own_msg1))
         .await
         .context("Failed to send Msg1")?;
 
@@ -538,6 +668,8 @@ pub async fn roll_over(
         .try_into_msg1()
         .context("Failed to read Msg1")?;
 
+    ensure_cet_sets_match(&own_cet_ranges, &cet_ranges(&msg1.cets))?;
+
     let lock_amount = taker_lock_amount + maker_lock_amount;
 
     let commit_desc = commit_descriptor(
@@ -724,6 +856,7 @@ pub async fn roll_over(
         revoked_commit,
         settlement_event_id: announcement.id,
         refund_timelock: rollover_params.refund_timelock,
+        cet_timelock,
     })
 }
 
@@ -782,6 +915,50 @@ impl AllParams {
     }
 }
 
+/// The price ranges of the CETs per oracle event.
+type CetRanges = HashMap<String, HashSet<RangeInclusive<u64>>>;
+
+fn cet_ranges<T>(cets: &HashMap<String, Vec<(RangeInclusive<u64>, T)>>) -> CetRanges {
+    cets.iter()
+        .map(|(event_id, cets)| {
+            let ranges = cets.iter().map(|(range, _)| range.clone()).collect();
+
+            (event_id.clone(), ranges)
+        })
+        .collect()
+}
+
+/// Ensure the counterparty sent signatures for exactly the CETs we built.
+///
+/// A different set of CETs means the parties disagree on the payout curve or the oracle events.
+/// Detecting this upfront gives a clearer error than a signature failing to verify.
+fn ensure_cet_sets_match(own: &CetRanges, other: &CetRanges) -> Result<()> {
+    let mut own_events = own.keys().collect::<Vec<_>>();
+    let mut other_events = other.keys().collect::<Vec<_>>();
+    own_events.sort();
+    other_events.sort();
+
+    if own_events != other_events {
+        anyhow::bail!(
+            "CET set mismatch: expected CETs for events {own_events:?} but got {other_events:?}"
+        );
+    }
+
+    for (event_id, own_ranges) in own {
+        let other_ranges = &other[event_id];
+
+        if own_ranges != other_ranges {
+            let n_own = own_ranges.len();
+            let missing = own_ranges.difference(other_ranges).count();
+            let unexpected = other_ranges.difference(own_ranges).count();
+
+            anyhow::bail!("CET set mismatch for event {event_id}: {missing} of our {n_own} price ranges missing, {unexpected} unexpected");
+        }
+    }
+
+    Ok(())
+}
+
 async fn verify_cets(
     (oracle_pk, nonce_pks): (schnorrsig::PublicKey, Vec<schnorrsig::PublicKey>),
     other: PartyParams,
@@ -873,9 +1050,170 @@ fn verify_cet_encsig(
     )
 }
 
+/// Inform the projection about a milestone of the contract setup.
+///
+/// Failing to do so only affects what the UI shows, hence it must not abort the setup.
+async fn report_progress<C>(channel: &C, order_id: OrderId, progress: SetupProgress)
+where
+    C: SendAsyncSafe<projection::SetupProgressed, ()>,
+{
+    if channel
+        .send_async_safe(projection::SetupProgressed { order_id, progress })
+        .await
+        .is_err()
+    {
+        tracing::warn!(%order_id, ?progress, "Unable to report setup progress");
+    }
+}
+
 /// Wrapper for the msg
 fn format_expect_msg_within(msg: &str) -> String {
     let seconds = MSG_TIMEOUT.as_secs();
 
     format!("Expected {msg} within {seconds} seconds")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::cfd::Position;
+    use crate::model::BitMexPriceEventId;
+    use futures::channel::mpsc;
+    use rust_decimal_macros::dec;
+    use std::str::FromStr;
+    use time::OffsetDateTime;
+    use xtra::Actor as _;
+    use xtra_productivity::xtra_productivity;
+
+    #[tokio::test]
+    async fn underfunded_wallet_fails_setup_before_msg0_is_sent() {
+        let (wallet, fut) = UnderfundedWallet.create(None).run();
+        tokio::spawn(fut);
+        let (sink, mut sent) = mpsc::unbounded::<SetupMsg>();
+        let (_incoming, stream) = mpsc::unbounded::<SetupMsg>();
+
+        let error = new(
+            sink.sink_map_err(anyhow::Error::from),
+            stream,
+            (dummy_oracle_pk(), dummy_announcement()),
+            dummy_setup_params(),
+            Box::new(wallet.clone()),
+            Box::new(wallet.clone()),
+            Box::new(wallet.clone()),
+            Box::new(wallet),
+            None,
+            Role::Taker,
+        )
+        .await
+        .unwrap_err();
+
+        assert!(matches!(
+            error.downcast_ref::<ContractSetupError>(),
+            Some(ContractSetupError::FailedBeforeLockSignature(_))
+        ));
+        assert!(format!("{error:#}").contains("Insufficient funds"));
+        assert!(
+            sent.try_next().unwrap().is_none(),
+            "no message must be sent to the peer"
+        );
+    }
+
+    #[test]
+    fn mismatched_cet_set_is_detected_before_verifying_signatures() {
+        let own = cet_ranges(&HashMap::from([(
+            "event".to_owned(),
+            vec![(0..=99, ()), (100..=199, ()), (200..=u64::MAX, ())],
+        )]));
+        let other = cet_ranges(&HashMap::from([(
+            "event".to_owned(),
+            vec![(0..=99, ()), (100..=u64::MAX, ())],
+        )]));
+
+        let error = ensure_cet_sets_match(&own, &other).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "CET set mismatch for event event: 2 of our 3 price ranges missing, 1 unexpected"
+        );
+        assert!(ensure_cet_sets_match(&own, &own).is_ok());
+    }
+
+    #[test]
+    fn cets_for_other_event_are_a_mismatch() {
+        let own = cet_ranges(&HashMap::from([(
+            "event".to_owned(),
+            vec![(0..=u64::MAX, ())],
+        )]));
+        let other = cet_ranges(&HashMap::from([(
+            "other_event".to_owned(),
+            vec![(0..=u64::MAX, ())],
+        )]));
+
+        let error = ensure_cet_sets_match(&own, &other).unwrap_err();
+
+        assert!(error.to_string().starts_with("CET set mismatch"));
+    }
+
+    struct UnderfundedWallet;
+
+    impl xtra::Actor for UnderfundedWallet {}
+
+    #[xtra_productivity(message_impl = false)]
+    impl UnderfundedWallet {
+        fn handle_check_funds(&mut self, msg: wallet::CheckFunds) -> Result<()> {
+            Err(wallet::WalletError::InsufficientFunds {
+                required: msg.amount,
+                available: Amount::ZERO,
+            }
+            .into())
+        }
+
+        fn handle_build_party_params(
+            &mut self,
+            _: wallet::BuildPartyParams,
+        ) -> Result<PartyParams> {
+            unreachable!("party params must not be built without sufficient funds")
+        }
+
+        fn handle_sign(&mut self, _: wallet::Sign) -> Result<PartiallySignedTransaction> {
+            unreachable!("nothing to sign without party params")
+        }
+
+        fn handle_setup_progressed(&mut self, _: projection::SetupProgressed) {}
+    }
+
+    fn dummy_setup_params() -> SetupParams {
+        SetupParams::new(
+            OrderId::default(),
+            Amount::from_sat(100_000),
+            Amount::from_sat(100_000),
+            Identity::from_str("69a42aa90da8b065b9532b62bff940a3ba07dbbb11d4482c7db83a7e049a9f1e")
+                .unwrap(),
+            Price::new(dec!(40_000)).unwrap(),
+            Usd::new(dec!(100)),
+            Leverage::new(2).unwrap(),
+            24 * 60 * 60 / 10,
+            12,
+            TxFeeRate::default(),
+            FeeAccount::new(Position::Long, Role::Taker),
+            200,
+            RoundingStrategy::default(),
+        )
+        .unwrap()
+    }
+
+    fn dummy_oracle_pk() -> schnorrsig::PublicKey {
+        schnorrsig::PublicKey::from_str(
+            "ddd4636845a90185991826be5a494cde9f4a6947b1727217afedc6292fa4caf7",
+        )
+        .unwrap()
+    }
+
+    fn dummy_announcement() -> oracle::Announcement {
+        oracle::Announcement {
+            id: BitMexPriceEventId::with_20_digits(OffsetDateTime::now_utc()),
+            expected_outcome_time: OffsetDateTime::now_utc(),
+            nonce_pks: Vec::new(),
+        }
+    }
+}
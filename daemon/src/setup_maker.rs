@@ -4,18 +4,22 @@ use crate::maker_inc_connections::TakerMessage;
 use crate::model::cfd::Dlc;
 use crate::model::cfd::Order;
 use crate::model::cfd::OrderId;
+use crate::model::cfd::RejectReason;
 use crate::model::cfd::Role;
 use crate::model::cfd::SetupCompleted;
 use crate::model::Identity;
 use crate::model::Usd;
+use crate::monitor;
 use crate::oracle::Announcement;
 use crate::process_manager;
+use crate::projection;
 use crate::setup_contract;
 use crate::wallet;
 use crate::wire;
 use crate::wire::MakerToTaker;
 use crate::wire::SetupMsg;
 use crate::Tasks;
+use anyhow::anyhow;
 use anyhow::Context;
 use anyhow::Result;
 use async_trait::async_trait;
@@ -24,6 +28,7 @@ use futures::channel::mpsc::UnboundedSender;
 use futures::future;
 use futures::SinkExt;
 use maia::secp256k1_zkp::schnorrsig;
+use std::time::Duration;
 use xtra::prelude::MessageChannel;
 use xtra::Address;
 use xtra_productivity::xtra_productivity;
@@ -33,14 +38,17 @@ use xtras::LogFailure;
 pub struct Actor {
     order: Order,
     quantity: Usd,
-    n_payouts: usize,
     oracle_pk: schnorrsig::PublicKey,
     announcement: Announcement,
+    check_funds: Box<dyn MessageChannel<wallet::CheckFunds>>,
     build_party_params: Box<dyn MessageChannel<wallet::BuildPartyParams>>,
     sign: Box<dyn MessageChannel<wallet::Sign>>,
+    projection: Box<dyn MessageChannel<projection::SetupProgressed>>,
+    unconfirmed_inputs: Option<Box<dyn MessageChannel<monitor::GetUnconfirmedInputs>>>,
     taker: Box<dyn MessageChannel<maker_inc_connections::TakerMessage>>,
     confirm_order: Box<dyn MessageChannel<maker_inc_connections::ConfirmOrder>>,
     taker_id: Identity,
+    setup_timeout: Duration,
     on_stopping: Vec<Box<dyn MessageChannel<Stopping<Self>>>>,
     setup_msg_sender: Option<UnboundedSender<SetupMsg>>,
     tasks: Tasks,
@@ -52,15 +60,19 @@ impl Actor {
     pub fn new(
         db: sqlx::SqlitePool,
         process_manager: Address<process_manager::Actor>,
-        (order, quantity, n_payouts): (Order, Usd, usize),
+        (order, quantity): (Order, Usd),
         (oracle_pk, announcement): (schnorrsig::PublicKey, Announcement),
+        check_funds: &(impl MessageChannel<wallet::CheckFunds> + 'static),
         build_party_params: &(impl MessageChannel<wallet::BuildPartyParams> + 'static),
         sign: &(impl MessageChannel<wallet::Sign> + 'static),
+        projection: &(impl MessageChannel<projection::SetupProgressed> + 'static),
+        unconfirmed_inputs: Option<Box<dyn MessageChannel<monitor::GetUnconfirmedInputs>>>,
         (taker, confirm_order, taker_id): (
             &(impl MessageChannel<maker_inc_connections::TakerMessage> + 'static),
             &(impl MessageChannel<maker_inc_connections::ConfirmOrder> + 'static),
             Identity,
         ),
+        setup_timeout: Duration,
         (on_stopping0, on_stopping1): (
             &(impl MessageChannel<Stopping<Self>> + 'static),
             &(impl MessageChannel<Stopping<Self>> + 'static),
@@ -70,14 +82,17 @@ impl Actor {
             executor: command::Executor::new(db, process_manager),
             order,
             quantity,
-            n_payouts,
             oracle_pk,
             announcement,
+            check_funds: check_funds.clone_channel(),
             build_party_params: build_party_params.clone_channel(),
             sign: sign.clone_channel(),
+            projection: projection.clone_channel(),
+            unconfirmed_inputs,
             taker: taker.clone_channel(),
             confirm_order: confirm_order.clone_channel(),
             taker_id,
+            setup_timeout,
             on_stopping: vec![on_stopping0.clone_channel(), on_stopping1.clone_channel()],
             setup_msg_sender: None,
             tasks: Tasks::default(),
@@ -86,6 +101,7 @@ impl Actor {
 
     async fn contract_setup(&mut self, this: xtra::Address<Self>) -> Result<()> {
         let order_id = self.order.id;
+        let cet_timelock = self.order.cet_timelock;
 
         let (sender, receiver) = mpsc::unbounded();
         // store the writing end to forward messages from the taker to
@@ -94,7 +110,7 @@ impl Actor {
 
         let setup_params = self
             .executor
-            .execute(order_id, |cfd| cfd.start_contract_setup())
+            .execute(order_id, |cfd| cfd.start_contract_setup(cet_timelock))
             .await?;
 
         let taker_id = setup_params.counterparty_identity();
@@ -109,11 +125,16 @@ impl Actor {
             receiver,
             (self.oracle_pk, self.announcement.clone()),
             setup_params,
+            self.check_funds.clone_channel(),
             self.build_party_params.clone_channel(),
             self.sign.clone_channel(),
+            self.projection.clone_channel(),
+            self.unconfirmed_inputs
+                .as_ref()
+                .map(|channel| channel.clone_channel()),
             Role::Maker,
-            self.n_payouts,
         );
+        let contract_future = setup_contract::with_timeout(contract_future, self.setup_timeout);
 
         self.tasks.add(async move {
             let _: Result<(), xtra::Disconnected> = match contract_future.await {
@@ -184,18 +205,51 @@ impl Actor {
         }
     }
 
-    fn handle(&mut self, _msg: Rejected, ctx: &mut xtra::Context<Self>) {
+    fn handle(&mut self, msg: Rejected, ctx: &mut xtra::Context<Self>) {
+        let Rejected(reason) = msg;
+
         let _ = self
             .taker
             .send(TakerMessage {
                 taker_id: self.taker_id,
-                msg: MakerToTaker::RejectOrder(self.order.id),
+                msg: MakerToTaker::RejectOrder {
+                    order_id: self.order.id,
+                    reason,
+                },
             })
             .log_failure("Failed to reject order to taker")
             .await;
 
-        self.complete(SetupCompleted::rejected(self.order.id), ctx)
-            .await
+        self.complete(
+            SetupCompleted::rejected_due_to(self.order.id, reason.into()),
+            ctx,
+        )
+        .await
+    }
+
+    fn handle(&mut self, msg: Cancelled, ctx: &mut xtra::Context<Self>) {
+        let order_id = self.order.id;
+
+        if msg.taker_id != self.taker_id {
+            tracing::warn!(%order_id, taker_id = %msg.taker_id, "Ignoring cancellation from a different taker");
+            return;
+        }
+
+        if self.setup_msg_sender.is_some() {
+            tracing::warn!(%order_id, "Ignoring cancellation, contract setup already started");
+            return;
+        }
+
+        tracing::info!(%order_id, "Taker cancelled order request");
+
+        self.complete(
+            SetupCompleted::Failed {
+                order_id,
+                error: anyhow!("Order request cancelled by taker"),
+            },
+            ctx,
+        )
+        .await
     }
 
     fn handle(&mut self, msg: SetupSucceeded, ctx: &mut xtra::Context<Self>) {
@@ -236,20 +290,22 @@ impl xtra::Actor for Actor {
             let min = self.order.min_quantity;
             let max = self.order.max_quantity;
 
-            let reason =
-                format!("Order rejected: quantity {quantity} not in range [{min}, {max}]",);
-            tracing::info!("{reason}");
+            tracing::info!("Order rejected: quantity {quantity} not in range [{min}, {max}]");
 
+            let reason = RejectReason::QuantityOutOfRange;
             let _ = self
                 .taker
                 .send(maker_inc_connections::TakerMessage {
                     taker_id: self.taker_id,
-                    msg: wire::MakerToTaker::RejectOrder(self.order.id),
+                    msg: wire::MakerToTaker::RejectOrder {
+                        order_id: self.order.id,
+                        reason,
+                    },
                 })
                 .await;
 
             self.complete(
-                SetupCompleted::rejected_due_to(self.order.id, anyhow::format_err!(reason)),
+                SetupCompleted::rejected_due_to(self.order.id, reason.into()),
                 ctx,
             )
             .await;
@@ -277,7 +333,14 @@ pub struct Accepted;
 /// Message sent from the `maker_cfd::Actor` to the
 /// `setup_maker::Actor` to inform that the maker user has rejected
 /// the taker order request from the taker.
-pub struct Rejected;
+pub struct Rejected(pub RejectReason);
+
+/// Message sent from the `maker_cfd::Actor` to the
+/// `setup_maker::Actor` to inform that the taker withdrew its order
+/// request.
+pub struct Cancelled {
+    pub taker_id: Identity,
+}
 
 /// Message sent from the spawned task to `setup_maker::Actor` to
 /// notify that the contract setup has finished successfully.
@@ -2,17 +2,21 @@ use crate::command;
 use crate::connection;
 use crate::model::cfd::Dlc;
 use crate::model::cfd::OrderId;
+use crate::model::cfd::RejectReason;
 use crate::model::cfd::Role;
 use crate::model::cfd::SetupCompleted;
+use crate::model::Leverage;
 use crate::model::Usd;
 use crate::oracle::Announcement;
 use crate::process_manager;
+use crate::projection;
 use crate::setup_contract;
 use crate::wallet;
 use crate::wire;
 use crate::wire::SetupMsg;
 use crate::Tasks;
 use anyhow::anyhow;
+use anyhow::bail;
 use anyhow::Context;
 use anyhow::Result;
 use async_trait::async_trait;
@@ -31,12 +35,16 @@ const MAKER_RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
 pub struct Actor {
     order_id: OrderId,
     quantity: Usd,
-    n_payouts: usize,
+    leverage: Leverage,
+    cet_timelock: u32,
     oracle_pk: schnorrsig::PublicKey,
     announcement: Announcement,
+    check_funds: Box<dyn MessageChannel<wallet::CheckFunds>>,
     build_party_params: Box<dyn MessageChannel<wallet::BuildPartyParams>>,
     sign: Box<dyn MessageChannel<wallet::Sign>>,
+    projection: Box<dyn MessageChannel<projection::SetupProgressed>>,
     maker: xtra::Address<connection::Actor>,
+    setup_timeout: Duration,
     setup_msg_sender: Option<UnboundedSender<SetupMsg>>,
     tasks: Tasks,
     executor: command::Executor,
@@ -47,21 +55,28 @@ impl Actor {
     pub fn new(
         db: sqlx::SqlitePool,
         process_manager: Address<process_manager::Actor>,
-        (order_id, quantity, n_payouts): (OrderId, Usd, usize),
+        (order_id, quantity, leverage, cet_timelock): (OrderId, Usd, Leverage, u32),
         (oracle_pk, announcement): (schnorrsig::PublicKey, Announcement),
+        check_funds: &(impl MessageChannel<wallet::CheckFunds> + 'static),
         build_party_params: &(impl MessageChannel<wallet::BuildPartyParams> + 'static),
         sign: &(impl MessageChannel<wallet::Sign> + 'static),
+        projection: &(impl MessageChannel<projection::SetupProgressed> + 'static),
         maker: xtra::Address<connection::Actor>,
+        setup_timeout: Duration,
     ) -> Self {
         Self {
             order_id,
             quantity,
-            n_payouts,
+            leverage,
+            cet_timelock,
             oracle_pk,
             announcement,
+            check_funds: check_funds.clone_channel(),
             build_party_params: build_party_params.clone_channel(),
             sign: sign.clone_channel(),
+            projection: projection.clone_channel(),
             maker,
+            setup_timeout,
             setup_msg_sender: None,
             tasks: Tasks::default(),
             executor: command::Executor::new(db, process_manager),
@@ -80,9 +95,10 @@ impl Actor {
         let order_id = self.order_id;
         tracing::info!(%order_id, "Order got accepted");
 
+        let cet_timelock = self.cet_timelock;
         let setup_params = self
             .executor
-            .execute(order_id, |cfd| cfd.start_contract_setup())
+            .execute(order_id, |cfd| cfd.start_contract_setup(cet_timelock))
             .await?;
 
         let (sender, receiver) = mpsc::unbounded::<SetupMsg>();
@@ -96,11 +112,14 @@ impl Actor {
             receiver,
             (self.oracle_pk, self.announcement.clone()),
             setup_params,
+            self.check_funds.clone_channel(),
             self.build_party_params.clone_channel(),
             self.sign.clone_channel(),
+            self.projection.clone_channel(),
+            None,
             Role::Taker,
-            self.n_payouts,
         );
+        let contract_future = setup_contract::with_timeout(contract_future, self.setup_timeout);
 
         let this = ctx.address().expect("self to be alive");
         self.tasks.add(async move {
@@ -115,18 +134,45 @@ impl Actor {
 
     fn handle(&mut self, msg: Rejected, ctx: &mut xtra::Context<Self>) -> Result<()> {
         let order_id = self.order_id;
-        tracing::info!(%order_id, "Order got rejected");
+        let Rejected(reason) = msg;
+        tracing::info!(%order_id, %reason, "Order got rejected");
 
-        let reason = if msg.is_invalid_order {
-            anyhow::format_err!("Invalid order id: {order_id}")
-        } else {
-            anyhow::format_err!("Unknown")
-        };
+        if let Err(e) = self
+            .executor
+            .execute(order_id, |cfd| {
+                cfd.setup_contract(SetupCompleted::rejected_due_to(order_id, reason.into()))
+            })
+            .await
+        {
+            tracing::warn!("{:#}", e);
+        }
+
+        ctx.stop();
+
+        Ok(())
+    }
+
+    fn handle(&mut self, _: Cancel, ctx: &mut xtra::Context<Self>) -> Result<()> {
+        let order_id = self.order_id;
+
+        if self.is_accepted() {
+            bail!("Cannot cancel order request for {order_id}, the maker already accepted it");
+        }
+
+        tracing::info!(%order_id, "Cancelling order request");
+
+        self.maker
+            .send(wire::TakerToMaker::CancelOrderRequest { order_id })
+            .await
+            .context("Failed to send cancellation to maker")?;
 
         if let Err(e) = self
             .executor
             .execute(order_id, |cfd| {
-                cfd.setup_contract(SetupCompleted::rejected_due_to(order_id, reason))
+                cfd.setup_contract(SetupCompleted::Failed {
+                    order_id,
+                    error: anyhow!("Order request cancelled by taker"),
+                })
             })
             .await
         {
@@ -222,6 +268,7 @@ impl xtra::Actor for Actor {
             .send(connection::TakeOrder {
                 order_id: self.order_id,
                 quantity: self.quantity,
+                leverage: self.leverage,
                 address,
             })
             .await;
@@ -256,11 +303,12 @@ pub struct Accepted;
 /// Message sent from the `connection::Actor` to the
 /// `setup_taker::Actor` to notify that the order taken was rejected
 /// by the maker.
-pub struct Rejected {
-    /// Used to indicate whether the rejection stems from the order ID
-    /// not being recognised by the maker.
-    is_invalid_order: bool,
-}
+pub struct Rejected(pub RejectReason);
+
+/// Message sent from the `taker_cfd::Actor` to the
+/// `setup_taker::Actor` to withdraw the order request before the
+/// maker accepted it.
+pub struct Cancel;
 
 /// Message sent from the spawned task to `setup_taker::Actor` to
 /// notify that the contract setup has finished successfully.
@@ -276,23 +324,6 @@ struct SetupFailed {
     error: anyhow::Error,
 }
 
-impl Rejected {
-    /// Order was rejected by the maker for not specific reason.
-    pub fn without_reason() -> Self {
-        Rejected {
-            is_invalid_order: false,
-        }
-    }
-
-    /// Order was rejected by the maker because it did not recognise
-    /// the order ID provided.
-    pub fn invalid_order_id() -> Self {
-        Rejected {
-            is_invalid_order: true,
-        }
-    }
-}
-
 /// Message sent from the spawned task to `setup_taker::Actor` to
 /// notify that the timeout has been reached.
 ///
@@ -1,13 +1,19 @@
 use crate::cfd_actors;
 use crate::cfd_actors::insert_cfd_and_update_feed;
 use crate::collab_settlement_taker;
+use crate::command;
 use crate::connection;
+use crate::health;
+use crate::metrics::Metrics;
+use crate::model::cfd::validate_n_payouts;
 use crate::model::cfd::Cfd;
 use crate::model::cfd::Order;
 use crate::model::cfd::OrderId;
 use crate::model::cfd::Origin;
 use crate::model::cfd::Role;
+use crate::model::cfd::SetupCompleted;
 use crate::model::Identity;
+use crate::model::Leverage;
 use crate::model::Position;
 use crate::model::Price;
 use crate::model::Usd;
@@ -18,9 +24,11 @@ use crate::projection;
 use crate::setup_taker;
 use crate::wallet;
 use crate::Tasks;
+use anyhow::bail;
 use anyhow::Context as _;
 use anyhow::Result;
 use bdk::bitcoin::secp256k1::schnorrsig;
+use std::time::Duration;
 use xtra::prelude::*;
 use xtra::Actor as _;
 use xtra_productivity::xtra_productivity;
@@ -28,9 +36,19 @@ use xtras::AddressMap;
 
 pub struct CurrentOrder(pub Option<Order>);
 
+/// Query the order the maker broadcast most recently, if any.
+pub struct GetCurrentOrder;
+
 pub struct TakeOffer {
     pub order_id: OrderId,
     pub quantity: Usd,
+    /// The leverage to take the order with, defaults to the leverage of the order.
+    pub leverage: Option<Leverage>,
+}
+
+/// Withdraw an order request the maker has not accepted yet.
+pub struct CancelOrderRequest {
+    pub order_id: OrderId,
 }
 
 pub struct ProposeSettlement {
@@ -38,6 +56,15 @@ pub struct ProposeSettlement {
     pub current_price: Price,
 }
 
+/// A module-private message to continue taking an order once the oracle announcement was fetched.
+struct AnnouncementFetched {
+    order_id: OrderId,
+    quantity: Usd,
+    leverage: Leverage,
+    cet_timelock: u32,
+    announcement: Result<oracle::Announcement>,
+}
+
 pub struct Actor<O, W> {
     db: sqlx::SqlitePool,
     wallet: Address<W>,
@@ -48,10 +75,11 @@ pub struct Actor<O, W> {
     setup_actors: AddressMap<OrderId, setup_taker::Actor>,
     collab_settlement_actors: AddressMap<OrderId, collab_settlement_taker::Actor>,
     oracle_actor: Address<O>,
-    n_payouts: usize,
     tasks: Tasks,
     current_order: Option<Order>,
     maker_identity: Identity,
+    setup_timeout: Duration,
+    metrics: Metrics,
 }
 
 impl<O, W> Actor<O, W>
@@ -67,8 +95,9 @@ where
         process_manager_actor: Address<process_manager::Actor>,
         conn_actor: Address<connection::Actor>,
         oracle_actor: Address<O>,
-        n_payouts: usize,
         maker_identity: Identity,
+        setup_timeout: Duration,
+        metrics: Metrics,
     ) -> Self {
         Self {
             db,
@@ -78,12 +107,13 @@ where
             process_manager_actor,
             conn_actor,
             oracle_actor,
-            n_payouts,
             setup_actors: AddressMap::default(),
             collab_settlement_actors: AddressMap::default(),
             tasks: Tasks::default(),
             current_order: None,
             maker_identity,
+            setup_timeout,
+            metrics,
         }
     }
 }
@@ -105,6 +135,8 @@ impl<O, W> Actor<O, W> {
                     .await?;
             }
             None => {
+                self.current_order = None;
+
                 self.projection_actor
                     .send(projection::Update(Option::<Order>::None))
                     .await?;
@@ -113,6 +145,21 @@ impl<O, W> Actor<O, W> {
         Ok(())
     }
 
+    fn handle_get_current_order(&mut self, _: GetCurrentOrder) -> Option<Order> {
+        self.current_order.clone()
+    }
+
+    async fn handle_cancel_order_request(&mut self, msg: CancelOrderRequest) -> Result<()> {
+        let CancelOrderRequest { order_id } = msg;
+
+        self.setup_actors
+            .send_fallible(&order_id, setup_taker::Cancel)
+            .await
+            .with_context(|| format!("No pending order request for order {order_id}"))??;
+
+        Ok(())
+    }
+
     async fn handle_propose_settlement(&mut self, msg: ProposeSettlement) -> Result<()> {
         let ProposeSettlement {
             order_id,
@@ -127,7 +174,6 @@ impl<O, W> Actor<O, W> {
         let (addr, fut) = collab_settlement_taker::Actor::new(
             order_id,
             current_price,
-            self.n_payouts,
             self.conn_actor.clone(),
             self.process_manager_actor.clone(),
             self.db.clone(),
@@ -159,23 +205,34 @@ impl<O, W> Actor<O, W> {
             tracing::warn!("Failed to handle oracle attestation: {:#}", e)
         }
     }
+
+    fn handle_ping(&mut self, _: health::Ping) {}
 }
 
 #[xtra_productivity]
 impl<O, W> Actor<O, W>
 where
     O: xtra::Handler<oracle::GetAnnouncement> + xtra::Handler<oracle::MonitorAttestation>,
-    W: xtra::Handler<wallet::BuildPartyParams> + xtra::Handler<wallet::Sign>,
+    W: xtra::Handler<wallet::BuildPartyParams>
+        + xtra::Handler<wallet::Sign>
+        + xtra::Handler<wallet::CheckFunds>,
 {
-    async fn handle_take_offer(&mut self, msg: TakeOffer) -> Result<()> {
-        let TakeOffer { order_id, quantity } = msg;
+    async fn handle_take_offer(
+        &mut self,
+        msg: TakeOffer,
+        ctx: &mut xtra::Context<Self>,
+    ) -> Result<()> {
+        let TakeOffer {
+            order_id,
+            quantity,
+            leverage,
+        } = msg;
 
-        let disconnected = self
-            .setup_actors
-            .get_disconnected(order_id)
-            .with_context(|| {
-                format!("Contract setup for order {order_id} is already in progress")
-            })?;
+        let quantity = Usd::try_new(quantity.into_decimal())?;
+
+        if self.setup_actors.get_connected(&order_id).is_some() {
+            bail!("Contract setup for order {order_id} is already in progress");
+        }
 
         let mut conn = self.db.acquire().await?;
 
@@ -186,18 +243,60 @@ where
 
         tracing::info!("Taking current order: {:?}", &current_order);
 
+        // The maker decides how many CETs we have to create and sign, don't let it go overboard
+        validate_n_payouts(current_order.n_payouts)?;
+
+        // Refuse orders attested by an oracle we don't trust, the CETs would be worthless to us.
+        // Orders that don't tell us their oracle are refused as well, we cannot verify them.
+        let oracle_pk = current_order
+            .oracle_pk
+            .with_context(|| format!("Order {order_id} does not specify its oracle"))?;
+        if oracle_pk != self.oracle_pk {
+            bail!(
+                "Order {order_id} uses oracle {oracle_pk} but we only accept oracle {}",
+                self.oracle_pk
+            );
+        }
+
+        // Refuse orders settling on an event that already happened, the oracle may have attested
+        // to it already and we would be unable to construct a contract around it
+        let oracle_event_id = current_order.oracle_event_id;
+        if oracle_event_id.has_likely_occured() {
+            bail!(
+                "Order {order_id} settles on oracle event {oracle_event_id} which has likely already occurred"
+            );
+        }
+
         // We create the cfd here without any events yet, only static data
         // Once the contract setup completes (rejected / accepted / failed) the first event will be
         // recorded
+        let leverage = leverage.unwrap_or(current_order.leverage);
+
         let cfd = Cfd::from_order(
-            current_order.clone(),
+            current_order.clone().with_leverage(leverage),
             Position::Long,
             quantity,
             self.maker_identity,
             Role::Taker,
         );
 
+        // The maker picks the CET timelock, make sure it does not undercut the refund transaction
+        cfd.validate_cet_timelock(current_order.cet_timelock)
+            .with_context(|| format!("Order {order_id} has an invalid CET timelock"))?;
+
+        // Make sure our wallet can fund the margin before we record anything, otherwise the
+        // contract setup would only fail once the maker has already accepted.
+        self.wallet
+            .send(wallet::CheckFunds {
+                order_id,
+                amount: cfd.margin(),
+                fee_rate: cfd.initial_tx_fee_rate(),
+            })
+            .await
+            .context("Failed to send message to wallet actor")??;
+
         insert_cfd_and_update_feed(&cfd, &mut conn, &self.projection_actor).await?;
+        self.metrics.record_take();
 
         // Cleanup own order feed, after inserting the cfd.
         // Due to the 1:1 relationship between order and cfd we can never create another cfd for the
@@ -206,26 +305,80 @@ where
             .send(projection::Update(Option::<Order>::None))
             .await?;
 
+        // Fetching the announcement may be retried for a while, do that in the background so we
+        // don't block the actor. The take is continued in `handle_announcement_fetched`.
+        let this = ctx.address().expect("self to be alive");
+        let oracle_actor = self.oracle_actor.clone();
         let price_event_id = current_order.oracle_event_id;
-        let announcement = self
-            .oracle_actor
-            .send(oracle::GetAnnouncement(price_event_id))
-            .await?
-            .with_context(|| format!("Announcement {price_event_id} not found"))?;
+        let cet_timelock = current_order.cet_timelock;
+        let quantity = cfd.quantity();
+        let leverage = cfd.leverage();
+
+        self.tasks.add(async move {
+            let announcement = oracle::get_announcement(&oracle_actor, price_event_id).await;
+
+            let result = this
+                .send(AnnouncementFetched {
+                    order_id,
+                    quantity,
+                    leverage,
+                    cet_timelock,
+                    announcement,
+                })
+                .await;
+
+            match result {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => tracing::error!(%order_id, "Failed to take order: {e:#}"),
+                Err(_) => tracing::warn!(%order_id, "Cfd actor stopped before order was taken"),
+            }
+        });
+
+        Ok(())
+    }
+
+    async fn handle_announcement_fetched(&mut self, msg: AnnouncementFetched) -> Result<()> {
+        let AnnouncementFetched {
+            order_id,
+            quantity,
+            leverage,
+            cet_timelock,
+            announcement,
+        } = msg;
+
+        let announcement = match announcement {
+            Ok(announcement) => announcement,
+            Err(error) => {
+                // The cfd has been recorded already, fail its setup so it does not stay pending
+                command::Executor::new(self.db.clone(), self.process_manager_actor.clone())
+                    .execute(order_id, |cfd| {
+                        cfd.setup_contract(SetupCompleted::Failed {
+                            order_id,
+                            error: error.context("Announcement is unavailable"),
+                        })
+                    })
+                    .await?;
+
+                return Ok(());
+            }
+        };
 
         let (addr, fut) = setup_taker::Actor::new(
             self.db.clone(),
             self.process_manager_actor.clone(),
-            (cfd.id(), cfd.quantity(), self.n_payouts),
+            (order_id, quantity, leverage, cet_timelock),
             (self.oracle_pk, announcement),
             &self.wallet,
             &self.wallet,
+            &self.wallet,
+            &self.projection_actor,
             self.conn_actor.clone(),
+            self.setup_timeout,
         )
         .create(None)
         .run();
 
-        disconnected.insert(addr);
+        self.setup_actors.insert(order_id, addr);
 
         self.tasks.add(fut);
 
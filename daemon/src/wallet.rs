@@ -1,3 +1,4 @@
+use crate::model::cfd::OrderId;
 use crate::model::Timestamp;
 use crate::model::TxFeeRate;
 use crate::model::WalletInfo;
@@ -23,7 +24,8 @@ use bdk::KeychainKind;
 use bdk::SignOptions;
 use maia::PartyParams;
 use maia::TxBuilderExt;
-use std::collections::HashSet;
+use serde::Serialize;
+use std::collections::HashMap;
 use std::time::Duration;
 use tokio::sync::watch;
 use xtra_productivity::xtra_productivity;
@@ -31,7 +33,8 @@ use xtras::SendInterval;
 
 pub struct Actor {
     wallet: bdk::Wallet<ElectrumBlockchain, bdk::database::MemoryDatabase>,
-    used_utxos: HashSet<OutPoint>,
+    /// UTXOs that were used in a lock transaction, together with the order they are reserved for.
+    used_utxos: HashMap<OutPoint, OrderId>,
     tasks: Tasks,
     sender: watch::Sender<Option<WalletInfo>>,
 }
@@ -40,6 +43,12 @@ pub struct Actor {
 #[error("The transaction is already in the blockchain")]
 pub struct TransactionAlreadyInBlockchain;
 
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq)]
+pub enum WalletError {
+    #[error("Insufficient funds: {required} required but only {available} available")]
+    InsufficientFunds { required: Amount, available: Amount },
+}
+
 impl Actor {
     pub fn new(
         electrum_rpc_url: &str,
@@ -63,7 +72,7 @@ impl Actor {
             wallet,
             tasks: Tasks::default(),
             sender,
-            used_utxos: HashSet::default(),
+            used_utxos: HashMap::default(),
         };
 
         Ok((actor, receiver))
@@ -122,14 +131,15 @@ impl Actor {
     pub fn build_party_params(
         &mut self,
         BuildPartyParams {
+            order_id,
             amount,
             identity_pk,
             fee_rate,
         }: BuildPartyParams,
     ) -> Result<PartyParams> {
-        let psbt = self
-            .wallet
-            .build_lock_tx(amount, &mut self.used_utxos, fee_rate.into())?;
+        let psbt =
+            self.wallet
+                .build_lock_tx(order_id, amount, &mut self.used_utxos, fee_rate.into())?;
 
         Ok(PartyParams {
             lock_psbt: psbt,
@@ -139,6 +149,29 @@ impl Actor {
         })
     }
 
+    pub fn handle_check_funds(&mut self, msg: CheckFunds) -> Result<()> {
+        // Build the lock transaction against a copy of the reservations so that none of the
+        // selected UTXOs stay reserved for an order that may never be set up.
+        let mut used_utxos = self.used_utxos.clone();
+
+        self.wallet.build_lock_tx(
+            msg.order_id,
+            msg.amount,
+            &mut used_utxos,
+            msg.fee_rate.into(),
+        )?;
+
+        Ok(())
+    }
+
+    pub fn handle_list_utxos(&mut self, _msg: ListUtxos) -> Result<Vec<Utxo>> {
+        self.wallet.list_utxos(&self.used_utxos)
+    }
+
+    pub fn handle_new_address(&mut self, _msg: NewAddress) -> Result<Address> {
+        self.wallet.new_address()
+    }
+
     pub fn handle_withdraw(&mut self, msg: Withdraw) -> Result<Txid> {
         self.sync_internal()?;
 
@@ -199,11 +232,22 @@ impl xtra::Actor for Actor {
 }
 
 pub struct BuildPartyParams {
+    /// The order the UTXOs of the lock transaction are reserved for.
+    pub order_id: OrderId,
     pub amount: Amount,
     pub identity_pk: PublicKey,
     pub fee_rate: TxFeeRate,
 }
 
+/// Check whether the wallet can fund a lock transaction without reserving any UTXOs.
+///
+/// Fails with [`WalletError::InsufficientFunds`] if the balance does not cover the amount.
+pub struct CheckFunds {
+    pub order_id: OrderId,
+    pub amount: Amount,
+    pub fee_rate: TxFeeRate,
+}
+
 /// Private message to trigger a sync.
 struct Sync;
 
@@ -217,10 +261,27 @@ pub struct Withdraw {
     pub address: Address,
 }
 
+/// List the UTXOs of the wallet, including the ones reserved for a lock transaction.
+pub struct ListUtxos;
+
+/// Derive a fresh receive address, advancing the external keychain index.
+pub struct NewAddress;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Utxo {
+    pub outpoint: OutPoint,
+    #[serde(with = "::bdk::bitcoin::util::amount::serde::as_btc")]
+    pub amount: Amount,
+    /// The order whose lock transaction spends this UTXO, if any.
+    pub reserved_for: Option<OrderId>,
+}
+
 /// Bitcoin error codes: <https://github.com/bitcoin/bitcoin/blob/97d3500601c1d28642347d014a6de1e38f53ae4e/src/rpc/protocol.h#L23>
 pub enum RpcErrorCode {
     /// General error during transaction or block submission Error code -25.
     RpcVerifyError,
+    /// Transaction or block was rejected by network rules. Error code -26.
+    RpcVerifyRejected,
     /// Transaction already in chain. Error code -27.
     RpcVerifyAlreadyInChain,
 }
@@ -229,6 +290,7 @@ impl From<RpcErrorCode> for i64 {
     fn from(code: RpcErrorCode) -> Self {
         match code {
             RpcErrorCode::RpcVerifyError => -25,
+            RpcErrorCode::RpcVerifyRejected => -26,
             RpcErrorCode::RpcVerifyAlreadyInChain => -27,
         }
     }
@@ -241,10 +303,15 @@ impl From<RpcErrorCode> for i64 {
 trait BuildLockTx {
     fn build_lock_tx(
         &mut self,
+        order_id: OrderId,
         amount: Amount,
-        used_utxos: &mut HashSet<OutPoint>,
+        used_utxos: &mut HashMap<OutPoint, OrderId>,
         fee_rate: FeeRate,
     ) -> Result<PartiallySignedTransaction>;
+
+    fn list_utxos(&self, used_utxos: &HashMap<OutPoint, OrderId>) -> Result<Vec<Utxo>>;
+
+    fn new_address(&self) -> Result<Address>;
 }
 
 impl<B, D> BuildLockTx for bdk::Wallet<B, D>
@@ -253,30 +320,65 @@ where
 {
     fn build_lock_tx(
         &mut self,
+        order_id: OrderId,
         amount: Amount,
-        used_utxos: &mut HashSet<OutPoint>,
+        used_utxos: &mut HashMap<OutPoint, OrderId>,
         fee_rate: FeeRate,
     ) -> Result<PartiallySignedTransaction> {
+        // Building the lock transaction again for the same order releases the UTXOs that were
+        // reserved for it previously.
+        used_utxos.retain(|_, reserved_for| *reserved_for != order_id);
+
         let mut builder = self.build_tx();
 
         builder
             .ordering(TxOrdering::Bip69Lexicographic) // TODO: I think this is pointless but we did this in maia.
             .fee_rate(fee_rate)
-            .unspendable(used_utxos.iter().copied().collect())
+            .unspendable(used_utxos.keys().copied().collect())
             .add_2of2_multisig_recipient(amount);
 
-        let (psbt, _) = builder.finish()?;
+        let (psbt, _) = match builder.finish() {
+            Ok(built) => built,
+            Err(bdk::Error::InsufficientFunds { needed, available }) => {
+                return Err(WalletError::InsufficientFunds {
+                    required: Amount::from_sat(needed),
+                    available: Amount::from_sat(available),
+                }
+                .into())
+            }
+            Err(e) => return Err(e.into()),
+        };
 
         let used_inputs = psbt
             .global
             .unsigned_tx
             .input
             .iter()
-            .map(|input| input.previous_output);
+            .map(|input| (input.previous_output, order_id));
         used_utxos.extend(used_inputs);
 
         Ok(psbt)
     }
+
+    fn list_utxos(&self, used_utxos: &HashMap<OutPoint, OrderId>) -> Result<Vec<Utxo>> {
+        let utxos = self
+            .list_unspent()?
+            .into_iter()
+            .map(|utxo| Utxo {
+                outpoint: utxo.outpoint,
+                amount: Amount::from_sat(utxo.txout.value),
+                reserved_for: used_utxos.get(&utxo.outpoint).copied(),
+            })
+            .collect();
+
+        Ok(utxos)
+    }
+
+    fn new_address(&self) -> Result<Address> {
+        let address = self.get_address(AddressIndex::New)?.address;
+
+        Ok(address)
+    }
 }
 
 #[cfg(test)]
@@ -289,10 +391,11 @@ mod tests {
     #[test]
     fn creating_two_lock_transactions_uses_different_utxos() {
         let mut wallet = new_test_wallet(&mut thread_rng(), Amount::from_sat(1000), 10).unwrap();
-        let mut used_utxos = HashSet::new();
+        let mut used_utxos = HashMap::new();
 
         let lock_tx_1 = wallet
             .build_lock_tx(
+                OrderId::default(),
                 Amount::from_sat(2500),
                 &mut used_utxos,
                 FeeRate::default_min_relay_fee(),
@@ -300,6 +403,7 @@ mod tests {
             .unwrap();
         let lock_tx_2 = wallet
             .build_lock_tx(
+                OrderId::default(),
                 Amount::from_sat(2500),
                 &mut used_utxos,
                 FeeRate::default_min_relay_fee(),
@@ -330,6 +434,106 @@ mod tests {
         let expected_num_utxos = 6;
 
         assert_eq!(utxos_in_transaction.len(), expected_num_utxos);
-        assert_eq!(utxos_in_transaction, used_utxos);
+        assert_eq!(
+            utxos_in_transaction,
+            used_utxos.keys().copied().collect::<HashSet<_>>()
+        );
+    }
+
+    #[test]
+    fn building_lock_tx_above_balance_fails_with_insufficient_funds() {
+        let mut wallet = new_test_wallet(&mut thread_rng(), Amount::from_sat(1000), 10).unwrap();
+        let mut used_utxos = HashMap::new();
+
+        let error = wallet
+            .build_lock_tx(
+                OrderId::default(),
+                Amount::from_sat(20_000),
+                &mut used_utxos,
+                FeeRate::default_min_relay_fee(),
+            )
+            .unwrap_err();
+
+        match error.downcast_ref::<WalletError>() {
+            Some(WalletError::InsufficientFunds {
+                required,
+                available,
+            }) => {
+                assert!(required > available);
+                assert!(*available <= Amount::from_sat(10_000));
+            }
+            None => panic!("expected insufficient funds error, got {error:#}"),
+        }
+        assert!(used_utxos.is_empty());
+    }
+
+    #[test]
+    fn rebuilding_lock_tx_for_same_order_reuses_its_reservation() {
+        let mut wallet = new_test_wallet(&mut thread_rng(), Amount::from_sat(1000), 10).unwrap();
+        let mut used_utxos = HashMap::new();
+        let order_id = OrderId::default();
+
+        let mut build_lock_tx = |used_utxos: &mut HashMap<OutPoint, OrderId>| {
+            wallet
+                .build_lock_tx(
+                    order_id,
+                    Amount::from_sat(6000),
+                    used_utxos,
+                    FeeRate::default_min_relay_fee(),
+                )
+                .unwrap()
+        };
+
+        // More than half of the wallet's funds, a second reservation on top of the first one
+        // could not be funded.
+        let lock_tx_1 = build_lock_tx(&mut used_utxos);
+        let lock_tx_2 = build_lock_tx(&mut used_utxos);
+
+        assert_eq!(used_utxos.len(), lock_tx_2.global.unsigned_tx.input.len());
+        assert!(lock_tx_1.global.unsigned_tx.input.len() > 5);
+    }
+
+    #[test]
+    fn reserved_utxo_reports_its_order_id() {
+        let mut wallet = new_test_wallet(&mut thread_rng(), Amount::from_sat(1000), 10).unwrap();
+        let mut used_utxos = HashMap::new();
+        let order_id = OrderId::default();
+
+        let lock_tx = wallet
+            .build_lock_tx(
+                order_id,
+                Amount::from_sat(2500),
+                &mut used_utxos,
+                FeeRate::default_min_relay_fee(),
+            )
+            .unwrap();
+        let reserved = lock_tx
+            .global
+            .unsigned_tx
+            .input
+            .iter()
+            .map(|i| i.previous_output)
+            .collect::<HashSet<_>>();
+
+        let utxos = wallet.list_utxos(&used_utxos).unwrap();
+
+        assert_eq!(utxos.len(), 10);
+        for utxo in utxos {
+            if reserved.contains(&utxo.outpoint) {
+                assert_eq!(utxo.reserved_for, Some(order_id));
+            } else {
+                assert_eq!(utxo.reserved_for, None);
+            }
+        }
+    }
+
+    #[test]
+    fn consecutive_new_addresses_are_distinct() {
+        let wallet = new_test_wallet(&mut thread_rng(), Amount::from_sat(1000), 10).unwrap();
+
+        let first = wallet.new_address().unwrap();
+        let second = wallet.new_address().unwrap();
+
+        assert_ne!(first, second);
     }
 }
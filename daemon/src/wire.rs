@@ -1,7 +1,9 @@
 use crate::model::cfd::Order;
 use crate::model::cfd::OrderId;
+use crate::model::cfd::RejectReason;
 use crate::model::BitMexPriceEventId;
 use crate::model::FundingRate;
+use crate::model::Leverage;
 use crate::model::Price;
 use crate::model::Timestamp;
 use crate::model::TxFeeRate;
@@ -9,6 +11,7 @@ use crate::model::Usd;
 use crate::noise::NOISE_MAX_MSG_LEN;
 use crate::noise::NOISE_TAG_LEN;
 use anyhow::bail;
+use anyhow::Context;
 use anyhow::Result;
 use bdk::bitcoin::secp256k1::Signature;
 use bdk::bitcoin::util::psbt::PartiallySignedTransaction;
@@ -16,6 +19,8 @@ use bdk::bitcoin::Address;
 use bdk::bitcoin::Amount;
 use bdk::bitcoin::PublicKey;
 use bytes::BytesMut;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
 use futures::stream::SplitSink;
 use futures::stream::SplitStream;
 use maia::secp256k1_zkp::EcdsaAdaptorSignature;
@@ -27,10 +32,18 @@ use serde::de::DeserializeOwned;
 use serde::Deserialize;
 use serde::Serialize;
 use snow::TransportState;
+use socket2::SockRef;
+use socket2::TcpKeepalive;
 use std::collections::HashMap;
 use std::fmt;
+use std::io::Read as _;
+use std::io::Write as _;
 use std::marker::PhantomData;
 use std::ops::RangeInclusive;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::TcpStream;
 use tokio_util::codec::Decoder;
 use tokio_util::codec::Encoder;
@@ -40,12 +53,32 @@ use tokio_util::codec::LengthDelimitedCodec;
 pub type Read<D, E> = SplitStream<Framed<TcpStream, EncryptedJsonCodec<D, E>>>;
 pub type Write<D, E> = SplitSink<Framed<TcpStream, EncryptedJsonCodec<D, E>>, E>;
 
+/// Enable TCP keep-alive on the socket, sending the first probe after `interval` of inactivity.
+pub fn enable_tcp_keepalive(stream: &TcpStream, interval: Duration) -> Result<()> {
+    SockRef::from(stream)
+        .set_tcp_keepalive(&TcpKeepalive::new().with_time(interval))
+        .context("Failed to enable TCP keep-alive")
+}
+
+/// The version of the wire protocol, exchanged in the [`TakerToMaker::Hello`] handshake.
+///
+/// Peers of different versions must still be able to read each other's `Hello`, otherwise they
+/// cannot tell the user that they are incompatible. The version is therefore serialized as a plain
+/// semver string (e.g. `"3.0.0"`) and this representation must never change.
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, PartialOrd)]
 pub struct Version(semver::Version);
 
 impl Version {
     pub fn current() -> Self {
-        Self(semver::Version::new(2, 0, 0))
+        Self(semver::Version::new(3, 0, 0))
+    }
+
+    /// Whether we can talk to a peer on the `other` version.
+    ///
+    /// A minor version only adds messages without payload which peers of an older minor version
+    /// ignore, see [`MakerToTaker::Unknown`]. Anything else requires a new major version.
+    pub fn is_compatible_with(&self, other: &Version) -> bool {
+        self.0.major == other.0.major
     }
 }
 
@@ -90,9 +123,16 @@ pub mod taker_to_maker {
 #[allow(clippy::large_enum_variant)]
 pub enum TakerToMaker {
     Hello(Version),
+    /// Ask the maker to send its current order, e.g. after (re)connecting.
+    RequestCurrentOrder,
     TakeOrder {
         order_id: OrderId,
         quantity: Usd,
+        leverage: Leverage,
+    },
+    /// Withdraw a `TakeOrder` request the maker has not accepted yet.
+    CancelOrderRequest {
+        order_id: OrderId,
     },
     ProposeRollover {
         order_id: OrderId,
@@ -110,17 +150,29 @@ pub enum TakerToMaker {
         order_id: OrderId,
         msg: taker_to_maker::Settlement,
     },
+    /// Announce the compression algorithms we can decode, sent right after the `Hello`.
+    SupportedCompression(Vec<Compression>),
+    /// A message of a type introduced by a newer minor version of the protocol.
+    ///
+    /// Only messages without payload can be ignored like this, new messages carrying a payload
+    /// require a new major version.
+    #[serde(other)]
+    Unknown,
 }
 
 impl fmt::Display for TakerToMaker {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             TakerToMaker::TakeOrder { .. } => write!(f, "TakeOrder"),
+            TakerToMaker::CancelOrderRequest { .. } => write!(f, "CancelOrderRequest"),
             TakerToMaker::Protocol { msg, .. } => write!(f, "Protocol::{msg}"),
             TakerToMaker::ProposeRollover { .. } => write!(f, "ProposeRollover"),
             TakerToMaker::RolloverProtocol { msg, .. } => write!(f, "RolloverProtocol::{msg}"),
             TakerToMaker::Settlement { msg, .. } => write!(f, "Settlement::{msg}"),
             TakerToMaker::Hello(_) => write!(f, "Hello"),
+            TakerToMaker::RequestCurrentOrder => write!(f, "RequestCurrentOrder"),
+            TakerToMaker::SupportedCompression(_) => write!(f, "SupportedCompression"),
+            TakerToMaker::Unknown => write!(f, "Unknown"),
         }
     }
 }
@@ -134,8 +186,14 @@ pub enum MakerToTaker {
     Heartbeat,
     CurrentOrder(Option<Order>),
     ConfirmOrder(OrderId), // TODO: Include payout curve in "accept" message from maker
-    RejectOrder(OrderId),
+    RejectOrder {
+        order_id: OrderId,
+        reason: RejectReason,
+    },
     InvalidOrderId(OrderId),
+    /// A contract setup for the order is already in progress, e.g. because the take was sent
+    /// twice.
+    TakeOrderAlreadyInProgress(OrderId),
     Protocol {
         order_id: OrderId,
         msg: SetupMsg,
@@ -155,6 +213,14 @@ pub enum MakerToTaker {
         order_id: OrderId,
         msg: maker_to_taker::Settlement,
     },
+    /// Reply to [`TakerToMaker::SupportedCompression`] with the algorithms both sides support.
+    SupportedCompression(Vec<Compression>),
+    /// A message of a type introduced by a newer minor version of the protocol.
+    ///
+    /// Only messages without payload can be ignored like this, new messages carrying a payload
+    /// require a new major version.
+    #[serde(other)]
+    Unknown,
 }
 
 pub mod maker_to_taker {
@@ -184,32 +250,132 @@ impl fmt::Display for MakerToTaker {
             MakerToTaker::Heartbeat { .. } => write!(f, "Heartbeat"),
             MakerToTaker::CurrentOrder(_) => write!(f, "CurrentOrder"),
             MakerToTaker::ConfirmOrder(_) => write!(f, "ConfirmOrder"),
-            MakerToTaker::RejectOrder(_) => write!(f, "RejectOrder"),
+            MakerToTaker::RejectOrder { .. } => write!(f, "RejectOrder"),
             MakerToTaker::InvalidOrderId(_) => write!(f, "InvalidOrderId"),
+            MakerToTaker::TakeOrderAlreadyInProgress(_) => {
+                write!(f, "TakeOrderAlreadyInProgress")
+            }
             MakerToTaker::Protocol { msg, .. } => write!(f, "Protocol::{msg}"),
             MakerToTaker::ConfirmRollover { .. } => write!(f, "ConfirmRollover"),
             MakerToTaker::RejectRollover(_) => write!(f, "RejectRollover"),
             MakerToTaker::RolloverProtocol { msg, .. } => write!(f, "RolloverProtocol::{msg}"),
             MakerToTaker::Settlement { msg, .. } => write!(f, "Settlement::{msg}"),
+            MakerToTaker::SupportedCompression(_) => write!(f, "SupportedCompression"),
+            MakerToTaker::Unknown => write!(f, "Unknown"),
         }
     }
 }
 
+/// Largest frame we accept from a peer, in bytes.
+///
+/// Generously sized to fit the setup messages which carry all CETs and their adaptor signatures.
+pub const MAX_FRAME_LENGTH: usize = 8 * 1024 * 1024;
+
+/// Deepest nesting of JSON arrays and objects we accept in a message from a peer.
+///
+/// Our own messages are nested much less deeply.
+pub const MAX_NESTING_DEPTH: usize = 32;
+
+/// Messages whose JSON is larger than this many bytes are compressed, if the peer supports it.
+///
+/// Smaller messages are not worth the CPU time, in practice only the setup and rollover messages
+/// carrying the CETs exceed it.
+pub const COMPRESSION_THRESHOLD: usize = 16 * 1024;
+
+/// The first two bytes of every gzip stream.
+///
+/// JSON can never start with these, which allows us to tell compressed from plain messages without
+/// changing the framing.
+const GZIP_MAGIC_BYTES: [u8; 2] = [0x1f, 0x8b];
+
+/// Compression algorithms a peer can announce support for.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Compression {
+    Gzip,
+    /// An algorithm introduced by a newer version.
+    #[serde(other)]
+    Unknown,
+}
+
+impl Compression {
+    /// All algorithms supported by this version, in order of preference.
+    pub fn supported() -> Vec<Compression> {
+        vec![Compression::Gzip]
+    }
+}
+
+/// Switches the [`EncryptedJsonCodec`] to compressing outgoing messages.
+///
+/// The codec is no longer reachable once the connection is split into read and write halves, hence
+/// the switch is shared with whoever processes the peer's [`TakerToMaker::SupportedCompression`] or
+/// [`MakerToTaker::SupportedCompression`] message.
+#[derive(Debug, Clone, Default)]
+pub struct CompressionSwitch(Arc<AtomicBool>);
+
+impl CompressionSwitch {
+    /// Enable compression if the peer supports an algorithm we support.
+    pub fn enable_if_supported(&self, theirs: &[Compression]) -> bool {
+        let supported = theirs.contains(&Compression::Gzip);
+        if supported {
+            self.0.store(true, Ordering::Relaxed);
+        }
+
+        supported
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
 /// A codec that can decode encrypted JSON into the type `D` and encode `E` to encrypted JSON.
+///
+/// Compressed messages are always decoded. Outgoing messages are only compressed once enabled
+/// through the [`CompressionSwitch`], so that peers which don't support it keep working.
 pub struct EncryptedJsonCodec<D, E> {
     _type: PhantomData<(D, E)>,
     inner: LengthDelimitedCodec,
     transport_state: TransportState,
+    compression: CompressionSwitch,
 }
 
 impl<D, E> EncryptedJsonCodec<D, E> {
     pub fn new(transport_state: TransportState) -> Self {
         Self {
             _type: PhantomData,
-            inner: LengthDelimitedCodec::new(),
+            inner: LengthDelimitedCodec::builder()
+                .max_frame_length(MAX_FRAME_LENGTH)
+                .new_codec(),
             transport_state,
+            compression: CompressionSwitch::default(),
         }
     }
+
+    pub fn compression_switch(&self) -> CompressionSwitch {
+        self.compression.clone()
+    }
+}
+
+fn compress(bytes: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(bytes)?;
+
+    Ok(encoder.finish()?)
+}
+
+/// Decompress a gzip stream, refusing to inflate it beyond [`MAX_FRAME_LENGTH`].
+fn decompress(bytes: &[u8]) -> Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    GzDecoder::new(bytes)
+        .take(MAX_FRAME_LENGTH as u64 + 1)
+        .read_to_end(&mut decompressed)
+        .context("Failed to decompress message")?;
+
+    if decompressed.len() > MAX_FRAME_LENGTH {
+        bail!("Decompressed message exceeds {MAX_FRAME_LENGTH} bytes");
+    }
+
+    Ok(decompressed)
 }
 
 impl<D, E> Decoder for EncryptedJsonCodec<D, E>
@@ -220,7 +386,7 @@ where
     type Error = anyhow::Error;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        let bytes = match self.inner.decode(src)? {
+        let bytes = match self.inner.decode(src).context("Failed to decode frame")? {
             None => return Ok(None),
             Some(bytes) => bytes,
         };
@@ -228,7 +394,11 @@ where
         let decrypted = bytes
             .chunks(NOISE_MAX_MSG_LEN as usize)
             .map(|chunk| {
-                let mut buf = vec![0u8; chunk.len() - NOISE_TAG_LEN as usize];
+                let len = chunk
+                    .len()
+                    .checked_sub(NOISE_TAG_LEN as usize)
+                    .context("Encrypted chunk is shorter than its tag")?;
+                let mut buf = vec![0u8; len];
                 self.transport_state.read_message(chunk, &mut *buf)?;
                 Ok(buf)
             })
@@ -237,12 +407,54 @@ where
             .flatten()
             .collect::<Vec<u8>>();
 
+        let decrypted = if decrypted.starts_with(&GZIP_MAGIC_BYTES) {
+            decompress(&decrypted)?
+        } else {
+            decrypted
+        };
+
+        ensure_max_nesting_depth(&decrypted, MAX_NESTING_DEPTH)?;
         let item = serde_json::from_slice(&decrypted)?;
 
         Ok(Some(item))
     }
 }
 
+/// Reject JSON that nests arrays or objects deeper than `max_depth` before deserializing it.
+///
+/// This is a cheap scan over the bytes, it does not validate the JSON.
+fn ensure_max_nesting_depth(json: &[u8], max_depth: usize) -> Result<()> {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for byte in json {
+        if in_string {
+            match (escaped, byte) {
+                (true, _) => escaped = false,
+                (false, b'\\') => escaped = true,
+                (false, b'"') => in_string = false,
+                (false, _) => {}
+            }
+            continue;
+        }
+
+        match byte {
+            b'"' => in_string = true,
+            b'[' | b'{' => {
+                depth += 1;
+                if depth > max_depth {
+                    bail!("Message exceeds maximum nesting depth of {max_depth}");
+                }
+            }
+            b']' | b'}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
 impl<D, E> Encoder<E> for EncryptedJsonCodec<D, E>
 where
     E: Serialize,
@@ -250,7 +462,11 @@ where
     type Error = anyhow::Error;
 
     fn encode(&mut self, item: E, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        let bytes = serde_json::to_vec(&item)?;
+        let mut bytes = serde_json::to_vec(&item)?;
+
+        if self.compression.is_enabled() && bytes.len() > COMPRESSION_THRESHOLD {
+            bytes = compress(&bytes)?;
+        }
 
         let encrypted = bytes
             .chunks((NOISE_MAX_MSG_LEN - NOISE_TAG_LEN) as usize)
@@ -542,3 +758,239 @@ impl From<CfdTransactions> for RolloverMsg1 {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use snow::Builder;
+
+    #[test]
+    fn version_serializes_to_plain_semver_string() {
+        let json = serde_json::to_value(Version::current()).unwrap();
+
+        assert_eq!(json, serde_json::json!("3.0.0"));
+    }
+
+    #[tokio::test]
+    async fn keepalive_is_enabled_on_socket() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        assert!(!SockRef::from(&stream).keepalive().unwrap());
+
+        enable_tcp_keepalive(&stream, Duration::from_secs(30)).unwrap();
+
+        assert!(SockRef::from(&stream).keepalive().unwrap());
+    }
+
+    #[test]
+    fn hello_serializes_to_stable_shape() {
+        let json = serde_json::to_value(TakerToMaker::Hello(Version::current())).unwrap();
+
+        assert_eq!(
+            json,
+            serde_json::json!({ "type": "Hello", "payload": "3.0.0" })
+        );
+    }
+
+    #[test]
+    fn hello_of_other_versions_can_be_deserialized() {
+        let json = r#"{"type":"Hello","payload":"1.2.3"}"#;
+
+        let version = match serde_json::from_str(json).unwrap() {
+            TakerToMaker::Hello(version) => version,
+            _ => panic!("expected Hello"),
+        };
+
+        assert_eq!(version, Version(semver::Version::new(1, 2, 3)));
+        assert!(version < Version::current());
+    }
+
+    #[test]
+    fn message_of_unknown_type_is_deserialized_as_unknown() {
+        let json = r#"{"type":"SomethingNew"}"#;
+
+        assert!(matches!(
+            serde_json::from_str(json).unwrap(),
+            MakerToTaker::Unknown
+        ));
+        assert!(matches!(
+            serde_json::from_str(json).unwrap(),
+            TakerToMaker::Unknown
+        ));
+    }
+
+    #[test]
+    fn unknown_message_of_peer_with_newer_minor_version_is_ignored() {
+        let current = Version::current();
+        let newer_minor = Version(semver::Version::new(
+            current.0.major,
+            current.0.minor + 1,
+            0,
+        ));
+        let (mut sender, mut receiver) = codecs();
+
+        let mut buf = BytesMut::new();
+        sender
+            .encode(serde_json::json!({ "type": "SomethingNew" }), &mut buf)
+            .unwrap();
+        let decoded = receiver.decode(&mut buf).unwrap().unwrap();
+
+        assert!(current.is_compatible_with(&newer_minor));
+        assert!(matches!(
+            serde_json::from_value(decoded).unwrap(),
+            MakerToTaker::Unknown
+        ));
+    }
+
+    #[test]
+    fn peers_of_different_major_versions_are_incompatible() {
+        let current = Version::current();
+        let next_major = Version(semver::Version::new(current.0.major + 1, 0, 0));
+        let first_of_same_major = Version(semver::Version::new(current.0.major, 0, 0));
+
+        assert!(!current.is_compatible_with(&next_major));
+        assert!(!next_major.is_compatible_with(&current));
+        assert!(current.is_compatible_with(&first_of_same_major));
+    }
+
+    #[test]
+    fn malformed_message_of_known_type_is_still_rejected() {
+        let json = r#"{"type":"InvalidOrderId","payload":{"not":"an order id"}}"#;
+
+        let result = serde_json::from_str::<MakerToTaker>(json);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn known_messages_round_trip() {
+        let json = serde_json::to_string(&MakerToTaker::Heartbeat).unwrap();
+        assert_eq!(json, r#"{"type":"Heartbeat"}"#);
+
+        assert!(matches!(
+            serde_json::from_str(&json).unwrap(),
+            MakerToTaker::Heartbeat
+        ));
+    }
+
+    #[test]
+    fn oversized_frame_is_rejected_without_reading_it() {
+        let (_, mut receiver) = codecs();
+
+        let announced_length = (MAX_FRAME_LENGTH + 1) as u32;
+        let mut src = BytesMut::from(&announced_length.to_be_bytes()[..]);
+
+        let error = receiver.decode(&mut src).unwrap_err();
+
+        assert!(
+            format!("{error:#}").contains("Failed to decode frame"),
+            "{error:#}"
+        );
+    }
+
+    #[test]
+    fn deeply_nested_message_is_rejected() {
+        let (mut sender, mut receiver) = codecs();
+        let depth = MAX_NESTING_DEPTH + 1;
+        let nested: serde_json::Value =
+            serde_json::from_str(&format!("{}{}", "[".repeat(depth), "]".repeat(depth))).unwrap();
+
+        let mut buf = BytesMut::new();
+        sender.encode(nested, &mut buf).unwrap();
+        let error = receiver.decode(&mut buf).unwrap_err();
+
+        assert!(
+            format!("{error:#}").contains("maximum nesting depth"),
+            "{error:#}"
+        );
+    }
+
+    #[test]
+    fn message_within_limits_roundtrips() {
+        let (mut sender, mut receiver) = codecs();
+        let message = serde_json::json!({ "brackets": "[[[[{{{{", "nested": [[[1]]] });
+
+        let mut buf = BytesMut::new();
+        sender.encode(message.clone(), &mut buf).unwrap();
+        let decoded = receiver.decode(&mut buf).unwrap();
+
+        assert_eq!(decoded, Some(message));
+    }
+
+    #[test]
+    fn large_message_roundtrips_through_compressed_codec() {
+        let (mut sender, mut receiver) = codecs();
+        sender
+            .compression_switch()
+            .enable_if_supported(&Compression::supported());
+        let message = serde_json::json!({ "cets": vec!["adaptor signature"; 10_000] });
+        let uncompressed_len = serde_json::to_vec(&message).unwrap().len();
+
+        let mut buf = BytesMut::new();
+        sender.encode(message.clone(), &mut buf).unwrap();
+        assert!(
+            buf.len() < uncompressed_len / 10,
+            "message was not compressed"
+        );
+        let decoded = receiver.decode(&mut buf).unwrap();
+
+        assert_eq!(decoded, Some(message));
+    }
+
+    #[test]
+    fn message_is_not_compressed_unless_enabled() {
+        let (mut sender, mut receiver) = codecs();
+        let message = serde_json::json!({ "cets": vec!["adaptor signature"; 10_000] });
+        let uncompressed_len = serde_json::to_vec(&message).unwrap().len();
+
+        let mut buf = BytesMut::new();
+        sender.encode(message.clone(), &mut buf).unwrap();
+        assert!(buf.len() > uncompressed_len);
+        let decoded = receiver.decode(&mut buf).unwrap();
+
+        assert_eq!(decoded, Some(message));
+    }
+
+    #[test]
+    fn compression_is_not_enabled_for_unsupported_algorithms() {
+        let switch = CompressionSwitch::default();
+
+        assert!(!switch.enable_if_supported(&[]));
+        assert!(!switch.is_enabled());
+    }
+
+    #[test]
+    fn nesting_depth_ignores_brackets_in_strings() {
+        let json = br#"{"a":"[[[[\"[[[[","b":[1]}"#;
+
+        assert!(ensure_max_nesting_depth(json, 2).is_ok());
+        assert!(ensure_max_nesting_depth(json, 1).is_err());
+    }
+
+    type JsonCodec = EncryptedJsonCodec<serde_json::Value, serde_json::Value>;
+
+    /// Returns the codecs of both ends of an established noise session.
+    fn codecs() -> (JsonCodec, JsonCodec) {
+        let params = "Noise_NN_25519_ChaChaPoly_BLAKE2s";
+        let mut initiator = Builder::new(params.parse().unwrap())
+            .build_initiator()
+            .unwrap();
+        let mut responder = Builder::new(params.parse().unwrap())
+            .build_responder()
+            .unwrap();
+
+        let mut msg = vec![0u8; NOISE_MAX_MSG_LEN as usize];
+        let mut payload = vec![0u8; NOISE_MAX_MSG_LEN as usize];
+        let len = initiator.write_message(&[], &mut msg).unwrap();
+        responder.read_message(&msg[..len], &mut payload).unwrap();
+        let len = responder.write_message(&[], &mut msg).unwrap();
+        initiator.read_message(&msg[..len], &mut payload).unwrap();
+
+        (
+            EncryptedJsonCodec::new(initiator.into_transport_mode().unwrap()),
+            EncryptedJsonCodec::new(responder.into_transport_mode().unwrap()),
+        )
+    }
+}
@@ -78,6 +78,8 @@ export class State {
                 return "Rejected";
             case StateKey.PENDING_OPEN:
                 return "Pending Open";
+            case StateKey.LOCK_SEEN:
+                return "Lock Seen";
             case StateKey.OPEN:
                 return "Open";
             case StateKey.PENDING_COMMIT:
@@ -133,6 +135,7 @@ export class State {
             case StateKey.INCOMING_ROLLOVER_PROPOSAL:
             case StateKey.OUTGOING_ROLLOVER_PROPOSAL:
             case StateKey.PENDING_OPEN:
+            case StateKey.LOCK_SEEN:
             case StateKey.REFUNDED:
             case StateKey.SETUP_FAILED:
             case StateKey.CLOSED:
@@ -149,6 +152,7 @@ export class State {
                 return StateGroupKey.OPENING;
 
             case StateKey.PENDING_OPEN:
+            case StateKey.LOCK_SEEN:
             case StateKey.OPEN:
             case StateKey.PENDING_COMMIT:
             case StateKey.OPEN_COMMITTED:
@@ -191,6 +195,7 @@ const enum StateKey {
     CONTRACT_SETUP = "ContractSetup",
     REJECTED = "Rejected",
     PENDING_OPEN = "PendingOpen",
+    LOCK_SEEN = "LockSeen",
     OPEN = "Open",
     PENDING_CLOSE = "PendingClose",
     PENDING_COMMIT = "PendingCommit",
@@ -9,7 +9,13 @@ use daemon::bdk::bitcoin::Amount;
 use daemon::bdk::FeeRate;
 use daemon::bitmex_price_feed;
 use daemon::db;
+use daemon::maker_cfd;
+use daemon::metrics::Metrics;
+use daemon::model::cfd::OrderId;
 use daemon::model::cfd::Role;
+use daemon::model::Leverage;
+use daemon::model::RoundingStrategy;
+use daemon::model::Usd;
 use daemon::monitor;
 use daemon::oracle;
 use daemon::projection;
@@ -20,13 +26,18 @@ use daemon::MakerActorSystem;
 use daemon::HEARTBEAT_INTERVAL;
 use daemon::N_PAYOUTS;
 use daemon::SETTLEMENT_INTERVAL;
+use daemon::SETTLEMENT_PROPOSAL_VALIDITY;
+use daemon::SETUP_TIMEOUT;
+use daemon::TCP_KEEPALIVE_INTERVAL;
 use rocket::fairing::AdHoc;
 use shared_bin::logger;
 use shared_bin::logger::LevelFilter;
+use shared_bin::websocket;
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::str::FromStr;
 use tokio_tasks::Tasks;
+use uuid::Uuid;
 use xtra::Actor;
 use xtras::supervisor;
 
@@ -54,10 +65,60 @@ struct Opts {
     #[clap(short, long, default_value = "Debug")]
     log_level: LevelFilter,
 
+    /// How fractional satoshis of payouts and funding fees are rounded in our orders, one of
+    /// fees-up-payouts-down, bankers.
+    #[clap(long, default_value = "fees-up-payouts-down")]
+    rounding: RoundingStrategy,
+
+    /// Highest leverage takers are allowed to take our orders with.
+    #[clap(long, default_value = "2")]
+    max_leverage: u8,
+
+    /// Number of blocks the commit transaction has to be confirmed before a CET can be published.
+    #[clap(long, default_value = "12")]
+    cet_timelock: u32,
+
+    /// If set, takes up to this quantity are accepted without waiting for the operator, e.g. 1000.
+    #[clap(long)]
+    auto_accept_max_quantity: Option<Usd>,
+
+    /// Highest leverage of a take that is accepted automatically.
+    #[clap(long, default_value = "2")]
+    auto_accept_max_leverage: u8,
+
+    /// Upper bound for the total quantity of all open CFDs up to which takes are accepted
+    /// automatically. Required if auto-accept is enabled.
+    #[clap(long)]
+    auto_accept_max_exposure: Option<Usd>,
+
+    /// Reject takes whose lock transaction inputs are not confirmed yet.
+    #[clap(long)]
+    require_confirmed_inputs: bool,
+
     #[clap(subcommand)]
     network: Network,
 }
 
+impl Opts {
+    fn auto_accept_takes(&self) -> Result<Option<maker_cfd::AutoAcceptTakes>> {
+        let max_quantity = match self.auto_accept_max_quantity {
+            Some(max_quantity) => max_quantity,
+            None => return Ok(None),
+        };
+
+        let max_exposure = self
+            .auto_accept_max_exposure
+            .context("--auto-accept-max-exposure is required to auto-accept takes")?;
+        let max_leverage = Leverage::new(self.auto_accept_max_leverage)?;
+
+        Ok(Some(maker_cfd::AutoAcceptTakes {
+            max_quantity,
+            max_leverage,
+            max_exposure,
+        }))
+    }
+}
+
 #[derive(Parser)]
 enum Network {
     /// Run on mainnet.
@@ -67,7 +128,7 @@ enum Network {
         electrum: String,
 
         #[clap(subcommand)]
-        withdraw: Option<Withdraw>,
+        command: Option<Command>,
     },
     /// Run on testnet.
     Testnet {
@@ -76,7 +137,7 @@ enum Network {
         electrum: String,
 
         #[clap(subcommand)]
-        withdraw: Option<Withdraw>,
+        command: Option<Command>,
     },
     /// Run on signet
     Signet {
@@ -85,12 +146,21 @@ enum Network {
         electrum: String,
 
         #[clap(subcommand)]
-        withdraw: Option<Withdraw>,
+        command: Option<Command>,
+    },
+    /// Run on regtest, e.g. against a local bitcoind and electrs for integration testing.
+    Regtest {
+        /// URL to the electrum backend to use for the wallet.
+        #[clap(long)]
+        electrum: String,
+
+        #[clap(subcommand)]
+        command: Option<Command>,
     },
 }
 
 #[derive(Subcommand)]
-enum Withdraw {
+enum Command {
     Withdraw {
         /// Optionally specify the amount of Bitcoin to be withdrawn. If not specified the wallet
         /// will be drained. Amount is to be specified with denomination, e.g. "0.1 BTC"
@@ -104,6 +174,11 @@ enum Withdraw {
         #[clap(long)]
         address: bdk::bitcoin::Address,
     },
+    /// Print the event log of a CFD as JSON, e.g. to attach it to a support request.
+    ExportCfd {
+        /// The id of the CFD's order.
+        order_id: Uuid,
+    },
 }
 
 impl Network {
@@ -112,6 +187,7 @@ impl Network {
             Network::Mainnet { electrum, .. } => electrum,
             Network::Testnet { electrum, .. } => electrum,
             Network::Signet { electrum, .. } => electrum,
+            Network::Regtest { electrum, .. } => electrum,
         }
     }
 
@@ -120,6 +196,7 @@ impl Network {
             Network::Mainnet { .. } => bitcoin::Network::Bitcoin,
             Network::Testnet { .. } => bitcoin::Network::Testnet,
             Network::Signet { .. } => bitcoin::Network::Signet,
+            Network::Regtest { .. } => bitcoin::Network::Regtest,
         }
     }
 
@@ -128,14 +205,16 @@ impl Network {
             Network::Mainnet { .. } => base.join("mainnet"),
             Network::Testnet { .. } => base.join("testnet"),
             Network::Signet { .. } => base.join("signet"),
+            Network::Regtest { .. } => base.join("regtest"),
         }
     }
 
-    fn withdraw(&self) -> &Option<Withdraw> {
+    fn command(&self) -> &Option<Command> {
         match self {
-            Network::Mainnet { withdraw, .. } => withdraw,
-            Network::Testnet { withdraw, .. } => withdraw,
-            Network::Signet { withdraw, .. } => withdraw,
+            Network::Mainnet { command, .. } => command,
+            Network::Testnet { command, .. } => command,
+            Network::Signet { command, .. } => command,
+            Network::Regtest { command, .. } => command,
         }
     }
 }
@@ -163,6 +242,18 @@ async fn main() -> Result<()> {
         tokio::fs::create_dir_all(&data_dir).await?;
     }
 
+    if let Some(Command::ExportCfd { order_id }) = opts.network.command() {
+        let db = db::connect_read_only(data_dir.join("maker.sqlite")).await?;
+        let mut conn = db.acquire().await?;
+
+        let events = db::export_cfd_events(OrderId::from(*order_id), &mut conn).await?;
+        println!("{events:#}");
+
+        return Ok(());
+    }
+
+    let auto_accept_takes = opts.auto_accept_takes()?;
+
     let seed = RandomSeed::initialize(&data_dir.join("maker_seed")).await?;
 
     let bitcoin_network = opts.network.bitcoin_network();
@@ -175,11 +266,11 @@ async fn main() -> Result<()> {
     let (wallet, wallet_fut) = wallet.create(None).run();
     tasks.add(wallet_fut);
 
-    if let Some(Withdraw::Withdraw {
+    if let Some(Command::Withdraw {
         amount,
         address,
         fee,
-    }) = opts.network.withdraw()
+    }) = opts.network.command()
     {
         wallet
             .send(wallet::Withdraw {
@@ -225,7 +316,7 @@ async fn main() -> Result<()> {
         db.clone(),
         wallet.clone(),
         oracle,
-        |channel| oracle::Actor::new(db.clone(), channel, SETTLEMENT_INTERVAL),
+        |channel| oracle::Actor::new(db.clone(), channel, SETTLEMENT_INTERVAL, oracle),
         {
             |channel| {
                 let electrum = opts.network.electrum().to_string();
@@ -234,10 +325,19 @@ async fn main() -> Result<()> {
         },
         SETTLEMENT_INTERVAL,
         N_PAYOUTS,
+        opts.rounding,
+        Leverage::new(opts.max_leverage)?,
+        opts.cet_timelock,
+        auto_accept_takes,
+        opts.require_confirmed_inputs,
+        SETTLEMENT_PROPOSAL_VALIDITY,
+        SETUP_TIMEOUT,
         projection_actor.clone(),
         identity_sk,
         HEARTBEAT_INTERVAL,
+        TCP_KEEPALIVE_INTERVAL,
         p2p_socket,
+        Metrics::default(),
     )?;
 
     let (supervisor, price_feed) = supervisor::Actor::new(
@@ -248,10 +348,17 @@ async fn main() -> Result<()> {
     let (_supervisor_address, task) = supervisor.create(None).run();
     tasks.add(task);
 
-    let (proj_actor, projection_feeds) =
-        projection::Actor::new(db.clone(), Role::Maker, bitcoin_network, &price_feed);
+    let (proj_actor, projection_feeds) = projection::Actor::new(
+        db.clone(),
+        Role::Maker,
+        bitcoin_network,
+        &price_feed,
+        projection::DEBOUNCE_INTERVAL,
+    );
     tasks.add(projection_context.run(proj_actor));
 
+    tasks.add(maker.watchdog());
+
     rocket::custom(figment)
         .manage(projection_feeds)
         .manage(wallet_feed_receiver)
@@ -259,15 +366,31 @@ async fn main() -> Result<()> {
         .manage(auth_username)
         .manage(auth_password)
         .manage(bitcoin_network)
+        .manage(price_feed)
+        .manage(projection_actor)
         .mount(
             "/api",
             rocket::routes![
                 routes::maker_feed,
+                websocket::feeds,
                 routes::post_sell_order,
+                routes::put_order_price,
                 routes::post_cfd_action,
+                routes::post_cfd_resync,
                 routes::get_health_check,
+                routes::get_health,
+                routes::get_closing_fee_estimate,
+                routes::get_commit_tx_fee,
+                routes::get_utxos,
+                routes::post_new_address,
+                routes::get_metrics,
                 routes::post_withdraw_request,
                 routes::get_cfds,
+                routes::get_cfds_page,
+                routes::put_cfd_label,
+                routes::delete_cfd_label,
+                routes::put_cfd_archive,
+                routes::delete_cfd_archive,
                 routes::get_takers,
             ],
         )
@@ -292,3 +415,39 @@ async fn main() -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn regtest_maps_to_bitcoin_regtest() {
+        let opts = Opts::parse_from(["maker", "regtest", "--electrum", "tcp://localhost:60401"]);
+
+        assert_eq!(opts.network.bitcoin_network(), bitcoin::Network::Regtest);
+        assert_eq!(opts.network.electrum(), "tcp://localhost:60401");
+        assert_eq!(
+            opts.network.data_dir(PathBuf::from("/data")),
+            PathBuf::from("/data/regtest")
+        );
+        assert!(opts.network.command().is_none());
+    }
+
+    #[test]
+    fn export_cfd_takes_order_id() {
+        let order_id = "3d3d8c3e-8c86-4e3b-9f3c-8f6a1a1d2b7e";
+        let opts = Opts::parse_from([
+            "maker",
+            "regtest",
+            "--electrum",
+            "tcp://localhost:60401",
+            "export-cfd",
+            order_id,
+        ]);
+
+        assert!(matches!(
+            opts.network.command(),
+            Some(Command::ExportCfd { order_id: id }) if id.to_string() == order_id
+        ));
+    }
+}
@@ -1,17 +1,27 @@
 use anyhow::Result;
 use daemon::bdk;
 use daemon::bdk::bitcoin::Network;
+use daemon::bitmex_price_feed;
+use daemon::db;
+use daemon::health;
+use daemon::model::cfd::validate_n_payouts;
+use daemon::model::cfd::ClosingFeeEstimate;
+use daemon::model::cfd::CommitTxFee;
 use daemon::model::cfd::OrderId;
+use daemon::model::cfd::TxUnavailable;
 use daemon::model::FundingRate;
 use daemon::model::Identity;
 use daemon::model::OpeningFee;
 use daemon::model::Price;
+use daemon::model::Timestamp;
 use daemon::model::TxFeeRate;
 use daemon::model::Usd;
 use daemon::model::WalletInfo;
 use daemon::oracle;
+use daemon::projection;
 use daemon::projection::Cfd;
 use daemon::projection::CfdAction;
+use daemon::projection::CfdStateCategory;
 use daemon::projection::Feeds;
 use daemon::wallet;
 use daemon::MakerActorSystem;
@@ -27,6 +37,7 @@ use rocket_basicauth::Authenticated;
 use rust_embed::RustEmbed;
 use rust_embed_rocket::EmbeddedFileExt;
 use serde::Deserialize;
+use shared_bin::AmountUnit;
 use shared_bin::ToSseEvent;
 use std::borrow::Cow;
 use std::path::PathBuf;
@@ -37,31 +48,46 @@ use uuid::Uuid;
 pub type Maker = MakerActorSystem<oracle::Actor, wallet::Actor>;
 
 #[allow(clippy::too_many_arguments)]
-#[rocket::get("/feed")]
+#[rocket::get("/feed?<unit>&<include_archived>")]
 pub async fn maker_feed(
+    unit: Option<AmountUnit>,
+    include_archived: Option<bool>,
     rx: &State<Feeds>,
     rx_wallet: &State<watch::Receiver<Option<WalletInfo>>>,
     _auth: Authenticated,
 ) -> EventStream![] {
+    let unit = unit.unwrap_or_default();
+    let include_archived = include_archived.unwrap_or_default();
     let rx = rx.inner();
     let mut rx_cfds = rx.cfds.clone();
     let mut rx_order = rx.order.clone();
     let mut rx_wallet = rx_wallet.inner().clone();
     let mut rx_quote = rx.quote.clone();
+    let mut rx_proposals = rx.proposals.clone();
+    let mut rx_exposure = rx
+        .exposure
+        .clone()
+        .expect("maker projection to publish its exposure");
     let mut rx_connected_takers = rx.connected_takers.clone();
 
     EventStream! {
         let wallet_info = rx_wallet.borrow().clone();
-        yield wallet_info.to_sse_event();
+        yield wallet_info.to_sse_event_in(unit);
 
         let order = rx_order.borrow().clone();
-        yield order.to_sse_event();
+        yield order.to_sse_event_in(unit);
 
         let quote = rx_quote.borrow().clone();
         yield quote.to_sse_event();
 
-        let cfds = rx_cfds.borrow().clone();
-        yield cfds.to_sse_event();
+        let cfds = projection::filter_archived(rx_cfds.borrow().clone(), include_archived);
+        yield cfds.to_sse_event_in(unit);
+
+        let proposals = rx_proposals.borrow().clone();
+        yield proposals.to_sse_event();
+
+        let exposure = *rx_exposure.borrow();
+        yield exposure.to_sse_event_in(unit);
 
         let takers = rx_connected_takers.borrow().clone();
         yield takers.to_sse_event();
@@ -70,29 +96,58 @@ pub async fn maker_feed(
             select! {
                 Ok(()) = rx_wallet.changed() => {
                     let wallet_info = rx_wallet.borrow().clone();
-                    yield wallet_info.to_sse_event();
+                    yield wallet_info.to_sse_event_in(unit);
                 },
                 Ok(()) = rx_order.changed() => {
                     let order = rx_order.borrow().clone();
-                    yield order.to_sse_event();
+                    yield order.to_sse_event_in(unit);
                 }
                 Ok(()) = rx_connected_takers.changed() => {
                     let takers = rx_connected_takers.borrow().clone();
                     yield takers.to_sse_event();
                 }
                 Ok(()) = rx_cfds.changed() => {
-                    let cfds = rx_cfds.borrow().clone();
-                    yield cfds.to_sse_event();
+                    let cfds = projection::filter_archived(rx_cfds.borrow().clone(), include_archived);
+                    yield cfds.to_sse_event_in(unit);
                 }
                 Ok(()) = rx_quote.changed() => {
                     let quote = rx_quote.borrow().clone();
                     yield quote.to_sse_event();
                 }
+                Ok(()) = rx_proposals.changed() => {
+                    let proposals = rx_proposals.borrow().clone();
+                    yield proposals.to_sse_event();
+                }
+                Ok(()) = rx_exposure.changed() => {
+                    let exposure = *rx_exposure.borrow();
+                    yield exposure.to_sse_event_in(unit);
+                }
             }
         }
     }
 }
 
+/// The maker PUTs this to re-price the current CfdOrder
+#[derive(Debug, Clone, Deserialize)]
+pub struct UpdateOrderPriceRequest {
+    pub price: Price,
+}
+
+#[rocket::put("/order/price", data = "<request>")]
+pub async fn put_order_price(
+    request: Json<UpdateOrderPriceRequest>,
+    maker: &State<Maker>,
+    _auth: Authenticated,
+) -> Result<(), HttpApiProblem> {
+    maker.update_order_price(request.price).await.map_err(|e| {
+        HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+            .title("Updating offer price failed")
+            .detail(format!("{e:#}"))
+    })?;
+
+    Ok(())
+}
+
 /// The maker POSTs this to create a new CfdOrder
 #[derive(Debug, Clone, Deserialize)]
 pub struct CfdNewOrderRequest {
@@ -106,6 +161,8 @@ pub struct CfdNewOrderRequest {
     // TODO: This is not inline with other parts of the API! We should not expose internal types
     // here. We have to specify sats for here because of that.
     pub opening_fee: Option<OpeningFee>,
+    /// Number of payouts on the payout curve, defaults to the one the maker was started with.
+    pub n_payouts: Option<usize>,
 }
 
 #[rocket::post("/order/sell", data = "<order>")]
@@ -114,6 +171,14 @@ pub async fn post_sell_order(
     maker: &State<Maker>,
     _auth: Authenticated,
 ) -> Result<(), HttpApiProblem> {
+    if let Some(n_payouts) = order.n_payouts {
+        validate_n_payouts(n_payouts).map_err(|e| {
+            HttpApiProblem::new(StatusCode::BAD_REQUEST)
+                .title("Invalid number of payouts")
+                .detail(format!("{e:#}"))
+        })?;
+    }
+
     maker
         .new_order(
             order.price,
@@ -122,6 +187,7 @@ pub async fn post_sell_order(
             order.tx_fee_rate,
             order.funding_rate,
             order.opening_fee,
+            order.n_payouts,
         )
         .await
         .map_err(|e| {
@@ -133,7 +199,9 @@ pub async fn post_sell_order(
     Ok(())
 }
 
-#[rocket::post("/cfd/<id>/<action>")]
+/// Ranked below the routes for specific actions like `/cfd/<id>/resync`, which would collide
+/// otherwise.
+#[rocket::post("/cfd/<id>/<action>", rank = 2)]
 pub async fn post_cfd_action(
     id: Uuid,
     action: String,
@@ -158,12 +226,27 @@ pub async fn post_cfd_action(
             tracing::error!(msg);
             return Err(HttpApiProblem::new(StatusCode::BAD_REQUEST).detail(msg));
         }
+        CfdAction::Rollover => {
+            let msg = "Rollover can only be triggered by taker";
+            tracing::error!(msg);
+            return Err(HttpApiProblem::new(StatusCode::BAD_REQUEST).detail(msg));
+        }
+        CfdAction::CancelOrder => {
+            let msg = "Order requests can only be cancelled by taker";
+            tracing::error!(msg);
+            return Err(HttpApiProblem::new(StatusCode::BAD_REQUEST).detail(msg));
+        }
     };
 
     result.map_err(|e| {
         tracing::warn!(order_id=%id, %action, "Processing action failed: {e:#}");
 
-        HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+        let status = match e.downcast_ref::<TxUnavailable>() {
+            Some(_) => StatusCode::CONFLICT,
+            None => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        HttpApiProblem::new(status)
             .title(action.to_string() + " failed")
             .detail(format!("{e:#}"))
     })?;
@@ -171,9 +254,120 @@ pub async fn post_cfd_action(
     Ok(())
 }
 
+/// Re-scans the blockchain and re-fetches the oracle attestation for a CFD that appears stuck.
+///
+/// The resulting state changes are published on the CFD feed as they happen.
+#[rocket::post("/cfd/<id>/resync")]
+pub async fn post_cfd_resync(
+    id: Uuid,
+    maker: &State<Maker>,
+    _auth: Authenticated,
+) -> Result<Status, HttpApiProblem> {
+    maker.resync(OrderId::from(id)).await.map_err(|e| {
+        let status = match e.downcast_ref::<db::CfdNotFound>() {
+            Some(_) => StatusCode::NOT_FOUND,
+            None => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        HttpApiProblem::new(status)
+            .title("Resync failed")
+            .detail(format!("{e:#}"))
+    })?;
+
+    Ok(Status::Accepted)
+}
+
+/// Estimates the on-chain fees of closing the CFD collaboratively vs. force-closing it.
+#[rocket::get("/cfd/<id>/closing-fees")]
+pub async fn get_closing_fee_estimate(
+    id: Uuid,
+    maker: &State<Maker>,
+    _auth: Authenticated,
+) -> Result<Json<ClosingFeeEstimate>, HttpApiProblem> {
+    let estimate = maker
+        .estimate_closing_fees(OrderId::from(id))
+        .await
+        .map_err(|e| {
+            HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+                .title("Estimating closing fees failed")
+                .detail(format!("{e:#}"))
+        })?;
+
+    Ok(Json(estimate))
+}
+
+/// Reports the fee and size of the commit transaction, so users know the cost of force-closing.
+#[rocket::get("/cfd/<id>/commit-fee")]
+pub async fn get_commit_tx_fee(
+    id: Uuid,
+    maker: &State<Maker>,
+    _auth: Authenticated,
+) -> Result<Json<CommitTxFee>, HttpApiProblem> {
+    let commit_tx_fee = maker.commit_tx_fee(OrderId::from(id)).await.map_err(|e| {
+        HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+            .title("Computing commit transaction fee failed")
+            .detail(format!("{e:#}"))
+    })?;
+
+    Ok(Json(commit_tx_fee))
+}
+
+/// Lists the UTXOs of the wallet and the orders they are reserved for, for debugging stuck
+/// contract setups.
+#[rocket::get("/debug/utxos")]
+pub async fn get_utxos(
+    maker: &State<Maker>,
+    _auth: Authenticated,
+) -> Result<Json<Vec<wallet::Utxo>>, HttpApiProblem> {
+    let utxos = maker.list_utxos().await.map_err(|e| {
+        HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+            .title("Listing UTXOs failed")
+            .detail(format!("{e:#}"))
+    })?;
+
+    Ok(Json(utxos))
+}
+
+/// Derives a fresh receive address from the wallet.
+#[rocket::post("/wallet/address")]
+pub async fn post_new_address(
+    maker: &State<Maker>,
+    _auth: Authenticated,
+) -> Result<Json<bdk::bitcoin::Address>, HttpApiProblem> {
+    let address = maker.new_address().await.map_err(|e| {
+        HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+            .title("Deriving new address failed")
+            .detail(format!("{e:#}"))
+    })?;
+
+    Ok(Json(address))
+}
+
+/// Counters of CFD lifecycle events in the Prometheus text format.
+#[rocket::get("/metrics")]
+pub fn get_metrics(maker: &State<Maker>, _auth: Authenticated) -> String {
+    maker.metrics.render()
+}
+
 #[rocket::get("/alive")]
 pub fn get_health_check() {}
 
+#[rocket::get("/health")]
+pub async fn get_health(
+    maker: &State<Maker>,
+    price_feed: &State<xtra::Address<bitmex_price_feed::Actor>>,
+) -> (Status, Json<health::Report>) {
+    let report = maker.health(price_feed.inner()).await;
+
+    let status = if report.is_healthy() {
+        Status::Ok
+    } else {
+        Status::ServiceUnavailable
+    };
+
+    (status, Json(report))
+}
+
 #[derive(RustEmbed)]
 #[folder = "../maker-frontend/dist/maker"]
 struct Asset;
@@ -231,18 +425,90 @@ pub async fn post_withdraw_request(
     Ok(url)
 }
 
-#[rocket::get("/cfds")]
+/// Lists all CFDs, optionally only those whose state falls into the given category (`open`,
+/// `closed` or `failed`).
+///
+/// Archived CFDs are only included if `include_archived` is set.
+#[rocket::get("/cfds?<state>&<include_archived>")]
 pub async fn get_cfds<'r>(
+    state: Option<String>,
+    include_archived: Option<bool>,
     rx: &State<Feeds>,
     _auth: Authenticated,
 ) -> Result<Json<Vec<Cfd>>, HttpApiProblem> {
+    let category = state
+        .map(|state| {
+            state.parse::<CfdStateCategory>().map_err(|_| {
+                HttpApiProblem::new(StatusCode::BAD_REQUEST)
+                    .detail(format!("Invalid state filter: {state}"))
+            })
+        })
+        .transpose()?;
+
     let rx = rx.inner();
     let rx_cfds = rx.cfds.clone();
-    let cfds = rx_cfds.borrow().clone();
+    let cfds = projection::filter_archived(
+        rx_cfds.borrow().clone(),
+        include_archived.unwrap_or_default(),
+    );
+
+    let cfds = match category {
+        Some(category) => projection::filter_by_state(cfds, category),
+        None => cfds,
+    };
 
     Ok(Json(cfds))
 }
 
+/// Default number of CFDs returned per page if the client does not specify a `limit`.
+const DEFAULT_PAGE_LIMIT: u32 = 20;
+/// Upper bound for the number of CFDs returned per page.
+const MAX_PAGE_LIMIT: u32 = 100;
+
+/// Loads CFDs from the database instead of the live feed, newest first.
+///
+/// Use `next.created_at` and `next.id` of the returned page as `before` and `before_id` to fetch
+/// the following page.
+#[rocket::get("/cfds/page?<limit>&<before>&<before_id>")]
+pub async fn get_cfds_page(
+    limit: Option<u32>,
+    before: Option<i64>,
+    before_id: Option<i64>,
+    projection: &State<xtra::Address<projection::Actor>>,
+    _auth: Authenticated,
+) -> Result<Json<projection::CfdPage>, HttpApiProblem> {
+    let limit = limit.unwrap_or(DEFAULT_PAGE_LIMIT).min(MAX_PAGE_LIMIT);
+
+    let before = match (before, before_id) {
+        (Some(created_at), Some(id)) => Some(db::CfdCursor {
+            created_at: Timestamp::new(created_at),
+            id,
+        }),
+        (None, None) => None,
+        _ => {
+            return Err(HttpApiProblem::new(StatusCode::BAD_REQUEST)
+                .title("Invalid cursor")
+                .detail("`before` and `before_id` have to be given together"))
+        }
+    };
+
+    let page = projection
+        .send(projection::LoadCfds { before, limit })
+        .await
+        .map_err(|e| {
+            HttpApiProblem::new(StatusCode::SERVICE_UNAVAILABLE)
+                .title("Projection actor unavailable")
+                .detail(e.to_string())
+        })?
+        .map_err(|e| {
+            HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+                .title("Loading CFDs failed")
+                .detail(format!("{e:#}"))
+        })?;
+
+    Ok(Json(page))
+}
+
 #[rocket::get("/takers")]
 pub async fn get_takers<'r>(
     rx: &State<Feeds>,
@@ -254,3 +520,96 @@ pub async fn get_takers<'r>(
 
     Ok(Json(takers))
 }
+
+/// The user PUTs this to label a CFD
+#[derive(Debug, Clone, Deserialize)]
+pub struct CfdLabelRequest {
+    pub label: String,
+}
+
+#[rocket::put("/cfd/<id>/label", data = "<request>")]
+pub async fn put_cfd_label(
+    id: Uuid,
+    request: Json<CfdLabelRequest>,
+    projection: &State<xtra::Address<projection::Actor>>,
+    _auth: Authenticated,
+) -> Result<(), HttpApiProblem> {
+    set_cfd_label(
+        OrderId::from(id),
+        Some(request.into_inner().label),
+        projection,
+    )
+    .await
+}
+
+#[rocket::delete("/cfd/<id>/label")]
+pub async fn delete_cfd_label(
+    id: Uuid,
+    projection: &State<xtra::Address<projection::Actor>>,
+    _auth: Authenticated,
+) -> Result<(), HttpApiProblem> {
+    set_cfd_label(OrderId::from(id), None, projection).await
+}
+
+async fn set_cfd_label(
+    order_id: OrderId,
+    label: Option<String>,
+    projection: &xtra::Address<projection::Actor>,
+) -> Result<(), HttpApiProblem> {
+    projection
+        .send(projection::SetLabel { order_id, label })
+        .await
+        .map_err(|e| {
+            HttpApiProblem::new(StatusCode::SERVICE_UNAVAILABLE)
+                .title("Projection actor unavailable")
+                .detail(e.to_string())
+        })?
+        .map_err(|e| {
+            HttpApiProblem::new(StatusCode::BAD_REQUEST)
+                .title("Labelling CFD failed")
+                .detail(format!("{e:#}"))
+        })?;
+
+    Ok(())
+}
+
+/// Hide a CFD from the feed, it can still be requested with `include_archived=true`.
+#[rocket::put("/cfd/<id>/archive")]
+pub async fn put_cfd_archive(
+    id: Uuid,
+    projection: &State<xtra::Address<projection::Actor>>,
+    _auth: Authenticated,
+) -> Result<(), HttpApiProblem> {
+    set_cfd_archived(OrderId::from(id), true, projection).await
+}
+
+#[rocket::delete("/cfd/<id>/archive")]
+pub async fn delete_cfd_archive(
+    id: Uuid,
+    projection: &State<xtra::Address<projection::Actor>>,
+    _auth: Authenticated,
+) -> Result<(), HttpApiProblem> {
+    set_cfd_archived(OrderId::from(id), false, projection).await
+}
+
+async fn set_cfd_archived(
+    order_id: OrderId,
+    archived: bool,
+    projection: &xtra::Address<projection::Actor>,
+) -> Result<(), HttpApiProblem> {
+    projection
+        .send(projection::SetArchived { order_id, archived })
+        .await
+        .map_err(|e| {
+            HttpApiProblem::new(StatusCode::SERVICE_UNAVAILABLE)
+                .title("Projection actor unavailable")
+                .detail(e.to_string())
+        })?
+        .map_err(|e| {
+            HttpApiProblem::new(StatusCode::BAD_REQUEST)
+                .title("Archiving CFD failed")
+                .detail(format!("{e:#}"))
+        })?;
+
+    Ok(())
+}
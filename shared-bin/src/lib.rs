@@ -1,4 +1,5 @@
 pub mod logger;
 mod to_sse_event;
+pub mod websocket;
 
 pub use crate::to_sse_event::*;
@@ -1,55 +1,165 @@
 use crate::ConnectionCloseReason::MakerVersionOutdated;
+use crate::ConnectionCloseReason::ProtocolViolation;
+use crate::ConnectionCloseReason::Requested;
 use crate::ConnectionCloseReason::TakerVersionOutdated;
 use daemon::bdk::bitcoin::Amount;
+use daemon::bdk::bitcoin::Denomination;
 use daemon::connection;
 use daemon::model;
 use daemon::model::Identity;
 use daemon::model::Timestamp;
 use daemon::projection::Cfd;
 use daemon::projection::CfdOrder;
+use daemon::projection::CfdStateCategory;
+use daemon::projection::Denominated;
+use daemon::projection::Exposure;
+use daemon::projection::PendingProposal;
 use daemon::projection::Quote;
 use rocket::response::stream::Event;
+use rocket::FromFormField;
 use serde::Serialize;
+use serde_json::Value;
 
 pub trait ToSseEvent {
-    fn to_sse_event(&self) -> Event;
+    /// The name of the feed this is published on.
+    fn feed(&self) -> &'static str;
+
+    /// Serialize for the feed with all bitcoin amounts in the given unit.
+    ///
+    /// Types without amounts serialize the same in every unit.
+    fn to_feed_json(&self, unit: AmountUnit) -> Value;
+
+    fn to_sse_event(&self) -> Event {
+        self.to_sse_event_in(AmountUnit::default())
+    }
+
+    /// Render the event with all bitcoin amounts in the given unit.
+    fn to_sse_event_in(&self, unit: AmountUnit) -> Event {
+        Event::json(&self.to_feed_json(unit)).event(self.feed())
+    }
+}
+
+/// The unit bitcoin amounts are rendered in on the feed.
+#[derive(Debug, Clone, Copy, PartialEq, FromFormField)]
+pub enum AmountUnit {
+    /// Bitcoin with 8 decimals, the default.
+    #[field(value = "btc")]
+    Btc,
+    #[field(value = "sat")]
+    Sat,
+}
+
+impl Default for AmountUnit {
+    fn default() -> Self {
+        AmountUnit::Btc
+    }
+}
+
+impl From<AmountUnit> for Denomination {
+    fn from(unit: AmountUnit) -> Self {
+        match unit {
+            AmountUnit::Btc => Denomination::Bitcoin,
+            AmountUnit::Sat => Denomination::Satoshi,
+        }
+    }
+}
+
+/// Serialize `value` for the feed.
+fn to_json<T>(value: &T) -> Value
+where
+    T: Serialize,
+{
+    serde_json::to_value(value).expect("feed types to serialize to JSON")
+}
+
+/// A CFD as published on the feed, annotated with the category of its state.
+#[derive(Debug, Clone, Serialize)]
+struct CfdWithStateCategory {
+    #[serde(flatten)]
+    cfd: Cfd,
+    state_category: CfdStateCategory,
 }
 
 impl ToSseEvent for Vec<Cfd> {
-    fn to_sse_event(&self) -> Event {
-        Event::json(&self).event("cfds")
+    fn feed(&self) -> &'static str {
+        "cfds"
+    }
+
+    fn to_feed_json(&self, unit: AmountUnit) -> Value {
+        let cfds = self
+            .iter()
+            .map(|cfd| CfdWithStateCategory {
+                cfd: cfd.clone().in_denomination(unit.into()),
+                state_category: cfd.state.category(),
+            })
+            .collect::<Vec<_>>();
+
+        to_json(&cfds)
     }
 }
 
 impl ToSseEvent for Vec<Identity> {
-    fn to_sse_event(&self) -> Event {
-        Event::json(&self).event("takers")
+    fn feed(&self) -> &'static str {
+        "takers"
+    }
+
+    fn to_feed_json(&self, _unit: AmountUnit) -> Value {
+        to_json(self)
+    }
+}
+
+impl ToSseEvent for Vec<PendingProposal> {
+    fn feed(&self) -> &'static str {
+        "proposals"
+    }
+
+    fn to_feed_json(&self, _unit: AmountUnit) -> Value {
+        to_json(self)
+    }
+}
+
+impl ToSseEvent for Exposure {
+    fn feed(&self) -> &'static str {
+        "exposure"
+    }
+
+    fn to_feed_json(&self, unit: AmountUnit) -> Value {
+        to_json(&self.in_denomination(unit.into()))
     }
 }
 
 impl ToSseEvent for Option<CfdOrder> {
-    fn to_sse_event(&self) -> Event {
-        Event::json(&self).event("order")
+    fn feed(&self) -> &'static str {
+        "order"
+    }
+
+    fn to_feed_json(&self, unit: AmountUnit) -> Value {
+        let order = self.clone().map(|order| order.in_denomination(unit.into()));
+
+        to_json(&order)
     }
 }
 
 #[derive(Debug, Clone, Serialize)]
 pub struct WalletInfo {
-    #[serde(with = "daemon::bdk::bitcoin::util::amount::serde::as_btc")]
-    balance: Amount,
+    balance: Denominated<Amount>,
     address: String,
     last_updated_at: Timestamp,
 }
 
 impl ToSseEvent for Option<model::WalletInfo> {
-    fn to_sse_event(&self) -> Event {
+    fn feed(&self) -> &'static str {
+        "wallet"
+    }
+
+    fn to_feed_json(&self, unit: AmountUnit) -> Value {
         let wallet_info = self.as_ref().map(|wallet_info| WalletInfo {
-            balance: wallet_info.balance,
+            balance: Denominated::new(wallet_info.balance).in_denomination(unit.into()),
             address: wallet_info.address.to_string(),
             last_updated_at: wallet_info.last_updated_at,
         });
 
-        Event::json(&wallet_info).event("wallet")
+        to_json(&wallet_info)
     }
 }
 
@@ -63,10 +173,16 @@ pub struct ConnectionStatus {
 pub enum ConnectionCloseReason {
     MakerVersionOutdated,
     TakerVersionOutdated,
+    Requested,
+    ProtocolViolation,
 }
 
 impl ToSseEvent for connection::ConnectionStatus {
-    fn to_sse_event(&self) -> Event {
+    fn feed(&self) -> &'static str {
+        "maker_status"
+    }
+
+    fn to_feed_json(&self, _unit: AmountUnit) -> Value {
         let connected = match self {
             connection::ConnectionStatus::Online => ConnectionStatus {
                 online: true,
@@ -85,16 +201,45 @@ impl ToSseEvent for connection::ConnectionStatus {
                             TakerVersionOutdated
                         }
                     }
+                    connection::ConnectionCloseReason::Requested => Requested,
+                    connection::ConnectionCloseReason::ProtocolViolation => ProtocolViolation,
                 }),
             },
         };
 
-        Event::json(&connected).event("maker_status")
+        to_json(&connected)
     }
 }
 
 impl ToSseEvent for Option<Quote> {
-    fn to_sse_event(&self) -> Event {
-        Event::json(self).event("quote")
+    fn feed(&self) -> &'static str {
+        "quote"
+    }
+
+    fn to_feed_json(&self, _unit: AmountUnit) -> Value {
+        to_json(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use daemon::model::Usd;
+    use serde_json::json;
+
+    #[test]
+    fn renders_amounts_in_btc_and_sats() {
+        let exposure = Exposure {
+            total_quantity_usd: Usd::new(1_000.into()),
+            total_margin: Denominated::new(Amount::from_sat(123_456_789)),
+            net_quantity_usd: Usd::new(1_000.into()),
+        };
+
+        let btc = exposure.to_feed_json(AmountUnit::Btc);
+        let sat = exposure.to_feed_json(AmountUnit::Sat);
+
+        assert_eq!(btc["total_margin"], json!(1.23456789));
+        assert_eq!(sat["total_margin"], json!(123_456_789));
+        assert_eq!(sat["total_quantity_usd"], json!("1000.00"));
     }
 }
@@ -0,0 +1,309 @@
+//! A websocket alternative to the SSE feeds.
+//!
+//! Some clients prefer a single websocket multiplexing all feeds over one SSE stream per feed.
+
+use crate::AmountUnit;
+use crate::ToSseEvent;
+use anyhow::Context;
+use anyhow::Result;
+use daemon::projection;
+use daemon::projection::Exposure;
+use daemon::projection::Feeds;
+use futures::future;
+use futures::SinkExt;
+use futures::StreamExt;
+use rocket::State;
+use rocket_basicauth::Authenticated;
+use rocket_ws::stream::DuplexStream;
+use rocket_ws::Channel;
+use rocket_ws::Message;
+use rocket_ws::WebSocket;
+use serde::Serialize;
+use serde_json::Value;
+use tokio::select;
+use tokio::sync::watch;
+
+/// A message on the websocket, tagged with the feed it was published on.
+#[derive(Debug, Serialize)]
+struct FeedMessage {
+    feed: &'static str,
+    data: Value,
+}
+
+impl FeedMessage {
+    fn new(item: &impl ToSseEvent, unit: AmountUnit) -> Self {
+        Self {
+            feed: item.feed(),
+            data: item.to_feed_json(unit),
+        }
+    }
+}
+
+/// Upgrade the request to a websocket which receives updates of all feeds.
+#[rocket::get("/ws")]
+pub fn feeds(websocket: WebSocket, feeds: &State<Feeds>, _auth: Authenticated) -> Channel<'static> {
+    let feeds = feeds.inner().clone();
+
+    websocket.channel(move |stream| {
+        Box::pin(async move {
+            if let Err(e) = forward_feeds(stream, feeds).await {
+                tracing::debug!("Websocket connection closed: {e:#}");
+            }
+
+            Ok(())
+        })
+    })
+}
+
+async fn forward_feeds(mut websocket: DuplexStream, feeds: Feeds) -> Result<()> {
+    let unit = AmountUnit::default();
+    let Feeds {
+        mut quote,
+        mut order,
+        mut connected_takers,
+        mut cfds,
+        mut proposals,
+        mut exposure,
+    } = feeds;
+
+    let current_quote = quote.borrow().clone();
+    send(&mut websocket, &current_quote, unit).await?;
+    let current_order = order.borrow().clone();
+    send(&mut websocket, &current_order, unit).await?;
+    let current_takers = connected_takers.borrow().clone();
+    send(&mut websocket, &current_takers, unit).await?;
+    let current_cfds = projection::filter_archived(cfds.borrow().clone(), false);
+    send(&mut websocket, &current_cfds, unit).await?;
+    let current_proposals = proposals.borrow().clone();
+    send(&mut websocket, &current_proposals, unit).await?;
+    if let Some(exposure) = &exposure {
+        let current_exposure = *exposure.borrow();
+        send(&mut websocket, &current_exposure, unit).await?;
+    }
+
+    loop {
+        select! {
+            Ok(()) = quote.changed() => {
+                let quote = quote.borrow().clone();
+                send(&mut websocket, &quote, unit).await?;
+            }
+            Ok(()) = order.changed() => {
+                let order = order.borrow().clone();
+                send(&mut websocket, &order, unit).await?;
+            }
+            Ok(()) = connected_takers.changed() => {
+                let takers = connected_takers.borrow().clone();
+                send(&mut websocket, &takers, unit).await?;
+            }
+            Ok(()) = cfds.changed() => {
+                let cfds = projection::filter_archived(cfds.borrow().clone(), false);
+                send(&mut websocket, &cfds, unit).await?;
+            }
+            Ok(()) = proposals.changed() => {
+                let proposals = proposals.borrow().clone();
+                send(&mut websocket, &proposals, unit).await?;
+            }
+            Some(current_exposure) = exposure_changed(&mut exposure) => {
+                send(&mut websocket, &current_exposure, unit).await?;
+            }
+            message = websocket.next() => match message {
+                None | Some(Ok(Message::Close(_))) => return Ok(()),
+                Some(Ok(_)) => {} // Pings are answered by tungstenite, everything else is ignored
+                Some(Err(e)) => return Err(e).context("Failed to read from websocket"),
+            }
+        }
+    }
+}
+
+/// Wait for the next exposure, never resolves if there is no exposure feed.
+async fn exposure_changed(exposure: &mut Option<watch::Receiver<Exposure>>) -> Option<Exposure> {
+    match exposure {
+        Some(exposure) => {
+            exposure.changed().await.ok()?;
+            Some(*exposure.borrow())
+        }
+        None => future::pending().await,
+    }
+}
+
+async fn send(
+    websocket: &mut DuplexStream,
+    item: &impl ToSseEvent,
+    unit: AmountUnit,
+) -> Result<()> {
+    let message = serde_json::to_string(&FeedMessage::new(item, unit))?;
+
+    websocket
+        .send(Message::Text(message))
+        .await
+        .context("Failed to send feed update")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use daemon::bdk::bitcoin::Amount;
+    use daemon::bitmex_price_feed;
+    use daemon::model::Price;
+    use daemon::model::Timestamp;
+    use daemon::model::Usd;
+    use daemon::projection::Denominated;
+    use rocket::config::LogLevel;
+    use rocket::fairing::AdHoc;
+    use rocket::Config;
+    use rocket_basicauth::Password;
+    use rocket_basicauth::Username;
+    use serde_json::json;
+    use std::net::Ipv4Addr;
+    use std::net::SocketAddr;
+    use tokio::net::TcpStream;
+    use tokio::sync::oneshot;
+    use tokio_tasks::Tasks;
+    use tokio_tungstenite::tungstenite;
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+    use tokio_tungstenite::tungstenite::http::HeaderValue;
+    use tokio_tungstenite::MaybeTlsStream;
+    use tokio_tungstenite::WebSocketStream;
+
+    /// `base64(itchysats:password)`
+    const AUTH_HEADER: &str = "Basic aXRjaHlzYXRzOnBhc3N3b3Jk";
+
+    #[tokio::test]
+    async fn publishes_cfd_and_quote_updates() {
+        let (quote_sender, quote) = watch::channel(None);
+        let (_order_sender, order) = watch::channel(None);
+        let (_takers_sender, connected_takers) = watch::channel(Vec::new());
+        let (cfds_sender, cfds) = watch::channel(Vec::new());
+        let (_proposals_sender, proposals) = watch::channel(Vec::new());
+        let (_exposure_sender, exposure) = watch::channel(Exposure {
+            total_quantity_usd: Usd::new(0.into()),
+            total_margin: Denominated::new(Amount::ZERO),
+            net_quantity_usd: Usd::new(0.into()),
+        });
+        let feeds = Feeds {
+            quote,
+            order,
+            connected_takers,
+            cfds,
+            proposals,
+            exposure: Some(exposure),
+        };
+        let (_tasks, address) = start_server(feeds).await;
+
+        let mut websocket = connect(address, Some(AUTH_HEADER)).await.unwrap();
+
+        let mut initial_feeds = Vec::new();
+        for _ in 0..6 {
+            initial_feeds.push(next_message(&mut websocket).await["feed"].clone());
+        }
+        assert_eq!(
+            initial_feeds,
+            vec!["quote", "order", "takers", "cfds", "proposals", "exposure"]
+        );
+
+        quote_sender
+            .send(Some(
+                bitmex_price_feed::Quote {
+                    timestamp: Timestamp::new(0),
+                    bid: Price::new(40_000.into()).unwrap(),
+                    ask: Price::new(40_010.into()).unwrap(),
+                }
+                .into(),
+            ))
+            .unwrap();
+        let quote = next_message(&mut websocket).await;
+        assert_eq!(quote["feed"], json!("quote"));
+        assert_eq!(quote["data"]["bid"], json!("40000.00"));
+
+        cfds_sender.send(Vec::new()).unwrap();
+        let cfds = next_message(&mut websocket).await;
+        assert_eq!(cfds, json!({ "feed": "cfds", "data": [] }));
+    }
+
+    #[tokio::test]
+    async fn rejects_connections_without_valid_credentials() {
+        let (_, quote) = watch::channel(None);
+        let (_, order) = watch::channel(None);
+        let (_, connected_takers) = watch::channel(Vec::new());
+        let (_, cfds) = watch::channel(Vec::new());
+        let (_, proposals) = watch::channel(Vec::new());
+        let (_, exposure) = watch::channel(Exposure {
+            total_quantity_usd: Usd::new(0.into()),
+            total_margin: Denominated::new(Amount::ZERO),
+            net_quantity_usd: Usd::new(0.into()),
+        });
+        let feeds = Feeds {
+            quote,
+            order,
+            connected_takers,
+            cfds,
+            proposals,
+            exposure: Some(exposure),
+        };
+        let (_tasks, address) = start_server(feeds).await;
+
+        assert!(connect(address, None).await.is_err());
+        assert!(connect(address, Some("Basic aXRjaHlzYXRzOndyb25n"))
+            .await
+            .is_err());
+    }
+
+    async fn start_server(feeds: Feeds) -> (Tasks, SocketAddr) {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let config = Config {
+            address: Ipv4Addr::LOCALHOST.into(),
+            port,
+            log_level: LogLevel::Off,
+            ..Config::debug_default()
+        };
+        let (ready_sender, ready) = oneshot::channel();
+
+        let rocket = rocket::custom(config)
+            .manage(feeds)
+            .manage(Username("itchysats"))
+            .manage("password".parse::<Password>().unwrap())
+            .mount("/api", rocket::routes![super::feeds])
+            .register("/api", rocket::catchers![rocket_basicauth::unauthorized])
+            .attach(AdHoc::on_liftoff("Notify test", |_| {
+                Box::pin(async move {
+                    let _ = ready_sender.send(());
+                })
+            }));
+
+        let mut tasks = Tasks::default();
+        tasks.add(async move {
+            let _ = rocket.launch().await;
+        });
+        ready.await.unwrap();
+
+        (tasks, SocketAddr::from((Ipv4Addr::LOCALHOST, port)))
+    }
+
+    async fn connect(
+        address: SocketAddr,
+        auth_header: Option<&'static str>,
+    ) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>> {
+        let mut request = format!("ws://{address}/api/ws").into_client_request()?;
+        if let Some(auth_header) = auth_header {
+            request
+                .headers_mut()
+                .insert("Authorization", HeaderValue::from_static(auth_header));
+        }
+
+        let (websocket, _) = tokio_tungstenite::connect_async(request).await?;
+
+        Ok(websocket)
+    }
+
+    async fn next_message(websocket: &mut WebSocketStream<MaybeTlsStream<TcpStream>>) -> Value {
+        loop {
+            if let tungstenite::Message::Text(text) = websocket.next().await.unwrap().unwrap() {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+}
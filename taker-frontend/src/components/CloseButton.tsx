@@ -27,7 +27,7 @@ interface Props {
 /// maker is online.
 export default function CloseButton({ cfd, request, status, buttonTitle, isForceCloseButton }: Props) {
     const disableCloseButton = cfd.state.getGroup() === StateGroupKey.CLOSED
-        || ![StateKey.OPEN, StateKey.PENDING_OPEN].includes(cfd.state.key);
+        || ![StateKey.OPEN, StateKey.PENDING_OPEN, StateKey.LOCK_SEEN].includes(cfd.state.key);
 
     let popoverBody = <>
         <Text>
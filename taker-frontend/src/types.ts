@@ -18,6 +18,7 @@ export interface Order {
     parcel_size: string;
     margin_per_parcel: number;
     leverage: number;
+    max_leverage: number;
     liquidation_price: string;
     creation_timestamp: number;
     settlement_time_interval_in_secs: number;
@@ -58,6 +59,7 @@ export interface Cfd {
     quantity_usd: number;
 
     margin: number;
+    margin_with_fees: number;
 
     profit_btc?: number;
     profit_percent?: number;
@@ -107,6 +109,8 @@ export class State {
                 return "Rejected";
             case StateKey.PENDING_OPEN:
                 return "Open";
+            case StateKey.LOCK_SEEN:
+                return "Lock Seen";
             case StateKey.OPEN:
                 return "Open";
             case StateKey.PENDING_COMMIT:
@@ -144,6 +148,7 @@ export class State {
         switch (this.key) {
             case StateKey.OPEN:
             case StateKey.PENDING_OPEN:
+            case StateKey.LOCK_SEEN:
             case StateKey.INCOMING_ROLLOVER_PROPOSAL:
             case StateKey.OUTGOING_ROLLOVER_PROPOSAL:
                 return green;
@@ -176,6 +181,7 @@ export class State {
                 return StateGroupKey.OPENING;
 
             case StateKey.PENDING_OPEN:
+            case StateKey.LOCK_SEEN:
             case StateKey.OPEN:
             case StateKey.PENDING_COMMIT:
             case StateKey.OPEN_COMMITTED:
@@ -206,6 +212,7 @@ export const enum StateKey {
     CONTRACT_SETUP = "ContractSetup",
     REJECTED = "Rejected",
     PENDING_OPEN = "PendingOpen",
+    LOCK_SEEN = "LockSeen",
     OPEN = "Open",
     PENDING_CLOSE = "PendingClose",
     PENDING_COMMIT = "PendingCommit",
@@ -277,4 +284,6 @@ export interface ConnectionStatus {
 export const enum ConnectionCloseReason {
     MAKER_VERSION_OUTDATED = "MakerVersionOutdated",
     TAKER_VERSION_OUTDATED = "TakerVersionOutdated",
+    REQUESTED = "Requested",
+    PROTOCOL_VIOLATION = "ProtocolViolation",
 }
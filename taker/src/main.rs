@@ -10,8 +10,11 @@ use daemon::bdk::FeeRate;
 use daemon::bitmex_price_feed;
 use daemon::connection::connect;
 use daemon::db;
+use daemon::metrics::Metrics;
+use daemon::model::cfd::OrderId;
 use daemon::model::cfd::Role;
 use daemon::model::Identity;
+use daemon::model::Price;
 use daemon::monitor;
 use daemon::oracle;
 use daemon::projection;
@@ -21,19 +24,22 @@ use daemon::seed::UmbrelSeed;
 use daemon::wallet;
 use daemon::TakerActorSystem;
 use daemon::HEARTBEAT_INTERVAL;
-use daemon::N_PAYOUTS;
 use daemon::SETTLEMENT_INTERVAL;
+use daemon::SETUP_TIMEOUT;
+use daemon::TCP_KEEPALIVE_INTERVAL;
 use rocket::fairing::AdHoc;
 use shared_bin::logger;
 use shared_bin::logger::LevelFilter;
+use shared_bin::websocket;
 use std::net::SocketAddr;
 use std::path::PathBuf;
-use std::str::FromStr;
 use std::time::Duration;
 use tokio_tasks::Tasks;
+use uuid::Uuid;
 use xtra::Actor;
 
 mod routes;
+mod status;
 
 pub const ANNOUNCEMENT_LOOKAHEAD: time::Duration = time::Duration::hours(24);
 
@@ -63,12 +69,26 @@ struct Opts {
     #[clap(short, long, default_value = "Debug")]
     log_level: LevelFilter,
 
-    /// Password for the web interface.
+    /// Password for the web interface and HTTP API.
     ///
-    /// If not provided, will be derived from the seed.
-    #[clap(long)]
+    /// If not provided, will be derived from the seed and printed on startup.
+    #[clap(
+        long,
+        alias = "api-password",
+        env = "ITCHYSATS_PASSWORD",
+        hide_env_values = true
+    )]
     password: Option<rocket_basicauth::Password>,
 
+    /// The public key of the oracle we accept attestations from.
+    ///
+    /// Orders of a maker using a different oracle cannot be taken.
+    #[clap(
+        long,
+        default_value = "ddd4636845a90185991826be5a494cde9f4a6947b1727217afedc6292fa4caf7"
+    )]
+    oracle_pk: schnorrsig::PublicKey,
+
     #[clap(subcommand)]
     network: Network,
 
@@ -96,7 +116,7 @@ enum Network {
         electrum: String,
 
         #[clap(subcommand)]
-        withdraw: Option<Withdraw>,
+        command: Option<Command>,
     },
     Testnet {
         /// URL to the electrum backend to use for the wallet.
@@ -104,7 +124,7 @@ enum Network {
         electrum: String,
 
         #[clap(subcommand)]
-        withdraw: Option<Withdraw>,
+        command: Option<Command>,
     },
     /// Run on signet
     Signet {
@@ -113,12 +133,21 @@ enum Network {
         electrum: String,
 
         #[clap(subcommand)]
-        withdraw: Option<Withdraw>,
+        command: Option<Command>,
+    },
+    /// Run on regtest, e.g. against a local bitcoind and electrs for integration testing.
+    Regtest {
+        /// URL to the electrum backend to use for the wallet.
+        #[clap(long)]
+        electrum: String,
+
+        #[clap(subcommand)]
+        command: Option<Command>,
     },
 }
 
 #[derive(Subcommand)]
-enum Withdraw {
+enum Command {
     Withdraw {
         /// Optionally specify the amount of Bitcoin to be withdrawn. If not specified the wallet
         /// will be drained. Amount is to be specified with denomination, e.g. "0.1 BTC"
@@ -132,6 +161,19 @@ enum Withdraw {
         #[clap(long)]
         address: Address,
     },
+    /// Print the event log of a CFD as JSON, e.g. to attach it to a support request.
+    ExportCfd {
+        /// The id of the CFD's order.
+        order_id: Uuid,
+    },
+    /// Print a table of all CFDs without starting the daemon.
+    Status {
+        /// The price to calculate the profit/loss of open CFDs at.
+        ///
+        /// If not given, the profit/loss is only shown for CFDs whose payout is already known.
+        #[clap(long)]
+        price: Option<Price>,
+    },
 }
 
 impl Network {
@@ -140,6 +182,7 @@ impl Network {
             Network::Mainnet { electrum, .. } => electrum,
             Network::Testnet { electrum, .. } => electrum,
             Network::Signet { electrum, .. } => electrum,
+            Network::Regtest { electrum, .. } => electrum,
         }
     }
 
@@ -148,6 +191,7 @@ impl Network {
             Network::Mainnet { .. } => bitcoin::Network::Bitcoin,
             Network::Testnet { .. } => bitcoin::Network::Testnet,
             Network::Signet { .. } => bitcoin::Network::Signet,
+            Network::Regtest { .. } => bitcoin::Network::Regtest,
         }
     }
 
@@ -156,14 +200,16 @@ impl Network {
             Network::Mainnet { .. } => base.join("mainnet"),
             Network::Testnet { .. } => base.join("testnet"),
             Network::Signet { .. } => base.join("signet"),
+            Network::Regtest { .. } => base.join("regtest"),
         }
     }
 
-    fn withdraw(&self) -> &Option<Withdraw> {
+    fn command(&self) -> &Option<Command> {
         match self {
-            Network::Mainnet { withdraw, .. } => withdraw,
-            Network::Testnet { withdraw, .. } => withdraw,
-            Network::Signet { withdraw, .. } => withdraw,
+            Network::Mainnet { command, .. } => command,
+            Network::Testnet { command, .. } => command,
+            Network::Signet { command, .. } => command,
+            Network::Regtest { command, .. } => command,
         }
     }
 }
@@ -191,6 +237,24 @@ async fn main() -> Result<()> {
         tokio::fs::create_dir_all(&data_dir).await?;
     }
 
+    if let Some(Command::ExportCfd { order_id }) = opts.network.command() {
+        let db = db::connect_read_only(data_dir.join("taker.sqlite")).await?;
+        let mut conn = db.acquire().await?;
+
+        let events = db::export_cfd_events(OrderId::from(*order_id), &mut conn).await?;
+        println!("{events:#}");
+
+        return Ok(());
+    }
+
+    if let Some(Command::Status { price }) = opts.network.command() {
+        let db = db::connect_read_only(data_dir.join("taker.sqlite")).await?;
+        let cfds = projection::load_all_cfds(&db, opts.network.bitcoin_network(), *price).await?;
+        print!("{}", status::render(&cfds));
+
+        return Ok(());
+    }
+
     let maker_identity = Identity::new(opts.maker_id);
 
     let bitcoin_network = opts.network.bitcoin_network();
@@ -218,11 +282,11 @@ async fn main() -> Result<()> {
     let (wallet, wallet_fut) = wallet.create(None).run();
     tasks.add(wallet_fut);
 
-    if let Some(Withdraw::Withdraw {
+    if let Some(Command::Withdraw {
         amount,
         address,
         fee,
-    }) = opts.network.withdraw()
+    }) = opts.network.command()
     {
         wallet
             .send(wallet::Withdraw {
@@ -238,11 +302,6 @@ async fn main() -> Result<()> {
     let auth_username = rocket_basicauth::Username("itchysats");
     tracing::info!("Authentication details: username='{auth_username}' password='{web_password}'");
 
-    // TODO: Actually fetch it from Olivia
-    let oracle = schnorrsig::PublicKey::from_str(
-        "ddd4636845a90185991826be5a494cde9f4a6947b1727217afedc6292fa4caf7",
-    )?;
-
     let figment = rocket::Config::figment()
         .merge(("address", opts.http_address.ip()))
         .merge(("port", opts.http_address.port()))
@@ -257,9 +316,9 @@ async fn main() -> Result<()> {
     let taker = TakerActorSystem::new(
         db.clone(),
         wallet.clone(),
-        oracle,
+        opts.oracle_pk,
         identity_sk,
-        |channel| oracle::Actor::new(db.clone(), channel, SETTLEMENT_INTERVAL),
+        |channel| oracle::Actor::new(db.clone(), channel, SETTLEMENT_INTERVAL, opts.oracle_pk),
         {
             |channel| {
                 let electrum = opts.network.electrum().to_string();
@@ -267,11 +326,13 @@ async fn main() -> Result<()> {
             }
         },
         bitmex_price_feed::Actor::new,
-        N_PAYOUTS,
         HEARTBEAT_INTERVAL,
         Duration::from_secs(10),
+        TCP_KEEPALIVE_INTERVAL,
+        SETUP_TIMEOUT,
         projection_actor.clone(),
         maker_identity,
+        Metrics::default(),
     )?;
 
     let (proj_actor, projection_feeds) = projection::Actor::new(
@@ -279,6 +340,7 @@ async fn main() -> Result<()> {
         Role::Taker,
         bitcoin_network,
         &taker.price_feed_actor,
+        projection::DEBOUNCE_INTERVAL,
     );
     tasks.add(projection_context.run(proj_actor));
 
@@ -291,6 +353,8 @@ async fn main() -> Result<()> {
         possible_addresses,
     ));
 
+    tasks.add(taker.watchdog());
+
     rocket::custom(figment)
         .manage(projection_feeds)
         .manage(wallet_feed_receiver)
@@ -299,14 +363,31 @@ async fn main() -> Result<()> {
         .manage(taker)
         .manage(auth_username)
         .manage(web_password)
+        .manage(projection_actor)
         .mount(
             "/api",
             rocket::routes![
                 routes::feed,
+                websocket::feeds,
                 routes::post_order_request,
+                routes::get_current_order,
                 routes::get_health_check,
+                routes::get_health,
+                routes::get_closing_fee_estimate,
+                routes::get_commit_tx_fee,
+                routes::put_auto_close_thresholds,
+                routes::delete_auto_close_thresholds,
+                routes::put_cfd_label,
+                routes::delete_cfd_label,
+                routes::put_cfd_archive,
+                routes::delete_cfd_archive,
+                routes::get_utxos,
+                routes::post_new_address,
+                routes::get_metrics,
                 routes::post_cfd_action,
+                routes::post_cfd_resync,
                 routes::post_withdraw_request,
+                routes::get_cfds_page,
             ],
         )
         .register("/api", rocket::catchers![rocket_basicauth::unauthorized])
@@ -343,3 +424,69 @@ async fn resolve_maker_addresses(maker_addr: &str) -> Result<Vec<SocketAddr>> {
     );
     Ok(possible_addresses)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn regtest_maps_to_bitcoin_regtest() {
+        let opts = Opts::parse_from([
+            "taker",
+            "--maker",
+            "localhost:9999",
+            "--maker-id",
+            "10d4ba2ac3f7a22da4009d813ff1bc3f404dfe2cc93a32bedf1512aa9951c95e",
+            "regtest",
+            "--electrum",
+            "tcp://localhost:60401",
+        ]);
+
+        assert_eq!(opts.network.bitcoin_network(), bitcoin::Network::Regtest);
+        assert_eq!(opts.network.electrum(), "tcp://localhost:60401");
+        assert_eq!(
+            opts.network.data_dir(PathBuf::from("/data")),
+            PathBuf::from("/data/regtest")
+        );
+        assert!(opts.network.command().is_none());
+    }
+
+    #[test]
+    fn oracle_pk_can_be_pinned() {
+        let oracle_pk = "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+        let opts = Opts::parse_from([
+            "taker",
+            "--maker",
+            "localhost:9999",
+            "--maker-id",
+            "10d4ba2ac3f7a22da4009d813ff1bc3f404dfe2cc93a32bedf1512aa9951c95e",
+            "--oracle-pk",
+            oracle_pk,
+            "regtest",
+        ]);
+
+        assert_eq!(opts.oracle_pk.to_string(), oracle_pk);
+    }
+
+    #[test]
+    fn status_takes_optional_price() {
+        let opts = Opts::parse_from([
+            "taker",
+            "--maker",
+            "localhost:9999",
+            "--maker-id",
+            "10d4ba2ac3f7a22da4009d813ff1bc3f404dfe2cc93a32bedf1512aa9951c95e",
+            "regtest",
+            "--electrum",
+            "tcp://localhost:60401",
+            "status",
+            "--price",
+            "40000",
+        ]);
+
+        assert!(matches!(
+            opts.network.command(),
+            Some(Command::Status { price: Some(price) }) if *price == "40000".parse().unwrap()
+        ));
+    }
+}
@@ -3,7 +3,14 @@ use daemon::bdk::bitcoin::Amount;
 use daemon::bdk::bitcoin::Network;
 use daemon::bitmex_price_feed;
 use daemon::connection::ConnectionStatus;
+use daemon::db;
+use daemon::health;
+use daemon::model;
+use daemon::model::cfd::AutoCloseThresholds;
+use daemon::model::cfd::ClosingFeeEstimate;
+use daemon::model::cfd::CommitTxFee;
 use daemon::model::cfd::OrderId;
+use daemon::model::cfd::TxUnavailable;
 use daemon::model::Leverage;
 use daemon::model::Price;
 use daemon::model::Timestamp;
@@ -12,6 +19,7 @@ use daemon::model::WalletInfo;
 use daemon::oracle;
 use daemon::projection;
 use daemon::projection::CfdAction;
+use daemon::projection::CfdOrder;
 use daemon::projection::Feeds;
 use daemon::wallet;
 use daemon::TakerActorSystem;
@@ -29,6 +37,7 @@ use rust_embed::RustEmbed;
 use rust_embed_rocket::EmbeddedFileExt;
 use serde::Deserialize;
 use serde::Serialize;
+use shared_bin::AmountUnit;
 use shared_bin::ToSseEvent;
 use std::borrow::Cow;
 use std::path::PathBuf;
@@ -40,17 +49,22 @@ type Taker = TakerActorSystem<oracle::Actor, wallet::Actor, bitmex_price_feed::A
 
 const HEARTBEAT_INTERVAL_SECS: u64 = 5;
 
-#[rocket::get("/feed")]
+#[rocket::get("/feed?<unit>&<include_archived>")]
 pub async fn feed(
+    unit: Option<AmountUnit>,
+    include_archived: Option<bool>,
     rx: &State<Feeds>,
     rx_wallet: &State<watch::Receiver<Option<WalletInfo>>>,
     rx_maker_status: &State<watch::Receiver<ConnectionStatus>>,
     _auth: Authenticated,
 ) -> EventStream![] {
+    let unit = unit.unwrap_or_default();
+    let include_archived = include_archived.unwrap_or_default();
     let rx = rx.inner();
     let mut rx_cfds = rx.cfds.clone();
     let mut rx_order = rx.order.clone();
     let mut rx_quote = rx.quote.clone();
+    let mut rx_proposals = rx.proposals.clone();
     let mut rx_wallet = rx_wallet.inner().clone();
     let mut rx_maker_status = rx_maker_status.inner().clone();
     let mut heartbeat =
@@ -58,25 +72,28 @@ pub async fn feed(
 
     EventStream! {
         let wallet_info = rx_wallet.borrow().clone();
-        yield wallet_info.to_sse_event();
+        yield wallet_info.to_sse_event_in(unit);
 
         let maker_status = rx_maker_status.borrow().clone();
         yield maker_status.to_sse_event();
 
         let order = rx_order.borrow().clone();
-        yield order.to_sse_event();
+        yield order.to_sse_event_in(unit);
 
         let quote = rx_quote.borrow().clone();
         yield quote.to_sse_event();
 
-        let cfds = rx_cfds.borrow().clone();
-        yield cfds.to_sse_event();
+        let cfds = projection::filter_archived(rx_cfds.borrow().clone(), include_archived);
+        yield cfds.to_sse_event_in(unit);
+
+        let proposals = rx_proposals.borrow().clone();
+        yield proposals.to_sse_event();
 
         loop{
             select! {
                 Ok(()) = rx_wallet.changed() => {
                     let wallet_info = rx_wallet.borrow().clone();
-                    yield wallet_info.to_sse_event();
+                    yield wallet_info.to_sse_event_in(unit);
                 },
                 Ok(()) = rx_maker_status.changed() => {
                     let maker_status = rx_maker_status.borrow().clone();
@@ -84,16 +101,20 @@ pub async fn feed(
                 },
                 Ok(()) = rx_order.changed() => {
                     let order = rx_order.borrow().clone();
-                    yield order.to_sse_event();
+                    yield order.to_sse_event_in(unit);
                 }
                 Ok(()) = rx_cfds.changed() => {
-                    let cfds = rx_cfds.borrow().clone();
-                    yield cfds.to_sse_event();
+                    let cfds = projection::filter_archived(rx_cfds.borrow().clone(), include_archived);
+                    yield cfds.to_sse_event_in(unit);
                 }
                 Ok(()) = rx_quote.changed() => {
                     let quote = rx_quote.borrow().clone();
                     yield quote.to_sse_event();
                 }
+                Ok(()) = rx_proposals.changed() => {
+                    let proposals = rx_proposals.borrow().clone();
+                    yield proposals.to_sse_event();
+                }
                 _ = heartbeat.tick() => {
                     yield Event::json(&Heartbeat::new()).event("heartbeat")
                 }
@@ -121,6 +142,9 @@ impl Heartbeat {
 pub struct CfdOrderRequest {
     pub order_id: OrderId,
     pub quantity: Usd,
+    /// Defaults to the leverage of the order.
+    #[serde(default)]
+    pub leverage: Option<Leverage>,
 }
 
 #[rocket::post("/cfd/order", data = "<cfd_order_request>")]
@@ -130,10 +154,20 @@ pub async fn post_order_request(
     _auth: Authenticated,
 ) -> Result<(), HttpApiProblem> {
     taker
-        .take_offer(cfd_order_request.order_id, cfd_order_request.quantity)
+        .take_offer(
+            cfd_order_request.order_id,
+            cfd_order_request.quantity,
+            cfd_order_request.leverage,
+        )
         .await
         .map_err(|e| {
-            HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+            let status = match e.downcast_ref::<wallet::WalletError>() {
+                Some(wallet::WalletError::InsufficientFunds { .. }) => StatusCode::BAD_REQUEST,
+                None if e.downcast_ref::<model::Error>().is_some() => StatusCode::BAD_REQUEST,
+                None => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+
+            HttpApiProblem::new(status)
                 .title("Order request failed")
                 .detail(format!("{e:#}"))
         })?;
@@ -141,7 +175,9 @@ pub async fn post_order_request(
     Ok(())
 }
 
-#[rocket::post("/cfd/<id>/<action>")]
+/// Ranked below the routes for specific actions like `/cfd/<id>/resync`, which would collide
+/// otherwise.
+#[rocket::post("/cfd/<id>/<action>", rank = 2)]
 pub async fn post_cfd_action(
     id: Uuid,
     action: String,
@@ -165,10 +201,17 @@ pub async fn post_cfd_action(
         }
         CfdAction::Commit => taker.commit(id).await,
         CfdAction::Settle => taker.propose_settlement(id).await,
+        CfdAction::Rollover => taker.rollover(id).await,
+        CfdAction::CancelOrder => taker.cancel_order_request(id).await,
     };
 
     result.map_err(|e| {
-        HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+        let status = match e.downcast_ref::<TxUnavailable>() {
+            Some(_) => StatusCode::CONFLICT,
+            None => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        HttpApiProblem::new(status)
             .title(action.to_string() + " failed")
             .detail(format!("{e:#}"))
     })?;
@@ -176,9 +219,175 @@ pub async fn post_cfd_action(
     Ok(())
 }
 
+/// The maker's current order, for clients that don't want to subscribe to the feed.
+#[rocket::get("/order")]
+pub async fn get_current_order(
+    taker: &State<Taker>,
+    _auth: Authenticated,
+) -> Result<Json<Option<CfdOrder>>, HttpApiProblem> {
+    let order = taker
+        .current_order()
+        .await
+        .and_then(|order| order.map(CfdOrder::try_from).transpose())
+        .map_err(|e| {
+            HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+                .title("Loading current order failed")
+                .detail(format!("{e:#}"))
+        })?;
+
+    Ok(Json(order))
+}
+
+/// Re-scans the blockchain and re-fetches the oracle attestation for a CFD that appears stuck.
+///
+/// The resulting state changes are published on the CFD feed as they happen.
+#[rocket::post("/cfd/<id>/resync")]
+pub async fn post_cfd_resync(
+    id: Uuid,
+    taker: &State<Taker>,
+    _auth: Authenticated,
+) -> Result<Status, HttpApiProblem> {
+    taker.resync(OrderId::from(id)).await.map_err(|e| {
+        let status = match e.downcast_ref::<db::CfdNotFound>() {
+            Some(_) => StatusCode::NOT_FOUND,
+            None => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        HttpApiProblem::new(status)
+            .title("Resync failed")
+            .detail(format!("{e:#}"))
+    })?;
+
+    Ok(Status::Accepted)
+}
+
+/// Estimates the on-chain fees of closing the CFD collaboratively vs. force-closing it.
+#[rocket::get("/cfd/<id>/closing-fees")]
+pub async fn get_closing_fee_estimate(
+    id: Uuid,
+    taker: &State<Taker>,
+    _auth: Authenticated,
+) -> Result<Json<ClosingFeeEstimate>, HttpApiProblem> {
+    let estimate = taker
+        .estimate_closing_fees(OrderId::from(id))
+        .await
+        .map_err(|e| {
+            HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+                .title("Estimating closing fees failed")
+                .detail(format!("{e:#}"))
+        })?;
+
+    Ok(Json(estimate))
+}
+
+/// Reports the fee and size of the commit transaction, so users know the cost of force-closing.
+#[rocket::get("/cfd/<id>/commit-fee")]
+pub async fn get_commit_tx_fee(
+    id: Uuid,
+    taker: &State<Taker>,
+    _auth: Authenticated,
+) -> Result<Json<CommitTxFee>, HttpApiProblem> {
+    let commit_tx_fee = taker.commit_tx_fee(OrderId::from(id)).await.map_err(|e| {
+        HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+            .title("Computing commit transaction fee failed")
+            .detail(format!("{e:#}"))
+    })?;
+
+    Ok(Json(commit_tx_fee))
+}
+
+/// Automatically propose a collaborative settlement once the CFD's profit or loss crosses the
+/// given thresholds.
+#[rocket::put("/cfd/<id>/auto-close", data = "<thresholds>")]
+pub async fn put_auto_close_thresholds(
+    id: Uuid,
+    thresholds: Json<AutoCloseThresholds>,
+    taker: &State<Taker>,
+    _auth: Authenticated,
+) -> Result<(), HttpApiProblem> {
+    taker
+        .set_auto_close_thresholds(OrderId::from(id), thresholds.into_inner())
+        .await
+        .map_err(|e| {
+            HttpApiProblem::new(StatusCode::BAD_REQUEST)
+                .title("Setting auto-close thresholds failed")
+                .detail(format!("{e:#}"))
+        })?;
+
+    Ok(())
+}
+
+#[rocket::delete("/cfd/<id>/auto-close")]
+pub async fn delete_auto_close_thresholds(
+    id: Uuid,
+    taker: &State<Taker>,
+    _auth: Authenticated,
+) -> Result<(), HttpApiProblem> {
+    taker
+        .clear_auto_close_thresholds(OrderId::from(id))
+        .await
+        .map_err(|e| {
+            HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+                .title("Clearing auto-close thresholds failed")
+                .detail(format!("{e:#}"))
+        })?;
+
+    Ok(())
+}
+
+/// Lists the UTXOs of the wallet and the orders they are reserved for, for debugging stuck
+/// contract setups.
+#[rocket::get("/debug/utxos")]
+pub async fn get_utxos(
+    taker: &State<Taker>,
+    _auth: Authenticated,
+) -> Result<Json<Vec<wallet::Utxo>>, HttpApiProblem> {
+    let utxos = taker.list_utxos().await.map_err(|e| {
+        HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+            .title("Listing UTXOs failed")
+            .detail(format!("{e:#}"))
+    })?;
+
+    Ok(Json(utxos))
+}
+
+/// Derives a fresh receive address from the wallet.
+#[rocket::post("/wallet/address")]
+pub async fn post_new_address(
+    taker: &State<Taker>,
+    _auth: Authenticated,
+) -> Result<Json<bdk::bitcoin::Address>, HttpApiProblem> {
+    let address = taker.new_address().await.map_err(|e| {
+        HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+            .title("Deriving new address failed")
+            .detail(format!("{e:#}"))
+    })?;
+
+    Ok(Json(address))
+}
+
+/// Counters of CFD lifecycle events in the Prometheus text format.
+#[rocket::get("/metrics")]
+pub fn get_metrics(taker: &State<Taker>, _auth: Authenticated) -> String {
+    taker.metrics.render()
+}
+
 #[rocket::get("/alive")]
 pub fn get_health_check() {}
 
+#[rocket::get("/health")]
+pub async fn get_health(taker: &State<Taker>) -> (Status, Json<health::Report>) {
+    let report = taker.health().await;
+
+    let status = if report.is_healthy() {
+        Status::Ok
+    } else {
+        Status::ServiceUnavailable
+    };
+
+    (status, Json(report))
+}
+
 #[derive(Debug, Clone, Copy, Deserialize)]
 pub struct MarginRequest {
     pub price: Price,
@@ -250,6 +459,148 @@ pub async fn post_withdraw_request(
     Ok(projection::to_mempool_url(txid, *network.inner()))
 }
 
+/// Default number of CFDs returned per page if the client does not specify a `limit`.
+const DEFAULT_PAGE_LIMIT: u32 = 20;
+/// Upper bound for the number of CFDs returned per page.
+const MAX_PAGE_LIMIT: u32 = 100;
+
+/// Loads CFDs from the database instead of the live feed, newest first.
+///
+/// Use `next.created_at` and `next.id` of the returned page as `before` and `before_id` to fetch
+/// the following page.
+#[rocket::get("/cfds/page?<limit>&<before>&<before_id>")]
+pub async fn get_cfds_page(
+    limit: Option<u32>,
+    before: Option<i64>,
+    before_id: Option<i64>,
+    projection: &State<xtra::Address<projection::Actor>>,
+    _auth: Authenticated,
+) -> Result<Json<projection::CfdPage>, HttpApiProblem> {
+    let limit = limit.unwrap_or(DEFAULT_PAGE_LIMIT).min(MAX_PAGE_LIMIT);
+
+    let before = match (before, before_id) {
+        (Some(created_at), Some(id)) => Some(db::CfdCursor {
+            created_at: Timestamp::new(created_at),
+            id,
+        }),
+        (None, None) => None,
+        _ => {
+            return Err(HttpApiProblem::new(StatusCode::BAD_REQUEST)
+                .title("Invalid cursor")
+                .detail("`before` and `before_id` have to be given together"))
+        }
+    };
+
+    let page = projection
+        .send(projection::LoadCfds { before, limit })
+        .await
+        .map_err(|e| {
+            HttpApiProblem::new(StatusCode::SERVICE_UNAVAILABLE)
+                .title("Projection actor unavailable")
+                .detail(e.to_string())
+        })?
+        .map_err(|e| {
+            HttpApiProblem::new(StatusCode::INTERNAL_SERVER_ERROR)
+                .title("Loading CFDs failed")
+                .detail(format!("{e:#}"))
+        })?;
+
+    Ok(Json(page))
+}
+
+/// The user PUTs this to label a CFD
+#[derive(Debug, Clone, Deserialize)]
+pub struct CfdLabelRequest {
+    pub label: String,
+}
+
+#[rocket::put("/cfd/<id>/label", data = "<request>")]
+pub async fn put_cfd_label(
+    id: Uuid,
+    request: Json<CfdLabelRequest>,
+    projection: &State<xtra::Address<projection::Actor>>,
+    _auth: Authenticated,
+) -> Result<(), HttpApiProblem> {
+    set_cfd_label(
+        OrderId::from(id),
+        Some(request.into_inner().label),
+        projection,
+    )
+    .await
+}
+
+#[rocket::delete("/cfd/<id>/label")]
+pub async fn delete_cfd_label(
+    id: Uuid,
+    projection: &State<xtra::Address<projection::Actor>>,
+    _auth: Authenticated,
+) -> Result<(), HttpApiProblem> {
+    set_cfd_label(OrderId::from(id), None, projection).await
+}
+
+async fn set_cfd_label(
+    order_id: OrderId,
+    label: Option<String>,
+    projection: &xtra::Address<projection::Actor>,
+) -> Result<(), HttpApiProblem> {
+    projection
+        .send(projection::SetLabel { order_id, label })
+        .await
+        .map_err(|e| {
+            HttpApiProblem::new(StatusCode::SERVICE_UNAVAILABLE)
+                .title("Projection actor unavailable")
+                .detail(e.to_string())
+        })?
+        .map_err(|e| {
+            HttpApiProblem::new(StatusCode::BAD_REQUEST)
+                .title("Labelling CFD failed")
+                .detail(format!("{e:#}"))
+        })?;
+
+    Ok(())
+}
+
+/// Hide a CFD from the feed, it can still be requested with `include_archived=true`.
+#[rocket::put("/cfd/<id>/archive")]
+pub async fn put_cfd_archive(
+    id: Uuid,
+    projection: &State<xtra::Address<projection::Actor>>,
+    _auth: Authenticated,
+) -> Result<(), HttpApiProblem> {
+    set_cfd_archived(OrderId::from(id), true, projection).await
+}
+
+#[rocket::delete("/cfd/<id>/archive")]
+pub async fn delete_cfd_archive(
+    id: Uuid,
+    projection: &State<xtra::Address<projection::Actor>>,
+    _auth: Authenticated,
+) -> Result<(), HttpApiProblem> {
+    set_cfd_archived(OrderId::from(id), false, projection).await
+}
+
+async fn set_cfd_archived(
+    order_id: OrderId,
+    archived: bool,
+    projection: &xtra::Address<projection::Actor>,
+) -> Result<(), HttpApiProblem> {
+    projection
+        .send(projection::SetArchived { order_id, archived })
+        .await
+        .map_err(|e| {
+            HttpApiProblem::new(StatusCode::SERVICE_UNAVAILABLE)
+                .title("Projection actor unavailable")
+                .detail(e.to_string())
+        })?
+        .map_err(|e| {
+            HttpApiProblem::new(StatusCode::BAD_REQUEST)
+                .title("Archiving CFD failed")
+                .detail(format!("{e:#}"))
+        })?;
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,95 @@
+use daemon::projection::Cfd;
+use std::fmt::Write;
+
+/// Render the CFDs as a table of order id, state, quantity and profit/loss.
+///
+/// The profit/loss is shown as `-` if it cannot be calculated, i.e. the payout is not known yet
+/// and no price was supplied.
+pub fn render(cfds: &[Cfd]) -> String {
+    let mut table = format!(
+        "{:<36}  {:<20}  {:>10}  {}\n",
+        "ORDER ID", "STATE", "QUANTITY", "P/L"
+    );
+
+    for cfd in cfds {
+        let profit = match (cfd.profit_btc, &cfd.profit_percent) {
+            (Some(btc), Some(percent)) => format!("{} ({percent}%)", btc.value()),
+            _ => "-".to_owned(),
+        };
+
+        writeln!(
+            table,
+            "{:<36}  {:<20}  {:>10}  {profit}",
+            cfd.order_id.to_string(),
+            format!("{:?}", cfd.state),
+            cfd.quantity_usd.to_string(),
+        )
+        .expect("writing to a string to succeed");
+    }
+
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use daemon::bdk::bitcoin::Network;
+    use daemon::db;
+    use daemon::model::cfd::OrderId;
+    use daemon::model::cfd::Role;
+    use daemon::model::FundingRate;
+    use daemon::model::Leverage;
+    use daemon::model::OpeningFee;
+    use daemon::model::Position;
+    use daemon::model::RoundingStrategy;
+    use daemon::model::TxFeeRate;
+    use daemon::projection;
+
+    #[tokio::test]
+    async fn status_lists_cfds_in_db() {
+        let db = db::memory().await.unwrap();
+        let cfd = dummy_cfd();
+        {
+            let mut conn = db.acquire().await.unwrap();
+            db::insert_cfd(&cfd, &mut conn).await.unwrap();
+        }
+
+        let with_price =
+            projection::load_all_cfds(&db, Network::Testnet, Some("61000".parse().unwrap()))
+                .await
+                .unwrap();
+        let without_price = projection::load_all_cfds(&db, Network::Testnet, None)
+            .await
+            .unwrap();
+
+        let table = render(&with_price);
+        let row = table.lines().nth(1).unwrap();
+        assert!(row.starts_with(&cfd.id().to_string()), "{table}");
+        assert!(row.contains("PendingSetup"), "{table}");
+        assert!(row.contains("1000"), "{table}");
+        assert!(row.contains("BTC"), "{table}");
+
+        let table = render(&without_price);
+        assert!(table.lines().nth(1).unwrap().ends_with("  -"), "{table}");
+    }
+
+    fn dummy_cfd() -> daemon::model::cfd::Cfd {
+        daemon::model::cfd::Cfd::new(
+            OrderId::default(),
+            Position::Long,
+            "60000".parse().unwrap(),
+            Leverage::new(2).unwrap(),
+            time::Duration::hours(24),
+            Role::Taker,
+            "1000".parse().unwrap(),
+            "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF"
+                .parse()
+                .unwrap(),
+            OpeningFee::default(),
+            FundingRate::default(),
+            TxFeeRate::default(),
+            daemon::N_PAYOUTS,
+            RoundingStrategy::default(),
+        )
+    }
+}
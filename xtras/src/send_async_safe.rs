@@ -29,10 +29,11 @@ where
 }
 
 #[async_trait]
-impl<A, M, E> SendAsyncSafe<M, Result<(), E>> for xtra::Address<A>
+impl<A, M, T, E> SendAsyncSafe<M, Result<T, E>> for xtra::Address<A>
 where
     A: xtra::Handler<M>,
-    M: xtra::Message<Result = Result<(), E>>,
+    M: xtra::Message<Result = Result<T, E>>,
+    T: Send,
     E: fmt::Display + Send,
 {
     async fn send_async_safe(&self, msg: M) -> Result<(), xtra::Disconnected> {
@@ -47,7 +48,7 @@ where
             let e = match send_fut.await {
                 Ok(Err(e)) => format!("{e:#}"),
                 Err(e) => format!("{e:#}"),
-                Ok(Ok(())) => return,
+                Ok(Ok(_)) => return,
             };
 
             tracing::warn!("Async message invocation failed: {:#}", e)
@@ -80,9 +81,10 @@ where
 }
 
 #[async_trait]
-impl<M, E> SendAsyncSafe<M, Result<(), E>> for Box<dyn xtra::prelude::MessageChannel<M>>
+impl<M, T, E> SendAsyncSafe<M, Result<T, E>> for Box<dyn xtra::prelude::MessageChannel<M>>
 where
-    M: xtra::Message<Result = Result<(), E>>,
+    M: xtra::Message<Result = Result<T, E>>,
+    T: Send,
     E: fmt::Display + Send,
 {
     async fn send_async_safe(&self, msg: M) -> Result<(), xtra::Disconnected> {
@@ -97,7 +99,7 @@ where
             let e = match send_fut.await {
                 Ok(Err(e)) => format!("{e:#}"),
                 Err(e) => format!("{e:#}"),
-                Ok(Ok(())) => return,
+                Ok(Ok(_)) => return,
             };
 
             tracing::warn!("Async message invocation failed: {e:#}")